@@ -31,6 +31,10 @@ fn bench_roll_byte(c: &mut Criterion) {
                 });
             });
 
+            group.bench_function(concat!(stringify!($name), "/digest_of"), |b| {
+                b.iter(|| black_box(rollsum::$name::digest_of(black_box(&data))));
+            });
+
             group.bench_function(concat!(stringify!($name), "/split"), |b| {
                 let mut engine = rollsum::$name::new();
                 b.iter(|| {
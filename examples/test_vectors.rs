@@ -0,0 +1,11 @@
+//! Print the test vectors of every engine as JSON
+//!
+//! Ports of the engines to other languages can check their boundaries and
+//! digests against this output: `cargo run --example test_vectors > vectors.json`
+
+fn main() {
+    print!(
+        "{}",
+        rollsum::vectors::to_json(&rollsum::vectors::generate())
+    );
+}
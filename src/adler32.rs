@@ -0,0 +1,270 @@
+use super::condition::{EdgeCondition, MaskEngine};
+use super::{ChunkBits, Chunker, Engine, SlidingEngine};
+use std::convert::TryFrom;
+
+pub type Digest = u32;
+
+/// Default chunk size used by `adler32` (log2)
+pub const CHUNK_BITS: u32 = 13;
+
+/// Default window size used by `adler32`
+pub const WINDOW_SIZE: usize = 64;
+
+/// Largest number of bits the edge condition can match, as it only looks at
+/// the 16 bit weighted sum
+pub const MAX_CHUNK_BITS: u32 = 16;
+
+/// Largest prime below 2^16, which both sums are taken modulo
+const MOD: u32 = 65521;
+
+/// Adler-32 checksum over a sliding window
+///
+/// The digest is the Adler-32 checksum of the last window of bytes, with
+/// the weighted sum in the high 16 bits as zlib stores it, so it can be
+/// compared with weak checksums of tools using Adler-32. Before the window
+/// is filled, it is the checksum of the bytes preceded by zeros.
+#[derive(Clone)]
+pub struct Adler32 {
+    /// Sum of the bytes plus one
+    a: u32,
+    /// Sum of `a` after each byte of the window
+    b: u32,
+    window: Box<[u8]>,
+    wofs: usize,
+    chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to the window size
+    filled: usize,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::with_window(WINDOW_SIZE, CHUNK_BITS)
+    }
+}
+
+impl Adler32 {
+    /// Create new Adler-32 engine with default chunking settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create new Adler-32 engine with custom chunking settings
+    ///
+    /// `chunk_bits` is number of bits that need to match in
+    /// the edge condition. `CHUNK_BITS` constant is the default.
+    pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
+        Self::with_window(WINDOW_SIZE, chunk_bits)
+    }
+
+    /// Create new Adler-32 engine summing `window_size` byte windows
+    ///
+    /// Panics unless `chunk_bits` is at most `MAX_CHUNK_BITS` and valid for
+    /// the window, see `ChunkBits::for_window`.
+    pub fn with_window(window_size: usize, chunk_bits: u32) -> Self {
+        assert!(window_size > 0);
+        let chunk_bits = ChunkBits::expect(
+            ChunkBits::with_max(chunk_bits, MAX_CHUNK_BITS)
+                .and_then(|bits| ChunkBits::for_window(bits.get(), window_size)),
+        );
+        Adler32 {
+            a: 1,
+            b: Self::window_mod(window_size),
+            window: vec![0; window_size].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits,
+            filled: 0,
+        }
+    }
+
+    fn window_mod(window_size: usize) -> u32 {
+        (window_size % MOD as usize) as u32
+    }
+
+    /// Find chunk edge using Adler-32 defaults.
+    ///
+    /// See `Engine::find_chunk_edge_cond`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let cond = Self::condition_for_bits(self.chunk_bits);
+        self.find_chunk_edge_with(buf, &cond)
+    }
+}
+
+impl Engine for Adler32 {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let prevch = self.window[self.wofs];
+        self.slide(prevch, newch);
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let window_size = self.window.len();
+        let filled = window_size.min(self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, window_size, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        (self.b << 16) | self.a
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Only the last window matters, as for `Bup`
+        let window_size = left.window.len();
+        if window_size != right.window.len() {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(window_size, |len| len.min(window_size));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + window_size - take + i) % window_size]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.a = 1;
+        self.b = Self::window_mod(self.window.len());
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl SlidingEngine for Adler32 {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        let window_size = self.window.len();
+        self.window[self.wofs] = new;
+        self.a = (self.a + MOD + new as u32 - old as u32) % MOD;
+        // Every byte of the window moves up one weight, and the one leaving
+        // takes its full weight and the initial one of `a` with it
+        let dropped = Self::window_mod(window_size) * old as u32 + 1;
+        self.b = ((self.b + self.a) % MOD + 256 * MOD - dropped) % MOD;
+        self.wofs = (self.wofs + 1) % window_size;
+        if self.filled < window_size {
+            self.filled += 1;
+        }
+    }
+}
+
+impl Chunker for Adler32 {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        Adler32::find_chunk_edge(self, buf)
+    }
+}
+
+/// Edge when the lowest `bits` bits of the Adler-32 weighted sum are zero
+///
+/// The plain sum of a window is far from uniform, and as the weighted sum
+/// is below 65521 it has its low bits all set less often than cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Adler32Condition {
+    bits: u32,
+}
+
+impl Adler32Condition {
+    /// Create a condition matching `bits` bits, at most `MAX_CHUNK_BITS`
+    pub const fn new(bits: u32) -> Self {
+        assert!(bits <= MAX_CHUNK_BITS);
+        Adler32Condition { bits }
+    }
+
+    /// Return the number of bits matched
+    pub const fn bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+impl EdgeCondition<Adler32> for Adler32Condition {
+    #[inline(always)]
+    fn is_edge(&self, engine: &Adler32) -> bool {
+        engine.b & ((1 << self.bits) - 1) == 0
+    }
+}
+
+impl MaskEngine for Adler32 {
+    type Condition = Adler32Condition;
+
+    fn condition_for_bits(bits: u32) -> Adler32Condition {
+        Adler32Condition::new(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1, 0);
+        for &byte in data {
+            a = (a + byte as u32) % MOD;
+            b = (b + a) % MOD;
+        }
+        (b << 16) | a
+    }
+
+    #[test]
+    fn digest_is_adler32_of_window() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        let mut engine = Adler32::with_window(9, 4);
+        engine.roll(b"Wikipedia");
+        assert_eq!(engine.digest(), 0x11e6_0398);
+
+        let data = rand_data(20_000);
+        for &window_size in &[1, 64, 5552, 65_536] {
+            let mut engine = Adler32::with_window(window_size, MAX_CHUNK_BITS);
+            let mut padded = vec![0; window_size];
+            padded.extend_from_slice(&data);
+            for (i, &b) in data.iter().enumerate() {
+                engine.roll_byte(b);
+                if i % 97 == 0 || i + 1 == window_size {
+                    let window = &padded[i + 1..i + 1 + window_size];
+                    assert_eq!(engine.digest(), adler32(window), "{} {}", window_size, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edge_expected_size() {
+        let data = rand_data(2 * 1024 * 1024);
+        for bits in 8..13 {
+            let mut engine = Adler32::new_with_chunk_bits(bits);
+            let (mut count, mut total) = (0, 0);
+            let mut remaining = &data[..];
+            while let Some((i, _)) = engine.find_chunk_edge(remaining) {
+                count += 1;
+                total += i;
+                remaining = &remaining[i..];
+            }
+            let expected = (1 << bits) as f64;
+            let average = total as f64 / count as f64;
+            assert!((average - expected).abs() / expected < 0.1, "{}", average);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "exceed the maximum of 16")]
+    fn rejects_too_many_bits() {
+        Adler32::new_with_chunk_bits(17);
+    }
+}
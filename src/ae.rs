@@ -0,0 +1,148 @@
+use super::Chunker;
+use std::cmp;
+
+/// AE (Asymmetric Extremum) chunker
+///
+/// Every position of a chunk has the value of the 8 bytes ending there,
+/// read as a big endian integer. The chunk ends `window_size` bytes past
+/// its maximum value, once no larger value was found in between, or at
+/// `max_size` bytes. The window only extends to the right of the maximum,
+/// so no state has to be kept to the left of it, and a single comparison
+/// per byte suffices. The digest reported for an edge is the maximum value.
+///
+/// Unlike mask based conditions, which may never match data of low entropy
+/// and end up forcing edges, a maximum always exists, so such data is still
+/// chunked at content defined positions. For random data the average chunk
+/// size is `(e - 1) * window_size`.
+///
+/// Based on "AE: An Asymmetric Extremum Content Defined Chunking Algorithm
+/// for Fast and Bandwidth-Efficient Data Deduplication" (Zhang et al.,
+/// INFOCOM 2015).
+#[derive(Debug, Clone)]
+pub struct AeChunker {
+    window_size: usize,
+    max_size: usize,
+    /// Last 8 bytes of the current chunk
+    value: u64,
+    /// Largest value of the current chunk and its position in the chunk
+    max_value: u64,
+    max_pos: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl AeChunker {
+    /// Create a new chunker ending chunks `window_size` bytes past their
+    /// maximum, forcing edges at `max_size` bytes
+    pub fn new(window_size: usize, max_size: usize) -> Self {
+        assert!(window_size > 0);
+        assert!(window_size < max_size);
+        AeChunker {
+            window_size,
+            max_size,
+            value: 0,
+            max_value: 0,
+            max_pos: 0,
+            len: 0,
+        }
+    }
+
+    /// Create a new chunker with an average chunk size of about `avg_size`
+    /// bytes for random data, forcing edges at eight times the average
+    pub fn with_avg_size(avg_size: usize) -> Self {
+        let window_size = cmp::max(1, (avg_size as f64 / (std::f64::consts::E - 1.0)) as usize);
+        Self::new(
+            window_size,
+            cmp::max(avg_size, window_size + 1).saturating_mul(8),
+        )
+    }
+
+    /// Return the window size
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl Chunker for AeChunker {
+    type Digest = u64;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u64)> {
+        for (i, &b) in buf.iter().enumerate() {
+            self.value = (self.value << 8) | b as u64;
+            let pos = self.len;
+            self.len += 1;
+            if pos == 0 || self.value > self.max_value {
+                self.max_value = self.value;
+                self.max_pos = pos;
+            }
+            if pos == self.max_pos + self.window_size || self.len >= self.max_size {
+                let digest = self.max_value;
+                self.value = 0;
+                self.len = 0;
+                return Some((i + 1, digest));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, chunk_sizes, rand_data, with_insert};
+
+    #[test]
+    fn average_size() {
+        let data = rand_data(4 * 1024 * 1024);
+        let mut chunker = AeChunker::with_avg_size(4096);
+        let sizes = chunk_sizes(&mut chunker, &data);
+        assert!(sizes.iter().all(|&s| s <= chunker.max_size()));
+        assert!(sizes.iter().all(|&s| s > chunker.window_size()));
+        let mean = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        assert!((mean - 4096.0).abs() / 4096.0 < 0.1, "{}", mean);
+    }
+
+    #[test]
+    fn low_entropy_still_content_defined() {
+        // Constant runs have no larger value, so they end a window past
+        // their start rather than at `max_size`
+        let mut data = vec![0; 100_000];
+        data[50_000..].fill(7);
+        let sizes = chunk_sizes(&mut AeChunker::new(1000, 8192), &data);
+        assert!(sizes.iter().all(|&s| s < 8192));
+    }
+
+    #[test]
+    fn forced_edges() {
+        // Rising values keep moving the maximum
+        let data: Vec<u8> = (0..1000).map(|i| (i / 4) as u8).collect();
+        let mut chunker = AeChunker::new(300, 500);
+        assert_eq!(chunk_sizes(&mut chunker, &data), vec![500, 500]);
+    }
+
+    #[test]
+    fn incremental_matches_whole() {
+        let data = rand_data(512 * 1024);
+        let new = || AeChunker::new(700, 8192);
+        let whole = chunk_edges(&mut new(), &data);
+        assert_eq!(chunk_edges_framed(&mut new(), &data, 307), whole);
+    }
+
+    #[test]
+    fn resynchronizes_after_insert() {
+        let data = rand_data(256 * 1024);
+        let modified = with_insert(&data);
+
+        let tail = |data: &[u8]| {
+            let mut sizes = chunk_sizes(&mut AeChunker::with_avg_size(2048), data);
+            sizes.drain(..sizes.len() / 2);
+            sizes
+        };
+        assert_eq!(tail(&data), tail(&modified));
+    }
+}
@@ -0,0 +1,249 @@
+use super::condition::MaskEngine;
+#[cfg(feature = "gear")]
+use super::NormalizedChunker;
+use super::{BuildError, Chunker, MinMaxChunker, Params};
+
+enum Inner {
+    #[cfg(feature = "bup")]
+    Bup(MinMaxChunker<super::Bup, <super::Bup as MaskEngine>::Condition>),
+    #[cfg(feature = "gear")]
+    Gear(MinMaxChunker<super::Gear, <super::Gear as MaskEngine>::Condition>),
+    #[cfg(feature = "gear")]
+    Normalized(NormalizedChunker<super::Gear>),
+    #[cfg(feature = "fastcdc")]
+    FastCdc(super::FastCdc),
+}
+
+/// Chunker configured at runtime by `Params`
+///
+/// Digests of all engines are widened to `u64`. Size bounds beyond the
+/// address space, e.g. `u64::MAX` for unbounded chunks, are saturated to
+/// `usize::MAX`.
+pub struct AnyChunker {
+    params: Params,
+    inner: Inner,
+}
+
+impl AnyChunker {
+    /// Create the chunker described by `params`
+    ///
+    /// Fails if the parameters are invalid, see `Params::validate`, or if
+    /// the feature of the engine is disabled.
+    pub fn new<P: Into<Params>>(params: P) -> Result<Self, BuildError> {
+        let params = params.into();
+        params.validate()?;
+        let (min, max) = (
+            crate::saturating_usize(params.min_size()),
+            crate::saturating_usize(params.max_size()),
+        );
+        let inner = match params {
+            #[cfg(feature = "bup")]
+            Params::Bup { chunk_bits, .. } => Inner::Bup(MinMaxChunker::new(
+                super::Bup::new_with_chunk_bits(chunk_bits),
+                min,
+                max,
+            )),
+            #[cfg(feature = "gear")]
+            Params::Gear { chunk_bits, .. } => Inner::Gear(MinMaxChunker::new(
+                super::Gear::new_with_chunk_bits(chunk_bits),
+                min,
+                max,
+            )),
+            #[cfg(feature = "gear")]
+            Params::Normalized {
+                chunk_bits, level, ..
+            } => Inner::Normalized(NormalizedChunker::with_level(
+                super::Gear::new_with_chunk_bits(chunk_bits),
+                min,
+                max,
+                level,
+            )),
+            #[cfg(feature = "fastcdc")]
+            Params::FastCdc {
+                chunk_bits, level, ..
+            } => {
+                crate::ChunkBits::for_window(chunk_bits, crate::fastcdc::WINDOW_SIZE)?;
+                Inner::FastCdc(super::FastCdc::with_sizes(chunk_bits, min, max, level))
+            }
+            #[allow(unreachable_patterns)]
+            params => return Err(BuildError::Disabled(params)),
+        };
+        Ok(AnyChunker { params, inner })
+    }
+
+    /// Create the chunker with the highest throughput on this machine,
+    /// averaging `avg_size` byte chunks
+    ///
+    /// For data which doesn't have to be chunked like by other tools. The
+    /// algorithm is chosen by `bench::fastest_algorithm`, once per process;
+    /// record `ChunkerParams::try_from(*chunker.params())`, which always
+    /// succeeds, to chunk the same way later. Chunks are between a quarter
+    /// and four times `avg_size`. Panics unless `avg_size` is a power of
+    /// two, see `ChunkBits::from_avg_size`.
+    #[cfg(feature = "bench")]
+    pub fn fastest(avg_size: u64) -> Self {
+        let chunk_bits = crate::ChunkBits::expect(crate::ChunkBits::from_avg_size(avg_size));
+        let params = crate::tuning::params_for_bits(crate::bench::fastest_algorithm(), chunk_bits);
+        Self::new(params).expect("parameters of an enabled algorithm")
+    }
+
+    /// Return the parameters of the chunker
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Start over as if newly created
+    pub fn reset(&mut self) {
+        *self = Self::new(self.params).expect("parameters validated by `new`");
+    }
+}
+
+impl Chunker for AnyChunker {
+    type Digest = u64;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u64)> {
+        match &mut self.inner {
+            #[cfg(feature = "bup")]
+            Inner::Bup(chunker) => chunker.find_chunk_edge(buf).map(|(i, d)| (i, d.into())),
+            #[cfg(feature = "gear")]
+            Inner::Gear(chunker) => chunker.find_chunk_edge(buf),
+            #[cfg(feature = "gear")]
+            Inner::Normalized(chunker) => chunker.find_chunk_edge(buf),
+            #[cfg(feature = "fastcdc")]
+            Inner::FastCdc(chunker) => chunker.find_chunk_edge(buf),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bup", feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{Algorithm, Bup, ChunkerParams, Gear};
+
+    fn edges<C: Chunker>(mut chunker: C, data: &[u8]) -> Vec<(usize, u64)>
+    where
+        C::Digest: Into<u64>,
+    {
+        let mut edges = Vec::new();
+        let mut offset = 0;
+        while let Some((i, digest)) = chunker.find_chunk_edge(&data[offset..]) {
+            offset += i;
+            edges.push((offset, digest.into()));
+        }
+        edges
+    }
+
+    #[test]
+    fn matches_concrete_chunkers() {
+        let data = rand_data(256 * 1024);
+        let bup = ChunkerParams {
+            min_size: 256,
+            max_size: 4096,
+            ..ChunkerParams::new(Algorithm::Bup, 10)
+        };
+        assert_eq!(
+            edges(AnyChunker::new(bup).unwrap(), &data),
+            edges(
+                MinMaxChunker::new(Bup::new_with_chunk_bits(10), 256, 4096),
+                &data
+            )
+        );
+        assert_eq!(
+            edges(
+                AnyChunker::new(ChunkerParams::new(Algorithm::Gear, 11)).unwrap(),
+                &data
+            ),
+            edges(Gear::new_with_chunk_bits(11), &data)
+        );
+        let normalized = Params::Normalized {
+            chunk_bits: 11,
+            min_size: 512,
+            max_size: 8192,
+            level: 1,
+        };
+        assert_eq!(
+            edges(AnyChunker::new(normalized).unwrap(), &data),
+            edges(
+                NormalizedChunker::with_level(Gear::new_with_chunk_bits(11), 512, 8192, 1),
+                &data
+            )
+        );
+    }
+
+    #[cfg(feature = "fastcdc")]
+    #[test]
+    fn constructs_fastcdc() {
+        use crate::FastCdc;
+
+        let data = rand_data(256 * 1024);
+        let fastcdc = Params::FastCdc {
+            chunk_bits: 11,
+            min_size: 512,
+            max_size: 8192,
+            level: 1,
+        };
+        assert_eq!(
+            edges(AnyChunker::new(fastcdc).unwrap(), &data),
+            edges(FastCdc::with_sizes(11, 512, 8192, 1), &data)
+        );
+        let small = Params::FastCdc {
+            chunk_bits: 5,
+            min_size: 0,
+            max_size: 1024,
+            level: 1,
+        };
+        assert!(matches!(
+            AnyChunker::new(small),
+            Err(BuildError::ChunkBits(_))
+        ));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let data = rand_data(64 * 1024);
+        let mut chunker = AnyChunker::new(ChunkerParams::new(Algorithm::Gear, 10)).unwrap();
+        assert!(chunker.find_chunk_edge(&data[..100]).is_none());
+        chunker.reset();
+        assert_eq!(
+            edges(chunker, &data),
+            edges(Gear::new_with_chunk_bits(10), &data)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let too_many_bits = ChunkerParams::new(Algorithm::Gear, 40);
+        assert!(matches!(
+            AnyChunker::new(too_many_bits),
+            Err(BuildError::ChunkBits(_))
+        ));
+        let inverted = Params::Bup {
+            chunk_bits: 10,
+            min_size: 4096,
+            max_size: 256,
+        };
+        assert_eq!(
+            AnyChunker::new(inverted).err(),
+            Some(BuildError::InvalidSizes {
+                min_size: 4096,
+                max_size: 256
+            })
+        );
+    }
+
+    #[cfg(feature = "bench")]
+    #[test]
+    fn fastest_is_stable_and_recordable() {
+        use std::convert::TryFrom;
+
+        let chunker = AnyChunker::fastest(8192);
+        let params = ChunkerParams::try_from(*chunker.params()).unwrap();
+        assert_eq!(params.chunk_bits, 13);
+        assert_eq!(AnyChunker::fastest(4096).params().min_size(), 1024);
+        for _ in 0..3 {
+            let again = ChunkerParams::try_from(*AnyChunker::fastest(8192).params());
+            assert_eq!(again, Ok(params));
+        }
+    }
+}
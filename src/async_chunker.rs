@@ -0,0 +1,878 @@
+use super::entropy::{ByteHistogram, Entropy};
+use super::progress::Progress;
+use super::throttle::RateLimiter;
+use super::Chunker;
+use futures_core::Stream;
+use futures_sink::Sink;
+use futures_util::{StreamExt, TryStreamExt};
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+/// Default number of bytes of complete chunks `channel` buffers before
+/// applying backpressure
+pub const DEFAULT_CAPACITY: usize = 1 << 20;
+
+/// Chunk emitted by `ChunkReceiver`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// Offset of the first byte of the chunk within the stream
+    pub offset: u64,
+    /// Contents of the chunk
+    pub data: Vec<u8>,
+    /// Entropy of the contents, if enabled with
+    /// `ChunkSender::set_estimate_entropy`
+    pub entropy: Option<Entropy>,
+}
+
+/// Errors reported by `ChunkSender`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The `ChunkReceiver` was dropped, so chunks can no longer be delivered
+    Disconnected,
+    /// A chunk grew past the limit given to `channel_with_limit`
+    LimitExceeded,
+    /// The stream was cancelled by `ChunkSender::cancel` or
+    /// `ChunkReceiver::cancel`
+    Cancelled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Disconnected => write!(f, "chunk receiver was dropped"),
+            Error::LimitExceeded => write!(f, "chunk exceeds the memory limit"),
+            Error::Cancelled => write!(f, "chunking was cancelled"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Helper thread waking the sender's task at the end of a throttle
+///
+/// One per channel, started by `ChunkSender::set_rate_limit` and stopped
+/// when the sender is dropped.
+struct Timer {
+    state: Mutex<TimerState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct TimerState {
+    /// Time to wake `waker` at
+    deadline: Option<Instant>,
+    waker: Option<Waker>,
+    stopped: bool,
+}
+
+impl Timer {
+    fn start() -> Arc<Self> {
+        let timer = Arc::new(Timer {
+            state: Mutex::new(TimerState::default()),
+            changed: Condvar::new(),
+        });
+        let thread_timer = timer.clone();
+        thread::spawn(move || thread_timer.run());
+        timer
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let deadline = match state.deadline {
+                Some(deadline) => deadline,
+                None => {
+                    state = self.changed.wait(state).unwrap();
+                    continue;
+                }
+            };
+            let now = Instant::now();
+            if deadline > now {
+                state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+            state.deadline = None;
+            let waker = state.waker.take();
+            // The task may be polled right away, which locks the timer again
+            drop(state);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            state = self.state.lock().unwrap();
+        }
+    }
+
+    /// Wake `waker` at `deadline`, instead of any task registered before
+    fn wake_at(&self, deadline: Instant, waker: &Waker) {
+        let mut state = self.state.lock().unwrap();
+        state.deadline = Some(deadline);
+        match &mut state.waker {
+            Some(registered) => registered.clone_from(waker),
+            registered => *registered = Some(waker.clone()),
+        }
+        self.changed.notify_one();
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.changed.notify_one();
+    }
+}
+
+/// Callback given to `ChunkSender::on_progress`
+type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
+
+struct Shared<C> {
+    chunker: C,
+    /// Bytes of the chunk which has not been completed yet
+    partial: Vec<u8>,
+    /// Stream offset of the first byte of `partial`
+    offset: u64,
+    /// Byte frequencies of `partial`, if estimating entropy
+    histogram: Option<ByteHistogram>,
+    /// Completed chunks waiting for the receiver
+    ready: VecDeque<Chunk>,
+    /// Total length of the chunks in `ready`
+    buffered: usize,
+    capacity: usize,
+    /// Maximum length of `partial`
+    max_partial: usize,
+    /// Whether `max_partial` was exceeded
+    failed: bool,
+    cancelled: bool,
+    /// Stream offset of the end of the last chunk received
+    received: u64,
+    closed: bool,
+    receiver_alive: bool,
+    /// Number of chunks emitted
+    chunks: u64,
+    total: Option<u64>,
+    progress: Option<ProgressFn>,
+    limiter: Option<RateLimiter>,
+    /// Time before which no more data is accepted
+    throttled_until: Option<Instant>,
+    send_waker: Option<Waker>,
+    recv_waker: Option<Waker>,
+}
+
+impl<C: Chunker> Shared<C> {
+    fn push(&mut self, mut buf: &[u8]) -> Result<(), Error> {
+        while let Some((i, _)) = self.chunker.find_chunk_edge(buf) {
+            self.extend_partial(&buf[..i])?;
+            self.emit();
+            buf = &buf[i..];
+        }
+        self.extend_partial(buf)
+    }
+}
+
+impl<C> Shared<C> {
+    fn extend_partial(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > self.max_partial - self.partial.len() {
+            return Err(Error::LimitExceeded);
+        }
+        self.partial.extend_from_slice(data);
+        if let Some(histogram) = &mut self.histogram {
+            histogram.update(data);
+        }
+        Ok(())
+    }
+
+    /// Give up on the stream after the limit was exceeded
+    fn fail(&mut self) {
+        self.failed = true;
+        self.closed = true;
+        self.partial = Vec::new();
+        wake(&mut self.recv_waker);
+    }
+
+    fn report_progress(&mut self) {
+        let progress = Progress {
+            bytes: self.offset + self.partial.len() as u64,
+            chunks: self.chunks,
+            total: self.total,
+        };
+        if let Some(f) = &mut self.progress {
+            f(&progress);
+        }
+    }
+
+    fn error(&self) -> Option<Error> {
+        if self.failed {
+            Some(Error::LimitExceeded)
+        } else if self.cancelled {
+            Some(Error::Cancelled)
+        } else {
+            None
+        }
+    }
+
+    /// End the stream at the last completed chunk
+    fn cancel(&mut self) {
+        self.cancelled = true;
+        self.closed = true;
+        self.partial = Vec::new();
+        wake(&mut self.recv_waker);
+        wake(&mut self.send_waker);
+    }
+
+    fn emit(&mut self) {
+        let data = mem::take(&mut self.partial);
+        let offset = self.offset;
+        self.chunks += 1;
+        self.offset += data.len() as u64;
+        self.buffered += data.len();
+        let entropy = self.histogram.as_mut().map(|histogram| {
+            let entropy = histogram.entropy();
+            histogram.reset();
+            entropy
+        });
+        self.ready.push_back(Chunk {
+            offset,
+            data,
+            entropy,
+        });
+        wake(&mut self.recv_waker);
+    }
+
+    fn close(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+        if !self.partial.is_empty() {
+            self.emit();
+        }
+        self.report_progress();
+        wake(&mut self.recv_waker);
+    }
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+/// Create a bounded chunking channel
+///
+/// Data written to the returned `ChunkSender` is split by `chunker`, and the
+/// resulting chunks are read from the `ChunkReceiver`. Once `capacity` bytes
+/// of complete chunks are waiting to be received, `poll_ready` on the sender
+/// stays pending until the receiver catches up, so a slow consumer slows the
+/// producer down instead of growing the buffer.
+///
+/// The chunk being accumulated when the sender is closed (or dropped) is
+/// emitted as the final chunk.
+pub fn channel<C: Chunker>(chunker: C, capacity: usize) -> (ChunkSender<C>, ChunkReceiver<C>) {
+    channel_with_limit(chunker, capacity, usize::MAX)
+}
+
+/// Create a bounded chunking channel which fails instead of buffering a
+/// chunk longer than `max_chunk_size` bytes
+///
+/// See `channel`. Unless the chunker enforces a maximum chunk size, a
+/// producer can make the channel buffer arbitrarily much data by sending
+/// data without chunk edges, which this limit guards against. Once a chunk
+/// would exceed the limit, the sender fails with `Error::LimitExceeded` and
+/// the receiver ends after the chunks completed before, with
+/// `ChunkReceiver::error` reporting the failure.
+pub fn channel_with_limit<C: Chunker>(
+    chunker: C,
+    capacity: usize,
+    max_chunk_size: usize,
+) -> (ChunkSender<C>, ChunkReceiver<C>) {
+    assert!(capacity > 0);
+    let shared = Arc::new(Mutex::new(Shared {
+        chunker,
+        partial: Vec::new(),
+        offset: 0,
+        histogram: None,
+        ready: VecDeque::new(),
+        buffered: 0,
+        capacity,
+        max_partial: max_chunk_size,
+        failed: false,
+        cancelled: false,
+        received: 0,
+        closed: false,
+        receiver_alive: true,
+        chunks: 0,
+        total: None,
+        progress: None,
+        limiter: None,
+        throttled_until: None,
+        send_waker: None,
+        recv_waker: None,
+    }));
+    (
+        ChunkSender {
+            shared: shared.clone(),
+            timer: None,
+        },
+        ChunkReceiver { shared },
+    )
+}
+
+/// Sending half of a chunking `channel`
+pub struct ChunkSender<C> {
+    shared: Arc<Mutex<Shared<C>>>,
+    /// Started with the first rate limit
+    timer: Option<Arc<Timer>>,
+}
+
+impl<C> ChunkSender<C> {
+    fn lock(&self) -> MutexGuard<'_, Shared<C>> {
+        self.shared.lock().unwrap()
+    }
+
+    /// Call `f` with the progress after every send and once when closing
+    ///
+    /// `total` is the expected stream length, which is only reported back.
+    /// `f` runs while the channel is locked, so it must not use the channel.
+    pub fn on_progress<F>(&mut self, total: Option<u64>, f: F)
+    where
+        F: FnMut(&Progress) + Send + 'static,
+    {
+        let mut shared = self.lock();
+        shared.total = total;
+        shared.progress = Some(Box::new(f));
+    }
+
+    /// Accept data at most at the rate of `limiter`
+    ///
+    /// `poll_ready` stays pending while the rate is exceeded; a helper thread,
+    /// started by the first call, wakes the task up when more data may be
+    /// sent.
+    pub fn set_rate_limit(&mut self, limiter: RateLimiter) {
+        self.lock().limiter = Some(limiter);
+        if self.timer.is_none() {
+            self.timer = Some(Timer::start());
+        }
+    }
+
+    /// Attach an entropy estimate to the chunks emitted from now on
+    ///
+    /// Bytes are counted as they are copied into the chunk being
+    /// accumulated, so consumers can skip compressing chunks which look
+    /// incompressible without scanning them again.
+    pub fn set_estimate_entropy(&mut self, enabled: bool) {
+        let mut shared = self.lock();
+        if !enabled {
+            shared.histogram = None;
+        } else if shared.histogram.is_none() {
+            let mut histogram = ByteHistogram::new();
+            histogram.update(&shared.partial);
+            shared.histogram = Some(histogram);
+        }
+    }
+
+    /// Stop chunking at the last completed chunk
+    ///
+    /// Unlike closing the sender, the partial chunk is dropped instead of
+    /// being emitted as the final chunk. The receiver still gets the chunks
+    /// completed before, then ends with `ChunkReceiver::error` reporting
+    /// `Error::Cancelled`. Returns the stream offset of the end of the last
+    /// completed chunk.
+    pub fn cancel(self) -> u64 {
+        let mut shared = self.lock();
+        shared.cancel();
+        shared.offset
+    }
+
+    fn poll_drained(&self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let mut shared = self.lock();
+        if let Some(error) = shared.error() {
+            Poll::Ready(Err(error))
+        } else if shared.ready.is_empty() {
+            Poll::Ready(Ok(()))
+        } else if !shared.receiver_alive {
+            Poll::Ready(Err(Error::Disconnected))
+        } else {
+            shared.send_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a, C: Chunker> Sink<&'a [u8]> for ChunkSender<C> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let mut shared = self.lock();
+        if let Some(error) = shared.error() {
+            Poll::Ready(Err(error))
+        } else if !shared.receiver_alive {
+            Poll::Ready(Err(Error::Disconnected))
+        } else if let Some(until) = shared.throttled_until.filter(|&u| u > Instant::now()) {
+            // Only throttled with a limiter, which started the timer
+            if let Some(timer) = &self.timer {
+                timer.wake_at(until, cx.waker());
+            }
+            Poll::Pending
+        } else if shared.buffered < shared.capacity {
+            Poll::Ready(Ok(()))
+        } else {
+            shared.send_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: &'a [u8]) -> Result<(), Error> {
+        let mut shared = self.lock();
+        if let Some(error) = shared.error() {
+            return Err(error);
+        }
+        if !shared.receiver_alive {
+            return Err(Error::Disconnected);
+        }
+        shared.push(item).inspect_err(|_| shared.fail())?;
+        if let Some(limiter) = &mut shared.limiter {
+            let now = Instant::now();
+            shared.throttled_until = Some(now + limiter.consume_at(item.len() as u64, now));
+        }
+        shared.report_progress();
+        Ok(())
+    }
+
+    /// Completes once every complete chunk has been received
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.poll_drained(cx)
+    }
+
+    /// Emits the partial chunk and completes once every chunk has been received
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.lock().close();
+        self.poll_drained(cx)
+    }
+}
+
+impl<C> Drop for ChunkSender<C> {
+    fn drop(&mut self) {
+        self.lock().close();
+        if let Some(timer) = &self.timer {
+            timer.stop();
+        }
+    }
+}
+
+/// Receiving half of a chunking `channel`
+pub struct ChunkReceiver<C> {
+    shared: Arc<Mutex<Shared<C>>>,
+}
+
+impl<C> ChunkReceiver<C> {
+    /// Return the error which ended the stream early, if any
+    pub fn error(&self) -> Option<Error> {
+        self.shared.lock().unwrap().error()
+    }
+
+    /// Stop chunking, dropping the chunks not received yet
+    ///
+    /// The sender fails with `Error::Cancelled` from then on. Returns the
+    /// stream offset of the end of the last chunk received, which is where
+    /// chunking has to resume later.
+    pub fn cancel(&mut self) -> u64 {
+        let mut shared = self.shared.lock().unwrap();
+        shared.cancel();
+        shared.ready.clear();
+        shared.buffered = 0;
+        shared.received
+    }
+}
+
+impl<C> Stream for ChunkReceiver<C> {
+    type Item = Chunk;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Chunk>> {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(chunk) = shared.ready.pop_front() {
+            shared.buffered -= chunk.data.len();
+            shared.received = chunk.offset + chunk.data.len() as u64;
+            wake(&mut shared.send_waker);
+            Poll::Ready(Some(chunk))
+        } else if shared.closed {
+            Poll::Ready(None)
+        } else {
+            shared.recv_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+impl<C> Drop for ChunkReceiver<C> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.receiver_alive = false;
+        wake(&mut shared.send_waker);
+    }
+}
+
+/// Boxed future returned by `ChunkSink` methods
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Asynchronous content-addressed chunk store, e.g. an object store
+pub trait ChunkSink {
+    /// Key under which chunks are stored
+    type Hash;
+
+    /// Return whether a chunk with `hash` is already stored
+    fn has<'a>(&'a self, hash: &'a Self::Hash) -> BoxFuture<'a, io::Result<bool>>;
+
+    /// Store `data` under `hash`
+    fn put<'a>(&'a self, hash: Self::Hash, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>>;
+}
+
+/// Statistics of an `upload`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// Number of chunks in the stream
+    pub chunks: u64,
+    /// Total length of the chunks in the stream
+    pub bytes: u64,
+    /// Number of chunks which were missing from the sink and uploaded
+    pub uploaded_chunks: u64,
+    /// Total length of the uploaded chunks
+    pub uploaded_bytes: u64,
+}
+
+/// Upload the chunks of `chunks` missing from `sink`
+///
+/// Each chunk is hashed with `hash`, and only uploaded when `sink.has`
+/// reports it missing. At most `concurrency` chunks are checked or uploaded at
+/// the same time, and because `chunks` is only polled when a slot is free,
+/// a chunking `channel` feeding this applies backpressure to its producer.
+///
+/// Identical chunks in flight at the same time may both be uploaded, so
+/// `put` must tolerate storing the same chunk twice.
+pub async fn upload<St, S, H>(
+    chunks: St,
+    sink: &S,
+    hash: H,
+    concurrency: usize,
+) -> io::Result<UploadStats>
+where
+    St: Stream<Item = Chunk>,
+    S: ChunkSink,
+    H: Fn(&[u8]) -> S::Hash,
+{
+    assert!(concurrency > 0);
+    chunks
+        .map(|chunk| {
+            let hash = hash(&chunk.data);
+            async move {
+                let len = chunk.data.len() as u64;
+                if sink.has(&hash).await? {
+                    Ok((len, false))
+                } else {
+                    sink.put(hash, chunk.data).await?;
+                    Ok((len, true))
+                }
+            }
+        })
+        .buffer_unordered(concurrency)
+        .try_fold(
+            UploadStats::default(),
+            |mut stats, (len, uploaded)| async move {
+                stats.chunks += 1;
+                stats.bytes += len;
+                if uploaded {
+                    stats.uploaded_chunks += 1;
+                    stats.uploaded_bytes += len;
+                }
+                Ok(stats)
+            },
+        )
+        .await
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+    use futures::executor::block_on;
+    use futures::{future, SinkExt, StreamExt};
+    use std::task::Waker;
+
+    fn expected_chunks(data: &[u8]) -> Vec<Chunk> {
+        let mut gear = Gear::new_with_chunk_bits(10);
+        let mut result = Vec::new();
+        let mut offset = 0;
+        while let Some((i, _)) = gear.find_chunk_edge(&data[offset..]) {
+            result.push(Chunk {
+                offset: offset as u64,
+                data: data[offset..offset + i].to_vec(),
+                entropy: None,
+            });
+            offset += i;
+        }
+        result.push(Chunk {
+            offset: offset as u64,
+            data: data[offset..].to_vec(),
+            entropy: None,
+        });
+        result
+    }
+
+    #[test]
+    fn chunks_match_sync_chunking() {
+        let data = rand_data(256 * 1024);
+        let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 4096);
+
+        let send = async {
+            for frame in data.chunks(1000) {
+                tx.send(frame).await.unwrap();
+            }
+            tx.close().await.unwrap();
+        };
+        let (_, chunks) = block_on(future::join(send, rx.collect::<Vec<_>>()));
+
+        assert_eq!(chunks, expected_chunks(&data));
+    }
+
+    #[test]
+    fn estimates_entropy() {
+        let data = [rand_data(64 * 1024), vec![b'a'; 100 * 1024]].concat();
+        let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 1 << 20);
+        tx.set_estimate_entropy(true);
+        let send = async {
+            for frame in data.chunks(1000) {
+                tx.send(frame).await.unwrap();
+            }
+            tx.close().await.unwrap();
+        };
+        let (_, chunks) = block_on(future::join(send, rx.collect::<Vec<_>>()));
+
+        let expected: Vec<_> = expected_chunks(&data)
+            .into_iter()
+            .map(|chunk| Chunk {
+                entropy: Some(Entropy::of(&chunk.data)),
+                ..chunk
+            })
+            .collect();
+        assert_eq!(chunks, expected);
+        assert!(chunks[0].entropy.unwrap().is_incompressible());
+        assert!(!chunks.last().unwrap().entropy.unwrap().is_incompressible());
+    }
+
+    #[test]
+    fn reports_progress() {
+        let data = rand_data(64 * 1024);
+        let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 1 << 20);
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        tx.on_progress(Some(data.len() as u64), move |p| {
+            sink.lock().unwrap().push(*p)
+        });
+
+        let send = async {
+            for frame in data.chunks(1000) {
+                tx.send(frame).await.unwrap();
+            }
+            tx.close().await.unwrap();
+        };
+        let (_, chunks) = block_on(future::join(send, rx.collect::<Vec<_>>()));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.len(), data.chunks(1000).len() + 1);
+        assert_eq!(reports[0].bytes, 1000);
+        let last = reports.last().unwrap();
+        assert_eq!(last.bytes, data.len() as u64);
+        assert_eq!(last.chunks, chunks.len() as u64);
+        assert_eq!(last.fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn sender_cancel_stops_at_chunk_boundary() {
+        let data = rand_data(64 * 1024);
+        let expected = expected_chunks(&data);
+        let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 1 << 20);
+        block_on(tx.feed(&data[..30_000])).unwrap();
+        let end = tx.cancel();
+
+        let chunks = block_on(rx.collect::<Vec<_>>());
+        assert_eq!(chunks, expected[..chunks.len()]);
+        assert!(end <= 30_000);
+        assert_eq!(end, expected[chunks.len()].offset);
+    }
+
+    #[test]
+    fn receiver_cancel_reports_received() {
+        let data = rand_data(64 * 1024);
+        let (mut tx, mut rx) = channel(Gear::new_with_chunk_bits(10), 1 << 20);
+        block_on(tx.feed(&data[..])).unwrap();
+        let first = block_on(rx.next()).unwrap();
+        assert_eq!(rx.cancel(), first.data.len() as u64);
+        assert_eq!(rx.error(), Some(Error::Cancelled));
+        assert!(block_on(rx.next()).is_none());
+        assert_eq!(block_on(tx.send(&data[..10])), Err(Error::Cancelled));
+    }
+
+    #[test]
+    fn rate_limit_delays_sender() {
+        use std::time::Duration;
+
+        let data = rand_data(64 * 1024);
+        let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 1 << 20);
+        tx.set_rate_limit(RateLimiter::with_burst(256 * 1024, 16 * 1024));
+        let start = Instant::now();
+        let send = async {
+            for frame in data.chunks(4096) {
+                tx.send(frame).await.unwrap();
+            }
+            tx.close().await.unwrap();
+        };
+        let (_, chunks) = block_on(future::join(send, rx.collect::<Vec<_>>()));
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        assert_eq!(chunks, expected_chunks(&data));
+    }
+
+    #[test]
+    fn slow_receiver_applies_backpressure() {
+        let data = rand_data(64 * 1024);
+        let (mut tx, mut rx) = channel(Gear::new_with_chunk_bits(8), 1024);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        let mut sent = 0;
+        for frame in data.chunks(16) {
+            match Pin::new(&mut tx).poll_ready(&mut cx) {
+                Poll::Ready(Ok(())) => Pin::new(&mut tx).start_send(frame).unwrap(),
+                Poll::Ready(Err(e)) => panic!("{}", e),
+                Poll::Pending => break,
+            }
+            sent += frame.len();
+        }
+        assert!(sent < data.len());
+        assert!(tx.lock().buffered >= 1024);
+        assert!(tx.lock().buffered < 1024 + sent);
+
+        assert!(matches!(rx.poll_next_unpin(&mut cx), Poll::Ready(Some(_))));
+        while tx.lock().buffered >= 1024 {
+            assert!(matches!(rx.poll_next_unpin(&mut cx), Poll::Ready(Some(_))));
+        }
+        assert!(matches!(
+            Pin::new(&mut tx).poll_ready(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    /// Sink which yields once in every call, to exercise concurrency
+    #[derive(Default)]
+    struct MemorySink {
+        chunks: Mutex<std::collections::HashMap<Vec<u8>, Vec<u8>>>,
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    impl MemorySink {
+        async fn enter(&self) {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            let mut yielded = false;
+            future::poll_fn(|cx| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            self.in_flight.lock().unwrap().0 -= 1;
+        }
+    }
+
+    impl ChunkSink for MemorySink {
+        type Hash = Vec<u8>;
+
+        fn has<'a>(&'a self, hash: &'a Vec<u8>) -> BoxFuture<'a, io::Result<bool>> {
+            Box::pin(async move {
+                self.enter().await;
+                Ok(self.chunks.lock().unwrap().contains_key(hash))
+            })
+        }
+
+        fn put<'a>(&'a self, hash: Vec<u8>, data: Vec<u8>) -> BoxFuture<'a, io::Result<()>> {
+            Box::pin(async move {
+                self.enter().await;
+                self.chunks.lock().unwrap().insert(hash, data);
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn upload_skips_stored_chunks() {
+        let data = rand_data(128 * 1024);
+        let sink = MemorySink::default();
+        let upload_data = |data: Vec<u8>| {
+            let (mut tx, rx) = channel(Gear::new_with_chunk_bits(10), 4096);
+            let send = async move {
+                tx.send(&data[..]).await.unwrap();
+                tx.close().await.unwrap();
+            };
+            let upload = upload(rx, &sink, |chunk| chunk.to_vec(), 4);
+            block_on(future::join(send, upload)).1.unwrap()
+        };
+
+        let first = upload_data(data.clone());
+        assert!(first.chunks > 50);
+        assert_eq!(first.bytes, data.len() as u64);
+        // Random data can still contain a few tiny duplicate chunks
+        let stored = sink.chunks.lock().unwrap().len() as u64;
+        assert!(first.uploaded_chunks >= stored);
+        assert!(first.uploaded_chunks + 5 > first.chunks);
+
+        let mut modified = data.clone();
+        modified[60000] ^= 1;
+        let second = upload_data(modified);
+        assert_eq!(second.bytes, data.len() as u64);
+        assert!(second.uploaded_chunks >= 1);
+        assert!(second.uploaded_chunks <= 3);
+
+        let in_flight = *sink.in_flight.lock().unwrap();
+        assert_eq!(in_flight.0, 0);
+        assert!(in_flight.1 > 1);
+        assert!(in_flight.1 <= 4);
+    }
+
+    #[test]
+    fn limit_stops_unbounded_chunks() {
+        let data = rand_data(16 * 1024);
+        let (mut tx, mut rx) = channel_with_limit(Gear::new_with_chunk_bits(10), 1 << 20, 8192);
+        let send = async {
+            tx.send(&data[..]).await.unwrap();
+            // Zeroes never contain a Gear chunk edge
+            let result = tx.send(&[0; 8192][..]).await;
+            assert_eq!(result, Err(Error::LimitExceeded));
+            assert_eq!(tx.send(&b"abc"[..]).await, Err(Error::LimitExceeded));
+        };
+        let (_, chunks) = block_on(future::join(send, rx.by_ref().collect::<Vec<_>>()));
+
+        let mut expected = expected_chunks(&data);
+        expected.pop();
+        assert_eq!(chunks, expected);
+        assert_eq!(rx.error(), Some(Error::LimitExceeded));
+        assert!(tx.lock().partial.is_empty());
+    }
+
+    #[test]
+    fn dropped_receiver_disconnects() {
+        let (mut tx, rx) = channel(Gear::new(), 1024);
+        drop(rx);
+        assert_eq!(block_on(tx.send(&b"abc"[..])), Err(Error::Disconnected));
+    }
+}
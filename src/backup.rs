@@ -0,0 +1,305 @@
+use super::{for_each_chunk, ChunkHash, ChunkStore, Chunker};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Chunks of one file in a `Manifest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileEntry {
+    /// Path of the file relative to the backed up directory
+    pub path: PathBuf,
+    /// Size of the file in bytes
+    pub len: u64,
+    /// Hash of every chunk of the file in order
+    pub chunks: Vec<ChunkHash>,
+}
+
+/// Files of a backed up directory tree, sorted by path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manifest {
+    /// Every regular file found
+    pub files: Vec<FileEntry>,
+    /// Number of chunks which were not stored before
+    pub new_chunks: u64,
+    /// Bytes in chunks which were not stored before
+    pub new_bytes: u64,
+}
+
+impl Manifest {
+    /// Return the entry of the file at `path`, relative to the backed up
+    /// directory
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&FileEntry> {
+        let path = path.as_ref();
+        self.files
+            .binary_search_by(|f| f.path.as_path().cmp(path))
+            .ok()
+            .map(|i| &self.files[i])
+    }
+
+    /// Return the total size of all files
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.len).sum()
+    }
+}
+
+/// Append the path of every regular file below `dir` to `files`
+///
+/// Symbolic links are not followed.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            walk(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// How chunker state is handled between consecutive files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowPolicy {
+    /// Start every file with a new chunker
+    #[default]
+    Reset,
+    /// Keep the chunker of the previous file, in path order, when both are
+    /// at most `max_len` bytes long
+    ///
+    /// Like chunking the files packed into one stream, the rolling window
+    /// and the state of bounded chunkers carry over, but chunks still end at
+    /// the end of every file. Boundaries only depend on the directory
+    /// contents, not on the number of threads.
+    CarrySmall { max_len: u64 },
+}
+
+/// Chunk every regular file below `root` and store the chunks in `store`
+///
+/// Files are chunked on `threads` threads, each using a chunker from
+/// `new_chunker` and identifying chunks by `hash`. Symbolic links and other
+/// special files are skipped. The first error stops the backup; chunks
+/// stored by then are left in `store`.
+pub fn backup_dir<C, F, H, S>(
+    root: &Path,
+    new_chunker: F,
+    hash: H,
+    store: &mut S,
+    threads: usize,
+) -> io::Result<Manifest>
+where
+    C: Chunker,
+    F: Fn() -> C + Sync,
+    H: Fn(&[u8]) -> ChunkHash + Sync,
+    S: ChunkStore + Send,
+{
+    backup_dir_with(root, new_chunker, hash, store, threads, WindowPolicy::Reset)
+}
+
+/// `backup_dir` with chunker state handled between files by `policy`
+pub fn backup_dir_with<C, F, H, S>(
+    root: &Path,
+    new_chunker: F,
+    hash: H,
+    store: &mut S,
+    threads: usize,
+    policy: WindowPolicy,
+) -> io::Result<Manifest>
+where
+    C: Chunker,
+    F: Fn() -> C + Sync,
+    H: Fn(&[u8]) -> ChunkHash + Sync,
+    S: ChunkStore + Send,
+{
+    let mut paths = Vec::new();
+    walk(root, &mut paths)?;
+    paths.sort();
+
+    // Runs of files sharing a chunker, chunked in order by one thread
+    let mut runs = Vec::new();
+    match policy {
+        WindowPolicy::Reset => runs.extend((0..paths.len()).map(|i| i..i + 1)),
+        WindowPolicy::CarrySmall { max_len } => {
+            let mut previous_small = false;
+            for (i, path) in paths.iter().enumerate() {
+                let small = fs::metadata(path)?.len() <= max_len;
+                match runs.last_mut() {
+                    Some(run) if small && previous_small => *run = run.start..i + 1,
+                    _ => runs.push(i..i + 1),
+                }
+                previous_small = small;
+            }
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let store = Mutex::new(store);
+    let new_chunks = AtomicUsize::new(0);
+    let new_bytes = AtomicUsize::new(0);
+
+    let back_up = |path: &Path, chunker: &mut C| -> io::Result<FileEntry> {
+        let mut entry = FileEntry {
+            path: path.strip_prefix(root).unwrap().to_path_buf(),
+            len: 0,
+            chunks: Vec::new(),
+        };
+        for_each_chunk(fs::File::open(path)?, chunker, |_, chunk| {
+            let id = hash(chunk);
+            let mut store = store.lock().unwrap();
+            if !store.has(&id)? {
+                store.put(&id, chunk)?;
+                new_chunks.fetch_add(1, Ordering::Relaxed);
+                new_bytes.fetch_add(chunk.len(), Ordering::Relaxed);
+            }
+            entry.len += chunk.len() as u64;
+            entry.chunks.push(id);
+            Ok(())
+        })?;
+        Ok(entry)
+    };
+
+    let worker = || -> io::Result<Vec<(usize, FileEntry)>> {
+        let mut entries = Vec::new();
+        while !failed.load(Ordering::Relaxed) {
+            let Some(run) = runs.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                break;
+            };
+            let mut chunker = new_chunker();
+            for i in run.clone() {
+                let entry = back_up(&paths[i], &mut chunker)
+                    .inspect_err(|_| failed.store(true, Ordering::Relaxed))?;
+                entries.push((i, entry));
+            }
+        }
+        Ok(entries)
+    };
+
+    let results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads.max(1)).map(|_| s.spawn(worker)).collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut entries = Vec::with_capacity(paths.len());
+    for result in results {
+        entries.extend(result?);
+    }
+    entries.sort_by_key(|&(i, _)| i);
+    Ok(Manifest {
+        files: entries.into_iter().map(|(_, entry)| entry).collect(),
+        new_chunks: new_chunks.into_inner() as u64,
+        new_bytes: new_bytes.into_inner() as u64,
+    })
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::store::MemoryStore;
+    use crate::tests::rand_data;
+    use crate::{Gear, MinMaxChunker};
+
+    #[test]
+    fn backs_up_directory_tree() {
+        let root = std::env::temp_dir().join(format!("rollsum-backup-{}", std::process::id()));
+        let data = rand_data(300 * 1024);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("one"), &data).unwrap();
+        fs::write(root.join("a/b/copy"), &data).unwrap();
+        fs::write(root.join("a/empty"), b"").unwrap();
+        fs::write(root.join("a/tail"), &data[100_000..]).unwrap();
+
+        let mut store = MemoryStore::new();
+        let hash = |chunk: &[u8]| weak_hash(&[chunk]);
+        let manifest =
+            backup_dir(&root, || Gear::new_with_chunk_bits(13), hash, &mut store, 3).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let paths: Vec<_> = manifest.files.iter().map(|f| f.path.clone()).collect();
+        let expected: Vec<PathBuf> = vec![
+            "a/b/copy".into(),
+            "a/empty".into(),
+            "a/tail".into(),
+            "one".into(),
+        ];
+        assert_eq!(paths, expected);
+        assert_eq!(manifest.total_bytes(), 3 * 300 * 1024 - 100_000);
+
+        let one = manifest.get("one").unwrap();
+        assert_eq!(manifest.get("a/b/copy").unwrap().chunks, one.chunks);
+        assert!(manifest.get("a/empty").unwrap().chunks.is_empty());
+        let tail = &manifest.get("a/tail").unwrap().chunks;
+        assert!(tail.ends_with(&one.chunks[one.chunks.len() - 5..]));
+
+        // Only the first chunk of the tail file can be new
+        assert!(manifest.new_chunks as usize <= one.chunks.len() + 1);
+        assert_eq!(store.len() as u64, manifest.new_chunks);
+        let mut restored = Vec::new();
+        for id in &one.chunks {
+            restored.extend(store.get(id).unwrap());
+        }
+        assert!(restored == data);
+    }
+
+    #[test]
+    fn carries_window_across_small_files() {
+        let root = std::env::temp_dir().join(format!("rollsum-carry-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let data = rand_data(400 * 1024);
+        let sizes = [5000, 3000, 200 * 1024, 7000, 9000, 4000, 60 * 1024];
+        let mut files = Vec::new();
+        let mut offset = 0;
+        for (i, &len) in sizes.iter().enumerate() {
+            let contents = &data[offset..offset + len];
+            fs::write(root.join(format!("f{}", i)), contents).unwrap();
+            files.push(contents);
+            offset += len;
+        }
+
+        let hash = |chunk: &[u8]| weak_hash(&[chunk]);
+        let chunker = || MinMaxChunker::new(Gear::new_with_chunk_bits(11), 512, 4096);
+        let policy = WindowPolicy::CarrySmall { max_len: 10_000 };
+        let backup = |policy, threads| {
+            let mut store = MemoryStore::new();
+            backup_dir_with(&root, chunker, hash, &mut store, threads, policy).unwrap()
+        };
+        let carried = backup(policy, 3);
+        let reset = backup(WindowPolicy::Reset, 3);
+        assert_eq!(backup(policy, 1), carried);
+        fs::remove_dir_all(&root).unwrap();
+
+        // Files 0-1 and 3-5 are chunked as runs
+        let mut expected = Vec::new();
+        for run in [&files[0..2], &files[2..3], &files[3..6], &files[6..7]] {
+            let mut chunker = chunker();
+            for file in run {
+                let mut chunks = Vec::new();
+                for_each_chunk(*file, &mut chunker, |_, chunk| {
+                    chunks.push(hash(chunk));
+                    Ok(())
+                })
+                .unwrap();
+                expected.push(chunks);
+            }
+        }
+        let chunks: Vec<_> = carried.files.iter().map(|f| f.chunks.clone()).collect();
+        assert_eq!(chunks, expected);
+        assert_eq!(carried.files[2], reset.files[2]);
+        assert_eq!(carried.files[0], reset.files[0]);
+        assert_ne!(carried.files[1..], reset.files[1..]);
+    }
+
+    #[test]
+    fn missing_directory_fails() {
+        let mut store = MemoryStore::new();
+        let hash = |chunk: &[u8]| weak_hash(&[chunk]);
+        let root = Path::new("/nonexistent/rollsum-backup");
+        assert!(backup_dir(root, || Gear::new_with_chunk_bits(13), hash, &mut store, 2).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+use super::{Algorithm, AnyChunker, Chunker, ChunkerParams, Params};
+use std::convert::TryFrom;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Time `probe` spends on each candidate
+pub const DEFAULT_DURATION: Duration = Duration::from_millis(20);
+
+/// Length of the pseudo random data chunked by the probe
+const SAMPLE_LEN: usize = 1 << 20;
+
+/// Measured throughput of one chunker configuration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throughput {
+    pub params: Params,
+    /// Number of bytes chunked
+    pub bytes: u64,
+    /// Time taken to chunk them
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Return the throughput in bytes per second
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// Return the throughput in megabytes (10^6 bytes) per second
+    pub fn mb_per_sec(&self) -> f64 {
+        self.bytes_per_sec() / 1e6
+    }
+}
+
+/// Outcome of `probe`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Probe {
+    /// Throughput of every candidate, in the order they were given
+    pub results: Vec<Throughput>,
+    /// Whether this crate was built with optimizations
+    ///
+    /// Unoptimized builds are an order of magnitude slower, which is worth
+    /// warning about outside of development.
+    pub optimized: bool,
+}
+
+impl Probe {
+    /// Return the candidate with the highest throughput
+    pub fn fastest(&self) -> Option<&Throughput> {
+        self.results
+            .iter()
+            .max_by(|a, b| a.bytes_per_sec().total_cmp(&b.bytes_per_sec()))
+    }
+}
+
+/// Return one configuration of every enabled engine, averaging about
+/// `2^chunk_bits` byte chunks
+pub fn candidates(chunk_bits: u32) -> Vec<Params> {
+    let (min_size, max_size) = (1 << chunk_bits.saturating_sub(2), 1 << (chunk_bits + 2));
+    let _ = (min_size, max_size);
+    vec![
+        #[cfg(feature = "bup")]
+        Params::Bup {
+            chunk_bits,
+            min_size,
+            max_size,
+        },
+        #[cfg(feature = "gear")]
+        Params::Gear {
+            chunk_bits,
+            min_size,
+            max_size,
+        },
+        #[cfg(feature = "gear")]
+        Params::Normalized {
+            chunk_bits,
+            min_size,
+            max_size,
+            level: crate::normalized::NORMALIZATION_LEVEL,
+        },
+        #[cfg(feature = "fastcdc")]
+        Params::FastCdc {
+            chunk_bits,
+            min_size,
+            max_size,
+            level: crate::normalized::NORMALIZATION_LEVEL,
+        },
+    ]
+}
+
+/// Measure how fast every enabled engine chunks on this machine, with 8 KiB
+/// average chunks
+///
+/// Takes `DEFAULT_DURATION` per engine. The results vary from run to run,
+/// and between machines, so they must only be used to pick among
+/// configurations the application accepts anyway.
+pub fn probe() -> Probe {
+    probe_params(&candidates(13), DEFAULT_DURATION)
+}
+
+/// Measure the throughput of each of `params`, chunking for at least
+/// `duration` each
+///
+/// Panics if any of `params` is invalid or its engine is disabled.
+pub fn probe_params(params: &[Params], duration: Duration) -> Probe {
+    let sample = sample();
+    let results = params
+        .iter()
+        .map(|&params| {
+            let mut chunker = AnyChunker::new(params).unwrap_or_else(|e| panic!("{}", e));
+            let mut bytes = 0;
+            let start = Instant::now();
+            loop {
+                let mut rest = &sample[..];
+                while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+                    rest = &rest[i..];
+                }
+                bytes += sample.len() as u64;
+                if start.elapsed() >= duration {
+                    break;
+                }
+            }
+            Throughput {
+                params,
+                bytes,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect();
+    Probe {
+        results,
+        optimized: !cfg!(debug_assertions),
+    }
+}
+
+/// Return the enabled algorithm with the highest throughput on this machine
+///
+/// Only algorithms with an `Algorithm` identifier are candidates, so the
+/// choice can be recorded in `ChunkerParams`. The probe runs on the first
+/// call only, and every later call returns the same algorithm, so chunk
+/// boundaries stay the same for the lifetime of the process. They can differ
+/// in the next one, which is why the choice has to be recorded.
+pub fn fastest_algorithm() -> Algorithm {
+    static FASTEST: OnceLock<Algorithm> = OnceLock::new();
+    *FASTEST.get_or_init(|| {
+        let params: Vec<_> = candidates(13)
+            .into_iter()
+            .filter(|&params| ChunkerParams::try_from(params).is_ok())
+            .collect();
+        let probe = probe_params(&params, DEFAULT_DURATION);
+        let fastest = probe.fastest().expect("no chunking engine is enabled");
+        ChunkerParams::try_from(fastest.params).unwrap().algorithm
+    })
+}
+
+/// Return pseudo random data, which has edges for every engine
+fn sample() -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..SAMPLE_LEN)
+        .map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_every_candidate() {
+        let params = candidates(12);
+        assert!(!params.is_empty());
+        let probe = probe_params(&params, Duration::from_millis(1));
+        assert_eq!(probe.optimized, !cfg!(debug_assertions));
+        let probed: Vec<_> = probe.results.iter().map(|t| t.params).collect();
+        assert_eq!(probed, params);
+        for result in &probe.results {
+            assert!(result.bytes >= SAMPLE_LEN as u64);
+            assert!(result.mb_per_sec() > 0.0);
+        }
+        let fastest = probe.fastest().unwrap();
+        assert!(probe
+            .results
+            .iter()
+            .all(|t| t.bytes_per_sec() <= fastest.bytes_per_sec()));
+    }
+}
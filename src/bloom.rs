@@ -0,0 +1,189 @@
+use super::ChunkHash;
+use std::convert::TryFrom;
+use std::io;
+
+/// Magic bytes at the start of a serialized filter
+pub const MAGIC: [u8; 4] = *b"RSBF";
+
+/// Version of the serialization format
+pub const VERSION: u8 = 1;
+
+const BLOCK_BITS: u64 = 512;
+const BLOCK_WORDS: usize = (BLOCK_BITS / 64) as usize;
+
+type Block = [u64; BLOCK_WORDS];
+
+/// Blocked Bloom filter of chunk hashes
+///
+/// Answers "is this chunk possibly stored?" with no false negatives, so
+/// ingest can skip index lookups for chunks which are definitely new. All the
+/// bits of a hash are set in one 512 bit block, so a lookup touches a single
+/// cache line.
+///
+/// Bit positions are taken directly from the hash, which therefore must be a
+/// strong (uniformly distributed) hash of the chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    blocks: Vec<Block>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create a filter sized for `expected_items` hashes with
+    /// approximately `false_positive_rate` false positives
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        assert!(false_positive_rate > 0.0 && false_positive_rate < 1.0);
+        let ln2 = std::f64::consts::LN_2;
+        let items = expected_items.max(1) as f64;
+        let bits = -items * false_positive_rate.ln() / (ln2 * ln2);
+        // Blocking makes the filter slightly less accurate, compensate a bit
+        let blocks = (bits * 1.1 / BLOCK_BITS as f64).ceil() as usize;
+        let hashes = (bits / items * ln2).round().clamp(1.0, 16.0) as u32;
+        Self::with_size(blocks, hashes)
+    }
+
+    /// Create a filter of `blocks` 512 bit blocks setting `hashes` bits per
+    /// inserted hash
+    pub fn with_size(blocks: usize, hashes: u32) -> Self {
+        assert!(blocks > 0);
+        assert!(hashes > 0);
+        BloomFilter {
+            blocks: vec![[0; BLOCK_WORDS]; blocks],
+            hashes,
+        }
+    }
+
+    /// Return the size of the filter in bytes
+    pub fn size_bytes(&self) -> usize {
+        self.blocks.len() * BLOCK_WORDS * 8
+    }
+
+    fn positions(&self, hash: &ChunkHash) -> (usize, impl Iterator<Item = usize>) {
+        let word = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&hash[i * 8..i * 8 + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        let block = (word(0) % self.blocks.len() as u64) as usize;
+        let h1 = word(1);
+        let h2 = word(2) | 1;
+        let bits = (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) >> (64 - 9)) as usize);
+        (block, bits)
+    }
+
+    /// Add `hash` to the filter
+    pub fn insert(&mut self, hash: &ChunkHash) {
+        let (block, bits) = self.positions(hash);
+        let block = &mut self.blocks[block];
+        for bit in bits {
+            block[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Return whether `hash` may have been added to the filter
+    ///
+    /// `false` means `hash` was definitely never inserted.
+    pub fn contains(&self, hash: &ChunkHash) -> bool {
+        let (block, mut bits) = self.positions(hash);
+        let block = &self.blocks[block];
+        bits.all(|bit| block[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Serialize the filter
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(17 + self.size_bytes());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.hashes.to_le_bytes());
+        bytes.extend_from_slice(&(self.blocks.len() as u64).to_le_bytes());
+        for word in self.blocks.iter().flatten() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize a filter serialized by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if bytes.len() < 17 || bytes[..4] != MAGIC {
+            return Err(invalid("not a serialized bloom filter"));
+        }
+        if bytes[4] != VERSION {
+            return Err(invalid("unsupported bloom filter version"));
+        }
+        let mut hashes = [0; 4];
+        hashes.copy_from_slice(&bytes[5..9]);
+        let mut blocks = [0; 8];
+        blocks.copy_from_slice(&bytes[9..17]);
+        let hashes = u32::from_le_bytes(hashes);
+        let blocks = u64::from_le_bytes(blocks);
+        let data = &bytes[17..];
+        let len = blocks
+            .checked_mul(BLOCK_BITS / 8)
+            .and_then(|len| usize::try_from(len).ok());
+        if hashes == 0 || blocks == 0 || len != Some(data.len()) {
+            return Err(invalid("corrupted bloom filter"));
+        }
+
+        let mut filter = Self::with_size(blocks as usize, hashes);
+        for (word, bytes) in filter.blocks.iter_mut().flatten().zip(data.chunks(8)) {
+            let mut le = [0; 8];
+            le.copy_from_slice(bytes);
+            *word = u64::from_le_bytes(le);
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn hashes(count: usize, seed: u8) -> Vec<ChunkHash> {
+        let mut data = rand_data(count * 32);
+        data.iter_mut().for_each(|b| *b ^= seed);
+        data.chunks(32)
+            .map(|c| {
+                let mut hash = [0; 32];
+                hash.copy_from_slice(c);
+                hash
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_false_negatives_and_expected_false_positives() {
+        let inserted = hashes(10000, 0);
+        let mut filter = BloomFilter::new(inserted.len(), 0.01);
+        for hash in &inserted {
+            filter.insert(hash);
+        }
+        assert!(inserted.iter().all(|h| filter.contains(h)));
+
+        let others = hashes(10000, 0x5a);
+        let false_positives = others.iter().filter(|h| filter.contains(h)).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let mut filter = BloomFilter::new(100, 0.001);
+        for hash in &hashes(100, 0) {
+            filter.insert(hash);
+        }
+        let bytes = filter.to_bytes();
+        assert_eq!(BloomFilter::from_bytes(&bytes).unwrap(), filter);
+        assert!(BloomFilter::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(BloomFilter::from_bytes(b"nope").is_err());
+
+        // Block counts whose size overflows
+        for &blocks in &[1 << 55, u64::MAX] {
+            let mut bytes = bytes[..9].to_vec();
+            bytes.extend_from_slice(&u64::to_le_bytes(blocks));
+            let err = BloomFilter::from_bytes(&bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use super::{Chunker, EdgeResult};
+use std::cmp;
+
+/// BorgBackup's default chunker parameters, as `(min_exp, max_exp,
+/// mask_bits, window_size)`
+pub const DEFAULT_PARAMS: (u32, u32, u32, usize) = (19, 23, 21, 4095);
+
+/// Borg's buzhash table, before xoring with the seed
+///
+/// From `table_base` in Borg's `src/borg/_chunker.c`, copyright the Borg
+/// Collective and Jonas Borgström, under the BSD 3-clause license.
+pub const TABLE_BASE: [u32; 256] = [
+    0xe7f831ec, 0xf4026465, 0xafb50cae, 0x6d553c7a, 0xd639efe3, 0x19a7b895, 0x9aba5b21, 0x5417d6d4,
+    0x35fd2b84, 0xd1f6a159, 0x3f8e323f, 0xb419551c, 0xf444cebf, 0x21dc3b80, 0xde8d1e36, 0x84a32436,
+    0xbeb35a9d, 0xa36f24aa, 0xa4e60186, 0x98d18ffe, 0x3f042f9e, 0xdb228bcd, 0x096474b7, 0x5c20c2f7,
+    0xf9eec872, 0xe8625275, 0xb9d38f80, 0xd48eb716, 0x22a950b4, 0x3cbaaeaa, 0xc37cddd3, 0x8fea6f6a,
+    0x1d55d526, 0x7fd6d3b3, 0xdaa072ee, 0x4345ac40, 0xa077c642, 0x8f2bd45b, 0x28509110, 0x55557613,
+    0xffc17311, 0xd961ffef, 0xe532c287, 0xaab95937, 0x46d38365, 0xb065c703, 0xf2d91d0f, 0x92cd4bb0,
+    0x4007c712, 0xf35509dd, 0x505b2f69, 0x557ead81, 0x310f4563, 0xbddc5be8, 0x9760f38c, 0x701e0205,
+    0x00157244, 0x14912826, 0xdc4ca32b, 0x67b196de, 0x5db292e8, 0x8c1b406b, 0x01f34075, 0xfa2520f7,
+    0x73bc37ab, 0x1e18bc30, 0xfe2c6cb3, 0x20c522d0, 0x5639e3db, 0x942bda35, 0x899af9d1, 0xced44035,
+    0x98cc025b, 0x255f5771, 0x70fefa24, 0xe928fa4d, 0x2c030405, 0xb9325590, 0x20cb63bd, 0xa166305d,
+    0x80e52c0a, 0xa8fafe2f, 0x1ad13f7d, 0xcfaf3685, 0x6c83a199, 0x7d26718a, 0xde5dfcd9, 0x79cf7355,
+    0x8979d7fb, 0xebf8c55e, 0xebe408e4, 0xcd2affba, 0xe483be6e, 0xe239d6de, 0x5dc1e9e0, 0x0473931f,
+    0x851b097c, 0xac5db249, 0x09c0f9f2, 0xd8d2f134, 0xe6f38e41, 0xb1c71bf1, 0x52b6e4db, 0x07224424,
+    0x6cf73e85, 0x4f25d89c, 0x782a7d74, 0x10a68dcd, 0x3a868189, 0xd570d2dc, 0x69630745, 0x9542ed86,
+    0x331cd6b2, 0xa84b5b28, 0x07879c9d, 0x38372f64, 0x7185db11, 0x25ba7c83, 0x01061523, 0xe6792f9f,
+    0xe5df07d1, 0x4321b47f, 0x7d2469d8, 0x1a3a4f90, 0x48be29a3, 0x669071af, 0x8ec8dd31, 0x0810bfbf,
+    0x813a06b4, 0x68538345, 0x65865ddc, 0x43a71b8e, 0x78619a56, 0x5a34451d, 0x5bdaa3ed, 0x71edc7e9,
+    0x17ac9a20, 0x78d10bfa, 0x6c1e7f35, 0xd51839d9, 0x240cbc51, 0x33513cc1, 0xd2b4f795, 0xccaa8186,
+    0x0babe682, 0xa33cf164, 0x18c643ea, 0xc1ca105f, 0x9959147a, 0x6d3d94de, 0x0b654fbe, 0xed902ca0,
+    0x7d835cb5, 0x99ba1509, 0x6445c922, 0x495e76c2, 0xf07194bc, 0xa1631d7e, 0x677076a5, 0x89fffe35,
+    0x1a49bcf3, 0x8e6c948a, 0x0144c917, 0x8d93aea1, 0x16f87ddf, 0xc8f25d49, 0x1fb11297, 0x27e750cd,
+    0x2f422da1, 0xdee89a77, 0x1534c643, 0x457b7b8b, 0xaf172f7a, 0x6b9b09d6, 0x33573f7f, 0xf14e15c4,
+    0x526467d5, 0xaf488241, 0x87c3ee0d, 0x33be490c, 0x95aa6e52, 0x43ec242e, 0xd77de99b, 0xd018334f,
+    0x5b78d407, 0x498eb66b, 0xb1279fa8, 0xb38b0ea6, 0x90718376, 0xe325dee2, 0x8e2f2cba, 0xcaa5bdec,
+    0x9d652c56, 0xad68f5cb, 0xa77591af, 0x88e37ee8, 0xf8faa221, 0xfcbbbe47, 0x4f407786, 0xaf393889,
+    0xf444a1d9, 0x15ae1a2f, 0x40aa7097, 0x6f9486ac, 0x29d232a3, 0xe47609e9, 0xe8b631ff, 0xba8565f4,
+    0x11288749, 0x46c9a838, 0xeb1b7cd8, 0xf516bbb1, 0xfb74fda0, 0x010996e6, 0x4c994653, 0x1d889512,
+    0x53dcd9a3, 0xdd074697, 0x1e78e17c, 0x637c98bf, 0x930bb219, 0xcf7f75b0, 0xcb9355fb, 0x9e623009,
+    0xe466d82c, 0x28f968d3, 0xfeb385d9, 0x238e026c, 0xb8ed0560, 0x0c6a027a, 0x3d6fec4b, 0xbb4b2ec2,
+    0xe715031c, 0xeded011d, 0xcdc4d3b9, 0xc456fc96, 0xdd0eea20, 0xb3df8ec9, 0x12351993, 0xd9cbb01c,
+    0x603147a2, 0xcf37d17d, 0xf7fcd9dc, 0xd8556fa3, 0x104c8131, 0x13152774, 0xb4715811, 0x6a72c2c9,
+    0xc5ae37bb, 0xa76ce12a, 0x8150d8f3, 0x2ec29218, 0xa35f0984, 0x48c0647e, 0x0b5ff98c, 0x71893f7b,
+];
+
+/// Return the buzhash of `window`: the table entries of its bytes, the first
+/// one rotated left by `window.len() - 1` and the last not at all, xored
+fn buzhash(table: &[u32; 256], window: &[u8]) -> u32 {
+    let n = window.len();
+    window.iter().enumerate().fold(0, |sum, (i, &b)| {
+        sum ^ table[b as usize].rotate_left(((n - 1 - i) & 0x1f) as u32)
+    })
+}
+
+/// Chunker reproducing the buzhash chunker of BorgBackup 1.x
+///
+/// The table is `TABLE_BASE` with every entry xored with the `seed` of the
+/// repository key, 0 for unencrypted repositories.
+///
+/// Like Borg, the edge condition is tested on the window *starting* after
+/// the chunk: a chunk of `n` bytes ends when the low `mask_bits` bits of the
+/// hash of the `window_size` bytes from offset `n` on are all zero, for
+/// `n` from `2^min_exp` up to `2^max_exp - window_size` (exclusive), and is
+/// cut at `2^max_exp` bytes otherwise. Borg only tests windows followed by
+/// at least one more byte, so the end of the data is never cut within its
+/// last `window_size` bytes.
+///
+/// As that window and the byte after it have to be seen before the edge,
+/// `find_chunk_edge_result` leaves the bytes it could not decide on
+/// unconsumed, to be passed again with the following data, and the rest of
+/// the data joins the last chunk at the end of the stream. The crate's
+/// drivers, like `StreamChunker`, do both, so the edges don't depend on how
+/// the data is split. `find_chunk_edge` has to consume all of `buf` when it
+/// finds no edge, so it only finds edges decided within `buf`.
+pub struct BorgChunker {
+    table: Box<[u32; 256]>,
+    min_size: usize,
+    max_size: usize,
+    mask: u32,
+    window_size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl BorgChunker {
+    /// Create a chunker with Borg's default parameters
+    pub fn new(seed: u32) -> Self {
+        let (min_exp, max_exp, mask_bits, window_size) = DEFAULT_PARAMS;
+        Self::with_params(seed, min_exp, max_exp, mask_bits, window_size)
+    }
+
+    /// Create a chunker with the parameters of Borg's `--chunker-params
+    /// buzhash,min_exp,max_exp,mask_bits,window_size`
+    ///
+    /// Panics unless `min_exp <= max_exp < usize::BITS`, `mask_bits < 32`,
+    /// `window_size > 0` and `2^min_exp + window_size < 2^max_exp`, as Borg
+    /// requires.
+    pub fn with_params(
+        seed: u32,
+        min_exp: u32,
+        max_exp: u32,
+        mask_bits: u32,
+        window_size: usize,
+    ) -> Self {
+        Self::with_table(&TABLE_BASE, seed, min_exp, max_exp, mask_bits, window_size)
+    }
+
+    /// `with_params` using `table_base` in place of `TABLE_BASE`
+    pub fn with_table(
+        table_base: &[u32; 256],
+        seed: u32,
+        min_exp: u32,
+        max_exp: u32,
+        mask_bits: u32,
+        window_size: usize,
+    ) -> Self {
+        assert!(min_exp <= max_exp && max_exp < usize::BITS);
+        assert!(mask_bits < 32);
+        assert!(window_size > 0);
+        assert!((1usize << min_exp).saturating_add(window_size) < 1 << max_exp);
+        let mut table = Box::new([0; 256]);
+        for (entry, &base) in table.iter_mut().zip(table_base.iter()) {
+            *entry = base ^ seed;
+        }
+        BorgChunker {
+            table,
+            min_size: 1 << min_exp,
+            max_size: 1 << max_exp,
+            mask: (1 << mask_bits) - 1,
+            window_size,
+            len: 0,
+        }
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn edge(&mut self, offset: usize, digest: u32) -> EdgeResult<u32> {
+        self.len = 0;
+        EdgeResult::Found { offset, digest }
+    }
+
+    fn need_more(&mut self, consumed: usize) -> EdgeResult<u32> {
+        self.len += consumed;
+        EdgeResult::NeedMore { consumed }
+    }
+
+    /// Cut at `max_size`, once no window before it is left to test and the
+    /// data goes on after it
+    fn cut_at_max(&mut self, buf: &[u8]) -> EdgeResult<u32> {
+        let offset = self.max_size - self.len;
+        if offset < buf.len() {
+            self.edge(offset, 0)
+        } else {
+            self.need_more(buf.len())
+        }
+    }
+
+    /// Scan `buf`, with the window at `self.len` starting at `buf[0]`, or
+    /// further if there are bytes before `min_size` left
+    ///
+    /// Borg's buffer holds `max_size` bytes from the chunk start and it stops
+    /// hashing `window_size` bytes before its end, so windows from
+    /// `max_size - window_size` on are never tested.
+    fn scan(&mut self, buf: &[u8]) -> EdgeResult<u32> {
+        let w = self.window_size;
+        let stop_at = self.max_size - w;
+        let mut i = self.min_size.saturating_sub(self.len);
+        if self.len + i >= stop_at {
+            return self.cut_at_max(buf);
+        }
+        // Windows are tested once the byte after them is in `buf`
+        if i + w >= buf.len() {
+            return self.need_more(cmp::min(i, buf.len()));
+        }
+        let mut sum = buzhash(&self.table, &buf[i..i + w]);
+        let rotation = (w & 0x1f) as u32;
+        loop {
+            if sum & self.mask == 0 {
+                return self.edge(i, sum);
+            }
+            if self.len + i + 1 >= stop_at {
+                return self.cut_at_max(buf);
+            }
+            if i + 1 + w >= buf.len() {
+                return self.need_more(i + 1);
+            }
+            sum = sum.rotate_left(1)
+                ^ self.table[buf[i] as usize].rotate_left(rotation)
+                ^ self.table[buf[i + w] as usize];
+            i += 1;
+        }
+    }
+}
+
+impl Chunker for BorgChunker {
+    type Digest = u32;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u32)> {
+        match self.scan(buf) {
+            EdgeResult::Found { offset, digest } => Some((offset, digest)),
+            EdgeResult::NeedMore { consumed } => {
+                self.len += buf.len() - consumed;
+                None
+            }
+        }
+    }
+
+    fn find_chunk_edge_result(&mut self, buf: &[u8]) -> EdgeResult<u32> {
+        self.scan(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, rand_data};
+    use std::convert::TryFrom;
+
+    fn table() -> [u32; 256] {
+        let data = rand_data(1024);
+        let mut table = [0; 256];
+        for (entry, b) in table.iter_mut().zip(data.chunks(4)) {
+            *entry = u32::from_le_bytes(<[u8; 4]>::try_from(b).unwrap());
+        }
+        table
+    }
+
+    /// Chunk boundaries as computed by Borg's `chunker_process`, hashing
+    /// every window from scratch
+    fn reference(
+        table: &[u32; 256],
+        min: usize,
+        max: usize,
+        mask: u32,
+        w: usize,
+        data: &[u8],
+    ) -> Vec<usize> {
+        let mut edges = Vec::new();
+        let mut last = 0;
+        loop {
+            // Only windows followed by another byte are tested
+            let mut position = last + min;
+            if position + w >= data.len() {
+                return edges;
+            }
+            while position - last < max - w
+                && buzhash(table, &data[position..position + w]) & mask != 0
+            {
+                position += 1;
+                if position + w >= data.len() {
+                    return edges;
+                }
+            }
+            if position - last >= max - w {
+                position = last + max;
+            }
+            edges.push(position);
+            last = position;
+        }
+    }
+
+    #[test]
+    fn rolling_matches_buzhash() {
+        let table = table();
+        let data = rand_data(10_000);
+        let w = 4095;
+        let mut sum = buzhash(&table, &data[..w]);
+        for i in 0..data.len() - w {
+            sum = sum.rotate_left(1)
+                ^ table[data[i] as usize].rotate_left((w & 0x1f) as u32)
+                ^ table[data[i + w] as usize];
+            assert_eq!(sum, buzhash(&table, &data[i + 1..i + 1 + w]));
+        }
+    }
+
+    #[test]
+    fn borg_vectors() {
+        // From `test_chunkify` in Borg's `src/borg/testsuite/chunker.py`
+        let data = b"abcdefghijklmnop";
+        assert_eq!(buzhash(&BorgChunker::new(0).table, data), 3795437769);
+        assert_eq!(buzhash(&BorgChunker::new(1).table, data), 3795400502);
+
+        let data = b"foobarboobaz".repeat(3);
+        let chunkify = |seed, min_exp, mask_bits, w| -> Vec<Vec<u8>> {
+            let mut chunker = BorgChunker::with_params(seed, min_exp, 23, mask_bits, w);
+            chunker.chunks(&data).map(|c| c.to_vec()).collect()
+        };
+        let parts =
+            |parts: &[&[u8]]| -> Vec<Vec<u8>> { parts.iter().map(|p| p.to_vec()).collect() };
+        assert_eq!(
+            chunkify(0, 1, 2, 2),
+            parts(&[b"fooba", b"rboobaz", b"fooba", b"rboobaz", b"fooba", b"rboobaz"])
+        );
+        assert_eq!(
+            chunkify(1, 1, 2, 2),
+            parts(&[
+                b"fo", b"obarb", b"oob", b"azf", b"oobarb", b"oob", b"azf", b"oobarb", b"oobaz"
+            ])
+        );
+        assert_eq!(
+            chunkify(2, 1, 2, 2),
+            parts(&[
+                b"foob",
+                b"ar",
+                b"boobazfoob",
+                b"ar",
+                b"boobazfoob",
+                b"ar",
+                b"boobaz"
+            ])
+        );
+        assert_eq!(chunkify(0, 2, 2, 3), parts(&[&data]));
+        assert_eq!(
+            chunkify(1, 2, 2, 3),
+            parts(&[
+                b"foobar",
+                b"boobazfo",
+                b"obar",
+                b"boobazfo",
+                b"obar",
+                b"boobaz"
+            ])
+        );
+        assert_eq!(
+            chunkify(2, 2, 2, 3),
+            parts(&[
+                b"foob",
+                b"arboobaz",
+                b"foob",
+                b"arboobaz",
+                b"foob",
+                b"arboobaz"
+            ])
+        );
+        assert_eq!(chunkify(0, 3, 2, 3), parts(&[&data]));
+        assert_eq!(
+            chunkify(1, 3, 2, 3),
+            parts(&[b"foobarbo", b"obazfoobar", b"boobazfo", b"obarboobaz"])
+        );
+        assert_eq!(
+            chunkify(2, 3, 2, 3),
+            parts(&[b"foobarboobaz", b"foobarboobaz", b"foobarboobaz"])
+        );
+
+        // A chunk cut at the maximum size
+        let mut data = vec![b'0'; 3 << 22];
+        data.push(b'Y');
+        let mut chunker = BorgChunker::with_params(0, 1, 23, 2, 2);
+        let sizes: Vec<_> = chunker.chunks(&data).map(|c| c.len()).collect();
+        assert_eq!(sizes, vec![1 << 23, (1 << 22) + 1]);
+    }
+
+    #[test]
+    fn bundled_table() {
+        let data = rand_data(256 * 1024);
+        let seeded: Vec<u32> = TABLE_BASE.iter().map(|&t| t ^ 0xdead_beef).collect();
+        let seeded = <[u32; 256]>::try_from(&seeded[..]).unwrap();
+        let expected = reference(&seeded, 1 << 8, 1 << 12, (1 << 9) - 1, 63, &data);
+        assert!(expected.len() > 100);
+
+        let mut chunker = BorgChunker::with_params(0xdead_beef, 8, 12, 9, 63);
+        assert_eq!(chunk_edges(&mut chunker, &data), expected);
+    }
+
+    #[test]
+    fn matches_reference_and_streams() {
+        let table = table();
+        let data = rand_data(2 * 1024 * 1024);
+        let seeded: Vec<u32> = table.iter().map(|&t| t ^ 0x1234_5678).collect();
+        let seeded = <[u32; 256]>::try_from(&seeded[..]).unwrap();
+        // The second set cuts many chunks at the maximum size, and drops
+        // edges in the windows just before it
+        for &(max_exp, mask_bits, w, len) in &[(14, 11, 255, data.len()), (13, 12, 1023, 1 << 19)] {
+            let data = &data[..len];
+            let new = || BorgChunker::with_table(&table, 0x1234_5678, 10, max_exp, mask_bits, w);
+            let mask = (1 << mask_bits) - 1;
+            let expected = reference(&seeded, 1 << 10, 1 << max_exp, mask, w, data);
+            assert!(expected.len() > 50);
+            assert_eq!(chunk_edges(&mut new(), data), expected);
+            for &frame in &[1, 100, 4096, 50_000] {
+                let edges = chunk_edges_framed(&mut new(), data, frame);
+                assert_eq!(edges, expected, "{} {}", mask_bits, frame);
+            }
+
+            let mut offsets = Vec::new();
+            crate::for_each_chunk(data, &mut new(), |offset, _| {
+                offsets.push(offset as usize);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(offsets[1..], expected[..]);
+        }
+    }
+}
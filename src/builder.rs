@@ -0,0 +1,312 @@
+#[cfg(feature = "bup")]
+use super::bup::{self, Bup, BupDyn};
+#[cfg(any(feature = "bup", feature = "gear"))]
+use super::condition::MaskEngine;
+#[cfg(feature = "gear")]
+use super::gear::{self, Gear};
+#[cfg(any(feature = "bup", feature = "gear"))]
+use super::{ChunkBits, MinMaxChunker};
+use super::{InvalidChunkBits, Params};
+use std::error;
+use std::fmt;
+
+/// Error returned by the `build` methods of the builders, `AnyChunker::new`
+/// and `Params::validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// Unsupported number of chunk bits
+    ChunkBits(InvalidChunkBits),
+    /// Minimum chunk size above the maximum, or a maximum of 0
+    InvalidSizes { min_size: usize, max_size: usize },
+    /// Window size the engine doesn't support
+    UnsupportedWindow { window_size: usize },
+    /// Parameters of an engine whose feature is disabled
+    Disabled(Params),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::ChunkBits(e) => e.fmt(f),
+            BuildError::InvalidSizes { min_size, max_size } => write!(
+                f,
+                "invalid chunk sizes: minimum {} and maximum {}",
+                min_size, max_size
+            ),
+            BuildError::UnsupportedWindow { window_size } => {
+                write!(f, "unsupported window of {} bytes", window_size)
+            }
+            BuildError::Disabled(params) => write!(f, "engine of {:?} is not enabled", params),
+        }
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BuildError::ChunkBits(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<InvalidChunkBits> for BuildError {
+    fn from(e: InvalidChunkBits) -> Self {
+        BuildError::ChunkBits(e)
+    }
+}
+
+/// Validate the size bounds, none meaning unbounded
+#[cfg(any(feature = "bup", feature = "gear"))]
+fn sizes(min_size: Option<usize>, max_size: Option<usize>) -> Result<(usize, usize), BuildError> {
+    let min_size = min_size.unwrap_or(0);
+    let max_size = max_size.unwrap_or(usize::MAX);
+    if min_size > max_size || max_size == 0 {
+        return Err(BuildError::InvalidSizes { min_size, max_size });
+    }
+    Ok((min_size, max_size))
+}
+
+/// Builder of `Gear` engines and chunkers, see `Gear::builder`
+#[cfg(feature = "gear")]
+#[derive(Debug, Clone)]
+pub struct GearBuilder {
+    chunk_bits: u32,
+    table: Option<Box<[gear::Digest; 256]>>,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+}
+
+#[cfg(feature = "gear")]
+impl GearBuilder {
+    pub(crate) fn new() -> Self {
+        GearBuilder {
+            chunk_bits: gear::CHUNK_BITS,
+            table: None,
+            min_size: None,
+            max_size: None,
+        }
+    }
+
+    /// Set the number of bits matched by the edge condition
+    pub fn chunk_bits(mut self, chunk_bits: u32) -> Self {
+        self.chunk_bits = chunk_bits;
+        self
+    }
+
+    /// Use a custom table of random values, see `Gear::with_table`
+    pub fn table(mut self, table: [gear::Digest; 256]) -> Self {
+        self.table = Some(Box::new(table));
+        self
+    }
+
+    /// Use the table derived from `seed`, see `gear::table_from_seed`
+    pub fn seed(self, seed: u64) -> Self {
+        self.table(gear::table_from_seed(seed))
+    }
+
+    /// Set the minimum chunk size of `build_chunker`
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Set the maximum chunk size of `build_chunker`
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Create the engine, ignoring the chunk sizes
+    pub fn build(&self) -> Result<Gear, BuildError> {
+        let chunk_bits = ChunkBits::new(self.chunk_bits)?.get();
+        Ok(match &self.table {
+            Some(table) => Gear::with_table(chunk_bits, **table),
+            None => Gear::new_with_chunk_bits(chunk_bits),
+        })
+    }
+
+    /// Create a chunker enforcing the chunk sizes around the engine
+    pub fn build_chunker(
+        &self,
+    ) -> Result<MinMaxChunker<Gear, <Gear as MaskEngine>::Condition>, BuildError> {
+        let (min_size, max_size) = sizes(self.min_size, self.max_size)?;
+        Ok(MinMaxChunker::new(self.build()?, min_size, max_size))
+    }
+}
+
+/// Builder of `Bup` and `BupDyn` engines and chunkers, see `Bup::builder`
+///
+/// Unlike `Bup::new_with_chunk_bits`, the average chunk size has to be at
+/// least the window size, see `ChunkBits::for_window`.
+#[cfg(feature = "bup")]
+#[derive(Debug, Clone)]
+pub struct BupBuilder {
+    chunk_bits: u32,
+    window_size: usize,
+    min_size: Option<usize>,
+    max_size: Option<usize>,
+}
+
+#[cfg(feature = "bup")]
+impl BupBuilder {
+    pub(crate) fn new() -> Self {
+        BupBuilder {
+            chunk_bits: bup::CHUNK_BITS,
+            window_size: bup::WINDOW_SIZE,
+            min_size: None,
+            max_size: None,
+        }
+    }
+
+    /// Set the number of bits matched by the edge condition
+    pub fn chunk_bits(mut self, chunk_bits: u32) -> Self {
+        self.chunk_bits = chunk_bits;
+        self
+    }
+
+    /// Set the window size, which only `build_dyn` supports changing
+    pub fn window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    /// Set the minimum chunk size of `build_chunker`
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = Some(min_size);
+        self
+    }
+
+    /// Set the maximum chunk size of `build_chunker`
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    fn chunk_bits_for_window(&self) -> Result<u32, BuildError> {
+        if self.window_size == 0 {
+            return Err(BuildError::UnsupportedWindow { window_size: 0 });
+        }
+        Ok(ChunkBits::for_window(self.chunk_bits, self.window_size)?.get())
+    }
+
+    /// Create a `Bup` engine, ignoring the chunk sizes
+    ///
+    /// Fails unless the window size is the default one of `Bup`.
+    pub fn build(&self) -> Result<Bup, BuildError> {
+        let chunk_bits = self.chunk_bits_for_window()?;
+        if self.window_size != bup::WINDOW_SIZE {
+            return Err(BuildError::UnsupportedWindow {
+                window_size: self.window_size,
+            });
+        }
+        Ok(Bup::new_with_chunk_bits(chunk_bits))
+    }
+
+    /// Create a `BupDyn` engine, ignoring the chunk sizes
+    pub fn build_dyn(&self) -> Result<BupDyn, BuildError> {
+        Ok(BupDyn::new(self.window_size, self.chunk_bits_for_window()?))
+    }
+
+    /// Create a chunker enforcing the chunk sizes around a `Bup` engine
+    pub fn build_chunker(
+        &self,
+    ) -> Result<MinMaxChunker<Bup, <Bup as MaskEngine>::Condition>, BuildError> {
+        let (min_size, max_size) = sizes(self.min_size, self.max_size)?;
+        Ok(MinMaxChunker::new(self.build()?, min_size, max_size))
+    }
+}
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    #[cfg(feature = "gear")]
+    use crate::Chunker;
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn gear_builder() {
+        let data = rand_data(64 * 1024);
+        let mut built = Gear::builder().chunk_bits(10).build().unwrap();
+        assert_eq!(
+            built.find_chunk_edge(&data),
+            Gear::new_with_chunk_bits(10).find_chunk_edge(&data)
+        );
+        let table = [7; 256];
+        let built = Gear::builder().table(table).build().unwrap();
+        assert_eq!(built.table(), &table);
+        let built = Gear::builder().seed(1).build().unwrap();
+        assert_eq!(built.table(), Gear::with_seed(13, 1).table());
+
+        let mut chunker = Gear::builder()
+            .chunk_bits(10)
+            .min_size(512)
+            .max_size(2048)
+            .build_chunker()
+            .unwrap();
+        let mut expected = MinMaxChunker::new(Gear::new_with_chunk_bits(10), 512, 2048);
+        assert_eq!(
+            chunker.find_chunk_edge(&data),
+            expected.find_chunk_edge(&data)
+        );
+
+        assert!(matches!(
+            Gear::builder().chunk_bits(40).build(),
+            Err(BuildError::ChunkBits(InvalidChunkBits::TooLarge {
+                bits: 40,
+                ..
+            }))
+        ));
+        assert_eq!(
+            Gear::builder()
+                .min_size(100)
+                .max_size(10)
+                .build_chunker()
+                .err(),
+            Some(BuildError::InvalidSizes {
+                min_size: 100,
+                max_size: 10
+            })
+        );
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn bup_builder() {
+        let data = rand_data(64 * 1024);
+        let mut built = Bup::builder().chunk_bits(10).build().unwrap();
+        assert_eq!(
+            built.find_chunk_edge(&data),
+            Bup::new_with_chunk_bits(10).find_chunk_edge(&data)
+        );
+        let mut built = Bup::builder().window_size(100).build_dyn().unwrap();
+        assert_eq!(
+            built.find_chunk_edge(&data),
+            BupDyn::new(100, bup::CHUNK_BITS).find_chunk_edge(&data)
+        );
+        assert!(Bup::builder()
+            .chunk_bits(10)
+            .max_size(4096)
+            .build_chunker()
+            .is_ok());
+
+        assert_eq!(
+            Bup::builder().window_size(100).build().err(),
+            Some(BuildError::UnsupportedWindow { window_size: 100 })
+        );
+        assert!(matches!(
+            Bup::builder().chunk_bits(4).build(),
+            Err(BuildError::ChunkBits(
+                InvalidChunkBits::SmallerThanWindow { .. }
+            ))
+        ));
+        assert_eq!(
+            Bup::builder().max_size(0).build_chunker().err(),
+            Some(BuildError::InvalidSizes {
+                min_size: 0,
+                max_size: 0
+            })
+        );
+    }
+}
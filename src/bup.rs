@@ -1,11 +1,15 @@
-use super::Engine;
+use super::condition::{MaskCondition, MaskEngine};
+use super::{ChunkBits, Chunker, Engine, InvalidChunkBits, SlidingEngine};
+use std::cmp;
+use std::convert::TryFrom;
 use std::default::Default;
+use std::hash::Hasher;
 use std::mem;
 
 pub type Digest = u32;
 
 const WINDOW_BITS: usize = 6;
-const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
+pub(crate) const WINDOW_SIZE: usize = 1 << WINDOW_BITS;
 
 const CHAR_OFFSET: usize = 31;
 
@@ -21,13 +25,19 @@ pub const CHUNK_BITS: u32 = 13;
 /// https://github.com/bup/bup/blob/706e8d273/lib/bup/bupsplit.c
 /// https://github.com/bup/bup/blob/706e8d273/lib/bup/bupsplit.h
 /// (a bit like https://godoc.org/camlistore.org/pkg/rollsum)
+#[derive(Clone)]
 pub struct Bup {
     state: State,
     window: [u8; WINDOW_SIZE],
     wofs: usize,
     chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to `WINDOW_SIZE`
+    filled: usize,
+    /// Whether `count_bits` counts without the legacy skipped bit
+    corrected_count_bits: bool,
 }
 
+#[derive(Clone)]
 struct State {
     s1: u32,
     s2: u32,
@@ -35,18 +45,30 @@ struct State {
 
 impl State {
     const fn new() -> Self {
+        Self::with_window(WINDOW_SIZE)
+    }
+
+    const fn with_window(window_size: usize) -> Self {
         Self {
-            s1: (WINDOW_SIZE * CHAR_OFFSET) as u32,
-            s2: (WINDOW_SIZE * (WINDOW_SIZE - 1) * CHAR_OFFSET) as u32,
+            s1: (window_size * CHAR_OFFSET) as u32,
+            s2: (window_size
+                .wrapping_mul(window_size - 1)
+                .wrapping_mul(CHAR_OFFSET)) as u32,
         }
     }
 
     #[inline(always)]
     fn add(&mut self, drop: u8, add: u8) {
-        self.s1 += add as u32;
-        self.s1 -= drop as u32;
-        self.s2 += self.s1;
-        self.s2 -= (WINDOW_SIZE * (drop as usize + CHAR_OFFSET)) as u32;
+        self.add_windowed(drop, add, WINDOW_SIZE)
+    }
+
+    #[inline(always)]
+    fn add_windowed(&mut self, drop: u8, add: u8, window_size: usize) {
+        self.s1 = self.s1.wrapping_add(add as u32).wrapping_sub(drop as u32);
+        self.s2 = self.s2.wrapping_add(self.s1);
+        self.s2 = self
+            .s2
+            .wrapping_sub(window_size.wrapping_mul(drop as usize + CHAR_OFFSET) as u32);
     }
 
     fn digest(&self) -> Digest {
@@ -61,6 +83,8 @@ impl Default for Bup {
             window: [0; WINDOW_SIZE],
             wofs: 0,
             chunk_bits: CHUNK_BITS,
+            filled: 0,
+            corrected_count_bits: false,
         }
     }
 }
@@ -78,10 +102,15 @@ impl Engine for Bup {
         let prevch = mem::replace(slot, newch);
         self.state.add(prevch, newch);
         self.wofs = (self.wofs + 1) % WINDOW_SIZE;
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
     }
 
     fn roll(&mut self, buf: &[u8]) {
+        let filled = cmp::min(WINDOW_SIZE, self.filled.saturating_add(buf.len()));
         crate::roll_windowed(self, WINDOW_SIZE, buf);
+        self.filled = filled;
     }
 
     #[inline(always)]
@@ -89,10 +118,53 @@ impl Engine for Bup {
         self.state.digest()
     }
 
+    fn digest_of(buf: &[u8]) -> Digest {
+        // A new engine has a zeroed window, and only the last window of `buf`
+        // matters, so every byte dropped from the window is a zero
+        let last_window = buf.windows(WINDOW_SIZE).next_back().unwrap_or(buf);
+        let mut state = State::new();
+        for &b in last_window {
+            state.add(0, b);
+        }
+        state.digest()
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(WINDOW_SIZE)
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        WINDOW_SIZE - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        if left.chunk_bits != right.chunk_bits
+            || left.corrected_count_bits != right.corrected_count_bits
+        {
+            return None;
+        }
+        // Only the last window matters, so roll the bytes of `right` still
+        // in its window over a copy of `left`
+        let mut combined = Bup {
+            state: left.state.clone(),
+            window: left.window,
+            wofs: left.wofs,
+            chunk_bits: left.chunk_bits,
+            filled: left.filled,
+            corrected_count_bits: left.corrected_count_bits,
+        };
+        let take = usize::try_from(right_len).map_or(WINDOW_SIZE, |len| cmp::min(len, WINDOW_SIZE));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + WINDOW_SIZE - take + i) % WINDOW_SIZE]);
+        }
+        Some(combined)
+    }
+
     #[inline]
     fn reset(&mut self) {
         *self = Bup {
             chunk_bits: self.chunk_bits,
+            corrected_count_bits: self.corrected_count_bits,
             ..Default::default()
         }
     }
@@ -114,6 +186,9 @@ impl Engine for Bup {
                     }
                 };
                 self.state.add(outgoing, incoming);
+                if self.filled < WINDOW_SIZE {
+                    self.filled += 1;
+                }
                 if cond(self) {
                     let digest = self.digest();
                     let end = i + 1;
@@ -127,6 +202,50 @@ impl Engine for Bup {
     }
 }
 
+impl SlidingEngine for Bup {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        self.window[self.wofs] = new;
+        self.state.add(old, new);
+        self.wofs = (self.wofs + 1) % WINDOW_SIZE;
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
+    }
+}
+
+impl Chunker for Bup {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        Bup::find_chunk_edge(self, buf)
+    }
+}
+
+/// `finish` returns the rolling digest of the bytes written so far, so only
+/// the last window of written bytes contributes to the hash.
+impl Hasher for Bup {
+    fn finish(&self) -> u64 {
+        self.digest().into()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.roll(bytes);
+    }
+}
+
+impl MaskEngine for Bup {
+    type Condition = MaskCondition;
+
+    fn condition_for_bits(bits: u32) -> MaskCondition {
+        MaskCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
 impl Bup {
     /// Create new Bup engine with default chunking settings
     pub fn new() -> Self {
@@ -138,19 +257,60 @@ impl Bup {
     /// `chunk_bits` is number of bits that need to match in
     /// the edge condition. `CHUNK_BITS` constant is the default.
     pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
-        assert!(chunk_bits < 32);
+        let chunk_bits = ChunkBits::expect(ChunkBits::new(chunk_bits));
         Bup {
             chunk_bits,
             ..Default::default()
         }
     }
 
+    /// Return a builder of engines and chunkers, validating their settings
+    pub fn builder() -> crate::builder::BupBuilder {
+        crate::builder::BupBuilder::new()
+    }
+
+    /// Create new Bup engine with custom chunking settings, failing if the
+    /// average chunk size is smaller than the 64 byte window
+    ///
+    /// `new_with_chunk_bits` accepts tiny averages for compatibility, but
+    /// most of their chunks end before the window is filled after the reset
+    /// at the previous edge. See `ChunkBits::for_window`.
+    pub fn try_new_with_chunk_bits(chunk_bits: u32) -> Result<Self, InvalidChunkBits> {
+        ChunkBits::for_window(chunk_bits, WINDOW_SIZE)?;
+        Ok(Self::new_with_chunk_bits(chunk_bits))
+    }
+
+    /// Create new Bup engine with an average chunk size of `avg_size` bytes
+    ///
+    /// Panics if `avg_size` is not a power of two.
+    pub fn new_with_avg_size(avg_size: u64) -> Self {
+        Self::new_with_chunk_bits(ChunkBits::expect(ChunkBits::from_avg_size(avg_size)))
+    }
+
+    /// Change the number of bits matched by the edge condition
+    ///
+    /// The rolling state is kept, so this can be called between chunks or
+    /// even in the middle of one.
+    pub fn set_chunk_bits(&mut self, chunk_bits: u32) {
+        self.chunk_bits = ChunkBits::expect(ChunkBits::new(chunk_bits));
+    }
+
+    /// Return the average chunk size in bytes
+    pub fn avg_size(&self) -> u64 {
+        1 << self.chunk_bits
+    }
+
     /// Find chunk edge using Bup defaults.
     ///
     /// See `Engine::find_chunk_edge_cond`.
     pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
-        let chunk_mask = (1 << self.chunk_bits) - 1;
-        self.find_chunk_edge_cond(buf, |e: &Bup| e.digest() & chunk_mask == chunk_mask)
+        let cond = self.edge_condition();
+        self.find_chunk_edge_with(buf, &cond)
+    }
+
+    /// Return the edge condition used by `find_chunk_edge`
+    pub fn edge_condition(&self) -> MaskCondition {
+        Self::condition_for_bits(self.chunk_bits)
     }
 
     /// Counts the number of low bits set in the rollsum, assuming
@@ -159,18 +319,39 @@ impl Bup {
     /// returned by `find_chunk_edge`).
     /// Be aware that there's a deliberate 'bug' in this function
     /// in order to match expected return values from other bupsplit
-    /// implementations.
+    /// implementations, unless enabled with `set_corrected_count_bits`.
     // Note: because of the state is reset after finding an edge, assist
     // users use this correctly by making them pass in a digest they've
     // obtained.
     pub fn count_bits(&self, digest: Digest) -> u32 {
+        let (legacy, corrected) = self.count_bits_both(digest);
+        if self.corrected_count_bits {
+            corrected
+        } else {
+            legacy
+        }
+    }
+
+    /// Return both the bupsplit compatible and the corrected result of
+    /// `count_bits`, in that order
+    ///
+    /// The compatible count ignores the bit after the `chunk_bits` matched
+    /// ones. This isn't actually a problem as the distribution of values
+    /// will be the same, but it is unexpected.
+    pub fn count_bits_both(&self, digest: Digest) -> (u32, u32) {
         let rsum = digest >> self.chunk_bits;
+        let corrected = rsum.trailing_ones() + self.chunk_bits;
+        let legacy = (rsum >> 1).trailing_ones() + self.chunk_bits;
+        (legacy, corrected)
+    }
 
-        // Ignore the next bit as well. This isn't actually
-        // a problem as the distribution of values will be the same,
-        // but it is unexpected.
-        let rsum = rsum >> 1;
-        rsum.trailing_ones() + self.chunk_bits
+    /// Choose whether `count_bits` returns the corrected count of all set
+    /// low bits instead of the bupsplit compatible one
+    ///
+    /// Off by default. Only enable it for new systems, which don't need to
+    /// agree with the levels of existing bupsplit based trees.
+    pub fn set_corrected_count_bits(&mut self, corrected: bool) {
+        self.corrected_count_bits = corrected;
     }
 
     fn add_to_window(&mut self, new_data: &[u8]) {
@@ -188,6 +369,321 @@ impl Bup {
     }
 }
 
+/// `Bup` with a window size chosen at runtime
+///
+/// The window is heap allocated. With the default window size of 64 bytes
+/// this produces exactly the digests and edges of `Bup`, which is faster.
+#[derive(Clone)]
+pub struct BupDyn {
+    state: State,
+    window: Box<[u8]>,
+    wofs: usize,
+    chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to the window size
+    filled: usize,
+}
+
+impl Default for BupDyn {
+    fn default() -> Self {
+        Self::new(WINDOW_SIZE, CHUNK_BITS)
+    }
+}
+
+impl BupDyn {
+    /// Create a new engine rolling over `window_size` bytes
+    ///
+    /// `chunk_bits` is the number of bits that need to match in the edge
+    /// condition, as for `Bup::new_with_chunk_bits`. Panics unless it is
+    /// valid for the window, see `ChunkBits::for_window`.
+    pub fn new(window_size: usize, chunk_bits: u32) -> Self {
+        assert!(window_size > 0);
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, window_size));
+        BupDyn {
+            state: State::with_window(window_size),
+            window: vec![0; window_size].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits,
+            filled: 0,
+        }
+    }
+
+    /// Find chunk edge using the engine's default edge condition
+    ///
+    /// See `Engine::find_chunk_edge_cond`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let cond = Self::condition_for_bits(self.chunk_bits);
+        self.find_chunk_edge_with(buf, &cond)
+    }
+}
+
+impl Engine for BupDyn {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let window_size = self.window.len();
+        let prevch = mem::replace(&mut self.window[self.wofs], newch);
+        self.state.add_windowed(prevch, newch, window_size);
+        self.wofs = (self.wofs + 1) % window_size;
+        if self.filled < window_size {
+            self.filled += 1;
+        }
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let window_size = self.window.len();
+        let filled = cmp::min(window_size, self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, window_size, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        self.state.digest()
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        let window_size = left.window.len();
+        if window_size != right.window.len() || left.chunk_bits != right.chunk_bits {
+            return None;
+        }
+        let mut combined = BupDyn {
+            state: left.state.clone(),
+            window: left.window.clone(),
+            wofs: left.wofs,
+            chunk_bits: left.chunk_bits,
+            filled: left.filled,
+        };
+        let take = usize::try_from(right_len).map_or(window_size, |len| cmp::min(len, window_size));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + window_size - take + i) % window_size]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.state = State::with_window(self.window.len());
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl SlidingEngine for BupDyn {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        let window_size = self.window.len();
+        self.window[self.wofs] = new;
+        self.state.add_windowed(old, new, window_size);
+        self.wofs = (self.wofs + 1) % window_size;
+        if self.filled < window_size {
+            self.filled += 1;
+        }
+    }
+}
+
+impl Chunker for BupDyn {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        BupDyn::find_chunk_edge(self, buf)
+    }
+}
+
+impl MaskEngine for BupDyn {
+    type Condition = MaskCondition;
+
+    fn condition_for_bits(bits: u32) -> MaskCondition {
+        MaskCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
+/// `Bup` with 64 bit accumulators and digest, for large chunks
+///
+/// The bupsplit digest is the low 16 bits of `s1` above the low 16 bits of
+/// `s2`, so edge conditions of more than 16 bits depend on the poorly
+/// distributed low bits of the window sum. Here a bijective mix of both
+/// whole accumulators is the digest, which keeps its low bits uniform for
+/// any `chunk_bits`. Edges differ from those of `Bup`.
+#[derive(Clone)]
+pub struct Bup64 {
+    state: WideState,
+    window: [u8; WINDOW_SIZE],
+    wofs: usize,
+    chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to `WINDOW_SIZE`
+    filled: usize,
+}
+
+#[derive(Clone)]
+struct WideState {
+    s1: u64,
+    s2: u64,
+}
+
+impl WideState {
+    const fn new() -> Self {
+        Self {
+            s1: (WINDOW_SIZE * CHAR_OFFSET) as u64,
+            s2: (WINDOW_SIZE * (WINDOW_SIZE - 1) * CHAR_OFFSET) as u64,
+        }
+    }
+
+    #[inline(always)]
+    fn add(&mut self, drop: u8, add: u8) {
+        self.s1 = self.s1.wrapping_add(add as u64).wrapping_sub(drop as u64);
+        self.s2 = self.s2.wrapping_add(self.s1);
+        self.s2 = self
+            .s2
+            .wrapping_sub((WINDOW_SIZE * (drop as usize + CHAR_OFFSET)) as u64);
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> u64 {
+        // splitmix64's finalizer, bijective, over both sums
+        let mut h = (self.s1 << 32) ^ self.s2;
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^ (h >> 31)
+    }
+}
+
+impl Default for Bup64 {
+    fn default() -> Self {
+        Bup64 {
+            state: WideState::new(),
+            window: [0; WINDOW_SIZE],
+            wofs: 0,
+            chunk_bits: CHUNK_BITS,
+            filled: 0,
+        }
+    }
+}
+
+impl Bup64 {
+    /// Create new engine with default chunking settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create new engine with custom chunking settings
+    ///
+    /// `chunk_bits` is the number of bits that need to match in the edge
+    /// condition. Panics unless it is valid for the 64 byte window, see
+    /// `ChunkBits::for_window`.
+    pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        Bup64 {
+            chunk_bits,
+            ..Default::default()
+        }
+    }
+
+    /// Find chunk edge using the engine's default edge condition
+    ///
+    /// See `Engine::find_chunk_edge_cond`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u64)> {
+        let cond = Self::condition_for_bits(self.chunk_bits);
+        self.find_chunk_edge_with(buf, &cond)
+    }
+}
+
+impl Engine for Bup64 {
+    type Digest = u64;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let prevch = mem::replace(&mut self.window[self.wofs], newch);
+        self.state.add(prevch, newch);
+        self.wofs = (self.wofs + 1) % WINDOW_SIZE;
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let filled = cmp::min(WINDOW_SIZE, self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, WINDOW_SIZE, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> u64 {
+        self.state.digest()
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(WINDOW_SIZE)
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        WINDOW_SIZE - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        if left.chunk_bits != right.chunk_bits {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(WINDOW_SIZE, |len| cmp::min(len, WINDOW_SIZE));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + WINDOW_SIZE - take + i) % WINDOW_SIZE]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        *self = Bup64 {
+            chunk_bits: self.chunk_bits,
+            ..Default::default()
+        }
+    }
+}
+
+impl SlidingEngine for Bup64 {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        self.window[self.wofs] = new;
+        self.state.add(old, new);
+        self.wofs = (self.wofs + 1) % WINDOW_SIZE;
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
+    }
+}
+
+impl Chunker for Bup64 {
+    type Digest = u64;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u64)> {
+        Bup64::find_chunk_edge(self, buf)
+    }
+}
+
+impl MaskEngine for Bup64 {
+    type Condition = MaskCondition;
+
+    fn condition_for_bits(bits: u32) -> MaskCondition {
+        MaskCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,6 +756,31 @@ mod tests {
         assert_eq!(expected_window, window_ordered(&bup));
     }
 
+    #[test]
+    fn hasher_matches_digest() {
+        let data = rand_data(1024);
+        let mut bup = Bup::new();
+        bup.roll(&data);
+
+        let mut hasher = Bup::new();
+        for part in data.chunks(10) {
+            hasher.write(part);
+        }
+        assert_eq!(hasher.finish(), u64::from(bup.digest()));
+    }
+
+    #[test]
+    fn tiny_averages_checked_against_window() {
+        assert!(Bup::try_new_with_chunk_bits(6).is_ok());
+        assert_eq!(
+            Bup::try_new_with_chunk_bits(5).err(),
+            Some(InvalidChunkBits::SmallerThanWindow {
+                bits: 5,
+                window_size: WINDOW_SIZE
+            })
+        );
+    }
+
     #[test]
     fn edge_expected_size() {
         let data = rand_data(2 * 1024 * 1024);
@@ -295,4 +816,70 @@ mod tests {
         assert_eq!(bup.count_bits(0b1011011111), 7);
         assert_eq!(bup.count_bits(0xFFFFFFFF), 31);
     }
+
+    #[test]
+    fn corrected_count_bits() {
+        let mut bup = Bup::new_with_chunk_bits(1);
+        assert_eq!(bup.count_bits_both(0b001), (1, 1));
+        assert_eq!(bup.count_bits_both(0b011), (1, 2));
+        assert_eq!(bup.count_bits_both(0b101), (2, 1));
+        assert_eq!(bup.count_bits_both(0xFFFFFFFF), (31, 32));
+
+        bup.set_corrected_count_bits(true);
+        assert_eq!(bup.count_bits(0b011), 2);
+        assert_eq!(bup.count_bits(0b0111), 3);
+        bup.reset();
+        assert_eq!(bup.count_bits(0xFFFFFFFF), 32);
+        bup.set_corrected_count_bits(false);
+        assert_eq!(bup.count_bits(0xFFFFFFFF), 31);
+    }
+
+    #[test]
+    fn dyn_default_window_matches_bup() {
+        let data = rand_data(256 * 1024);
+        let mut bup = Bup::new_with_chunk_bits(10);
+        let mut bup_dyn = BupDyn::new(WINDOW_SIZE, 10);
+        let mut remaining = &data[..];
+        while let Some((i, digest)) = bup.find_chunk_edge(remaining) {
+            assert_eq!(bup_dyn.find_chunk_edge(remaining), Some((i, digest)));
+            remaining = &remaining[i..];
+        }
+        assert_eq!(bup_dyn.find_chunk_edge(remaining), None);
+    }
+
+    #[test]
+    fn dyn_digest_depends_on_window() {
+        let data = rand_data(1024);
+        for &window_size in &[1, 48, 200] {
+            let digest = |buf: &[u8]| {
+                let mut bup = BupDyn::new(window_size, 10);
+                buf.iter().for_each(|&b| bup.roll_byte(b));
+                bup.digest()
+            };
+            assert_eq!(digest(&data), digest(&data[1024 - window_size..]));
+            assert_ne!(digest(&data), digest(&data[1024 - window_size + 1..]));
+
+            let mut rolled = BupDyn::new(window_size, 10);
+            rolled.roll(&data);
+            assert_eq!(rolled.digest(), digest(&data));
+            assert_eq!(rolled.window_size(), Some(window_size));
+        }
+    }
+
+    #[test]
+    fn wide_edge_expected_size() {
+        let data = rand_data(16 * 1024 * 1024);
+        for &bits in &[10, 14, 16, 18] {
+            let mut bup = Bup64::new_with_chunk_bits(bits);
+            let mut sizes = Vec::new();
+            let mut remaining = &data[..];
+            while let Some((i, _)) = bup.find_chunk_edge(remaining) {
+                sizes.push(i);
+                remaining = &remaining[i..];
+            }
+            let expected_average = (1 << bits) as f64;
+            let average = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+            assert!(dbg!((average - expected_average).abs() / expected_average) < 0.3)
+        }
+    }
 }
@@ -0,0 +1,146 @@
+use super::{for_each_chunk, ChunkHash, ChunkStore, Chunker};
+use sha2::{Digest, Sha512_256};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// zstd compression level used for new chunk files
+pub const COMPRESSION_LEVEL: i32 = 3;
+
+/// Return the casync chunk ID of `data` (its SHA-512/256 digest)
+pub fn chunk_id(data: &[u8]) -> ChunkHash {
+    Sha512_256::digest(data).into()
+}
+
+/// Chunk store in the casync `.castr` directory layout
+///
+/// Chunk `id` is stored zstd-compressed in `<dir>/<first 4 hex digits of
+/// id>/<hex id>.cacnk`, which is the layout read by casync and desync.
+/// Chunk IDs must be SHA-512/256 digests of the uncompressed chunks for those
+/// tools to accept the store, see `chunk_id`.
+pub struct CastrStore {
+    dir: PathBuf,
+}
+
+impl CastrStore {
+    /// Open the store in `dir`, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(CastrStore {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Return the path of the file storing chunk `id`
+    pub fn chunk_path(&self, id: &ChunkHash) -> PathBuf {
+        let mut hex = String::with_capacity(id.len() * 2);
+        for b in id {
+            write!(hex, "{:02x}", b).unwrap();
+        }
+        self.dir.join(&hex[..4]).join(hex + ".cacnk")
+    }
+
+    /// Store `data`, returning its chunk ID
+    pub fn put_chunk(&mut self, data: &[u8]) -> io::Result<ChunkHash> {
+        let id = chunk_id(data);
+        self.put(&id, data)?;
+        Ok(id)
+    }
+
+    /// Split everything read from `reader` with `chunker` and store the
+    /// chunks, returning the ID and length of every chunk in order
+    pub fn write_chunks<R, C>(
+        &mut self,
+        reader: R,
+        chunker: &mut C,
+    ) -> io::Result<Vec<(ChunkHash, u64)>>
+    where
+        R: Read,
+        C: Chunker,
+    {
+        let mut chunks = Vec::new();
+        for_each_chunk(reader, chunker, |_, chunk| {
+            chunks.push((self.put_chunk(chunk)?, chunk.len() as u64));
+            Ok(())
+        })?;
+        Ok(chunks)
+    }
+}
+
+impl ChunkStore for CastrStore {
+    fn has(&self, hash: &ChunkHash) -> io::Result<bool> {
+        Ok(self.chunk_path(hash).is_file())
+    }
+
+    /// Chunks are written to a temporary file first and renamed into place,
+    /// so concurrent readers never see partial chunks.
+    fn put(&mut self, hash: &ChunkHash, data: &[u8]) -> io::Result<()> {
+        let path = self.chunk_path(hash);
+        if path.is_file() {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        let tmp = path.with_extension(format!("cacnk.{}.tmp", std::process::id()));
+        fs::write(&tmp, zstd::encode_all(data, COMPRESSION_LEVEL)?)?;
+        fs::rename(tmp, path)
+    }
+
+    fn get(&self, hash: &ChunkHash) -> io::Result<Vec<u8>> {
+        let data = zstd::decode_all(fs::File::open(self.chunk_path(hash))?)?;
+        if chunk_id(&data) != *hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "chunk content does not match its ID",
+            ));
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    #[test]
+    fn writes_castr_layout() {
+        let dir = std::env::temp_dir().join(format!("rollsum-castr-{}", std::process::id()));
+        let data = rand_data(256 * 1024);
+        let mut store = CastrStore::open(dir.join("default.castr")).unwrap();
+        let chunks = store
+            .write_chunks(&data[..], &mut Gear::new_with_chunk_bits(14))
+            .unwrap();
+        assert!(chunks.len() > 5);
+
+        let mut reassembled = Vec::new();
+        for (id, len) in &chunks {
+            let path = store.chunk_path(id);
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            let fanout = path
+                .parent()
+                .unwrap()
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap();
+            assert_eq!(name.len(), 64 + ".cacnk".len());
+            assert!(name.starts_with(fanout));
+            assert_eq!(fanout.len(), 4);
+
+            let chunk = store.get(id).unwrap();
+            assert_eq!(chunk.len() as u64, *len);
+            reassembled.extend(chunk);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(reassembled == data);
+    }
+
+    #[test]
+    fn known_chunk_id() {
+        // SHA-512/256 of the empty string
+        let id = chunk_id(b"");
+        assert_eq!(id[..4], [0xc6, 0x72, 0xb8, 0xd1]);
+    }
+}
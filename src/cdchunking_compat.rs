@@ -0,0 +1,74 @@
+use super::{Chunker, Engine};
+use ::cdchunking::ChunkerImpl;
+
+/// Wrapper using one of this crate's engines as a `cdchunking::ChunkerImpl`
+///
+/// Allows passing e.g. `Gear` to `cdchunking::Chunker::new`.
+pub struct AsChunkerImpl<E>(pub E);
+
+impl<E: Engine + Chunker> ChunkerImpl for AsChunkerImpl<E> {
+    fn find_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        // `cdchunking` expects the index of the last byte of the chunk
+        Chunker::find_chunk_edge(&mut self.0, data).map(|(i, _)| i - 1)
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+/// Wrapper using a `cdchunking::ChunkerImpl` as a `Chunker`
+///
+/// The wrapped implementation is reset after every boundary, as
+/// `cdchunking::Chunker` does.
+pub struct FromChunkerImpl<I>(pub I);
+
+impl<I: ChunkerImpl> Chunker for FromChunkerImpl<I> {
+    type Digest = ();
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, ())> {
+        let last = self.0.find_boundary(buf)?;
+        self.0.reset();
+        Some((last + 1, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn split<C: Chunker>(mut chunker: C, mut data: &[u8]) -> Vec<&[u8]> {
+        let mut result = Vec::new();
+        while let Some((i, _)) = chunker.find_chunk_edge(data) {
+            result.push(&data[..i]);
+            data = &data[i..];
+        }
+        result.push(data);
+        result
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn engine_as_chunker_impl() {
+        use crate::Gear;
+
+        let data = rand_data(256 * 1024);
+        let cdc = cdchunking::Chunker::new(AsChunkerImpl(Gear::new_with_chunk_bits(10)));
+        let chunks: Vec<_> = cdc.slices(&data).collect();
+        assert!(chunks.len() > 100);
+        assert_eq!(chunks, split(Gear::new_with_chunk_bits(10), &data));
+    }
+
+    #[test]
+    fn chunker_impl_as_chunker() {
+        let data = rand_data(256 * 1024);
+        let cdc = cdchunking::Chunker::new(cdchunking::ZPAQ::new(10));
+        let chunks: Vec<_> = cdc.slices(&data).collect();
+        assert!(chunks.len() > 100);
+        assert_eq!(
+            chunks,
+            split(FromChunkerImpl(cdchunking::ZPAQ::new(10)), &data)
+        );
+    }
+}
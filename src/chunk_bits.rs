@@ -0,0 +1,194 @@
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+
+/// Number of digest bits matched by the edge condition of an engine
+///
+/// A chunker matching `n` bits splits on average every `2^n` bytes. Every
+/// engine constructor taking a number of bits validates it here and panics
+/// with the reason if it is out of range; validating user input with
+/// `ChunkBits::new` first reports the same reasons as errors instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u32", into = "u32"))]
+pub struct ChunkBits(u32);
+
+impl ChunkBits {
+    /// Largest number of bits supported by any engine
+    ///
+    /// `Bup` digests are only 32 bits wide, and wider engines share the
+    /// limit so that parameters stay interchangeable between them. Engines
+    /// whose edge condition looks at fewer bits validate with `with_max`.
+    pub const MAX: u32 = 31;
+
+    /// Validate `bits` for any engine
+    pub const fn new(bits: u32) -> Result<Self, InvalidChunkBits> {
+        Self::with_max(bits, Self::MAX)
+    }
+
+    /// Validate `bits` for an engine matching at most `max` bits, itself
+    /// at most `MAX`
+    pub const fn with_max(bits: u32, max: u32) -> Result<Self, InvalidChunkBits> {
+        let max = if max < Self::MAX { max } else { Self::MAX };
+        if bits > max {
+            return Err(InvalidChunkBits::TooLarge { bits, max });
+        }
+        Ok(ChunkBits(bits))
+    }
+
+    /// Validate `bits` for an engine rolling over `window_size` bytes
+    ///
+    /// The average chunk must be at least as large as the window, otherwise
+    /// most edges are found before the window even filled up after a reset
+    /// and depend on the preceding chunk rather than the content.
+    pub fn for_window(bits: u32, window_size: usize) -> Result<Self, InvalidChunkBits> {
+        let bits = Self::new(bits)?;
+        if bits.avg_size() < window_size as u64 {
+            return Err(InvalidChunkBits::SmallerThanWindow {
+                bits: bits.0,
+                window_size,
+            });
+        }
+        Ok(bits)
+    }
+
+    /// Return the number of bits matching an average chunk size of
+    /// `avg_size` bytes
+    pub fn from_avg_size(avg_size: u64) -> Result<Self, InvalidChunkBits> {
+        if !avg_size.is_power_of_two() {
+            return Err(InvalidChunkBits::NotPowerOfTwo { avg_size });
+        }
+        Self::new(avg_size.trailing_zeros())
+    }
+
+    /// Return the number of bits
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Return the average chunk size in bytes
+    pub const fn avg_size(self) -> u64 {
+        1 << self.0
+    }
+
+    /// Validate `bits`, panicking with the reason if it is invalid
+    #[cfg(any(
+        feature = "bup",
+        feature = "gear",
+        feature = "adler32",
+        feature = "crc32",
+        feature = "rabin"
+    ))]
+    pub(crate) fn expect(bits: Result<Self, InvalidChunkBits>) -> u32 {
+        match bits {
+            Ok(bits) => bits.0,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+impl TryFrom<u32> for ChunkBits {
+    type Error = InvalidChunkBits;
+
+    fn try_from(bits: u32) -> Result<Self, InvalidChunkBits> {
+        Self::new(bits)
+    }
+}
+
+impl From<ChunkBits> for u32 {
+    fn from(bits: ChunkBits) -> u32 {
+        bits.0
+    }
+}
+
+impl fmt::Display for ChunkBits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Error returned for an unsupported number of chunk bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidChunkBits {
+    /// More than the `max` bits of the engine, at most `ChunkBits::MAX`
+    TooLarge { bits: u32, max: u32 },
+    /// Average chunk size smaller than the rolling window
+    SmallerThanWindow { bits: u32, window_size: usize },
+    /// Average chunk size which no number of bits produces
+    NotPowerOfTwo { avg_size: u64 },
+}
+
+impl fmt::Display for InvalidChunkBits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidChunkBits::TooLarge { bits, max } => {
+                write!(f, "{} chunk bits exceed the maximum of {}", bits, max)
+            }
+            InvalidChunkBits::SmallerThanWindow { bits, window_size } => write!(
+                f,
+                "average chunk size of {} bits is smaller than the window of {} bytes",
+                bits, window_size
+            ),
+            InvalidChunkBits::NotPowerOfTwo { avg_size } => {
+                write!(f, "average chunk size {} is not a power of two", avg_size)
+            }
+        }
+    }
+}
+
+impl error::Error for InvalidChunkBits {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_ranges() {
+        assert_eq!(ChunkBits::new(13).map(ChunkBits::get), Ok(13));
+        assert_eq!(ChunkBits::new(31).map(ChunkBits::avg_size), Ok(1 << 31));
+        assert_eq!(
+            ChunkBits::new(32),
+            Err(InvalidChunkBits::TooLarge { bits: 32, max: 31 })
+        );
+        assert!(ChunkBits::with_max(16, 16).is_ok());
+        assert_eq!(
+            ChunkBits::with_max(17, 16),
+            Err(InvalidChunkBits::TooLarge { bits: 17, max: 16 })
+        );
+        assert_eq!(
+            ChunkBits::with_max(32, 40),
+            Err(InvalidChunkBits::TooLarge { bits: 32, max: 31 })
+        );
+        assert_eq!(ChunkBits::try_from(0), ChunkBits::new(0));
+
+        assert!(ChunkBits::for_window(6, 64).is_ok());
+        assert_eq!(
+            ChunkBits::for_window(5, 64),
+            Err(InvalidChunkBits::SmallerThanWindow {
+                bits: 5,
+                window_size: 64
+            })
+        );
+        assert_eq!(
+            ChunkBits::for_window(40, 64),
+            Err(InvalidChunkBits::TooLarge { bits: 40, max: 31 })
+        );
+
+        assert_eq!(ChunkBits::from_avg_size(8192), ChunkBits::new(13));
+        assert_eq!(
+            ChunkBits::from_avg_size(8000),
+            Err(InvalidChunkBits::NotPowerOfTwo { avg_size: 8000 })
+        );
+        assert_eq!(
+            ChunkBits::from_avg_size(0),
+            Err(InvalidChunkBits::NotPowerOfTwo { avg_size: 0 })
+        );
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    #[should_panic(expected = "exceed the maximum")]
+    fn constructors_panic_with_reason() {
+        crate::Bup::new_with_chunk_bits(32);
+    }
+}
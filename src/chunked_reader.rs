@@ -0,0 +1,252 @@
+use super::index::ChunkIndex;
+use super::ChunkStore;
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Number of chunks `ChunkedReader` caches by default
+pub const DEFAULT_CACHE_CHUNKS: usize = 8;
+
+/// Number of chunks `ChunkedReader` reads ahead by default
+pub const DEFAULT_READAHEAD: usize = 2;
+
+/// Reader of the stream described by a chunk index, fetching its chunks
+/// from a `ChunkStore`
+///
+/// The most recently used chunks are cached, so small reads and seeks
+/// within a few chunks don't fetch chunks again. When a chunk has to be
+/// fetched, the chunks following it are fetched along with it and cached, as
+/// most readers go on sequentially.
+pub struct ChunkedReader<'a, S> {
+    index: ChunkIndex<'a>,
+    store: &'a S,
+    position: u64,
+    /// Cached chunks by entry position, most recently used last
+    cache: VecDeque<(usize, Vec<u8>)>,
+    cache_chunks: usize,
+    readahead: usize,
+}
+
+impl<'a, S: ChunkStore> ChunkedReader<'a, S> {
+    /// Read the stream of `index` from `store`
+    pub fn new(index: ChunkIndex<'a>, store: &'a S) -> Self {
+        ChunkedReader {
+            index,
+            store,
+            position: 0,
+            cache: VecDeque::new(),
+            cache_chunks: DEFAULT_CACHE_CHUNKS,
+            readahead: DEFAULT_READAHEAD,
+        }
+    }
+
+    /// Keep up to `chunks` chunks cached, at least one
+    ///
+    /// The cache should hold more chunks than are read ahead, or chunks read
+    /// ahead are evicted before they are used.
+    pub fn set_cache_chunks(&mut self, chunks: usize) {
+        self.cache_chunks = chunks.max(1);
+        self.evict();
+    }
+
+    /// Fetch `chunks` chunks following every chunk fetched for a read
+    pub fn set_readahead(&mut self, chunks: usize) {
+        self.readahead = chunks;
+    }
+
+    /// Return the length of the stream
+    pub fn len(&self) -> u64 {
+        self.index.stream_len()
+    }
+
+    /// Return whether the stream is empty
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn evict(&mut self) {
+        while self.cache.len() > self.cache_chunks {
+            self.cache.pop_front();
+        }
+    }
+
+    fn fetch(&self, i: usize) -> io::Result<Vec<u8>> {
+        let entry = self.index.get(i).unwrap();
+        let data = self.store.get(&entry.hash)?;
+        if data.len() as u64 != entry.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stored chunk does not match the index",
+            ));
+        }
+        Ok(data)
+    }
+
+    /// Return chunk `i`, fetching it and the chunks after it unless cached
+    fn chunk(&mut self, i: usize) -> io::Result<&[u8]> {
+        if let Some(at) = self.cache.iter().position(|&(cached, _)| cached == i) {
+            let hit = self.cache.remove(at).unwrap();
+            self.cache.push_back(hit);
+        } else {
+            let data = self.fetch(i)?;
+            let ahead = (i + 1..self.index.len()).take(self.readahead);
+            let mut fetched = vec![(i, data)];
+            for next in ahead {
+                if self.cache.iter().all(|&(cached, _)| cached != next) {
+                    fetched.push((next, self.fetch(next)?));
+                }
+            }
+            // Chunk `i` ends up most recently used
+            self.cache.extend(fetched.into_iter().rev());
+            self.evict();
+        }
+        Ok(&self.cache.back().unwrap().1)
+    }
+}
+
+impl<'a, S: ChunkStore> Read for ChunkedReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(i) = self.index.find_offset_index(self.position) else {
+            return Ok(0);
+        };
+        let start = self.index.get(i).unwrap().offset;
+        let skip = (self.position - start) as usize;
+        let chunk = self.chunk(i)?;
+        let n = buf.len().min(chunk.len() - skip);
+        buf[..n].copy_from_slice(&chunk[skip..skip + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a, S: ChunkStore> Seek for ChunkedReader<'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => {
+                self.position = offset;
+                return Ok(offset);
+            }
+            SeekFrom::End(offset) => (self.len(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::index::{IndexEntry, IndexWriter};
+    use crate::merkle::tests::weak_hash;
+    use crate::store::MemoryStore;
+    use crate::tests::rand_data;
+    use crate::{Algorithm, ChunkerParams, Gear};
+    use std::cell::Cell;
+
+    fn store_stream(data: &[u8], store: &mut impl ChunkStore) -> Vec<u8> {
+        let params = ChunkerParams::new(Algorithm::Gear, 12);
+        let mut writer = IndexWriter::new(Vec::new(), &params).unwrap();
+        let mut gear = Gear::new_with_chunk_bits(12);
+        let mut offset = 0;
+        while offset < data.len() {
+            let (len, digest) = gear
+                .find_chunk_edge(&data[offset..])
+                .unwrap_or((data.len() - offset, 0));
+            let chunk = &data[offset..offset + len];
+            let hash = weak_hash(&[chunk]);
+            store.put(&hash, chunk).unwrap();
+            let entry = IndexEntry {
+                offset: offset as u64,
+                len: len as u64,
+                weak_digest: digest,
+                hash,
+            };
+            writer.push(&entry).unwrap();
+            offset += len;
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Store counting the chunks fetched from it
+    struct CountingStore(MemoryStore, Cell<usize>);
+
+    impl ChunkStore for CountingStore {
+        fn has(&self, hash: &crate::ChunkHash) -> io::Result<bool> {
+            self.0.has(hash)
+        }
+
+        fn put(&mut self, hash: &crate::ChunkHash, data: &[u8]) -> io::Result<()> {
+            self.0.put(hash, data)
+        }
+
+        fn get(&self, hash: &crate::ChunkHash) -> io::Result<Vec<u8>> {
+            self.1.set(self.1.get() + 1);
+            self.0.get(hash)
+        }
+    }
+
+    #[test]
+    fn reads_and_seeks() {
+        let data = rand_data(256 * 1024);
+        let mut store = CountingStore(MemoryStore::new(), Cell::new(0));
+        let index_data = store_stream(&data, &mut store);
+        let index = ChunkIndex::parse(&index_data).unwrap();
+        let mut reader = ChunkedReader::new(index, &store);
+        assert_eq!(reader.len(), data.len() as u64);
+
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert!(all == data);
+        // Every chunk was fetched once, readahead included
+        assert_eq!(store.1.get(), index.len());
+
+        for &(pos, len) in &[(1000, 10_000), (200_000, 56 * 1024), (5, 1)] {
+            reader.seek(SeekFrom::Start(pos)).unwrap();
+            let mut buf = vec![0; len];
+            reader.read_exact(&mut buf).unwrap();
+            assert!(buf[..] == data[pos as usize..pos as usize + len]);
+        }
+        assert_eq!(
+            reader.seek(SeekFrom::End(-10)).unwrap(),
+            data.len() as u64 - 10
+        );
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert!(tail[..] == data[data.len() - 10..]);
+        assert!(reader
+            .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+            .is_err());
+
+        // Small reads within a chunk are served from the cache
+        let fetched = store.1.get();
+        reader.seek(SeekFrom::Start(100_000)).unwrap();
+        let mut byte = [0];
+        for _ in 0..100 {
+            reader.read_exact(&mut byte).unwrap();
+        }
+        assert!(store.1.get() - fetched <= 1 + DEFAULT_READAHEAD);
+    }
+
+    #[test]
+    fn rejects_mismatched_chunks() {
+        let data = rand_data(64 * 1024);
+        let mut store = MemoryStore::new();
+        let index_data = store_stream(&data, &mut store);
+        let index = ChunkIndex::parse(&index_data).unwrap();
+        let mut damaged = MemoryStore::new();
+        let first = index.get(0).unwrap();
+        damaged.put(&first.hash, &data[..10]).unwrap();
+        let mut reader = ChunkedReader::new(index, &damaged);
+        reader.set_readahead(0);
+        let err = reader.read(&mut [0; 100]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,284 @@
+use super::Engine;
+use std::mem;
+
+/// Chunk edge condition
+///
+/// Unlike closures, the condition types in this module can be stored in
+/// configuration, compared and (with the `serde` feature) serialized.
+/// Closures `Fn(&E) -> bool` implement this trait too.
+pub trait EdgeCondition<E: ?Sized> {
+    /// Return whether the current state of `engine` is a chunk edge
+    fn is_edge(&self, engine: &E) -> bool;
+}
+
+impl<E: ?Sized, F> EdgeCondition<E> for F
+where
+    F: Fn(&E) -> bool,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        self(engine)
+    }
+}
+
+/// Engines whose edge condition matches a configurable number of digest bits
+///
+/// A condition matching `n` bits finds an edge on average every `2^n` bytes,
+/// which lets generic chunkers tighten or loosen the condition of any such
+/// engine.
+pub trait MaskEngine: Engine + Sized {
+    type Condition: EdgeCondition<Self>;
+
+    /// Return the condition matching `bits` bits of the digest
+    fn condition_for_bits(bits: u32) -> Self::Condition;
+
+    /// Return the number of bits matched by the engine's default condition
+    fn chunk_bits(&self) -> u32;
+}
+
+/// Edge when all bits of `mask` are set in the digest
+///
+/// This is the condition used by `bup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaskCondition {
+    mask: u64,
+}
+
+impl MaskCondition {
+    /// Create a condition matching the bits of `mask`
+    pub const fn new(mask: u64) -> Self {
+        MaskCondition { mask }
+    }
+
+    /// Create a condition matching the lowest `bits` bits
+    pub const fn with_bits(bits: u32) -> Self {
+        assert!(bits < 64);
+        MaskCondition::new((1 << bits) - 1)
+    }
+
+    /// Return the matched mask
+    pub const fn mask(&self) -> u64 {
+        self.mask
+    }
+}
+
+impl<E> EdgeCondition<E> for MaskCondition
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        engine.digest().into() & self.mask == self.mask
+    }
+}
+
+/// Edge when the bits of `mask` in the digest are those of `value`
+///
+/// This is the condition used by LBFS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaskValueCondition {
+    mask: u64,
+    value: u64,
+}
+
+impl MaskValueCondition {
+    /// Create a condition matching the bits of `value` within `mask`
+    pub const fn new(mask: u64, value: u64) -> Self {
+        MaskValueCondition {
+            mask,
+            value: value & mask,
+        }
+    }
+
+    /// Return the matched mask
+    pub const fn mask(&self) -> u64 {
+        self.mask
+    }
+
+    /// Return the value the masked digest has to equal
+    pub const fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+impl<E> EdgeCondition<E> for MaskValueCondition
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        engine.digest().into() & self.mask == self.value
+    }
+}
+
+/// Edge when all bits of `mask` are clear in the digest
+///
+/// This is the condition used by restic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ZeroMaskCondition {
+    mask: u64,
+}
+
+impl ZeroMaskCondition {
+    /// Create a condition requiring the bits of `mask` to be clear
+    pub const fn new(mask: u64) -> Self {
+        ZeroMaskCondition { mask }
+    }
+
+    /// Create a condition requiring the lowest `bits` bits to be clear
+    pub const fn with_bits(bits: u32) -> Self {
+        assert!(bits < 64);
+        ZeroMaskCondition::new((1 << bits) - 1)
+    }
+
+    /// Return the mask of bits required to be clear
+    pub const fn mask(&self) -> u64 {
+        self.mask
+    }
+}
+
+impl<E> EdgeCondition<E> for ZeroMaskCondition
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        engine.digest().into() & self.mask == 0
+    }
+}
+
+/// Edge when the highest `bits` bits of the digest are zero
+///
+/// This is the condition used by `gear`, whose high digest bits depend on
+/// the most input bytes. Digests narrower than `bits` are never edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PrefixZeroCondition {
+    bits: u32,
+}
+
+impl PrefixZeroCondition {
+    /// Create a condition requiring `bits` leading zero bits
+    pub const fn new(bits: u32) -> Self {
+        assert!(bits < 64);
+        PrefixZeroCondition { bits }
+    }
+
+    /// Return the number of leading zero bits required
+    pub const fn bits(&self) -> u32 {
+        self.bits
+    }
+}
+
+impl<E> EdgeCondition<E> for PrefixZeroCondition
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        let digest_bits = (mem::size_of::<E::Digest>() * 8) as u32;
+        self.bits == 0
+            || digest_bits
+                .checked_sub(self.bits)
+                .is_some_and(|shift| engine.digest().into() >> shift == 0)
+    }
+}
+
+/// Edge when the digest is strictly lower than `threshold`
+///
+/// Allows edge probabilities which are not a power of two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThresholdCondition {
+    threshold: u64,
+}
+
+impl ThresholdCondition {
+    /// Create a condition matching digests below `threshold`
+    pub const fn new(threshold: u64) -> Self {
+        ThresholdCondition { threshold }
+    }
+
+    /// Return the threshold
+    pub const fn threshold(&self) -> u64 {
+        self.threshold
+    }
+}
+
+impl<E> EdgeCondition<E> for ThresholdCondition
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+{
+    #[inline(always)]
+    fn is_edge(&self, engine: &E) -> bool {
+        engine.digest().into() < self.threshold
+    }
+}
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn edges<E: Engine + Default, C: EdgeCondition<E>>(mut data: &[u8], cond: C) -> Vec<usize> {
+        let mut engine = E::default();
+        let mut result = Vec::new();
+        while let Some((i, _)) = engine.find_chunk_edge_with(data, &cond) {
+            result.push(i);
+            data = &data[i..];
+        }
+        result
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn mask_matches_closure() {
+        use crate::Bup;
+
+        let data = rand_data(128 * 1024);
+        let closure = |e: &Bup| e.digest() & 0x3ff == 0x3ff;
+        let expected = edges(&data, closure);
+        assert!(expected.len() > 50);
+        assert_eq!(
+            edges::<Bup, _>(&data, MaskCondition::with_bits(10)),
+            expected
+        );
+        assert_eq!(edges::<Bup, _>(&data, MaskCondition::new(0x3ff)), expected);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn prefix_zero_and_threshold_agree() {
+        use crate::Gear;
+
+        let data = rand_data(128 * 1024);
+        let prefix = edges::<Gear, _>(&data, PrefixZeroCondition::new(10));
+        assert!(prefix.len() > 50);
+        assert_eq!(
+            edges::<Gear, _>(&data, ThresholdCondition::new(1 << 54)),
+            prefix
+        );
+        assert_eq!(
+            edges::<Gear, _>(&data, PrefixZeroCondition::new(0)).len(),
+            data.len()
+        );
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn prefix_zero_wider_than_digest() {
+        use crate::Bup;
+
+        let data = rand_data(128 * 1024);
+        assert!(edges::<Bup, _>(&data, PrefixZeroCondition::new(40)).is_empty());
+        assert!(edges::<Bup, _>(&data, PrefixZeroCondition::new(4)).len() > 50);
+    }
+}
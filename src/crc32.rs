@@ -0,0 +1,286 @@
+use super::condition::{MaskCondition, MaskEngine};
+use super::{ChunkBits, Chunker, Engine};
+use std::convert::TryFrom;
+use std::mem;
+use std::sync::{Arc, OnceLock};
+
+pub type Digest = u32;
+
+/// Default chunk size used by `crc32` (log2)
+pub const CHUNK_BITS: u32 = 13;
+
+/// Default window size used by `crc32`
+pub const WINDOW_SIZE: usize = 64;
+
+/// Reversed IEEE 802.3 polynomial, as used by zlib, PNG and most others
+const POLYNOMIAL: u32 = 0xEDB8_8320;
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+#[inline(always)]
+fn update(crc: u32, b: u8) -> u32 {
+    (crc >> 8) ^ CRC_TABLE[((crc ^ b as u32) & 0xff) as usize]
+}
+
+/// Tables precomputed for a window size
+struct Tables {
+    /// Contribution of a byte leaving the window, which is the CRC of the
+    /// byte followed by a window of zeros without the initial value
+    remove: [u32; 256],
+    /// Value turning the CRC of the window without the initial value and
+    /// final xor into the standard one
+    offset: u32,
+}
+
+impl Tables {
+    fn new(window_size: usize) -> Self {
+        let mut remove = [0; 256];
+        for (b, remove) in remove.iter_mut().enumerate() {
+            let mut crc = update(0, b as u8);
+            for _ in 0..window_size {
+                crc = update(crc, 0);
+            }
+            *remove = crc;
+        }
+        let mut offset = !0;
+        for _ in 0..window_size {
+            offset = update(offset, 0);
+        }
+        Tables {
+            remove,
+            offset: !offset,
+        }
+    }
+}
+
+fn default_tables() -> Arc<Tables> {
+    static TABLES: OnceLock<Arc<Tables>> = OnceLock::new();
+    TABLES
+        .get_or_init(|| Arc::new(Tables::new(WINDOW_SIZE)))
+        .clone()
+}
+
+/// CRC-32 over a sliding window
+///
+/// The digest is the standard CRC-32 (as computed by zlib) of the last
+/// window of bytes, so it can be compared with weak checksums of formats
+/// mandating CRC-32. Before the window is filled, it is the CRC of the bytes
+/// preceded by zeros.
+///
+/// CRC is linear, so the byte leaving the window is removed by xoring in
+/// its precomputed contribution.
+#[derive(Clone)]
+pub struct Crc32 {
+    /// CRC of the window without the initial value and final xor
+    crc: u32,
+    tables: Arc<Tables>,
+    window: Box<[u8]>,
+    wofs: usize,
+    chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to the window size
+    filled: usize,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32 {
+            crc: 0,
+            tables: default_tables(),
+            window: vec![0; WINDOW_SIZE].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits: CHUNK_BITS,
+            filled: 0,
+        }
+    }
+}
+
+impl Crc32 {
+    /// Create new CRC-32 engine with default chunking settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create new CRC-32 engine with custom chunking settings
+    ///
+    /// `chunk_bits` is number of bits that need to match in
+    /// the edge condition. `CHUNK_BITS` constant is the default.
+    pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        Crc32 {
+            chunk_bits,
+            ..Default::default()
+        }
+    }
+
+    /// Create new CRC-32 engine over `window_size` byte windows
+    ///
+    /// Computing the tables takes a moment, so create engines for the same
+    /// window by cloning one. Panics unless `chunk_bits` is valid for the
+    /// window, see `ChunkBits::for_window`.
+    pub fn with_window(window_size: usize, chunk_bits: u32) -> Self {
+        assert!(window_size > 0);
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, window_size));
+        Crc32 {
+            crc: 0,
+            tables: Arc::new(Tables::new(window_size)),
+            window: vec![0; window_size].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits,
+            filled: 0,
+        }
+    }
+
+    /// Find chunk edge using CRC-32 defaults.
+    ///
+    /// See `Engine::find_chunk_edge_cond`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let cond = Self::condition_for_bits(self.chunk_bits);
+        self.find_chunk_edge_with(buf, &cond)
+    }
+}
+
+impl Engine for Crc32 {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let window_size = self.window.len();
+        let prevch = mem::replace(&mut self.window[self.wofs], newch);
+        self.crc = update(self.crc, newch) ^ self.tables.remove[prevch as usize];
+        self.wofs = (self.wofs + 1) % window_size;
+        if self.filled < window_size {
+            self.filled += 1;
+        }
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let window_size = self.window.len();
+        let filled = window_size.min(self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, window_size, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        self.crc ^ self.tables.offset
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Only the last window matters, as for `Bup`
+        let window_size = left.window.len();
+        if window_size != right.window.len() {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(window_size, |len| len.min(window_size));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + window_size - take + i) % window_size]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.crc = 0;
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl Chunker for Crc32 {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        Crc32::find_chunk_edge(self, buf)
+    }
+}
+
+impl MaskEngine for Crc32 {
+    type Condition = MaskCondition;
+
+    fn condition_for_bits(bits: u32) -> MaskCondition {
+        MaskCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0, |crc, &b| update(crc, b))
+    }
+
+    #[test]
+    fn digest_is_crc32_of_window() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let mut engine = Crc32::with_window(9, 4);
+        engine.roll(b"123456789");
+        assert_eq!(engine.digest(), 0xcbf4_3926);
+
+        let data = rand_data(20_000);
+        for &window_size in &[1, 64, 4097] {
+            let mut engine = Crc32::with_window(window_size, 13);
+            let mut padded = vec![0; window_size];
+            padded.extend_from_slice(&data);
+            for (i, &b) in data.iter().enumerate() {
+                engine.roll_byte(b);
+                if i % 97 == 0 || i + 1 == window_size {
+                    let window = &padded[i + 1..i + 1 + window_size];
+                    assert_eq!(engine.digest(), crc32(window), "{} {}", window_size, i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edge_expected_size() {
+        let data = rand_data(2 * 1024 * 1024);
+        for bits in 8..13 {
+            let mut engine = Crc32::new_with_chunk_bits(bits);
+            let (mut count, mut total) = (0, 0);
+            let mut remaining = &data[..];
+            while let Some((i, _)) = engine.find_chunk_edge(remaining) {
+                count += 1;
+                total += i;
+                remaining = &remaining[i..];
+            }
+            let expected = (1 << bits) as f64;
+            let average = total as f64 / count as f64;
+            assert!((average - expected).abs() / expected < 0.1, "{}", average);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use super::{for_each_chunk, ChunkHash, ChunkStore, Chunker};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Magic bytes at the start of an archive
+pub const MAGIC: [u8; 4] = *b"RSDT";
+
+/// Version of the archive format written by `TarWriter`
+pub const VERSION: u8 = 1;
+
+const END: u8 = 0;
+const FILE: u8 = 1;
+const DIRECTORY: u8 = 2;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Return whether `path` is relative, `/` separated and stays below the
+/// directory it is unpacked into
+fn is_safe_path(path: &str) -> bool {
+    !path.is_empty()
+        && path.len() <= usize::from(u16::MAX)
+        && path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'))
+}
+
+/// Chunk referenced by a file in an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef {
+    /// Length of the chunk
+    pub len: u64,
+    /// Hash of the chunk in the store
+    pub hash: ChunkHash,
+}
+
+/// Kind of an archive entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// Entry read from an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarEntry {
+    /// `/` separated path relative to the archive root
+    pub path: String,
+    pub kind: EntryKind,
+    /// Chunks of the file contents in order, empty for directories
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl TarEntry {
+    /// Return the size of the file contents
+    pub fn size(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len).sum()
+    }
+
+    /// Write the file contents, fetched from `store`, to `out`
+    ///
+    /// Fails with `io::ErrorKind::InvalidData` if a stored chunk doesn't
+    /// have the recorded length.
+    pub fn extract<S: ChunkStore, W: Write>(&self, store: &S, mut out: W) -> io::Result<u64> {
+        for chunk in &self.chunks {
+            let data = store.get(&chunk.hash)?;
+            if data.len() as u64 != chunk.len {
+                return Err(invalid_data("stored chunk has the wrong length"));
+            }
+            out.write_all(&data)?;
+        }
+        Ok(self.size())
+    }
+}
+
+/// Writer of archives whose file contents are chunk references
+///
+/// Like a tar file, the archive lists directories and files in the order
+/// they are appended, but file contents are split into chunks which go to a
+/// `ChunkStore`, so identical data in any file of any archive written to
+/// the same store is stored once:
+///
+/// ```text
+/// archive: "RSDT" | version: u8 | entry* | 0: u8
+/// entry:   kind: u8 (1 file, 2 directory) | path_len: u16 | path
+///          | file only: chunk_count: u64 | (len: u64 | hash: [u8; 32])*
+/// ```
+///
+/// Integers are big-endian. Paths are UTF-8, relative and `/` separated.
+pub struct TarWriter<'a, W: Write, S: ChunkStore, H> {
+    inner: W,
+    store: &'a mut S,
+    hash: H,
+}
+
+impl<'a, W, S, H> TarWriter<'a, W, S, H>
+where
+    W: Write,
+    S: ChunkStore,
+    H: Fn(&[u8]) -> ChunkHash,
+{
+    /// Write the archive header to `inner`, storing chunks identified by
+    /// `hash` in `store`
+    pub fn new(mut inner: W, store: &'a mut S, hash: H) -> io::Result<Self> {
+        inner.write_all(&MAGIC)?;
+        inner.write_all(&[VERSION])?;
+        Ok(TarWriter { inner, store, hash })
+    }
+
+    fn write_header(&mut self, kind: u8, path: &str) -> io::Result<()> {
+        if !is_safe_path(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "archive paths must be relative and below the root",
+            ));
+        }
+        self.inner.write_all(&[kind])?;
+        self.inner.write_all(&(path.len() as u16).to_be_bytes())?;
+        self.inner.write_all(path.as_bytes())
+    }
+
+    /// Append a directory entry
+    pub fn append_dir(&mut self, path: &str) -> io::Result<()> {
+        self.write_header(DIRECTORY, path)
+    }
+
+    /// Append a file with the contents read from `reader`, split by
+    /// `chunker`, returning its size
+    ///
+    /// Chunks missing from the store are stored. Pass a new chunker for every
+    /// file to make its chunks independent of the files before it.
+    pub fn append_file<R: Read, C: Chunker>(
+        &mut self,
+        path: &str,
+        reader: R,
+        chunker: &mut C,
+    ) -> io::Result<u64> {
+        // The chunk count precedes the chunks, so collect them first
+        let mut chunks = Vec::new();
+        let (store, hash) = (&mut *self.store, &self.hash);
+        for_each_chunk(reader, chunker, |_, data| {
+            let id = hash(data);
+            if !store.has(&id)? {
+                store.put(&id, data)?;
+            }
+            chunks.push(ChunkRef {
+                len: data.len() as u64,
+                hash: id,
+            });
+            Ok(())
+        })?;
+        self.write_header(FILE, path)?;
+        self.inner.write_all(&(chunks.len() as u64).to_be_bytes())?;
+        for chunk in &chunks {
+            self.inner.write_all(&chunk.len.to_be_bytes())?;
+            self.inner.write_all(&chunk.hash)?;
+        }
+        Ok(chunks.iter().map(|c| c.len).sum())
+    }
+
+    /// Write the end of archive marker and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[END])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reader of archives written by `TarWriter`
+pub struct TarReader<R: Read> {
+    inner: R,
+    done: bool,
+}
+
+impl<R: Read> TarReader<R> {
+    /// Read the archive header from `inner`
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        inner.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid_data("not a deduplicated archive"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported archive version"));
+        }
+        Ok(TarReader { inner, done: false })
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the next entry, or `None` at the end of the archive
+    pub fn next_entry(&mut self) -> io::Result<Option<TarEntry>> {
+        if self.done {
+            return Ok(None);
+        }
+        let kind = match self.read_array::<1>()?[0] {
+            END => {
+                self.done = true;
+                return Ok(None);
+            }
+            FILE => EntryKind::File,
+            DIRECTORY => EntryKind::Directory,
+            _ => return Err(invalid_data("unknown archive entry kind")),
+        };
+        let len = u16::from_be_bytes(self.read_array()?);
+        let mut path = vec![0; usize::from(len)];
+        self.inner.read_exact(&mut path)?;
+        let path = String::from_utf8(path).map_err(|_| invalid_data("path is not UTF-8"))?;
+        if !is_safe_path(&path) {
+            return Err(invalid_data("archive path escapes the root"));
+        }
+        let mut chunks = Vec::new();
+        if kind == EntryKind::File {
+            let count = u64::from_be_bytes(self.read_array()?);
+            // Not preallocated, as a damaged count could be huge
+            for _ in 0..count {
+                let len = u64::from_be_bytes(self.read_array()?);
+                let hash = self.read_array()?;
+                chunks.push(ChunkRef { len, hash });
+            }
+        }
+        Ok(Some(TarEntry { path, kind, chunks }))
+    }
+}
+
+impl<R: Read> Iterator for TarReader<R> {
+    type Item = io::Result<TarEntry>;
+
+    fn next(&mut self) -> Option<io::Result<TarEntry>> {
+        self.next_entry().transpose()
+    }
+}
+
+/// Append the directories and regular files below `dir` to `entries`,
+/// sorted by path, with `prefix` prepended to their paths
+///
+/// Symbolic links are not followed.
+fn walk(dir: &Path, prefix: &str, entries: &mut Vec<(String, bool)>) -> io::Result<()> {
+    let mut children: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    children.sort_by_key(|entry| entry.file_name());
+    for entry in children {
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| invalid_data("path is not UTF-8"))?;
+        let name = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            entries.push((name.clone(), true));
+            walk(&entry.path(), &name, entries)?;
+        } else if file_type.is_file() {
+            entries.push((name, false));
+        }
+    }
+    Ok(())
+}
+
+/// Archive the directory tree below `root` to `out`, storing file contents
+/// in `store`, and return `out`
+///
+/// Every file is split by a new chunker from `new_chunker`. Symbolic links
+/// and other special files are skipped.
+pub fn archive_dir<W, S, C, F, H>(
+    root: &Path,
+    out: W,
+    store: &mut S,
+    new_chunker: F,
+    hash: H,
+) -> io::Result<W>
+where
+    W: Write,
+    S: ChunkStore,
+    C: Chunker,
+    F: Fn() -> C,
+    H: Fn(&[u8]) -> ChunkHash,
+{
+    let mut entries = Vec::new();
+    walk(root, "", &mut entries)?;
+    let mut writer = TarWriter::new(out, store, hash)?;
+    for (path, is_dir) in entries {
+        if is_dir {
+            writer.append_dir(&path)?;
+        } else {
+            let file = fs::File::open(root.join(&path))?;
+            writer.append_file(&path, file, &mut new_chunker())?;
+        }
+    }
+    writer.finish()
+}
+
+/// Recreate the directories and files of an archive below `dest`, fetching
+/// file contents from `store`
+///
+/// Existing files are overwritten.
+pub fn unpack<R: Read, S: ChunkStore>(archive: R, store: &S, dest: &Path) -> io::Result<()> {
+    for entry in TarReader::new(archive)? {
+        let entry = entry?;
+        let path = dest.join(&entry.path);
+        match entry.kind {
+            EntryKind::Directory => fs::create_dir_all(&path)?,
+            EntryKind::File => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                entry.extract(store, io::BufWriter::new(fs::File::create(&path)?))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::store::MemoryStore;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    fn hash(chunk: &[u8]) -> ChunkHash {
+        weak_hash(&[chunk])
+    }
+
+    #[test]
+    fn archives_and_unpacks_tree() {
+        let dir = std::env::temp_dir().join(format!("rollsum-tar-{}", std::process::id()));
+        let (root, dest) = (dir.join("src"), dir.join("dest"));
+        let data = rand_data(300 * 1024);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("empty_dir")).unwrap();
+        fs::write(root.join("one"), &data).unwrap();
+        fs::write(root.join("a/b/copy"), &data).unwrap();
+        fs::write(root.join("a/empty"), b"").unwrap();
+
+        let mut store = MemoryStore::new();
+        let chunker = || Gear::new_with_chunk_bits(13);
+        let archive = archive_dir(&root, Vec::new(), &mut store, chunker, hash).unwrap();
+        unpack(&archive[..], &store, &dest).unwrap();
+        let unpacked = (
+            fs::read(dest.join("one")).unwrap(),
+            fs::read(dest.join("a/b/copy")).unwrap(),
+            fs::read(dest.join("a/empty")).unwrap(),
+            dest.join("empty_dir").is_dir(),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(unpacked.0 == data);
+        assert!(unpacked.1 == data);
+        assert!(unpacked.2.is_empty());
+        assert!(unpacked.3);
+
+        let entries: Vec<_> = TarReader::new(&archive[..])
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let paths: Vec<_> = entries.iter().map(|e| (e.path.as_str(), e.kind)).collect();
+        assert_eq!(
+            paths,
+            [
+                ("a", EntryKind::Directory),
+                ("a/b", EntryKind::Directory),
+                ("a/b/copy", EntryKind::File),
+                ("a/empty", EntryKind::File),
+                ("empty_dir", EntryKind::Directory),
+                ("one", EntryKind::File),
+            ]
+        );
+        // The copy is stored once, and the archive holds references only
+        assert_eq!(entries[2].chunks, entries[5].chunks);
+        assert_eq!(store.len(), entries[5].chunks.len());
+        assert!(archive.len() < 10 * 1024);
+    }
+
+    #[test]
+    fn rejects_unsafe_paths() {
+        let mut store = MemoryStore::new();
+        let mut writer = TarWriter::new(Vec::new(), &mut store, hash).unwrap();
+        for path in ["", "/etc", "a/../b", "./a", "a//b", "a\\b"] {
+            let err = writer.append_dir(path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        let mut gear = Gear::new();
+        assert_eq!(writer.append_file("ok", &b"abc"[..], &mut gear).unwrap(), 3);
+        let mut archive = writer.finish().unwrap();
+
+        // Damage the path into "..", which the reader must refuse
+        let at = archive.iter().position(|&b| b == b'o').unwrap();
+        archive[at..at + 2].copy_from_slice(b"..");
+        let mut reader = TarReader::new(&archive[..]).unwrap();
+        assert_eq!(
+            reader.next_entry().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert!(TarReader::new(&b"RSDX\x01"[..]).is_err());
+    }
+}
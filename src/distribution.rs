@@ -0,0 +1,148 @@
+/// Distribution of chunk sizes produced by mask based chunking
+///
+/// Each byte past the minimum chunk size ends a chunk with probability
+/// `2^-chunk_bits`, independently of the others, so the number of bytes
+/// after the minimum follows a geometric distribution, cut off at the
+/// maximum chunk size. This holds for random-looking data; highly repetitive
+/// data may end up with any sizes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizeDistribution {
+    chunk_bits: u32,
+    min_size: u64,
+    max_size: u64,
+}
+
+impl SizeDistribution {
+    /// Create the distribution of chunk sizes without size bounds
+    pub fn new(chunk_bits: u32) -> Self {
+        assert!(chunk_bits < 64);
+        SizeDistribution {
+            chunk_bits,
+            min_size: 0,
+            max_size: u64::MAX,
+        }
+    }
+
+    /// Create the distribution of chunk sizes within `min_size..=max_size`,
+    /// as produced by `MinMaxChunker`
+    pub fn with_bounds(chunk_bits: u32, min_size: u64, max_size: u64) -> Self {
+        assert!(min_size < max_size);
+        SizeDistribution {
+            min_size,
+            max_size,
+            ..Self::new(chunk_bits)
+        }
+    }
+
+    /// Return the probability that a byte ends a chunk
+    pub fn edge_probability(&self) -> f64 {
+        (-(self.chunk_bits as f64)).exp2()
+    }
+
+    /// Return `ln(1 - p)` where `p` is the edge probability
+    fn ln_miss(&self) -> f64 {
+        (-self.edge_probability()).ln_1p()
+    }
+
+    /// Return the expected chunk size
+    pub fn expected_size(&self) -> f64 {
+        let p = self.edge_probability();
+        let span = (self.max_size - self.min_size) as f64;
+        // E[min(Y, span)] = (1 - (1 - p)^span) / p
+        self.min_size as f64 - (span * self.ln_miss()).exp_m1() / p
+    }
+
+    /// Return the size below or at which a `fraction` of the chunks are
+    pub fn percentile(&self, fraction: f64) -> u64 {
+        assert!((0.0..=1.0).contains(&fraction));
+        if fraction >= 1.0 {
+            return self.max_size;
+        }
+        let past_min = ((-fraction).ln_1p() / self.ln_miss()).ceil().max(1.0);
+        if past_min >= (self.max_size - self.min_size) as f64 {
+            self.max_size
+        } else {
+            self.min_size + past_min as u64
+        }
+    }
+
+    /// Return the median chunk size
+    pub fn median(&self) -> u64 {
+        self.percentile(0.5)
+    }
+
+    /// Return the probability that a chunk is longer than `size` bytes
+    pub fn probability_exceeding(&self, size: u64) -> f64 {
+        if size < self.min_size {
+            1.0
+        } else if size >= self.max_size {
+            0.0
+        } else {
+            ((size - self.min_size) as f64 * self.ln_miss()).exp()
+        }
+    }
+
+    /// Return the probability that a chunk ends at the maximum size rather
+    /// than at an edge found by the condition
+    pub fn forced_edge_probability(&self) -> f64 {
+        self.probability_exceeding(self.max_size - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unbounded() {
+        let dist = SizeDistribution::new(13);
+        assert!((dist.expected_size() - 8192.0).abs() < 1e-6);
+        // ln(2) * 8192, rounded up
+        assert_eq!(dist.median(), 5678);
+        assert!((dist.probability_exceeding(8192) - (-1f64).exp()).abs() < 1e-4);
+        assert_eq!(dist.forced_edge_probability(), 0.0);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn huge_averages() {
+        const GIB: u64 = 1 << 30;
+        let dist = SizeDistribution::with_bounds(30, GIB / 4, 4 * GIB);
+        let forced = (-3.75f64).exp();
+        assert!((dist.forced_edge_probability() - forced).abs() < 1e-6);
+        let expected = GIB as f64 / 4.0 + GIB as f64 * (1.0 - forced);
+        assert!((dist.expected_size() / expected - 1.0).abs() < 1e-6);
+        assert_eq!(dist.percentile(1.0), 4 * GIB);
+        assert_eq!(dist.percentile(0.999), 4 * GIB);
+        let median = GIB / 4 + (GIB as f64 * std::f64::consts::LN_2) as u64;
+        assert!(dist.median().abs_diff(median) <= 2);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn matches_minmax_chunker() {
+        use crate::tests::rand_data;
+        use crate::{Chunker, Gear, MinMaxChunker};
+
+        let data = rand_data(4 * 1024 * 1024);
+        let (bits, min, max) = (11, 512, 4096);
+        let dist = SizeDistribution::with_bounds(bits, min as u64, max as u64);
+        let mut chunker = MinMaxChunker::new(Gear::new_with_chunk_bits(bits), min, max);
+        let mut sizes = Vec::new();
+        let mut rest = &data[..];
+        while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+            sizes.push(i as u64);
+            rest = &rest[i..];
+        }
+        let mean = sizes.iter().sum::<u64>() as f64 / sizes.len() as f64;
+        assert!((mean / dist.expected_size() - 1.0).abs() < 0.05);
+
+        let forced = sizes.iter().filter(|&&s| s == max as u64).count() as f64;
+        let expected = dist.forced_edge_probability() * sizes.len() as f64;
+        assert!((forced - expected).abs() < expected * 0.3 + 5.0);
+
+        sizes.sort_unstable();
+        let median = sizes[sizes.len() / 2] as f64;
+        assert!((median / dist.median() as f64 - 1.0).abs() < 0.1);
+    }
+}
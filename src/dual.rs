@@ -0,0 +1,161 @@
+use super::{Chunker, Engine};
+use std::cmp;
+
+/// Wrapper rolling two engines over the same bytes
+///
+/// Meant for migrations between engines: the `Engine` implementation rolls
+/// both engines byte by byte and returns both digests, and `split` produces
+/// the chunk maps of both engines from a single pass over the input.
+#[derive(Default)]
+pub struct DualEngine<A, B> {
+    first: A,
+    second: B,
+    position: u64,
+}
+
+/// Edges found by `DualEngine::split`
+///
+/// Offsets are the stream offsets of the first bytes after each chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DualEdges<A, B> {
+    /// Edges and digests of the first engine
+    pub first: Vec<(u64, A)>,
+    /// Edges and digests of the second engine
+    pub second: Vec<(u64, B)>,
+}
+
+impl<A, B> DualEngine<A, B> {
+    /// Create a new wrapper around two engines
+    pub fn new(first: A, second: B) -> Self {
+        DualEngine {
+            first,
+            second,
+            position: 0,
+        }
+    }
+
+    /// Return the first engine
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    /// Return the second engine
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    /// Return the number of bytes passed to `split` so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Unwrap both engines
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Chunker, B: Chunker> DualEngine<A, B> {
+    /// Find chunk edges of both engines in `buf`.
+    ///
+    /// Both engines keep their state between calls, so `buf` can be a part of
+    /// a longer stream.
+    pub fn split(&mut self, buf: &[u8]) -> DualEdges<A::Digest, B::Digest> {
+        let first = edges(&mut self.first, self.position, buf);
+        let second = edges(&mut self.second, self.position, buf);
+        self.position += buf.len() as u64;
+        DualEdges { first, second }
+    }
+}
+
+fn edges<C: Chunker>(chunker: &mut C, position: u64, mut buf: &[u8]) -> Vec<(u64, C::Digest)> {
+    let mut result = Vec::new();
+    let mut offset = position;
+    while let Some((i, digest)) = chunker.find_chunk_edge(buf) {
+        offset += i as u64;
+        result.push((offset, digest));
+        buf = &buf[i..];
+    }
+    result
+}
+
+impl<A: Engine, B: Engine> Engine for DualEngine<A, B> {
+    type Digest = (A::Digest, B::Digest);
+
+    #[inline(always)]
+    fn roll_byte(&mut self, byte: u8) {
+        self.first.roll_byte(byte);
+        self.second.roll_byte(byte);
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        self.first.roll(buf);
+        self.second.roll(buf);
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Self::Digest {
+        (self.first.digest(), self.second.digest())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        cmp::max(
+            self.first.bytes_until_warm(),
+            self.second.bytes_until_warm(),
+        )
+    }
+
+    fn reset(&mut self) {
+        self.first.reset();
+        self.second.reset();
+        self.position = 0;
+    }
+}
+
+#[cfg(all(test, feature = "bup", feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{Bup, Gear};
+
+    #[test]
+    fn digests_match_single_engines() {
+        let data = rand_data(1024);
+        let mut dual = DualEngine::new(Bup::new(), Gear::new());
+        let mut bup = Bup::new();
+        let mut gear = Gear::new();
+        for &b in &data {
+            dual.roll_byte(b);
+            bup.roll_byte(b);
+            gear.roll_byte(b);
+            assert_eq!(dual.digest(), (bup.digest(), gear.digest()));
+        }
+    }
+
+    #[test]
+    fn split_matches_single_engines() {
+        let data = rand_data(256 * 1024);
+        let mut dual = DualEngine::new(Bup::new_with_chunk_bits(10), Gear::new_with_chunk_bits(11));
+        let mut result = DualEdges {
+            first: Vec::new(),
+            second: Vec::new(),
+        };
+        for frame in data.chunks(5000) {
+            let edges = dual.split(frame);
+            result.first.extend(edges.first);
+            result.second.extend(edges.second);
+        }
+        assert_eq!(dual.position(), data.len() as u64);
+
+        assert!(result.first.len() > 100);
+        assert!(result.second.len() > 50);
+        assert_eq!(
+            result.first,
+            edges(&mut Bup::new_with_chunk_bits(10), 0, &data)
+        );
+        assert_eq!(
+            result.second,
+            edges(&mut Gear::new_with_chunk_bits(11), 0, &data)
+        );
+    }
+}
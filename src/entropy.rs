@@ -0,0 +1,138 @@
+use std::fmt;
+
+/// Fixed point units of a bit per byte
+const SCALE: f64 = 4096.0;
+
+/// Shannon entropy of the byte frequencies of some data, in bits per byte
+///
+/// Ranges from 0 for a single repeated byte value to 8 for uniformly
+/// distributed bytes. It is stored as fixed point, so chunks carrying it can
+/// still be compared for equality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entropy(u16);
+
+impl Entropy {
+    /// Above this many bits per byte, general purpose compressors rarely
+    /// save anything
+    pub const INCOMPRESSIBLE: f64 = 7.5;
+
+    /// Estimate the entropy of `data`
+    pub fn of(data: &[u8]) -> Self {
+        let mut histogram = ByteHistogram::new();
+        histogram.update(data);
+        histogram.entropy()
+    }
+
+    /// Return the entropy in bits per byte
+    pub fn bits_per_byte(self) -> f64 {
+        self.0 as f64 / SCALE
+    }
+
+    /// Return whether compressing the data is likely a waste of time
+    ///
+    /// Byte frequencies don't see repeated sequences in data with random
+    /// looking bytes, so this can be wrong for e.g. duplicated encrypted
+    /// blocks within one chunk.
+    pub fn is_incompressible(self) -> bool {
+        self.bits_per_byte() >= Self::INCOMPRESSIBLE
+    }
+}
+
+impl fmt::Display for Entropy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} bits/byte", self.bits_per_byte())
+    }
+}
+
+/// Byte frequencies accumulated over data fed in pieces
+#[derive(Clone)]
+pub struct ByteHistogram {
+    counts: [u64; 256],
+    total: u64,
+}
+
+impl Default for ByteHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteHistogram {
+    /// Create an empty histogram
+    pub fn new() -> Self {
+        ByteHistogram {
+            counts: [0; 256],
+            total: 0,
+        }
+    }
+
+    /// Count the bytes of `data`
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.counts[b as usize] += 1;
+        }
+        self.total += data.len() as u64;
+    }
+
+    /// Return the number of bytes counted
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Return the entropy of the bytes counted so far, 0 if there are none
+    pub fn entropy(&self) -> Entropy {
+        if self.total == 0 {
+            return Entropy(0);
+        }
+        let total = self.total as f64;
+        let bits: f64 = self
+            .counts
+            .iter()
+            .filter(|&&count| count > 0)
+            .map(|&count| {
+                let p = count as f64 / total;
+                -p * p.log2()
+            })
+            .sum();
+        Entropy((bits * SCALE).round().clamp(0.0, 8.0 * SCALE) as u16)
+    }
+
+    /// Forget the bytes counted so far
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    #[test]
+    fn entropy_bounds() {
+        assert_eq!(Entropy::of(&[]).bits_per_byte(), 0.0);
+        assert_eq!(Entropy::of(&[7; 1000]).bits_per_byte(), 0.0);
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(Entropy::of(&all).bits_per_byte(), 8.0);
+        assert_eq!(Entropy::of(&[0, 1, 0, 1]).bits_per_byte(), 1.0);
+
+        let random = Entropy::of(&rand_data(64 * 1024));
+        assert!(random.is_incompressible(), "{}", random);
+        let text = Entropy::of(include_bytes!("lib.rs"));
+        assert!(!text.is_incompressible(), "{}", text);
+        assert!(text < random);
+    }
+
+    #[test]
+    fn histogram_accumulates_pieces() {
+        let data = rand_data(10_000);
+        let mut histogram = ByteHistogram::new();
+        for piece in data.chunks(333) {
+            histogram.update(piece);
+        }
+        assert_eq!(histogram.total(), data.len() as u64);
+        assert_eq!(histogram.entropy(), Entropy::of(&data));
+        histogram.reset();
+        assert_eq!(histogram.total(), 0);
+    }
+}
@@ -0,0 +1,492 @@
+use super::condition::{EdgeCondition, MaskEngine};
+use super::gear::DEFAULT_TABLE;
+use super::normalized::NORMALIZATION_LEVEL;
+use super::{ChunkBits, Chunker, Engine};
+use std::cmp;
+use std::convert::TryFrom;
+use std::num::Wrapping;
+use std::sync::Arc;
+
+pub type Digest = u64;
+
+/// Default chunk size used by `fastcdc` (log2)
+pub const CHUNK_BITS: u32 = 13;
+
+/// The effective window size used by `fastcdc`, the same as `gear`'s
+pub const WINDOW_SIZE: usize = crate::gear::WINDOW_SIZE;
+
+/// Number of high digest bits the masks of `FastCdcCondition` spread over
+const MASK_SPAN: u32 = 48;
+
+/// Highest digest bit the masks use, leaving the top bit clear so that
+/// `FastCdc2020` can check masks shifted left by one
+const MASK_TOP: u32 = 62;
+
+/// Edge when the bits of a mask spread over the high digest bits are clear
+///
+/// This is the condition of the FastCDC paper, whose masks pad the matched
+/// bits with zeros so that they depend on more bytes of the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FastCdcCondition {
+    mask: u64,
+}
+
+impl FastCdcCondition {
+    /// Create a condition matching `bits` bits spread over the 48 bits below
+    /// the highest one
+    pub const fn with_bits(bits: u32) -> Self {
+        assert!(bits <= MASK_SPAN);
+        let mut mask = 0;
+        let mut i = 0;
+        while i < bits {
+            mask |= 1 << (MASK_TOP - i * MASK_SPAN / bits);
+            i += 1;
+        }
+        FastCdcCondition { mask }
+    }
+
+    /// Return the matched mask
+    pub const fn mask(&self) -> u64 {
+        self.mask
+    }
+}
+
+impl EdgeCondition<FastCdc> for FastCdcCondition {
+    #[inline(always)]
+    fn is_edge(&self, engine: &FastCdc) -> bool {
+        engine.digest.0 & self.mask == 0
+    }
+}
+
+/// FastCDC chunking engine
+///
+/// Rolls the Gear hash, sharing `gear`'s default table. Its own
+/// `find_chunk_edge` follows the FastCDC paper: the first `min_size` bytes
+/// of a chunk are skipped without hashing, up to the average size the mask
+/// has `level` more bits than `chunk_bits`, past it `level` fewer, and an
+/// edge is forced at `max_size` bytes. `find_chunk_edge_cond` and the other
+/// `Engine` methods only roll the hash, like any other engine.
+///
+/// Based on "FastCDC: a Fast and Efficient Content-Defined Chunking Approach
+/// for Data Deduplication" (Xia et al., USENIX ATC 2016).
+#[derive(Clone)]
+pub struct FastCdc {
+    digest: Wrapping<Digest>,
+    chunk_bits: u32,
+    strict: FastCdcCondition,
+    loose: FastCdcCondition,
+    /// Normalization level, which `strict` and `loose` are computed with
+    level: u32,
+    min_size: usize,
+    max_size: usize,
+    /// Bytes rolled over since the last reset, up to `WINDOW_SIZE`
+    filled: usize,
+    /// Number of bytes of the current chunk consumed by `find_chunk_edge`
+    len: usize,
+}
+
+impl Default for FastCdc {
+    fn default() -> Self {
+        Self::new_with_chunk_bits(CHUNK_BITS)
+    }
+}
+
+impl FastCdc {
+    /// Create new FastCDC engine with default chunking settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create new FastCDC engine with custom chunking settings
+    ///
+    /// `chunk_bits` is the log2 of the average chunk size. As in the paper,
+    /// chunks are between a quarter and eight times the average.
+    pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        let avg_size = 1usize << chunk_bits;
+        Self::with_sizes(
+            chunk_bits,
+            avg_size / 4,
+            avg_size.saturating_mul(8),
+            NORMALIZATION_LEVEL,
+        )
+    }
+
+    /// Create new FastCDC engine with custom size bounds and normalization
+    /// level
+    ///
+    /// Level 0 disables normalization, leaving only the size bounds.
+    pub fn with_sizes(chunk_bits: u32, min_size: usize, max_size: usize, level: u32) -> Self {
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        assert!(min_size <= max_size);
+        assert!(max_size > 0);
+        let (strict, loose) = Self::conditions(chunk_bits, level);
+        FastCdc {
+            digest: Wrapping(0),
+            chunk_bits,
+            strict,
+            loose,
+            level,
+            min_size,
+            max_size,
+            filled: 0,
+            len: 0,
+        }
+    }
+
+    /// Create new FastCDC engine with an average chunk size of `avg_size`
+    /// bytes
+    ///
+    /// Panics if `avg_size` is not a power of two.
+    pub fn new_with_avg_size(avg_size: u64) -> Self {
+        Self::new_with_chunk_bits(ChunkBits::expect(ChunkBits::from_avg_size(avg_size)))
+    }
+
+    /// Change the number of bits matched by the edge condition
+    ///
+    /// The size bounds and the normalization level are kept. The rolling
+    /// state is kept too, so this can be called between chunks or even in the
+    /// middle of one.
+    pub fn set_chunk_bits(&mut self, chunk_bits: u32) {
+        self.chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        let (strict, loose) = Self::conditions(self.chunk_bits, self.level);
+        self.strict = strict;
+        self.loose = loose;
+    }
+
+    /// Return the conditions before and after the average chunk size
+    fn conditions(chunk_bits: u32, level: u32) -> (FastCdcCondition, FastCdcCondition) {
+        (
+            Self::condition_for_bits(cmp::min(chunk_bits.saturating_add(level), MASK_SPAN)),
+            Self::condition_for_bits(chunk_bits.saturating_sub(level)),
+        )
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Return the average chunk size in bytes
+    pub fn avg_size(&self) -> u64 {
+        1 << self.chunk_bits
+    }
+
+    fn edge(&mut self, end: usize) -> Option<(usize, Digest)> {
+        let digest = self.digest.0;
+        self.reset();
+        Some((end, digest))
+    }
+
+    /// Find chunk edge using the FastCDC algorithm.
+    ///
+    /// The size bounds apply across calls, as the engine keeps track of the
+    /// bytes of the current chunk.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let skip = cmp::min(self.min_size.saturating_sub(self.len), buf.len());
+        self.len += skip;
+        if self.len >= self.max_size {
+            return self.edge(skip);
+        }
+        let avg_size = 1usize << self.chunk_bits;
+        for (i, &b) in buf.iter().enumerate().skip(skip) {
+            self.roll_byte(b);
+            self.len += 1;
+            let cond = if self.len <= avg_size {
+                self.strict
+            } else {
+                self.loose
+            };
+            if cond.is_edge(self) || self.len >= self.max_size {
+                return self.edge(i + 1);
+            }
+        }
+        None
+    }
+}
+
+impl Engine for FastCdc {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, b: u8) {
+        self.digest <<= 1;
+        self.digest += Wrapping(DEFAULT_TABLE[b as usize]);
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let filled = cmp::min(WINDOW_SIZE, self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, WINDOW_SIZE, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        self.digest.0
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(WINDOW_SIZE)
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        WINDOW_SIZE - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // As for `Gear`, the bytes of `left` end up shifted by `right_len`
+        let shifted = u32::try_from(right_len)
+            .ok()
+            .and_then(|len| left.digest.0.checked_shl(len))
+            .unwrap_or(0);
+        let filled = usize::try_from(right_len).unwrap_or(usize::MAX);
+        Some(FastCdc {
+            digest: Wrapping(shifted) + right.digest,
+            filled: cmp::min(WINDOW_SIZE, left.filled.saturating_add(filled)),
+            ..left.clone()
+        })
+    }
+
+    fn reset(&mut self) {
+        self.digest = Wrapping(0);
+        self.filled = 0;
+        self.len = 0;
+    }
+}
+
+impl Chunker for FastCdc {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        FastCdc::find_chunk_edge(self, buf)
+    }
+}
+
+impl MaskEngine for FastCdc {
+    type Condition = FastCdcCondition;
+
+    fn condition_for_bits(bits: u32) -> FastCdcCondition {
+        FastCdcCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
+/// FastCDC rolling two bytes per iteration, from the 2020 revision of the
+/// paper
+///
+/// The first byte of each pair is hashed with a table shifted left by one
+/// and checked against the mask shifted left by one, which saves a shift per
+/// byte. The edges and digests are the same as those of the wrapped
+/// `FastCdc`, only found faster.
+///
+/// Based on "The Design of Fast Content-Defined Chunking for Data
+/// Deduplication Based Storage Systems" (Xia et al., IEEE TPDS 2020).
+#[derive(Clone)]
+pub struct FastCdc2020 {
+    engine: FastCdc,
+    shifted_table: Arc<[Digest; 256]>,
+}
+
+impl Default for FastCdc2020 {
+    fn default() -> Self {
+        Self::new(FastCdc::new())
+    }
+}
+
+impl FastCdc2020 {
+    /// Find the edges of `engine` two bytes at a time
+    pub fn new(engine: FastCdc) -> Self {
+        let mut shifted_table = [0; 256];
+        for (shifted, &value) in shifted_table.iter_mut().zip(DEFAULT_TABLE.iter()) {
+            *shifted = value << 1;
+        }
+        FastCdc2020 {
+            engine,
+            shifted_table: Arc::new(shifted_table),
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &FastCdc {
+        &self.engine
+    }
+
+    /// Find chunk edge using the FastCDC algorithm.
+    ///
+    /// See `FastCdc::find_chunk_edge`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let engine = &mut self.engine;
+        let skip = cmp::min(engine.min_size.saturating_sub(engine.len), buf.len());
+        engine.len += skip;
+        if engine.len >= engine.max_size {
+            return engine.edge(skip);
+        }
+        let avg_size = 1usize << engine.chunk_bits;
+        let table = &DEFAULT_TABLE;
+        let shifted_table = &*self.shifted_table;
+        let mut digest = engine.digest.0;
+        let mut i = skip;
+        while i < buf.len() {
+            let (mask, limit) = if engine.len < avg_size {
+                (engine.strict.mask, cmp::min(avg_size, engine.max_size))
+            } else {
+                (engine.loose.mask, engine.max_size)
+            };
+            let end = i + cmp::min(buf.len() - i, limit - engine.len);
+            let start = i;
+            let shifted_mask = mask << 1;
+            let mut found = None;
+            while i + 2 <= end {
+                let previous = digest;
+                digest = (digest << 2).wrapping_add(shifted_table[buf[i] as usize]);
+                if digest & shifted_mask == 0 {
+                    // The shift discarded the top bit of the digest
+                    found = Some((i + 1, (previous << 1).wrapping_add(table[buf[i] as usize])));
+                    break;
+                }
+                digest = digest.wrapping_add(table[buf[i + 1] as usize]);
+                i += 2;
+                if digest & mask == 0 {
+                    found = Some((i, digest));
+                    break;
+                }
+            }
+            if found.is_none() && i < end {
+                digest = (digest << 1).wrapping_add(table[buf[i] as usize]);
+                i += 1;
+                if digest & mask == 0 {
+                    found = Some((i, digest));
+                }
+            }
+            let consumed = found.map_or(i, |(edge, _)| edge) - start;
+            engine.len += consumed;
+            engine.filled = cmp::min(WINDOW_SIZE, engine.filled + consumed);
+            if let Some((edge, digest)) = found {
+                engine.reset();
+                return Some((edge, digest));
+            }
+            engine.digest = Wrapping(digest);
+            if engine.len >= engine.max_size {
+                return engine.edge(i);
+            }
+        }
+        None
+    }
+}
+
+impl Chunker for FastCdc2020 {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        FastCdc2020::find_chunk_edge(self, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, chunk_sizes, rand_data};
+
+    #[test]
+    fn masks_spread_bits() {
+        assert_eq!(FastCdcCondition::with_bits(0).mask(), 0);
+        for bits in 1..=MASK_SPAN {
+            let mask = FastCdcCondition::with_bits(bits).mask();
+            assert_eq!(mask.count_ones(), bits);
+            assert_eq!(mask.leading_zeros(), 63 - MASK_TOP);
+            assert!(mask.trailing_zeros() > MASK_TOP - MASK_SPAN);
+        }
+    }
+
+    #[test]
+    fn sizes_within_bounds() {
+        let data = rand_data(4 * 1024 * 1024);
+        let mut chunker = FastCdc::new_with_chunk_bits(12);
+        let sizes = chunk_sizes(&mut chunker, &data);
+        let (min, max) = (chunker.min_size(), chunker.max_size());
+        assert!(sizes[..sizes.len() - 1]
+            .iter()
+            .all(|&s| (min..=max).contains(&s)));
+        let mean = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        assert!((mean - 4096.0).abs() / 4096.0 < 0.2, "{}", mean);
+    }
+
+    #[test]
+    fn incremental_matches_whole() {
+        let data = rand_data(512 * 1024);
+        let new = || FastCdc::new_with_chunk_bits(11);
+        let whole = chunk_edges(&mut new(), &data);
+        assert_eq!(chunk_edges_framed(&mut new(), &data, 307), whole);
+    }
+
+    #[test]
+    fn set_chunk_bits_recomputes_masks() {
+        let data = rand_data(512 * 1024);
+        let mut chunker = FastCdc::with_sizes(13, 100, 50_000, 2);
+        chunker.set_chunk_bits(11);
+        assert_eq!(chunker.avg_size(), 2048);
+        assert_eq!(
+            chunk_sizes(&mut chunker, &data),
+            chunk_sizes(&mut FastCdc::with_sizes(11, 100, 50_000, 2), &data)
+        );
+        assert_eq!(
+            chunk_sizes(&mut FastCdc::new_with_avg_size(2048), &data),
+            chunk_sizes(&mut FastCdc::new_with_chunk_bits(11), &data)
+        );
+    }
+
+    #[test]
+    fn forced_edges() {
+        let data = vec![0; 10_000];
+        let mut chunker = FastCdc::with_sizes(10, 100, 100, 2);
+        assert!(chunk_sizes(&mut chunker, &data).iter().all(|&s| s == 100));
+    }
+
+    fn edges<C>(chunker: &mut C, data: &[u8], frame: usize) -> Vec<(usize, Digest)>
+    where
+        C: Chunker<Digest = Digest>,
+    {
+        let mut result = Vec::new();
+        for (frame_i, frame_data) in data.chunks(frame).enumerate() {
+            let mut consumed = 0;
+            while let Some((i, digest)) = chunker.find_chunk_edge(&frame_data[consumed..]) {
+                consumed += i;
+                result.push((frame_i * frame + consumed, digest));
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn two_bytes_matches_original() {
+        let data = rand_data(1024 * 1024);
+        let params = [
+            (11, 512, 16384, 2),
+            (8, 0, 1 << 20, 0),
+            (12, 1, 5000, 3),
+            // The maximum is below the average
+            (12, 0, 100, 2),
+        ];
+        for &(bits, min, max, level) in &params {
+            let new = || FastCdc::with_sizes(bits, min, max, level);
+            let expected = edges(&mut new(), &data, data.len());
+            assert!(expected.len() > 50);
+            for &frame in &[data.len(), 307, 2, 1] {
+                let actual = edges(&mut FastCdc2020::new(new()), &data, frame);
+                assert!(actual == expected, "{} {}", bits, frame);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use super::Chunker;
+
+/// Chunker splitting data into chunks of a fixed size
+///
+/// Every chunk is `size` bytes long, so an insertion shifts all the edges
+/// after it. This is the baseline content-defined chunking is measured
+/// against, and can replace any other `Chunker`. The digest reported for an
+/// edge is the last byte of the chunk.
+#[derive(Debug, Clone)]
+pub struct FixedChunker {
+    size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl FixedChunker {
+    /// Create a new chunker cutting every `size` bytes
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+        FixedChunker { size, len: 0 }
+    }
+
+    /// Return the chunk size
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Start a new chunk at the next byte
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Chunker for FixedChunker {
+    type Digest = u8;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u8)> {
+        let missing = self.size - self.len;
+        if buf.len() < missing {
+            self.len += buf.len();
+            return None;
+        }
+        self.len = 0;
+        Some((missing, buf[missing - 1]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges_framed, rand_data};
+
+    #[test]
+    fn fixed_sizes() {
+        let data = rand_data(10_000);
+        let mut chunker = FixedChunker::new(1000);
+        let mut remaining = &data[..];
+        while let Some((i, digest)) = chunker.find_chunk_edge(remaining) {
+            assert_eq!((i, digest), (1000, remaining[999]));
+            remaining = &remaining[i..];
+        }
+        assert!(remaining.is_empty());
+
+        // Incrementally, edges stay every 1000 bytes
+        let mut chunker = FixedChunker::new(1000);
+        let edges = chunk_edges_framed(&mut chunker, &data, 307);
+        assert_eq!(edges, (1..=10).map(|i| i * 1000).collect::<Vec<_>>());
+
+        chunker.find_chunk_edge(&data[..10]);
+        chunker.reset();
+        assert_eq!(chunker.find_chunk_edge(&data).map(|e| e.0), Some(1000));
+    }
+}
@@ -0,0 +1,310 @@
+use super::{Algorithm, ChunkerParams, Variant};
+use std::io::{self, Read, Write};
+
+/// Magic bytes at the start of a framed stream
+pub const MAGIC: [u8; 4] = *b"RSRL";
+
+/// Version of the framing format written by `FrameWriter`
+pub const VERSION: u8 = 1;
+
+/// Size of the stream header
+pub const HEADER_SIZE: usize = 4 + 1 + 2 + 2 + 1 + 8 + 8;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Encode the stream header for `params`, chunked by `variant`
+///
+/// Panics if `variant` doesn't implement the algorithm of `params`.
+pub fn encode_header(params: &ChunkerParams, variant: Variant) -> [u8; HEADER_SIZE] {
+    assert!(params.chunk_bits <= u32::from(u8::MAX));
+    assert_eq!(variant.algorithm(), params.algorithm);
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4] = VERSION;
+    header[5..7].copy_from_slice(&params.algorithm.id().to_be_bytes());
+    header[7..9].copy_from_slice(&variant.version().to_be_bytes());
+    header[9] = params.chunk_bits as u8;
+    header[10..18].copy_from_slice(&params.min_size.to_be_bytes());
+    header[18..26].copy_from_slice(&params.max_size.to_be_bytes());
+    header
+}
+
+/// Decode a stream header, returning the parameters and the variant
+///
+/// Fails with `io::ErrorKind::InvalidData` if the parameters are invalid,
+/// see `ChunkerParams::validate`, so that they can be passed to the engine
+/// constructors.
+pub fn decode_header(header: &[u8; HEADER_SIZE]) -> io::Result<(ChunkerParams, Variant)> {
+    if header[..4] != MAGIC {
+        return Err(invalid_data("not a framed chunk stream"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid_data("unsupported framing version"));
+    }
+    let mut id = [0; 2];
+    id.copy_from_slice(&header[5..7]);
+    let algorithm = Algorithm::from_id(u16::from_be_bytes(id))
+        .ok_or_else(|| invalid_data("unknown chunking algorithm"))?;
+    let mut version = [0; 2];
+    version.copy_from_slice(&header[7..9]);
+    let variant = Variant::from_version(algorithm, u16::from_be_bytes(version))
+        .ok_or_else(|| invalid_data("unknown algorithm variant"))?;
+    let mut min_size = [0; 8];
+    min_size.copy_from_slice(&header[10..18]);
+    let mut max_size = [0; 8];
+    max_size.copy_from_slice(&header[18..26]);
+    let params = ChunkerParams {
+        algorithm,
+        chunk_bits: u32::from(header[9]),
+        min_size: u64::from_be_bytes(min_size),
+        max_size: u64::from_be_bytes(max_size),
+    };
+    params
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((params, variant))
+}
+
+/// Writer of framed chunk streams
+///
+/// The stream starts with a header advertising the `ChunkerParams` and
+/// `Variant` used by the sender, followed by one frame per chunk:
+///
+/// ```text
+/// header: "RSRL" | version: u8 | algorithm: u16 | variant: u16
+///         | chunk_bits: u8 | min_size: u64 | max_size: u64
+/// frame:  length: u64 | data
+/// ```
+///
+/// Integers are big-endian. A frame of length 0 ends the stream.
+pub struct FrameWriter<W: Write> {
+    inner: W,
+    params: ChunkerParams,
+}
+
+impl<W: Write> FrameWriter<W> {
+    /// Write the stream header to `inner`, with the variant of the
+    /// algorithm used by this crate's engines
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the parameters are
+    /// invalid, see `ChunkerParams::validate`.
+    pub fn new(mut inner: W, params: &ChunkerParams) -> io::Result<Self> {
+        params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let variant = Variant::latest(params.algorithm);
+        inner.write_all(&encode_header(params, variant))?;
+        Ok(FrameWriter {
+            inner,
+            params: *params,
+        })
+    }
+
+    /// Return the advertised parameters
+    pub fn params(&self) -> &ChunkerParams {
+        &self.params
+    }
+
+    /// Write one chunk frame
+    ///
+    /// Panics if `chunk` is empty, as an empty frame ends the stream. Fails
+    /// with `io::ErrorKind::InvalidInput` if it is longer than the advertised
+    /// `max_size`, which readers reject.
+    pub fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        assert!(!chunk.is_empty());
+        if chunk.len() as u64 > self.params.max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk exceeds maximum chunk size",
+            ));
+        }
+        self.inner.write_all(&(chunk.len() as u64).to_be_bytes())?;
+        self.inner.write_all(chunk)
+    }
+
+    /// Write the end of stream marker and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&0u64.to_be_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reader of framed chunk streams
+///
+/// Frames longer than the advertised `max_size` are rejected.
+pub struct FrameReader<R: Read> {
+    inner: R,
+    params: ChunkerParams,
+    variant: Variant,
+    done: bool,
+}
+
+impl<R: Read> FrameReader<R> {
+    /// Read the stream header from `inner`
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0; HEADER_SIZE];
+        inner.read_exact(&mut header)?;
+        let (params, variant) = decode_header(&header)?;
+        Ok(FrameReader {
+            inner,
+            params,
+            variant,
+            done: false,
+        })
+    }
+
+    /// Read the stream header from `inner`, failing unless the sender used
+    /// the `expected` parameters and the variant used by this crate's
+    /// engines
+    pub fn with_expected(inner: R, expected: &ChunkerParams) -> io::Result<Self> {
+        let reader = Self::new(inner)?;
+        if reader.params != *expected {
+            return Err(invalid_data("chunker parameters do not match"));
+        }
+        if reader.variant != Variant::latest(expected.algorithm) {
+            return Err(invalid_data("algorithm variant does not match"));
+        }
+        Ok(reader)
+    }
+
+    /// Return the parameters advertised by the sender
+    pub fn params(&self) -> &ChunkerParams {
+        &self.params
+    }
+
+    /// Return the algorithm variant advertised by the sender
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Read the next chunk, or `None` at the end of the stream
+    pub fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut len = [0; 8];
+        self.inner.read_exact(&mut len)?;
+        let len = u64::from_be_bytes(len);
+        if len == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        if len > self.params.max_size {
+            return Err(invalid_data("frame exceeds maximum chunk size"));
+        }
+        let mut chunk = Vec::new();
+        (&mut self.inner).take(len).read_to_end(&mut chunk)?;
+        if (chunk.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(Some(chunk))
+    }
+
+    /// Return the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.read_chunk().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn params() -> ChunkerParams {
+        ChunkerParams {
+            min_size: 64,
+            max_size: 4096,
+            ..ChunkerParams::new(Algorithm::Gear, 10)
+        }
+    }
+
+    fn framed(chunks: &[&[u8]]) -> Vec<u8> {
+        let mut writer = FrameWriter::new(Vec::new(), &params()).unwrap();
+        for chunk in chunks {
+            writer.write_chunk(chunk).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = rand_data(10000);
+        let chunks: Vec<&[u8]> = data.chunks(3000).collect();
+        let stream = framed(&chunks);
+
+        let reader = FrameReader::with_expected(&stream[..], &params()).unwrap();
+        assert_eq!(*reader.params(), params());
+        assert_eq!(reader.variant(), Variant::GearV1);
+        let decoded: Vec<Vec<u8>> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(decoded, chunks);
+    }
+
+    #[test]
+    fn rejects_mismatched_params() {
+        let stream = framed(&[b"abc"]);
+        let other = ChunkerParams::new(Algorithm::Bup, 10);
+        let err = FrameReader::with_expected(&stream[..], &other)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut corrupted = stream.clone();
+        corrupted[6] = 0xff;
+        let err = FrameReader::new(&corrupted[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Unknown variant of a known algorithm
+        let mut corrupted = stream.clone();
+        corrupted[8] = 0xff;
+        let err = FrameReader::new(&corrupted[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Chunk bits out of range, minimum above maximum
+        let mut corrupted = stream.clone();
+        corrupted[9] = 40;
+        let err = FrameReader::new(&corrupted[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut corrupted = stream.clone();
+        corrupted[10..18].copy_from_slice(&8192u64.to_be_bytes());
+        let err = FrameReader::new(&corrupted[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let inverted = ChunkerParams {
+            min_size: 8192,
+            ..params()
+        };
+        let err = FrameWriter::new(Vec::new(), &inverted).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let data = rand_data(5000);
+        let mut writer = FrameWriter::new(Vec::new(), &params()).unwrap();
+        let err = writer.write_chunk(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let mut stream = encode_header(&params(), Variant::GearV1).to_vec();
+        stream.extend_from_slice(&(data.len() as u64).to_be_bytes());
+        stream.extend_from_slice(&data);
+        let mut reader = FrameReader::new(&stream[..]).unwrap();
+        let err = reader.read_chunk().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let stream = framed(&[&data[..100]]);
+        let mut reader = FrameReader::new(&stream[..HEADER_SIZE + 50]).unwrap();
+        let err = reader.read_chunk().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
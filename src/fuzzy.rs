@@ -0,0 +1,240 @@
+use super::{Bup, Engine};
+use std::cmp;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Smallest block size
+pub const MIN_BLOCK_SIZE: u64 = 3;
+
+/// Maximum length of the first signature
+pub const SIGNATURE_LENGTH: usize = 64;
+
+const HASH_INIT: u32 = 0x2802_1967;
+const HASH_PRIME: u32 = 0x0100_0193;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Minimum length of a substring two signatures must share to be compared
+const MIN_COMMON: usize = 7;
+
+/// Context-triggered piecewise hash
+///
+/// This follows the structure of ssdeep hashes, `block_size:sig1:sig2`, with
+/// the Bup rolling sum (64 byte window) instead of ssdeep's 7 byte rolling
+/// hash picking the trigger points, so the hashes are not interchangeable
+/// with ssdeep's.
+///
+/// A piece ends wherever the rolling digest modulo the block size is
+/// `block_size - 1`, and contributes one base64 character derived from an
+/// FNV hash of the piece. `sig1` uses `block_size` and `sig2` twice that.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FuzzyHash {
+    block_size: u64,
+    sig1: String,
+    sig2: String,
+}
+
+impl FuzzyHash {
+    /// Compute the fuzzy hash of `data`
+    pub fn new(data: &[u8]) -> Self {
+        let mut block_size = MIN_BLOCK_SIZE;
+        while block_size * (SIGNATURE_LENGTH as u64) < data.len() as u64 {
+            block_size *= 2;
+        }
+        loop {
+            let (sig1, sig2) = signatures(data, block_size);
+            if block_size > MIN_BLOCK_SIZE && sig1.len() < SIGNATURE_LENGTH / 2 {
+                block_size /= 2;
+                continue;
+            }
+            return FuzzyHash {
+                block_size,
+                sig1,
+                sig2,
+            };
+        }
+    }
+
+    /// Return the block size
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Return the similarity of `self` and `other`, from 0 (no similarity)
+    /// to 100 (identical signatures)
+    ///
+    /// Only hashes whose block sizes are equal or differ by a factor of two
+    /// can be compared; other pairs score 0.
+    pub fn compare(&self, other: &FuzzyHash) -> u32 {
+        let (a, b) = (self, other);
+        if a.block_size == b.block_size {
+            cmp::max(score(&a.sig1, &b.sig1), score(&a.sig2, &b.sig2))
+        } else if b.block_size.checked_mul(2) == Some(a.block_size) {
+            score(&a.sig1, &b.sig2)
+        } else if a.block_size.checked_mul(2) == Some(b.block_size) {
+            score(&a.sig2, &b.sig1)
+        } else {
+            0
+        }
+    }
+}
+
+fn signatures(data: &[u8], block_size: u64) -> (String, String) {
+    let mut roll = Bup::default();
+    let (mut h1, mut h2) = (HASH_INIT, HASH_INIT);
+    let (mut sig1, mut sig2) = (String::new(), String::new());
+    for &b in data {
+        roll.roll_byte(b);
+        h1 = h1.wrapping_mul(HASH_PRIME) ^ u32::from(b);
+        h2 = h2.wrapping_mul(HASH_PRIME) ^ u32::from(b);
+        let digest = u64::from(roll.digest());
+        // The last character of each signature covers all remaining data
+        if digest % block_size == block_size - 1 && sig1.len() < SIGNATURE_LENGTH - 1 {
+            sig1.push(BASE64[h1 as usize % 64] as char);
+            h1 = HASH_INIT;
+        }
+        if digest % (block_size * 2) == block_size * 2 - 1 && sig2.len() < SIGNATURE_LENGTH / 2 - 1
+        {
+            sig2.push(BASE64[h2 as usize % 64] as char);
+            h2 = HASH_INIT;
+        }
+    }
+    if h1 != HASH_INIT {
+        sig1.push(BASE64[h1 as usize % 64] as char);
+    }
+    if h2 != HASH_INIT {
+        sig2.push(BASE64[h2 as usize % 64] as char);
+    }
+    (sig1, sig2)
+}
+
+/// Drop characters repeated more than three times in a row, which carry
+/// little information
+fn eliminate_sequences(sig: &str) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(sig.len());
+    for &c in sig.as_bytes() {
+        let n = result.len();
+        if n < 3 || result[n - 3..].iter().any(|&p| p != c) {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Levenshtein distance where a substitution costs as much as a deletion
+/// and an insertion
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + if ca == cb { 0 } else { 2 };
+            diagonal = row[j + 1];
+            row[j + 1] = cmp::min(substitute, cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn score(a: &str, b: &str) -> u32 {
+    let (a, b) = (eliminate_sequences(a), eliminate_sequences(b));
+    if a.len() < MIN_COMMON || b.len() < MIN_COMMON {
+        return if !a.is_empty() && a == b { 100 } else { 0 };
+    }
+    if !a
+        .windows(MIN_COMMON)
+        .any(|w| b.windows(MIN_COMMON).any(|v| v == w))
+    {
+        return 0;
+    }
+    let distance = edit_distance(&a, &b) * SIGNATURE_LENGTH / (a.len() + b.len());
+    100 - cmp::min(100, distance * 100 / SIGNATURE_LENGTH) as u32
+}
+
+impl fmt::Display for FuzzyHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.block_size, self.sig1, self.sig2)
+    }
+}
+
+/// Error returned when parsing an invalid `FuzzyHash`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError;
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid fuzzy hash")
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl FromStr for FuzzyHash {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let mut parts = s.splitn(3, ':');
+        let block_size: u64 = parts
+            .next()
+            .and_then(|b| b.parse().ok())
+            .ok_or(ParseError)?;
+        let sig1 = parts.next().ok_or(ParseError)?;
+        let sig2 = parts.next().ok_or(ParseError)?;
+        let valid = |sig: &str, max| sig.len() <= max && sig.bytes().all(|c| BASE64.contains(&c));
+        if block_size < MIN_BLOCK_SIZE
+            || !(block_size / MIN_BLOCK_SIZE).is_power_of_two()
+            || !block_size.is_multiple_of(MIN_BLOCK_SIZE)
+            || !valid(sig1, SIGNATURE_LENGTH)
+            || !valid(sig2, SIGNATURE_LENGTH / 2)
+        {
+            return Err(ParseError);
+        }
+        Ok(FuzzyHash {
+            block_size,
+            sig1: sig1.to_string(),
+            sig2: sig2.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    #[test]
+    fn similar_data_scores_high() {
+        let data = rand_data(256 * 1024);
+        let hash = FuzzyHash::new(&data);
+        assert!(hash.sig1.len() >= SIGNATURE_LENGTH / 2);
+        assert_eq!(hash.compare(&hash), 100);
+
+        let mut edited = data.clone();
+        edited[100_000..100_100].fill(0);
+        edited.splice(200_000..200_000, rand_data(3000));
+        let edited = FuzzyHash::new(&edited);
+        assert_ne!(hash, edited);
+        assert!(hash.compare(&edited) > 70, "{} {}", hash, edited);
+
+        let unrelated = FuzzyHash::new(&rand_data(512 * 1024)[256 * 1024..]);
+        assert_eq!(hash.compare(&unrelated), 0);
+    }
+
+    #[test]
+    fn parse_roundtrip() {
+        let hash = FuzzyHash::new(&rand_data(10000));
+        let parsed: FuzzyHash = hash.to_string().parse().unwrap();
+        assert_eq!(parsed, hash);
+        assert_eq!("5:abc:de".parse::<FuzzyHash>(), Err(ParseError));
+        assert_eq!("6:ab!:de".parse::<FuzzyHash>(), Err(ParseError));
+        assert_eq!("6:abc".parse::<FuzzyHash>(), Err(ParseError));
+
+        // The largest block sizes compare without overflowing
+        let largest = format!("{}:abc:de", 3u64 << 62);
+        let largest: FuzzyHash = largest.parse().unwrap();
+        let other: FuzzyHash = format!("{}:abc:de", 3u64 << 61).parse().unwrap();
+        assert_eq!(largest.compare(&largest), 100);
+        assert_eq!(largest.compare(&other), other.compare(&largest));
+    }
+}
@@ -0,0 +1,348 @@
+use super::{ChunkHash, ChunkIndex};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::vec;
+
+/// Default number of hashes `ExternalSorter` keeps in memory (32 MiB)
+pub const DEFAULT_RUN_SIZE: usize = 1 << 20;
+
+static NEXT_SORTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Sorter of chunk hashes using bounded memory
+///
+/// Hashes are collected in memory until `run_size` of them are pending,
+/// which are then sorted and spilled to a run file in the temporary
+/// directory. `finish` merges the runs into one sorted stream without
+/// duplicates. Run files are deleted once the stream is dropped.
+pub struct ExternalSorter {
+    dir: PathBuf,
+    id: usize,
+    run_size: usize,
+    pending: Vec<ChunkHash>,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    /// Create a sorter spilling runs of `run_size` hashes to `dir`
+    pub fn new<P: AsRef<Path>>(dir: P, run_size: usize) -> Self {
+        assert!(run_size > 0);
+        ExternalSorter {
+            dir: dir.as_ref().to_path_buf(),
+            id: NEXT_SORTER.fetch_add(1, Ordering::Relaxed),
+            run_size,
+            pending: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Add a hash
+    pub fn push(&mut self, hash: ChunkHash) -> io::Result<()> {
+        self.pending.push(hash);
+        if self.pending.len() >= self.run_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sort_pending(&mut self) {
+        self.pending.sort_unstable();
+        self.pending.dedup();
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.sort_pending();
+        let path = self.dir.join(format!(
+            "rollsum-sort-{}-{}-{}.run",
+            std::process::id(),
+            self.id,
+            self.runs.len()
+        ));
+        // Pushed before writing so that a partial run is removed as well
+        self.runs.push(path.clone());
+        let mut out = BufWriter::new(fs::File::create(&path)?);
+        for hash in &self.pending {
+            out.write_all(hash)?;
+        }
+        out.flush()?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Return all pushed hashes, sorted and without duplicates
+    pub fn finish(mut self) -> io::Result<SortedHashes> {
+        self.sort_pending();
+        let runs = std::mem::take(&mut self.runs);
+        let mut sorted = SortedHashes {
+            sources: Vec::new(),
+            heap: BinaryHeap::new(),
+            last: None,
+            runs,
+        };
+        for path in &sorted.runs {
+            let file = BufReader::new(fs::File::open(path)?);
+            sorted.sources.push(Source::File(file));
+        }
+        let memory = std::mem::take(&mut self.pending);
+        sorted.sources.push(Source::Memory(memory.into_iter()));
+        for i in 0..sorted.sources.len() {
+            if let Some(hash) = sorted.sources[i].next()? {
+                sorted.heap.push(Reverse((hash, i)));
+            }
+        }
+        Ok(sorted)
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+enum Source {
+    Memory(vec::IntoIter<ChunkHash>),
+    File(BufReader<fs::File>),
+}
+
+impl Source {
+    fn next(&mut self) -> io::Result<Option<ChunkHash>> {
+        match self {
+            Source::Memory(iter) => Ok(iter.next()),
+            Source::File(file) => {
+                let mut hash = [0; 32];
+                match file.read_exact(&mut hash) {
+                    Ok(()) => Ok(Some(hash)),
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Sorted stream of distinct hashes returned by `ExternalSorter::finish`
+pub struct SortedHashes {
+    sources: Vec<Source>,
+    /// Smallest unread hash of every source which isn't exhausted
+    heap: BinaryHeap<Reverse<(ChunkHash, usize)>>,
+    last: Option<ChunkHash>,
+    runs: Vec<PathBuf>,
+}
+
+impl Iterator for SortedHashes {
+    type Item = io::Result<ChunkHash>;
+
+    fn next(&mut self) -> Option<io::Result<ChunkHash>> {
+        while let Some(Reverse((hash, i))) = self.heap.pop() {
+            match self.sources[i].next() {
+                Ok(Some(next)) => self.heap.push(Reverse((next, i))),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+            if self.last != Some(hash) {
+                self.last = Some(hash);
+                return Some(Ok(hash));
+            }
+        }
+        None
+    }
+}
+
+impl Drop for SortedHashes {
+    fn drop(&mut self) {
+        self.sources.clear();
+        for path in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Return the distinct chunk hashes referenced by `indexes`, sorted
+///
+/// See `ExternalSorter` for `dir` and `run_size`.
+pub fn referenced<'a, I>(indexes: I, dir: &Path, run_size: usize) -> io::Result<SortedHashes>
+where
+    I: IntoIterator<Item = ChunkIndex<'a>>,
+{
+    let hashes = indexes
+        .into_iter()
+        .flat_map(|index| index.iter().map(|entry| entry.hash));
+    sort(hashes, dir, run_size)
+}
+
+fn sort<I>(hashes: I, dir: &Path, run_size: usize) -> io::Result<SortedHashes>
+where
+    I: IntoIterator<Item = ChunkHash>,
+{
+    let mut sorter = ExternalSorter::new(dir, run_size);
+    for hash in hashes {
+        sorter.push(hash)?;
+    }
+    sorter.finish()
+}
+
+/// Counts reported by `unreferenced`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Number of distinct referenced chunks
+    pub referenced: u64,
+    /// Number of distinct stored chunks
+    pub stored: u64,
+    /// Number of stored chunks which aren't referenced
+    pub unreferenced: u64,
+}
+
+/// Find the chunks of a store which no snapshot references
+///
+/// `referenced` and `stored` list chunk hashes in any order and possibly
+/// with duplicates, e.g. the entries of all snapshot indexes and a listing
+/// of the store. Both are sorted with `ExternalSorter`, so memory use stays
+/// bounded by `run_size` however large the repository is. `f` is called
+/// with every stored hash which isn't referenced, in sorted order, and these
+/// are the chunks eligible for deletion.
+pub fn unreferenced<R, S, F>(
+    referenced: R,
+    stored: S,
+    dir: &Path,
+    run_size: usize,
+    mut f: F,
+) -> io::Result<GcStats>
+where
+    R: IntoIterator<Item = ChunkHash>,
+    S: IntoIterator<Item = ChunkHash>,
+    F: FnMut(ChunkHash) -> io::Result<()>,
+{
+    let mut referenced = sort(referenced, dir, run_size)?;
+    let stored = sort(stored, dir, run_size)?;
+
+    let mut stats = GcStats::default();
+    let mut next_referenced = referenced.next().transpose()?;
+    if next_referenced.is_some() {
+        stats.referenced += 1;
+    }
+    for hash in stored {
+        let hash = hash?;
+        stats.stored += 1;
+        while next_referenced.is_some_and(|r| r < hash) {
+            next_referenced = referenced.next().transpose()?;
+            if next_referenced.is_some() {
+                stats.referenced += 1;
+            }
+        }
+        if next_referenced != Some(hash) {
+            stats.unreferenced += 1;
+            f(hash)?;
+        }
+    }
+    for hash in referenced {
+        hash?;
+        stats.referenced += 1;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{Algorithm, ChunkerParams, IndexEntry, IndexWriter};
+    use std::collections::BTreeSet;
+
+    fn hashes(seed: usize, count: usize) -> Vec<ChunkHash> {
+        rand_data((seed + count) * 32)[seed * 32..]
+            .chunks(32)
+            .map(|c| {
+                let mut hash = [0; 32];
+                hash.copy_from_slice(c);
+                hash
+            })
+            .collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rollsum-gc-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn sorter_spills_and_merges() {
+        let dir = temp_dir("sort");
+        let mut input = hashes(0, 1000);
+        input.extend_from_slice(&hashes(500, 1000));
+        let mut sorter = ExternalSorter::new(&dir, 64);
+        for &hash in &input {
+            sorter.push(hash).unwrap();
+        }
+        assert!(fs::read_dir(&dir).unwrap().count() > 20);
+        let sorted: Vec<_> = sorter.finish().unwrap().map(Result::unwrap).collect();
+        let expected: Vec<_> = input
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(sorted, expected);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0, "runs are removed");
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn finds_unreferenced_chunks() {
+        let dir = temp_dir("unreferenced");
+        let snapshot = |hashes: &[ChunkHash]| {
+            let params = ChunkerParams::new(Algorithm::Gear, 13);
+            let mut writer = IndexWriter::new(Vec::new(), &params).unwrap();
+            for (i, &hash) in hashes.iter().enumerate() {
+                let entry = IndexEntry {
+                    offset: i as u64 * 10,
+                    len: 10,
+                    weak_digest: 0,
+                    hash,
+                };
+                writer.push(&entry).unwrap();
+            }
+            writer.finish().unwrap()
+        };
+        // Stored chunks 0..1500, snapshots reference 0..400 and 300..1000
+        let stored = hashes(0, 1500);
+        let snapshots = [snapshot(&stored[..400]), snapshot(&stored[300..1000])];
+        let indexes = snapshots.iter().map(|s| ChunkIndex::parse(s).unwrap());
+
+        let referenced: Vec<_> = referenced(indexes, &dir, 100)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(referenced.len(), 1000);
+
+        let mut garbage = Vec::new();
+        let stats = unreferenced(
+            referenced,
+            stored.iter().chain(&stored[1400..]).copied(),
+            &dir,
+            128,
+            |hash| {
+                garbage.push(hash);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            stats,
+            GcStats {
+                referenced: 1000,
+                stored: 1500,
+                unreferenced: 500,
+            }
+        );
+        let mut expected = stored[1000..].to_vec();
+        expected.sort_unstable();
+        assert_eq!(garbage, expected);
+        fs::remove_dir(&dir).unwrap();
+    }
+}
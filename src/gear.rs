@@ -1,7 +1,13 @@
-use super::Engine;
+use super::condition::{MaskEngine, PrefixZeroCondition};
+use super::{ChunkBits, Chunker, Engine};
+use std::cmp;
+use std::convert::TryFrom;
 use std::default::Default;
+use std::hash::Hasher;
 use std::mem;
 use std::num::Wrapping;
+use std::ptr;
+use std::sync::Arc;
 
 pub type Digest = u64;
 
@@ -14,9 +20,14 @@ pub const CHUNK_BITS: u32 = 13;
 /// The effective window size used by `gear`
 pub const WINDOW_SIZE: usize = mem::size_of::<Digest>() * 8;
 
+#[derive(Clone)]
 pub struct Gear {
     digest: Wrapping<Digest>,
     chunk_bits: u32,
+    /// Custom table of random values, `None` for `DEFAULT_TABLE`
+    table: Option<Arc<[Digest; 256]>>,
+    /// Bytes rolled over since the last reset, up to `WINDOW_SIZE`
+    filled: usize,
 }
 
 impl Default for Gear {
@@ -24,23 +35,64 @@ impl Default for Gear {
         Gear {
             digest: Wrapping(0),
             chunk_bits: CHUNK_BITS,
+            table: None,
+            filled: 0,
         }
     }
 }
 
+/// Table of random values of engines without a custom table
+pub(crate) static DEFAULT_TABLE: [Digest; 256] = G;
+
+// The table has no known generator: 20 of its entries have their top 16 bits
+// clear, which a uniform generator practically never produces. It can't be
+// replaced by one generated at startup from a seed without moving every
+// chunk boundary, so it stays embedded.
 include!("_gear_rand.rs");
 
+/// Return a table of random values for `Gear::with_table` derived from `seed`
+///
+/// The values are the outputs of splitmix64 seeded with `seed`, so other
+/// implementations can reproduce the table. Tables from the seeds chosen per
+/// tenant keep their chunk sizes from telling anything about each other's
+/// data, as long as the seeds stay secret.
+pub fn table_from_seed(seed: u64) -> [Digest; 256] {
+    let mut state = seed;
+    let mut table = [0; 256];
+    for value in table.iter_mut() {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *value = z ^ (z >> 31);
+    }
+    table
+}
+
+/// Return the digest of `buf` rolled over by a new engine using `table`
+fn digest_with(table: &[Digest; 256], buf: &[u8]) -> Digest {
+    let last_window = buf.windows(WINDOW_SIZE).next_back().unwrap_or(buf);
+    last_window.iter().fold(0, |digest: Digest, &b| {
+        (digest << 1).wrapping_add(table[b as usize])
+    })
+}
+
 impl Engine for Gear {
     type Digest = Digest;
 
     #[inline(always)]
     fn roll_byte(&mut self, b: u8) {
         self.digest <<= 1;
-        self.digest += Wrapping(G[b as usize]);
+        self.digest += Wrapping(self.table()[b as usize]);
+        if self.filled < WINDOW_SIZE {
+            self.filled += 1;
+        }
     }
 
     fn roll(&mut self, buf: &[u8]) {
+        let filled = cmp::min(WINDOW_SIZE, self.filled.saturating_add(buf.len()));
         crate::roll_windowed(self, WINDOW_SIZE, buf);
+        self.filled = filled;
     }
 
     #[inline(always)]
@@ -48,12 +100,73 @@ impl Engine for Gear {
         self.digest.0
     }
 
+    fn digest_of(buf: &[u8]) -> Digest {
+        digest_with(&DEFAULT_TABLE, buf)
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(WINDOW_SIZE)
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        WINDOW_SIZE - self.filled
+    }
+
     #[inline]
-    fn reset(&mut self) {
-        *self = Gear {
-            chunk_bits: self.chunk_bits,
-            ..Default::default()
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Every byte shifts the digest left by one, so the bytes of `left`
+        // end up shifted by `right_len`
+        if !ptr::eq(left.table(), right.table()) && left.table() != right.table() {
+            return None;
         }
+        let shifted = u32::try_from(right_len)
+            .ok()
+            .and_then(|len| left.digest.0.checked_shl(len))
+            .unwrap_or(0);
+        let filled = usize::try_from(right_len).unwrap_or(usize::MAX);
+        Some(Gear {
+            digest: Wrapping(shifted) + right.digest,
+            chunk_bits: left.chunk_bits,
+            table: left.table.clone(),
+            filled: cmp::min(WINDOW_SIZE, left.filled.saturating_add(filled)),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.digest = Wrapping(0);
+        self.filled = 0;
+    }
+}
+
+impl Chunker for Gear {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        Gear::find_chunk_edge(self, buf)
+    }
+}
+
+/// `finish` returns the rolling digest of the bytes written so far, so only
+/// the last window of written bytes contributes to the hash.
+impl Hasher for Gear {
+    fn finish(&self) -> u64 {
+        self.digest()
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.roll(bytes);
+    }
+}
+
+impl MaskEngine for Gear {
+    type Condition = PrefixZeroCondition;
+
+    fn condition_for_bits(bits: u32) -> PrefixZeroCondition {
+        PrefixZeroCondition::new(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
     }
 }
 
@@ -68,20 +181,83 @@ impl Gear {
     /// `chunk_bits` is number of bits that need to match in
     /// the edge condition. `CHUNK_BITS` constant is the default.
     pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
-        assert!(chunk_bits < 32);
+        let chunk_bits = ChunkBits::expect(ChunkBits::new(chunk_bits));
         Gear {
             chunk_bits,
             ..Default::default()
         }
     }
 
+    /// Return a builder of engines and chunkers, validating their settings
+    pub fn builder() -> crate::builder::GearBuilder {
+        crate::builder::GearBuilder::new()
+    }
+
+    /// Create new Gear engine using a custom table of random values instead
+    /// of the default one
+    ///
+    /// Chunk edges depend on the table, so a secret table keeps chunk sizes
+    /// from leaking information about the data. `Engine::digest_of` always
+    /// uses the default table, use `table_digest_of` for digests with this
+    /// one.
+    pub fn with_table(chunk_bits: u32, table: [Digest; 256]) -> Self {
+        Gear {
+            table: Some(Arc::new(table)),
+            ..Self::new_with_chunk_bits(chunk_bits)
+        }
+    }
+
+    /// Create new Gear engine using the table derived from `seed`, see
+    /// `table_from_seed`
+    pub fn with_seed(chunk_bits: u32, seed: u64) -> Self {
+        Self::with_table(chunk_bits, table_from_seed(seed))
+    }
+
+    /// Return the table of random values
+    #[inline(always)]
+    pub fn table(&self) -> &[Digest; 256] {
+        self.table.as_deref().unwrap_or(&DEFAULT_TABLE)
+    }
+
+    /// Return the digest of `buf` rolled over by a new engine using the
+    /// table of `self`
+    ///
+    /// Same as `Engine::digest_of` for engines with the default table.
+    pub fn table_digest_of(&self, buf: &[u8]) -> Digest {
+        digest_with(self.table(), buf)
+    }
+
+    /// Create new Gear engine with an average chunk size of `avg_size` bytes
+    ///
+    /// Panics if `avg_size` is not a power of two.
+    pub fn new_with_avg_size(avg_size: u64) -> Self {
+        Self::new_with_chunk_bits(ChunkBits::expect(ChunkBits::from_avg_size(avg_size)))
+    }
+
+    /// Change the number of bits matched by the edge condition
+    ///
+    /// The rolling state is kept, so this can be called between chunks or
+    /// even in the middle of one.
+    pub fn set_chunk_bits(&mut self, chunk_bits: u32) {
+        self.chunk_bits = ChunkBits::expect(ChunkBits::new(chunk_bits));
+    }
+
+    /// Return the average chunk size in bytes
+    pub fn avg_size(&self) -> u64 {
+        1 << self.chunk_bits
+    }
+
     /// Find chunk edge using Gear defaults.
     ///
     /// See `Engine::find_chunk_edge_cond`.
     pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
-        const DIGEST_SIZE: usize = mem::size_of::<Digest>() * 8;
-        let shift = DIGEST_SIZE as u32 - self.chunk_bits;
-        self.find_chunk_edge_cond(buf, |e: &Gear| (e.digest() >> shift) == 0)
+        let cond = self.edge_condition();
+        self.find_chunk_edge_with(buf, &cond)
+    }
+
+    /// Return the edge condition used by `find_chunk_edge`
+    pub fn edge_condition(&self) -> PrefixZeroCondition {
+        Self::condition_for_bits(self.chunk_bits)
     }
 }
 
@@ -113,6 +289,48 @@ mod tests {
         panic!("matching digest not found");
     }
 
+    #[test]
+    fn avg_size() {
+        let gear = Gear::new_with_avg_size(8192);
+        assert_eq!(gear.chunk_bits, 13);
+        assert_eq!(gear.avg_size(), 8192);
+        assert!(std::panic::catch_unwind(|| Gear::new_with_avg_size(8000)).is_err());
+    }
+
+    #[test]
+    fn set_chunk_bits() {
+        let data = rand_data(256 * 1024);
+        let mut gear = Gear::new();
+        gear.roll(&data[..1000]);
+        gear.set_chunk_bits(10);
+        let mut fresh = Gear::new_with_chunk_bits(10);
+        fresh.roll(&data[..1000]);
+        assert_eq!(
+            gear.find_chunk_edge(&data[1000..]),
+            fresh.find_chunk_edge(&data[1000..])
+        );
+    }
+
+    #[test]
+    fn hasher_matches_digest() {
+        use std::hash::Hash;
+
+        let data = rand_data(1024);
+        let mut gear = Gear::new();
+        gear.roll(&data);
+
+        let mut hasher = Gear::new();
+        hasher.write(&data[..100]);
+        hasher.write(&data[100..]);
+        assert_eq!(hasher.finish(), gear.digest());
+
+        let mut hasher = Gear::new();
+        0x0102u16.hash(&mut hasher);
+        let mut gear = Gear::new();
+        gear.roll(&0x0102u16.to_ne_bytes());
+        assert_eq!(hasher.finish(), gear.digest());
+    }
+
     #[test]
     fn edge_expected_size() {
         let data = rand_data(2 * 1024 * 1024);
@@ -132,4 +350,37 @@ mod tests {
             assert!(dbg!((average - expected_average).abs() / expected_average) < 0.1)
         }
     }
+
+    #[test]
+    fn seeded_tables() {
+        // The first output of splitmix64 seeded with 0
+        assert_eq!(table_from_seed(0)[0], 0xe220_a839_7b1d_cdaf);
+        assert_eq!(table_from_seed(42), table_from_seed(42));
+        assert_ne!(table_from_seed(42), table_from_seed(43));
+
+        let data = rand_data(256 * 1024);
+        let edges = |mut gear: Gear| {
+            let mut edges = Vec::new();
+            let mut remaining = &data[..];
+            while let Some((i, _)) = gear.find_chunk_edge(remaining) {
+                edges.push(i);
+                remaining = &remaining[i..];
+            }
+            edges
+        };
+        let seeded = edges(Gear::with_seed(10, 42));
+        assert_eq!(seeded, edges(Gear::with_table(10, table_from_seed(42))));
+        assert_ne!(seeded, edges(Gear::with_seed(10, 43)));
+        assert_ne!(seeded, edges(Gear::new_with_chunk_bits(10)));
+
+        for gear in &[Gear::new(), Gear::with_seed(10, 42)] {
+            let mut rolled = gear.clone();
+            rolled.roll(&data[..1000]);
+            assert_eq!(gear.table_digest_of(&data[..1000]), rolled.digest());
+        }
+        assert_ne!(
+            Gear::with_seed(10, 42).table_digest_of(&data[..1000]),
+            Gear::digest_of(&data[..1000])
+        );
+    }
 }
@@ -0,0 +1,130 @@
+use super::condition::{EdgeCondition, MaskEngine};
+use super::{Chunker, Engine};
+use std::collections::VecDeque;
+
+/// Wrapper recording the last digests of an engine
+///
+/// After every rolled byte, the stream offset of the next byte and the
+/// digest are recorded, keeping the last `capacity` of them. The history is
+/// kept when the engine is reset at an edge, so it can be inspected after a
+/// boundary, e.g. to look for near misses of the edge condition. Rolling is
+/// byte by byte, so only wrap engines while investigating.
+pub struct DigestHistory<E: Engine> {
+    inner: E,
+    capacity: usize,
+    entries: VecDeque<(u64, E::Digest)>,
+    position: u64,
+}
+
+impl<E: Engine> DigestHistory<E> {
+    /// Record the last `capacity` digests of `inner`
+    pub fn new(inner: E, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        DigestHistory {
+            inner,
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            position: 0,
+        }
+    }
+
+    /// Return the recorded offsets and digests, oldest first
+    pub fn history(&self) -> &VecDeque<(u64, E::Digest)> {
+        &self.entries
+    }
+
+    /// Forget the recorded digests
+    pub fn clear_history(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Return the number of bytes rolled over so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Return the wrapped engine
+    pub fn inner(&self) -> &E {
+        &self.inner
+    }
+
+    /// Unwrap the engine
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+impl<E: Engine> Engine for DigestHistory<E> {
+    type Digest = E::Digest;
+
+    fn roll_byte(&mut self, byte: u8) {
+        self.inner.roll_byte(byte);
+        self.position += 1;
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((self.position, self.inner.digest()));
+    }
+
+    fn digest(&self) -> E::Digest {
+        self.inner.digest()
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        self.inner.window_size()
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.inner.bytes_until_warm()
+    }
+
+    /// Reset the engine, keeping the history and position
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+}
+
+impl<E: MaskEngine> Chunker for DigestHistory<E> {
+    type Digest = E::Digest;
+
+    /// Find the end of the chunk using the default edge condition of the
+    /// wrapped engine
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        let cond = E::condition_for_bits(self.inner.chunk_bits());
+        self.find_chunk_edge_cond(buf, |h: &Self| cond.is_edge(&h.inner))
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    #[test]
+    fn records_digests_before_edges() {
+        let data = rand_data(64 * 1024);
+        let mut gear = Gear::new_with_chunk_bits(10);
+        let mut history = DigestHistory::new(Gear::new_with_chunk_bits(10), 16);
+        let mut offset = 0;
+        while let Some((i, digest)) = gear.find_chunk_edge(&data[offset..]) {
+            assert_eq!(history.find_chunk_edge(&data[offset..]), Some((i, digest)));
+            offset += i;
+            assert_eq!(history.position(), offset as u64);
+            assert_eq!(history.history().back(), Some(&(offset as u64, digest)));
+
+            // Digests recorded within the chunk are those of its prefixes
+            let start = offset - i;
+            for &(at, recorded) in history.history() {
+                if at > start as u64 {
+                    assert_eq!(recorded, Gear::digest_of(&data[start..at as usize]));
+                }
+            }
+        }
+        assert!(offset > 0);
+        assert_eq!(history.find_chunk_edge(&data[offset..]), None);
+        assert_eq!(history.history().len(), 16);
+        history.clear_history();
+        assert!(history.history().is_empty());
+    }
+}
@@ -0,0 +1,481 @@
+use super::index::ChunkIndex;
+use super::{for_each_chunk, AnyChunker, ChunkHash};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest range fetched with one request when coalescing missing chunks
+pub const MAX_REQUEST: u64 = 8 << 20;
+
+/// Default timeout of `HttpClient` for connecting and for each read or write
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unsupported(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, msg)
+}
+
+/// Source of byte ranges of the remote version of a file
+pub trait RangeSource {
+    /// Return the `len` bytes at `offset`
+    fn fetch(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+}
+
+/// Minimal HTTP/1.1 client for one resource
+///
+/// Only plain `http://` URLs are supported; put a TLS terminating proxy in
+/// front of remote servers which are not trusted not to be tampered with.
+/// Every fetched chunk is checked against its strong hash anyway.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    /// `host[:port]` as given in the URL
+    authority: String,
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpClient {
+    /// Create a client fetching `url`
+    pub fn new(url: &str) -> io::Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| unsupported("only http:// URLs are supported"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+                (host, port)
+            }
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "URL has no host",
+            ));
+        }
+        Ok(HttpClient {
+            authority: authority.to_string(),
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            path: path.to_string(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Set the timeout for connecting and for each read or write, instead
+    /// of `DEFAULT_TIMEOUT`
+    ///
+    /// Panics if `timeout` is zero.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        assert!(!timeout.is_zero());
+        self.timeout = timeout;
+        self
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no addresses")))
+    }
+
+    /// Fetch the whole resource, e.g. the chunk index
+    pub fn get(&self) -> io::Result<Vec<u8>> {
+        self.request(None)
+    }
+
+    fn request(&self, range: Option<(u64, u64)>) -> io::Result<Vec<u8>> {
+        let last = match range {
+            Some((offset, len)) => {
+                let last = len
+                    .checked_sub(1)
+                    .and_then(|len| offset.checked_add(len))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "invalid byte range")
+                    })?;
+                Some((offset, last))
+            }
+            None => None,
+        };
+        let mut stream = self.connect()?;
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            self.path, self.authority
+        );
+        if let Some((offset, last)) = last {
+            request += &format!("Range: bytes={}-{}\r\n", offset, last);
+        }
+        request += "\r\n";
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = BufReader::new(stream);
+        let mut line = String::new();
+        response.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or_else(|| invalid_data("malformed HTTP status line"))?;
+        let mut content_length = None;
+        loop {
+            line.clear();
+            if response.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let Some((name, value)) = header.split_once(':') else {
+                return Err(invalid_data("malformed HTTP header"));
+            };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                let len = value
+                    .parse::<u64>()
+                    .map_err(|_| invalid_data("malformed Content-Length"))?;
+                content_length = Some(len);
+            } else if name.eq_ignore_ascii_case("transfer-encoding")
+                && !value.eq_ignore_ascii_case("identity")
+            {
+                return Err(unsupported("transfer encodings are not supported"));
+            }
+        }
+        match (status, range) {
+            (206, Some(_)) | (200, None) => {}
+            (200, Some(_)) => return Err(unsupported("server ignored the range request")),
+            (404, _) => return Err(io::ErrorKind::NotFound.into()),
+            _ => return Err(io::Error::other(format!("HTTP status {}", status))),
+        }
+
+        let mut body = Vec::new();
+        match content_length {
+            Some(len) => {
+                response.take(len).read_to_end(&mut body)?;
+                if (body.len() as u64) < len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+            None => {
+                response.read_to_end(&mut body)?;
+            }
+        }
+        if let Some((_, len)) = range {
+            if body.len() as u64 != len {
+                return Err(invalid_data("server returned a different range"));
+            }
+        }
+        Ok(body)
+    }
+}
+
+impl RangeSource for HttpClient {
+    fn fetch(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.request(Some((offset, len)))
+    }
+}
+
+/// Outcome of `reconstruct`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaStats {
+    /// Number of chunks copied from the local file
+    pub reused_chunks: u64,
+    /// Total length of the chunks copied from the local file
+    pub reused_bytes: u64,
+    /// Number of chunks fetched from the source
+    pub fetched_chunks: u64,
+    /// Total length of the chunks fetched from the source
+    pub fetched_bytes: u64,
+    /// Number of ranges fetched
+    pub requests: u64,
+}
+
+/// Write the remote version of a file described by `index` to `out`,
+/// copying the chunks the `local` version has and fetching the others from
+/// `source`
+///
+/// `local` is chunked with the parameters of the index and its chunks are
+/// identified by `hash`, which must be the strong hash used for the index.
+/// Consecutive missing chunks are fetched with one request of at most
+/// `MAX_REQUEST` bytes, unless a chunk is longer. Invalid parameters or
+/// empty entries in the index, and fetched chunks which don't match their
+/// hash, fail with `io::ErrorKind::InvalidData`.
+pub fn reconstruct<L, S, H, W>(
+    index: &ChunkIndex,
+    mut local: L,
+    source: &mut S,
+    hash: H,
+    mut out: W,
+) -> io::Result<DeltaStats>
+where
+    L: Read + Seek,
+    S: RangeSource,
+    H: Fn(&[u8]) -> ChunkHash,
+    W: Write,
+{
+    // The index may come from an untrusted server
+    let mut chunker = AnyChunker::new(*index.params())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let entries: Vec<_> = index.iter().collect();
+    if entries.iter().any(|e| e.len == 0) {
+        return Err(invalid_data("empty chunk index entry"));
+    }
+
+    let mut known = HashMap::new();
+    for_each_chunk(&mut local, &mut chunker, |offset, data| {
+        known
+            .entry(hash(data))
+            .or_insert((offset, data.len() as u64));
+        Ok(())
+    })?;
+    let local_offset = |i: usize| {
+        known
+            .get(&entries[i].hash)
+            .filter(|&&(_, len)| len == entries[i].len)
+            .map(|&(offset, _)| offset)
+    };
+
+    let mut stats = DeltaStats::default();
+    let mut buf = Vec::new();
+    let mut i = 0;
+    while i < entries.len() {
+        if let Some(offset) = local_offset(i) {
+            let len = entries[i].len;
+            local.seek(SeekFrom::Start(offset))?;
+            buf.resize(crate::saturating_usize(len), 0);
+            local.read_exact(&mut buf)?;
+            out.write_all(&buf)?;
+            stats.reused_chunks += 1;
+            stats.reused_bytes += len;
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut len = 0u64;
+        while i < entries.len() && local_offset(i).is_none() {
+            match len.checked_add(entries[i].len) {
+                Some(next) if i == start || next <= MAX_REQUEST => len = next,
+                _ => break,
+            }
+            i += 1;
+        }
+        let data = source.fetch(entries[start].offset, len)?;
+        if data.len() as u64 != len {
+            return Err(invalid_data("source returned a different range"));
+        }
+        let mut rest = &data[..];
+        for entry in &entries[start..i] {
+            let (chunk, tail) = rest.split_at(entry.len as usize);
+            if hash(chunk) != entry.hash {
+                return Err(invalid_data("fetched chunk does not match the index"));
+            }
+            rest = tail;
+        }
+        out.write_all(&data)?;
+        stats.fetched_chunks += (i - start) as u64;
+        stats.fetched_bytes += len;
+        stats.requests += 1;
+    }
+    out.flush()?;
+    Ok(stats)
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::index::{IndexEntry, IndexWriter};
+    use crate::merkle::tests::weak_hash;
+    use crate::tests::rand_data;
+    use crate::{Algorithm, Chunker, ChunkerParams};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn hash(data: &[u8]) -> ChunkHash {
+        weak_hash(&[data])
+    }
+
+    fn index_of(data: &[u8]) -> Vec<u8> {
+        let params = ChunkerParams {
+            min_size: 1024,
+            max_size: 16 * 1024,
+            ..ChunkerParams::new(Algorithm::Gear, 12)
+        };
+        let mut chunker = AnyChunker::new(params).unwrap();
+        let mut writer = IndexWriter::new(Vec::new(), &params).unwrap();
+        let mut offset = 0;
+        while offset < data.len() {
+            let (len, weak_digest) = chunker
+                .find_chunk_edge(&data[offset..])
+                .unwrap_or((data.len() - offset, 0));
+            let chunk = &data[offset..offset + len];
+            writer
+                .push(&IndexEntry {
+                    offset: offset as u64,
+                    len: len as u64,
+                    weak_digest,
+                    hash: hash(chunk),
+                })
+                .unwrap();
+            offset += len;
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Serve `/index` and ranges of `/data` until `/stop` is requested
+    fn serve(index: Vec<u8>, data: Vec<u8>, requests: Arc<AtomicUsize>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let (mut request, mut range) = (String::new(), None);
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.trim_end().strip_prefix("Range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        range = Some((start.parse().unwrap(), end.parse::<usize>().unwrap()));
+                    }
+                    line.clear();
+                }
+                let (status, body) = match (request.split(' ').nth(1).unwrap(), range) {
+                    ("/stop", _) => return,
+                    ("/index", None) => ("200 OK", &index[..]),
+                    ("/data", Some((start, end))) => {
+                        requests.fetch_add(1, Ordering::Relaxed);
+                        ("206 Partial Content", &data[start..=end])
+                    }
+                    _ => ("404 Not Found", &[][..]),
+                };
+                let mut stream = reader.into_inner();
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn fetches_only_changed_chunks() {
+        let local = rand_data(512 * 1024);
+        let mut remote = local.clone();
+        remote[100_000..100_010].copy_from_slice(b"0123456789");
+        remote.splice(300_000..300_000, rand_data(20_000));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let port = serve(index_of(&remote), remote.clone(), requests.clone());
+        let url = |path| format!("http://127.0.0.1:{}{}", port, path);
+
+        let index_data = HttpClient::new(&url("/index")).unwrap().get().unwrap();
+        let index = ChunkIndex::parse(&index_data).unwrap();
+        let mut source = HttpClient::new(&url("/data")).unwrap();
+        let mut out = Vec::new();
+        let stats =
+            reconstruct(&index, io::Cursor::new(&local), &mut source, hash, &mut out).unwrap();
+        assert!(out == remote);
+        assert_eq!(
+            stats.reused_bytes + stats.fetched_bytes,
+            remote.len() as u64
+        );
+        assert!(stats.fetched_bytes < 80_000, "{:?}", stats);
+        assert!(stats.requests <= 4);
+        assert_eq!(requests.load(Ordering::Relaxed) as u64, stats.requests);
+
+        let missing = HttpClient::new(&url("/missing")).unwrap().get();
+        assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let _ = HttpClient::new(&url("/stop")).unwrap().get();
+    }
+
+    #[test]
+    fn rejects_tampered_ranges() {
+        struct Tampered(Vec<u8>);
+
+        impl RangeSource for Tampered {
+            fn fetch(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+                let mut data = self.0[offset as usize..(offset + len) as usize].to_vec();
+                data[0] ^= 1;
+                Ok(data)
+            }
+        }
+
+        let remote = rand_data(64 * 1024);
+        let index_data = index_of(&remote);
+        let index = ChunkIndex::parse(&index_data).unwrap();
+        let err = reconstruct(
+            &index,
+            io::Cursor::new(&[][..]),
+            &mut Tampered(remote),
+            hash,
+            io::sink(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_bad_ranges_and_stalled_servers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let url = format!("http://127.0.0.1:{}/data", port);
+        let mut client = HttpClient::new(&url)
+            .unwrap()
+            .with_timeout(Duration::from_millis(100));
+        for (offset, len) in [(0, 0), (u64::MAX, 2)] {
+            let err = client.fetch(offset, len).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        // The connection is accepted by the backlog but never answered
+        let err = client.fetch(0, 10).unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ));
+        drop(listener);
+    }
+
+    #[test]
+    fn parses_urls() {
+        let client = HttpClient::new("http://example.com:8080/a/b").unwrap();
+        assert_eq!(
+            (client.host.as_str(), client.port, client.path.as_str()),
+            ("example.com", 8080, "/a/b")
+        );
+        let client = HttpClient::new("http://[::1]").unwrap();
+        assert_eq!((client.host.as_str(), client.port), ("::1", 80));
+        let https = HttpClient::new("https://example.com/").unwrap_err();
+        assert_eq!(https.kind(), io::ErrorKind::Unsupported);
+        assert!(HttpClient::new("http://host:port/").is_err());
+    }
+}
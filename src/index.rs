@@ -0,0 +1,532 @@
+use super::{Algorithm, ChunkHash, ChunkerParams};
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// Magic bytes at the start of a chunk index file
+pub const MAGIC: [u8; 4] = *b"RSCI";
+
+/// Magic bytes at the end of a chunk index file
+pub const TRAILER_MAGIC: [u8; 4] = *b"RSCE";
+
+/// Version of the chunk index format written by `IndexWriter`
+pub const VERSION: u16 = 1;
+
+/// Size of the file header
+pub const HEADER_SIZE: usize = 32;
+
+/// Size of one entry
+pub const ENTRY_SIZE: usize = 56;
+
+/// Size of the file trailer
+pub const TRAILER_SIZE: usize = 16;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Chunk recorded in an index file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IndexEntry {
+    /// Offset of the first byte of the chunk within the stream
+    pub offset: u64,
+    /// Length of the chunk
+    pub len: u64,
+    /// Rolling digest at the chunk edge, widened to 64 bits (0 for a final
+    /// chunk without an edge)
+    pub weak_digest: u64,
+    /// Strong hash of the chunk contents
+    pub hash: ChunkHash,
+}
+
+impl IndexEntry {
+    fn encode(&self) -> [u8; ENTRY_SIZE] {
+        let mut out = [0; ENTRY_SIZE];
+        out[..8].copy_from_slice(&self.offset.to_le_bytes());
+        out[8..16].copy_from_slice(&self.len.to_le_bytes());
+        out[16..24].copy_from_slice(&self.weak_digest.to_le_bytes());
+        out[24..].copy_from_slice(&self.hash);
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Self {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&bytes[24..ENTRY_SIZE]);
+        IndexEntry {
+            offset: read_u64(&bytes[..8]),
+            len: read_u64(&bytes[8..16]),
+            weak_digest: read_u64(&bytes[16..24]),
+            hash,
+        }
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+/// CRC-32 (IEEE) lookup table
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continue the CRC-32 `crc` (0 initially) over `data`
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        (crc >> 8) ^ CRC_TABLE[((crc ^ u32::from(b)) & 0xff) as usize]
+    })
+}
+
+/// Streaming writer of chunk index files
+///
+/// All integers are little-endian, and entries have a fixed size, so an
+/// index can be searched in place, e.g. through a memory map:
+///
+/// ```text
+/// header:  "RSCI" | version: u16 | algorithm: u16 | chunk_bits: u32
+///          | reserved: u32 | min_size: u64 | max_size: u64
+/// entry:   offset: u64 | len: u64 | weak_digest: u64 | hash: [u8; 32]
+/// trailer: entry count: u64 | CRC-32 of all preceding bytes: u32 | "RSCE"
+/// ```
+///
+/// Entries cover the stream contiguously, in order, and none is empty.
+pub struct IndexWriter<W: Write> {
+    writer: W,
+    crc: u32,
+    count: u64,
+    /// End of the last entry
+    offset: u64,
+}
+
+impl<W: Write> IndexWriter<W> {
+    /// Start an index of a stream chunked with `params`
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` if the parameters are
+    /// invalid, see `ChunkerParams::validate`.
+    pub fn new(writer: W, params: &ChunkerParams) -> io::Result<Self> {
+        params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        header[6..8].copy_from_slice(&params.algorithm.id().to_le_bytes());
+        header[8..12].copy_from_slice(&params.chunk_bits.to_le_bytes());
+        header[16..24].copy_from_slice(&params.min_size.to_le_bytes());
+        header[24..32].copy_from_slice(&params.max_size.to_le_bytes());
+        let mut index = IndexWriter {
+            writer,
+            crc: 0,
+            count: 0,
+            offset: 0,
+        };
+        index.write(&header)?;
+        Ok(index)
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.crc = crc32(self.crc, data);
+        self.writer.write_all(data)
+    }
+
+    /// Append the entry of the next chunk
+    ///
+    /// Fails with `io::ErrorKind::InvalidInput` unless the chunk starts
+    /// where the previous one ended and is not empty.
+    pub fn push(&mut self, entry: &IndexEntry) -> io::Result<()> {
+        let invalid_input = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        if entry.offset != self.offset {
+            return Err(invalid_input("chunk index entries must be contiguous"));
+        }
+        if entry.len == 0 {
+            return Err(invalid_input("chunk index entries must not be empty"));
+        }
+        let end = entry
+            .offset
+            .checked_add(entry.len)
+            .ok_or_else(|| invalid_input("chunk index entry beyond the largest offset"))?;
+        self.write(&entry.encode())?;
+        self.offset = end;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the trailer and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let count = self.count.to_le_bytes();
+        self.write(&count)?;
+        let crc = self.crc.to_le_bytes();
+        self.writer.write_all(&crc)?;
+        self.writer.write_all(&TRAILER_MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Chunk index file parsed in place from a byte slice
+///
+/// Parsing checks the whole file against its checksum, the parameters with
+/// `ChunkerParams::validate`, and that entries are non-empty and cover the
+/// stream contiguously from offset 0, after which entries are decoded on
+/// access.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkIndex<'a> {
+    params: ChunkerParams,
+    entries: &'a [u8],
+}
+
+impl<'a> ChunkIndex<'a> {
+    /// Parse the index file `data`
+    pub fn parse(data: &'a [u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE + TRAILER_SIZE || data[..4] != MAGIC {
+            return Err(invalid_data("not a chunk index"));
+        }
+        if data[4..6] != VERSION.to_le_bytes() {
+            return Err(invalid_data("unsupported chunk index version"));
+        }
+        let trailer = &data[data.len() - TRAILER_SIZE..];
+        if trailer[12..] != TRAILER_MAGIC {
+            return Err(invalid_data("truncated chunk index"));
+        }
+        let count = read_u64(trailer);
+        let entries = &data[HEADER_SIZE..data.len() - TRAILER_SIZE];
+        if entries.len() as u64 != count.saturating_mul(ENTRY_SIZE as u64) {
+            return Err(invalid_data("chunk index entry count mismatch"));
+        }
+        let mut crc = [0; 4];
+        crc.copy_from_slice(&trailer[8..12]);
+        if crc32(0, &data[..data.len() - 8]) != u32::from_le_bytes(crc) {
+            return Err(invalid_data("chunk index checksum mismatch"));
+        }
+
+        let algorithm = Algorithm::from_id(u16::from_le_bytes([data[6], data[7]]))
+            .ok_or_else(|| invalid_data("unknown chunking algorithm"))?;
+        let params = ChunkerParams {
+            algorithm,
+            chunk_bits: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+            min_size: read_u64(&data[16..24]),
+            max_size: read_u64(&data[24..32]),
+        };
+        params
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut offset = 0u64;
+        for entry in entries.chunks(ENTRY_SIZE).map(IndexEntry::decode) {
+            if entry.offset != offset || entry.len == 0 {
+                return Err(invalid_data("chunk index entries not contiguous"));
+            }
+            offset = offset
+                .checked_add(entry.len)
+                .ok_or_else(|| invalid_data("chunk index entry beyond the largest offset"))?;
+        }
+        Ok(ChunkIndex { params, entries })
+    }
+
+    /// Return the parameters the stream was chunked with
+    pub fn params(&self) -> &ChunkerParams {
+        &self.params
+    }
+
+    /// Return the number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Return whether the index has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return entry `i`
+    pub fn get(&self, i: usize) -> Option<IndexEntry> {
+        if i < self.len() {
+            Some(IndexEntry::decode(&self.entries[i * ENTRY_SIZE..]))
+        } else {
+            None
+        }
+    }
+
+    /// Return an iterator over all entries
+    pub fn iter(&self) -> impl Iterator<Item = IndexEntry> + 'a {
+        self.entries.chunks(ENTRY_SIZE).map(IndexEntry::decode)
+    }
+
+    /// Return the length of the indexed stream
+    pub fn stream_len(&self) -> u64 {
+        self.get(self.len().wrapping_sub(1))
+            .map_or(0, |last| last.offset + last.len)
+    }
+
+    /// Return the entry of the chunk containing stream offset `offset`
+    pub fn find_offset(&self, offset: u64) -> Option<IndexEntry> {
+        self.get(self.find_offset_index(offset)?)
+    }
+
+    /// Return the position of the entry of the chunk containing stream
+    /// offset `offset`
+    pub fn find_offset_index(&self, offset: u64) -> Option<usize> {
+        // Entries are contiguous, so the first entry ending past `offset`
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let entry = self.get(mid).unwrap();
+            if entry.offset + entry.len <= offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo < self.len()).then_some(lo)
+    }
+}
+
+/// Distinct chunks of an `IndexDiff` category, with their total length
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkSet {
+    /// Strong hashes of the chunks, sorted
+    pub hashes: Vec<ChunkHash>,
+    /// Total length of the chunks, each distinct chunk counted once
+    pub bytes: u64,
+}
+
+impl ChunkSet {
+    fn push(&mut self, hash: ChunkHash, len: u64) {
+        self.hashes.push(hash);
+        self.bytes += len;
+    }
+}
+
+/// Difference between the chunks of two indexes, computed by `diff`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+    /// Chunks only in the new index, i.e. what a backup has to upload
+    pub added: ChunkSet,
+    /// Chunks only in the old index
+    pub removed: ChunkSet,
+    /// Chunks in both indexes
+    pub retained: ChunkSet,
+}
+
+fn distinct(index: &ChunkIndex) -> HashMap<ChunkHash, u64> {
+    index.iter().map(|e| (e.hash, e.len)).collect()
+}
+
+/// Compare the chunks of the `old` and `new` indexes by strong hash
+pub fn diff(old: &ChunkIndex, new: &ChunkIndex) -> IndexDiff {
+    let old = distinct(old);
+    let new = distinct(new);
+    let mut result = IndexDiff::default();
+    for (&hash, &len) in &new {
+        if old.contains_key(&hash) {
+            result.retained.push(hash, len);
+        } else {
+            result.added.push(hash, len);
+        }
+    }
+    for (&hash, &len) in &old {
+        if !new.contains_key(&hash) {
+            result.removed.push(hash, len);
+        }
+    }
+    for set in [&mut result.added, &mut result.removed, &mut result.retained] {
+        set.hashes.sort_unstable();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<IndexEntry> {
+        let mut offset = 0;
+        (0..100u64)
+            .map(|i| {
+                let len = 1000 + i * 37 % 500;
+                let entry = IndexEntry {
+                    offset,
+                    len,
+                    weak_digest: i * 0x0101_0101,
+                    hash: [i as u8; 32],
+                };
+                offset += len;
+                entry
+            })
+            .collect()
+    }
+
+    fn write(entries: &[IndexEntry]) -> Vec<u8> {
+        let params = ChunkerParams::new(Algorithm::Gear, 13);
+        let mut writer = IndexWriter::new(Vec::new(), &params).unwrap();
+        for entry in entries {
+            writer.push(entry).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let entries = entries();
+        let data = write(&entries);
+        assert_eq!(
+            data.len(),
+            HEADER_SIZE + entries.len() * ENTRY_SIZE + TRAILER_SIZE
+        );
+        let index = ChunkIndex::parse(&data).unwrap();
+        assert_eq!(*index.params(), ChunkerParams::new(Algorithm::Gear, 13));
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.iter().collect::<Vec<_>>(), entries);
+        assert_eq!(index.get(100), None);
+
+        let last = entries[99];
+        assert_eq!(index.stream_len(), last.offset + last.len);
+        assert_eq!(index.find_offset(0), Some(entries[0]));
+        assert_eq!(index.find_offset(entries[50].offset), Some(entries[50]));
+        assert_eq!(index.find_offset(entries[51].offset - 1), Some(entries[50]));
+        assert_eq!(index.find_offset(index.stream_len()), None);
+
+        let empty = write(&[]);
+        assert!(ChunkIndex::parse(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_damage() {
+        let data = write(&entries());
+        let kind = |data: &[u8]| ChunkIndex::parse(data).unwrap_err().kind();
+        let mut flipped = data.clone();
+        flipped[1000] ^= 1;
+        assert_eq!(kind(&flipped), io::ErrorKind::InvalidData);
+        assert_eq!(kind(&data[..data.len() - 1]), io::ErrorKind::InvalidData);
+        assert_eq!(kind(&data[4..]), io::ErrorKind::InvalidData);
+
+        let params = ChunkerParams::new(Algorithm::Bup, 13);
+        let mut writer = IndexWriter::new(Vec::new(), &params).unwrap();
+        let mut entry = entries()[1];
+        assert_eq!(
+            writer.push(&entry).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        entry.offset = 0;
+        writer.push(&entry).unwrap();
+        let empty = IndexEntry {
+            offset: entry.len,
+            len: 0,
+            ..entry
+        };
+        assert_eq!(
+            writer.push(&empty).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        let inverted = ChunkerParams {
+            min_size: 4096,
+            max_size: 1024,
+            ..params
+        };
+        assert!(IndexWriter::new(Vec::new(), &inverted).is_err());
+    }
+
+    /// Replace the bytes at `at` and fix up the checksum
+    fn tamper(data: &[u8], at: usize, bytes: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        data[at..at + bytes.len()].copy_from_slice(bytes);
+        let end = data.len() - 8;
+        let crc = crc32(0, &data[..end]);
+        data[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn rejects_invalid_contents() {
+        let data = write(&entries());
+        let kind = |data: &[u8]| ChunkIndex::parse(data).unwrap_err().kind();
+        assert!(ChunkIndex::parse(&tamper(&data, 0, &[])).is_ok());
+
+        // Header: chunk bits out of range, minimum above maximum
+        assert_eq!(
+            kind(&tamper(&data, 8, &40u32.to_le_bytes())),
+            io::ErrorKind::InvalidData
+        );
+        let inverted = tamper(&data, 16, &2u64.to_le_bytes());
+        assert_eq!(
+            kind(&tamper(&inverted, 24, &1u64.to_le_bytes())),
+            io::ErrorKind::InvalidData
+        );
+
+        // Entries: empty, overlapping, overflowing
+        let second = HEADER_SIZE + ENTRY_SIZE;
+        assert_eq!(
+            kind(&tamper(&data, HEADER_SIZE + 8, &0u64.to_le_bytes())),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            kind(&tamper(&data, second, &1u64.to_le_bytes())),
+            io::ErrorKind::InvalidData
+        );
+        let huge = tamper(&data, HEADER_SIZE + 8, &u64::MAX.to_le_bytes());
+        assert_eq!(
+            kind(&tamper(&huge, second, &u64::MAX.to_le_bytes())),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn diff_by_hash() {
+        let old = entries();
+        let mut new: Vec<_> = old[10..].to_vec();
+        new.push(IndexEntry {
+            hash: [0xaa; 32],
+            len: 10,
+            ..old[0]
+        });
+        // Repeated chunks count once
+        new.push(new[0]);
+        let mut offset = 0;
+        for entry in &mut new {
+            entry.offset = offset;
+            offset += entry.len;
+        }
+        let (old_data, new_data) = (write(&old), write(&new));
+        let diff = diff(
+            &ChunkIndex::parse(&old_data).unwrap(),
+            &ChunkIndex::parse(&new_data).unwrap(),
+        );
+
+        assert_eq!(diff.added.hashes, vec![[0xaa; 32]]);
+        assert_eq!(diff.added.bytes, 10);
+        let removed: Vec<_> = old[..10].iter().map(|e| e.hash).collect();
+        assert_eq!(diff.removed.hashes, removed);
+        assert_eq!(
+            diff.removed.bytes,
+            old[..10].iter().map(|e| e.len).sum::<u64>()
+        );
+        assert_eq!(diff.retained.hashes.len(), 90);
+        assert_eq!(
+            diff.retained.bytes,
+            old[10..].iter().map(|e| e.len).sum::<u64>()
+        );
+    }
+}
@@ -0,0 +1,189 @@
+use super::{for_each_chunk, ChunkHash, Chunker, MerkleBuilder};
+use std::io::{self, Read, Write};
+
+/// Chunk found by `chunk_and_hash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkInfo {
+    /// Offset of the first byte of the chunk within the stream
+    pub offset: u64,
+    /// Length of the chunk
+    pub len: usize,
+}
+
+/// Reader writing everything read from `inner` to `tee`
+struct Tee<'a, R, W> {
+    inner: R,
+    tee: &'a mut W,
+}
+
+impl<'a, R: Read, W: Write> Read for Tee<'a, R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.tee.write_all(&buf[..n])?;
+        Ok(n)
+    }
+}
+
+/// Chunk everything read from `reader`, feeding the whole stream to
+/// `stream_hasher` on the way
+///
+/// Every byte is read once and given to both the chunker and the hasher, so
+/// computing e.g. the SHA-256 of a whole file during ingestion doesn't need
+/// a second read. Any `io::Write` works as the hasher, including the
+/// RustCrypto hashers.
+///
+/// `f` is called with the contents of each chunk as it is found, and the
+/// list of all chunks is returned.
+pub fn chunk_and_hash<R, C, W, F>(
+    reader: R,
+    chunker: &mut C,
+    stream_hasher: &mut W,
+    mut f: F,
+) -> io::Result<Vec<ChunkInfo>>
+where
+    R: Read,
+    C: Chunker,
+    W: Write,
+    F: FnMut(ChunkInfo, &[u8]) -> io::Result<()>,
+{
+    let mut chunks = Vec::new();
+    let tee = Tee {
+        inner: reader,
+        tee: stream_hasher,
+    };
+    for_each_chunk(tee, chunker, |offset, data| {
+        let info = ChunkInfo {
+            offset,
+            len: data.len(),
+        };
+        chunks.push(info);
+        f(info, data)
+    })?;
+    Ok(chunks)
+}
+
+/// Result of `chunk_and_merkle`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleChunks {
+    /// All chunks of the stream
+    pub chunks: Vec<ChunkInfo>,
+    /// Strong hash of each chunk
+    pub hashes: Vec<ChunkHash>,
+    /// Merkle root of the chunk hashes, `None` for an empty stream
+    pub root: Option<ChunkHash>,
+}
+
+/// Chunk everything read from `reader`, computing the strong hash of each
+/// chunk and the Merkle root of those, as well as feeding the whole stream
+/// to `stream_hasher`
+///
+/// See `chunk_and_hash` and `MerkleBuilder`. `hash_chunk` computes the hash
+/// of a chunk and `combine` the hash of an inner tree node. `f` is called
+/// with each chunk and its hash as it is found.
+pub fn chunk_and_merkle<R, C, W, H, M, F>(
+    reader: R,
+    chunker: &mut C,
+    stream_hasher: &mut W,
+    hash_chunk: H,
+    combine: M,
+    mut f: F,
+) -> io::Result<MerkleChunks>
+where
+    R: Read,
+    C: Chunker,
+    W: Write,
+    H: Fn(&[u8]) -> ChunkHash,
+    M: Fn(&ChunkHash, &ChunkHash) -> ChunkHash,
+    F: FnMut(ChunkInfo, &ChunkHash, &[u8]) -> io::Result<()>,
+{
+    let mut tree = MerkleBuilder::new(combine);
+    let mut hashes = Vec::new();
+    let chunks = chunk_and_hash(reader, chunker, stream_hasher, |info, data| {
+        let hash = hash_chunk(data);
+        tree.push(hash);
+        hashes.push(hash);
+        f(info, &hash, data)
+    })?;
+    Ok(MerkleChunks {
+        chunks,
+        hashes,
+        root: tree.root(),
+    })
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    #[test]
+    fn chunks_and_tees_whole_stream() {
+        let data = rand_data(300 * 1024);
+        let mut whole = Vec::new();
+        let mut rebuilt = Vec::new();
+        let chunks = chunk_and_hash(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(12),
+            &mut whole,
+            |info, chunk| {
+                assert_eq!(info.offset, rebuilt.len() as u64);
+                rebuilt.extend_from_slice(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(whole, data);
+        assert_eq!(rebuilt, data);
+        assert!(chunks.len() > 10);
+        assert_eq!(
+            chunks.iter().map(|c| c.len).sum::<usize>(),
+            data.len(),
+            "chunks cover the stream"
+        );
+    }
+
+    #[test]
+    fn merkle_root_of_chunks() {
+        use crate::merkle::tests::{combine, weak_hash};
+
+        let data = rand_data(300 * 1024);
+        let mut whole = Vec::new();
+        let mut seen = 0;
+        let result = chunk_and_merkle(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(12),
+            &mut whole,
+            |chunk| weak_hash(&[chunk]),
+            combine,
+            |_, hash, chunk| {
+                assert_eq!(*hash, weak_hash(&[chunk]));
+                seen += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(whole, data);
+        assert_eq!(seen, result.chunks.len());
+        assert_eq!(result.hashes.len(), result.chunks.len());
+
+        let mut tree = MerkleBuilder::new(combine);
+        for (info, hash) in result.chunks.iter().zip(&result.hashes) {
+            let offset = info.offset as usize;
+            assert_eq!(*hash, weak_hash(&[&data[offset..offset + info.len]]));
+            tree.push(*hash);
+        }
+        assert_eq!(result.root, tree.root());
+
+        let empty = chunk_and_merkle(
+            &[][..],
+            &mut Gear::new(),
+            &mut io::sink(),
+            |chunk| weak_hash(&[chunk]),
+            combine,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(empty.root, None);
+    }
+}
@@ -0,0 +1,140 @@
+use super::ChunkerParams;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+/// Label of the key derivation, changed if the derivation ever changes
+const INFO_PREFIX: &[u8] = b"rollsum chunker params v1\0";
+
+/// Secret chunking material derived by `ChunkerParams::derive`
+///
+/// Deriving the same parameters with the same master key and context always
+/// gives the same material, so an archive only has to record its context.
+#[derive(Clone)]
+pub struct DerivedParams {
+    params: ChunkerParams,
+    /// Pseudorandom key all engine specific material is expanded from
+    prk: Hkdf<Sha256>,
+}
+
+impl ChunkerParams {
+    /// Derive keyed chunking material for these parameters from
+    /// `master_key` and a `context` such as an archive identifier
+    ///
+    /// HKDF-SHA256 is used, with the parameters and the context as info, so
+    /// different parameters or contexts give unrelated material.
+    pub fn derive(&self, master_key: &[u8], context: &[u8]) -> DerivedParams {
+        let mut info = INFO_PREFIX.to_vec();
+        info.extend_from_slice(&self.algorithm.id().to_be_bytes());
+        info.extend_from_slice(&self.chunk_bits.to_be_bytes());
+        info.extend_from_slice(&self.min_size.to_be_bytes());
+        info.extend_from_slice(&self.max_size.to_be_bytes());
+        info.extend_from_slice(context);
+
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(None, master_key)
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        DerivedParams {
+            params: *self,
+            prk: Hkdf::from_prk(&key).expect("32 bytes is a valid PRK length"),
+        }
+    }
+}
+
+impl DerivedParams {
+    /// Return the parameters the material was derived for
+    pub fn params(&self) -> &ChunkerParams {
+        &self.params
+    }
+
+    /// Fill `out` with material for the purpose named `label`
+    ///
+    /// Lets engines without dedicated support here derive their own seeds.
+    /// Panics if `out` is longer than 8160 bytes.
+    pub fn expand(&self, label: &str, out: &mut [u8]) {
+        self.prk
+            .expand(label.as_bytes(), out)
+            .expect("output too long for HKDF-SHA256");
+    }
+
+    /// Return a 32 byte seed for the purpose named `label`
+    pub fn seed(&self, label: &str) -> [u8; 32] {
+        let mut seed = [0; 32];
+        self.expand(label, &mut seed);
+        seed
+    }
+
+    /// Return a table of random values for `Gear::with_table`
+    pub fn gear_table(&self) -> [u64; 256] {
+        let mut bytes = [0; 256 * 8];
+        self.expand("gear table", &mut bytes);
+        let mut table = [0; 256];
+        for (value, bytes) in table.iter_mut().zip(bytes.chunks(8)) {
+            let mut word = [0; 8];
+            word.copy_from_slice(bytes);
+            *value = u64::from_le_bytes(word);
+        }
+        table
+    }
+
+    /// Create a Gear engine using the derived table
+    #[cfg(feature = "gear")]
+    pub fn gear(&self) -> crate::Gear {
+        crate::Gear::with_table(self.params.chunk_bits, self.gear_table())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Algorithm;
+
+    #[test]
+    fn deterministic_and_separated() {
+        let params = ChunkerParams::new(Algorithm::Gear, 12);
+        let a = params.derive(b"master", b"archive 1");
+        assert_eq!(
+            a.gear_table(),
+            params.derive(b"master", b"archive 1").gear_table()
+        );
+        assert_ne!(
+            a.gear_table(),
+            params.derive(b"master", b"archive 2").gear_table()
+        );
+        assert_ne!(
+            a.gear_table(),
+            params.derive(b"other", b"archive 1").gear_table()
+        );
+        assert_ne!(
+            a.seed("x"),
+            ChunkerParams::new(Algorithm::Gear, 13)
+                .derive(b"master", b"archive 1")
+                .seed("x")
+        );
+        assert_ne!(a.seed("x"), a.seed("y"));
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn keyed_gear_moves_edges() {
+        use crate::tests::rand_data;
+        use crate::Gear;
+
+        let edges = |mut gear: Gear, data: &[u8]| {
+            let mut edges = Vec::new();
+            let mut rest = data;
+            while let Some((i, _)) = gear.find_chunk_edge(rest) {
+                edges.push(data.len() - rest.len() + i);
+                rest = &rest[i..];
+            }
+            edges
+        };
+        let data = rand_data(256 * 1024);
+        let derived = ChunkerParams::new(Algorithm::Gear, 11).derive(b"master", b"ctx");
+        let keyed = edges(derived.gear(), &data);
+        let plain = edges(Gear::new_with_chunk_bits(11), &data);
+        assert_eq!(keyed, edges(derived.gear(), &data));
+        assert!(keyed.len() > 50);
+        assert!(keyed.iter().filter(|e| plain.contains(e)).count() < keyed.len() / 10);
+    }
+}
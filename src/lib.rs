@@ -3,13 +3,288 @@
 #[cfg(feature = "bup")]
 pub mod bup;
 #[cfg(feature = "bup")]
-pub use crate::bup::Bup;
+pub use crate::bup::{Bup, Bup64, BupDyn};
 
 #[cfg(feature = "gear")]
 pub mod gear;
 #[cfg(feature = "gear")]
 pub use crate::gear::Gear;
 
+/// FastCDC chunking over the Gear hash
+#[cfg(feature = "fastcdc")]
+pub mod fastcdc;
+#[cfg(feature = "fastcdc")]
+pub use crate::fastcdc::{FastCdc, FastCdc2020};
+
+/// Rabin fingerprints over GF(2), as used by LBFS and restic
+#[cfg(feature = "rabin")]
+pub mod rabin;
+#[cfg(feature = "rabin")]
+pub use crate::rabin::{LbfsChunker, Rabin, ResticChunker};
+
+/// Adler-32 checksum over a sliding window, as used for weak checksums
+#[cfg(feature = "adler32")]
+pub mod adler32;
+#[cfg(feature = "adler32")]
+pub use crate::adler32::Adler32;
+
+/// CRC-32 over a sliding window, as mandated by some formats for weak sums
+#[cfg(feature = "crc32")]
+pub mod crc32;
+#[cfg(feature = "crc32")]
+pub use crate::crc32::Crc32;
+
+/// librsync's RollSum weak checksum
+#[cfg(feature = "librsync")]
+pub mod librsync;
+
+/// rsync's weak rolling checksum
+#[cfg(feature = "rsync")]
+pub mod rsync;
+#[cfg(feature = "rsync")]
+pub use crate::rsync::RsyncSum;
+
+/// Chunking compatible with BorgBackup's buzhash chunker
+#[cfg(feature = "borg")]
+pub mod borg;
+#[cfg(feature = "borg")]
+pub use crate::borg::BorgChunker;
+
+/// Reusable chunk edge conditions
+pub mod condition;
+pub use crate::condition::{EdgeCondition, MaskEngine};
+
+/// Validated number of bits matched by edge conditions
+pub mod chunk_bits;
+pub use crate::chunk_bits::{ChunkBits, InvalidChunkBits};
+
+/// Minimum and maximum chunk sizes around any engine
+pub mod minmax;
+pub use crate::minmax::MinMaxChunker;
+
+/// Builders validating engine and chunker settings
+pub mod builder;
+pub use crate::builder::BuildError;
+
+/// Fixed size chunking, the baseline of content-defined chunking
+pub mod fixed;
+pub use crate::fixed::FixedChunker;
+
+/// RAM chunking, comparing bytes with the maximum of a window without hashing
+pub mod ram;
+pub use crate::ram::RamChunker;
+
+/// AE chunking, ending chunks a window past their maximum
+pub mod ae;
+pub use crate::ae::AeChunker;
+
+/// SeqCDC chunking, cutting after monotonic byte sequences without hashing
+pub mod seq;
+pub use crate::seq::SeqCdcChunker;
+/// MAXP chunking, cutting at local maxima of the digest
+pub mod maxp;
+pub use crate::maxp::MaxpChunker;
+
+/// Normalized chunking around mask based engines
+pub mod normalized;
+pub use crate::normalized::NormalizedChunker;
+
+/// TTTD chunking with backup edges around any engine
+pub mod tttd;
+pub use crate::tttd::TttdChunker;
+
+/// QuickCDC-style jumping over chunks seen before
+pub mod quick;
+pub use crate::quick::QuickChunker;
+
+/// RapidCDC-style skipping to the chunk sizes seen before
+pub mod rapid;
+pub use crate::rapid::RapidChunker;
+
+/// Description of chunker configurations
+pub mod params;
+pub use crate::params::{Algorithm, ChunkerParams, Params, Variant};
+
+/// Chunkers configured at runtime
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub mod any_chunker;
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub use crate::any_chunker::AnyChunker;
+
+/// Expected chunk sizes of mask based chunking
+pub mod distribution;
+pub use crate::distribution::SizeDistribution;
+
+/// Choosing chunking parameters from a data sample
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub mod tuning;
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub use crate::tuning::suggest_params;
+
+/// Ready-made chunker configurations for common workloads
+#[cfg(feature = "gear")]
+pub mod profiles;
+#[cfg(feature = "gear")]
+pub use crate::profiles::Profile;
+
+/// Measuring the throughput of the engines at runtime
+#[cfg(feature = "bench")]
+pub mod bench;
+
+/// Deriving keyed chunking parameters from a master secret
+#[cfg(feature = "hkdf")]
+pub mod keyed;
+
+/// Test vectors for ports to other languages
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub mod vectors;
+
+/// Checking stored chunk boundaries against the data
+#[cfg(any(feature = "bup", feature = "gear"))]
+pub mod verify;
+
+/// Estimating the compressibility of chunks
+pub mod entropy;
+pub use crate::entropy::Entropy;
+
+/// Content-addressed chunk stores
+pub mod store;
+pub use crate::store::{ChunkHash, ChunkStore};
+
+/// Packing chunks into large segments
+pub mod segment;
+pub use crate::segment::SegmentPacker;
+
+/// Chunk index files
+pub mod index;
+pub use crate::index::{ChunkIndex, IndexEntry, IndexWriter};
+
+/// Reading streams back from chunk indexes and stores
+pub mod chunked_reader;
+pub use crate::chunked_reader::ChunkedReader;
+
+/// Finding unreferenced chunks for garbage collection
+pub mod gc;
+
+/// Tagging chunks already in an index
+pub mod tagging;
+
+/// Bloom filters of chunk hashes
+pub mod bloom;
+
+/// Substring search using the rolling engines
+pub mod search;
+pub use crate::search::Searcher;
+
+/// Winnowing document fingerprints and chunking
+pub mod winnowing;
+pub use crate::winnowing::WinnowingChunker;
+
+/// Push-style chunking without I/O
+pub mod stream;
+pub use crate::stream::{EdgeTracker, StreamChunker};
+
+/// Chunking `BufRead` readers into owned chunks
+pub mod read_chunks;
+pub use crate::read_chunks::ReadChunks;
+
+/// Chunking streams from their end toward their start
+pub mod reverse;
+
+/// Chunking files and streams with progress reports
+pub mod progress;
+
+/// Limiting the rate of chunking
+pub mod throttle;
+
+/// Single pass chunking and whole stream hashing
+pub mod ingest;
+
+/// Multi-threaded chunking, hashing and compression
+pub mod pipeline;
+
+/// Merkle trees of chunk hashes
+pub mod merkle;
+pub use crate::merkle::MerkleBuilder;
+
+/// Chunking byte ranges of large streams
+pub mod range;
+
+/// Deduplicating many concurrent streams
+pub mod session;
+pub use crate::session::DedupSession;
+
+/// Keeping chunk boundaries stable across edits
+pub mod sticky;
+
+/// Finding known chunks in damaged streams
+pub mod resync;
+
+/// Context-triggered piecewise (fuzzy) hashes
+#[cfg(feature = "bup")]
+pub mod fuzzy;
+
+/// Bounded caches of recently seen digests
+pub mod lru;
+pub use crate::lru::DigestCache;
+
+/// Backing up directory trees into chunk stores
+#[cfg(feature = "backup-util")]
+pub mod backup;
+
+/// Deduplicating tar-like archives of chunk references
+#[cfg(feature = "dedup-tar")]
+pub mod dedup_tar;
+
+/// Fetching only the changed chunks of remote files over HTTP
+#[cfg(feature = "http")]
+pub mod http_delta;
+
+/// Persistent memory-mapped chunk index
+#[cfg(feature = "mmap")]
+pub mod mmap_index;
+
+/// casync compatible chunk store directories
+#[cfg(feature = "casync")]
+pub mod casync;
+
+/// Framed chunk streams
+pub mod framing;
+
+/// Deduplicating file copies sharing extents through reflinks
+#[cfg(all(feature = "reflink", target_os = "linux"))]
+pub mod reflink;
+
+/// Running two engines in lockstep
+pub mod dual;
+pub use crate::dual::DualEngine;
+
+/// Recording recent digests of an engine
+pub mod history;
+pub use crate::history::DigestHistory;
+
+/// Pools of reusable engines
+pub mod pool;
+pub use crate::pool::EnginePool;
+
+/// Asynchronous chunking adapters
+#[cfg(feature = "async")]
+pub mod async_chunker;
+
+/// Adapters for the RustCrypto `digest` traits
+#[cfg(feature = "digest")]
+pub mod rustcrypto;
+
+/// Adapters between this crate and the `cdchunking` crate
+#[cfg(feature = "cdchunking")]
+pub mod cdchunking_compat;
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::iter;
+
 /// Rolling sum engine trait
 pub trait Engine {
     type Digest;
@@ -22,12 +297,101 @@ pub trait Engine {
         buf.iter().for_each(|&b| self.roll_byte(b));
     }
 
+    /// Roll over several slices of bytes, as if they were one contiguous slice
+    fn roll_chained(&mut self, bufs: &[&[u8]]) {
+        bufs.iter().for_each(|buf| self.roll(buf));
+    }
+
+    /// Roll over the bytes of `iter`
+    ///
+    /// For data produced on the fly, e.g. by a decompressor, which would
+    /// otherwise have to be collected into a slice first.
+    fn roll_iter<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = u8>,
+        Self: Sized,
+    {
+        iter.into_iter().for_each(|b| self.roll_byte(b));
+    }
+
+    /// Roll over everything read from `reader`, returning the number of
+    /// bytes read
+    fn roll_from_reader<R: Read>(&mut self, mut reader: R) -> io::Result<u64>
+    where
+        Self: Sized,
+    {
+        let mut buf = [0; 16 * 1024];
+        let mut total = 0;
+        loop {
+            match read_block(&mut reader, &mut buf)? {
+                0 => return Ok(total),
+                n => {
+                    self.roll(&buf[..n]);
+                    total += n as u64;
+                }
+            }
+        }
+    }
+
     /// Return current rolling sum digest
     fn digest(&self) -> Self::Digest;
 
+    /// Return the digest of `buf` rolled over by a new engine
+    ///
+    /// Engines may specialize this to avoid maintaining their full state.
+    fn digest_of(buf: &[u8]) -> Self::Digest
+    where
+        Self: Default + Sized,
+    {
+        let mut engine = Self::default();
+        engine.roll(buf);
+        engine.digest()
+    }
+
+    /// Return the number of trailing bytes the digest depends on, if the
+    /// engine has a fixed window
+    ///
+    /// Once that many bytes have been rolled over, the digest no longer
+    /// depends on the initial state.
+    fn window_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Return how many more bytes have to be rolled over before the window
+    /// is filled, after which the digest no longer depends on the state
+    /// after the last reset
+    ///
+    /// Engines without a fixed window are always warmed up.
+    fn bytes_until_warm(&self) -> usize {
+        0
+    }
+
+    /// Return whether the window has been filled since the last reset
+    fn warmed_up(&self) -> bool {
+        self.bytes_until_warm() == 0
+    }
+
     /// Resets the internal state
     fn reset(&mut self);
 
+    /// Return the state after rolling over the bytes rolled by `left`,
+    /// followed by the `right_len` bytes rolled by `right` since its reset
+    ///
+    /// Lets the digest of a long stream be computed by rolling segments in
+    /// parallel and merging them. `Gear`, `FastCdc`, `Bup`, `BupDyn`,
+    /// `Bup64`, `Rabin`, `Adler32`, `Crc32`, `RsyncSum` and librsync's
+    /// `Rollsum` support it, returning `None` for engines configured
+    /// differently, e.g. with other tables or windows. Engines whose state
+    /// can't be merged return `None`, which is the default, as do wrappers
+    /// like `DualEngine` and `DigestHistory`.
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        let _ = (left, right, right_len);
+        None
+    }
+
     /// Find the end of the chunk.
     ///
     /// Feed engine bytes from `buf` and stop when chunk split was found.
@@ -57,21 +421,460 @@ pub trait Engine {
         }
         None
     }
+
+    /// Find the end of the chunk using an `EdgeCondition`.
+    ///
+    /// See `find_chunk_edge_cond`.
+    fn find_chunk_edge_with<C>(&mut self, buf: &[u8], cond: &C) -> Option<(usize, Self::Digest)>
+    where
+        C: EdgeCondition<Self>,
+        Self: Sized,
+    {
+        self.find_chunk_edge_cond(buf, |e: &Self| cond.is_edge(e))
+    }
+
+    /// Find the end of the chunk in data split over several slices.
+    ///
+    /// Behaves as `find_chunk_edge_cond` called on the concatenation of
+    /// `bufs`: the returned offset is relative to the start of `bufs[0]`.
+    fn find_chunk_edge_cond_chained<F>(
+        &mut self,
+        bufs: &[&[u8]],
+        cond: F,
+    ) -> Option<(usize, Self::Digest)>
+    where
+        F: Fn(&Self) -> bool,
+    {
+        let mut offset = 0;
+        for buf in bufs {
+            if let Some((i, digest)) = self.find_chunk_edge_cond(buf, &cond) {
+                return Some((offset + i, digest));
+            }
+            offset += buf.len();
+        }
+        None
+    }
+
+    /// Find the end of the chunk in the bytes of `iter`.
+    ///
+    /// Takes bytes from `iter` up to and including the last byte of the
+    /// chunk, so the next chunk starts with the next byte it yields. The
+    /// number of bytes taken is returned as `offset` or `consumed`.
+    fn find_chunk_edge_cond_iter<I, F>(&mut self, iter: &mut I, cond: F) -> EdgeResult<Self::Digest>
+    where
+        I: Iterator<Item = u8>,
+        F: Fn(&Self) -> bool,
+        Self: Sized,
+    {
+        let mut consumed = 0;
+        for b in iter {
+            self.roll_byte(b);
+            consumed += 1;
+
+            if cond(self) {
+                let digest = self.digest();
+                self.reset();
+                return EdgeResult::Found {
+                    offset: consumed,
+                    digest,
+                };
+            }
+        }
+        EdgeResult::NeedMore { consumed }
+    }
+}
+
+/// Engine with a fixed window, rolled by giving the byte leaving it
+///
+/// For rsync style searches sliding a window over a buffer the caller
+/// holds: once the first window is rolled over, each step is
+/// `slide(buf[i - window_size], buf[i])`.
+pub trait SlidingEngine: Engine {
+    /// Roll over `new`, with `old` leaving the window
+    ///
+    /// `old` has to be the byte rolled over `window_size` bytes before, or 0
+    /// while fewer have been rolled over since the last reset; any other
+    /// byte gives a digest not matching the window. The engine doesn't read
+    /// its own copy of the window, but keeps it up to date, so `slide` and
+    /// `roll_byte` can be mixed.
+    fn slide(&mut self, old: u8, new: u8);
+}
+
+/// Outcome of `Chunker::find_chunk_edge_result`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeResult<D> {
+    /// A chunk edge was found. `offset` is the offset of the first byte after
+    /// the chunk, so `offset` bytes were consumed.
+    Found { offset: usize, digest: D },
+    /// No chunk edge was found. `consumed` bytes were fed to the chunker,
+    /// the rest of the buffer (if any) has to be passed again.
+    NeedMore { consumed: usize },
+}
+
+/// Chunk edge at an absolute position in a stream
+///
+/// Returned by APIs tracking stream positions, e.g. `EdgeTracker`, instead of
+/// the buffer relative `(offset, digest)` of `find_chunk_edge`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkEdge<D> {
+    /// Offset of the first byte of the chunk in the stream
+    pub start: u64,
+    /// Offset of the first byte after the chunk in the stream
+    pub end: u64,
+    /// Length of the chunk, `end - start`, saturated at `u32::MAX`
+    pub len: u32,
+    /// Digest of the chunk reported by the chunker
+    pub digest: D,
+}
+
+impl<D> ChunkEdge<D> {
+    /// Return the edge of the chunk from `start` to `end`
+    pub fn new(start: u64, end: u64, digest: D) -> Self {
+        assert!(start <= end);
+        ChunkEdge {
+            start,
+            end,
+            len: u32::try_from(end - start).unwrap_or(u32::MAX),
+            digest,
+        }
+    }
+}
+
+/// Outcome of `Chunker::find_chunk_edge_limited`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanStop<D> {
+    /// The edge condition matched. `offset` is the offset of the first byte
+    /// after the chunk.
+    Edge { offset: usize, digest: D },
+    /// The scan limit was reached before the end of the buffer without an
+    /// edge; `consumed` (the limit) bytes were fed to the chunker.
+    Limit { consumed: usize },
+    /// The whole buffer was fed to the chunker without an edge.
+    Exhausted { consumed: usize },
+}
+
+impl<D> EdgeResult<D> {
+    /// Return the number of bytes consumed by the chunker
+    pub fn consumed(&self) -> usize {
+        match *self {
+            EdgeResult::Found { offset, .. } => offset,
+            EdgeResult::NeedMore { consumed } => consumed,
+        }
+    }
+
+    /// Return the edge offset and digest, if an edge was found
+    pub fn edge(self) -> Option<(usize, D)> {
+        match self {
+            EdgeResult::Found { offset, digest } => Some((offset, digest)),
+            EdgeResult::NeedMore { .. } => None,
+        }
+    }
+}
+
+/// Content-defined chunker trait
+///
+/// Implemented by engines which have a default edge condition (e.g.
+/// `Bup::find_chunk_edge`), so code splitting data into chunks does not need
+/// to know which engine or condition is in use.
+pub trait Chunker {
+    type Digest;
+
+    /// Find the end of the chunk.
+    ///
+    /// Same semantics as `Engine::find_chunk_edge_cond`, using the chunker's
+    /// own edge condition.
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Self::Digest)>;
+
+    /// Find the end of the chunk, reporting how much of `buf` was consumed
+    /// when no edge is found.
+    fn find_chunk_edge_result(&mut self, buf: &[u8]) -> EdgeResult<Self::Digest> {
+        match self.find_chunk_edge(buf) {
+            Some((offset, digest)) => EdgeResult::Found { offset, digest },
+            None => EdgeResult::NeedMore {
+                consumed: buf.len(),
+            },
+        }
+    }
+
+    /// Find the end of the chunk, feeding at most `budget` bytes of `buf` to
+    /// the chunker.
+    ///
+    /// Lets event loops chunk huge buffers in bounded slices of work: when
+    /// the budget runs out first, `NeedMore` reports how much was consumed,
+    /// and the search resumes by calling this again with the rest of `buf`.
+    fn find_chunk_edge_budget(&mut self, buf: &[u8], budget: usize) -> EdgeResult<Self::Digest> {
+        let part = &buf[..cmp::min(buf.len(), budget)];
+        match self.find_chunk_edge(part) {
+            Some((offset, digest)) => EdgeResult::Found { offset, digest },
+            None => EdgeResult::NeedMore {
+                consumed: part.len(),
+            },
+        }
+    }
+
+    /// Find the end of the chunk, feeding at most `max_scan` bytes of `buf`
+    /// to the chunker and reporting why the search stopped.
+    ///
+    /// Unlike `find_chunk_edge_budget`, running out of `max_scan` is told
+    /// apart from running out of `buf`, so callers enforcing their own
+    /// maximum chunk size know when to force an edge.
+    fn find_chunk_edge_limited(&mut self, buf: &[u8], max_scan: usize) -> ScanStop<Self::Digest> {
+        let limited = max_scan < buf.len();
+        let part = &buf[..cmp::min(buf.len(), max_scan)];
+        match self.find_chunk_edge(part) {
+            Some((offset, digest)) => ScanStop::Edge { offset, digest },
+            None if limited => ScanStop::Limit {
+                consumed: part.len(),
+            },
+            None => ScanStop::Exhausted {
+                consumed: part.len(),
+            },
+        }
+    }
+
+    /// Find the end of the chunk in data split over several slices.
+    ///
+    /// The returned offset is relative to the start of `bufs[0]`, see
+    /// `Engine::find_chunk_edge_cond_chained`.
+    fn find_chunk_edge_chained(&mut self, bufs: &[&[u8]]) -> Option<(usize, Self::Digest)> {
+        let mut offset = 0;
+        for buf in bufs {
+            if let Some((i, digest)) = self.find_chunk_edge(buf) {
+                return Some((offset + i, digest));
+            }
+            offset += buf.len();
+        }
+        None
+    }
+
+    /// Find the end of the chunk in a ring buffer.
+    ///
+    /// The returned offset is a logical index into `buf`.
+    fn find_chunk_edge_deque(&mut self, buf: &VecDeque<u8>) -> Option<(usize, Self::Digest)> {
+        let (front, back) = buf.as_slices();
+        self.find_chunk_edge_chained(&[front, back])
+    }
+
+    /// Find the end of the chunk in the bytes of `iter`.
+    ///
+    /// Same semantics as `Engine::find_chunk_edge_cond_iter`. Bytes are fed
+    /// to the chunker one at a time, so prefer slices where the data is
+    /// available as one.
+    fn find_chunk_edge_iter<I>(&mut self, iter: &mut I) -> EdgeResult<Self::Digest>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let mut consumed = 0;
+        for b in iter {
+            consumed += 1;
+            if let Some((_, digest)) = self.find_chunk_edge(&[b]) {
+                return EdgeResult::Found {
+                    offset: consumed,
+                    digest,
+                };
+            }
+        }
+        EdgeResult::NeedMore { consumed }
+    }
+
+    /// Return an iterator over the chunks of `buf`
+    ///
+    /// The last item is the bytes after the last edge, if any, which the
+    /// chunker has consumed as the start of a chunk continuing past `buf`.
+    fn chunks<'a, 'c>(&'c mut self, buf: &'a [u8]) -> ChunkIter<'a, 'c, Self>
+    where
+        Self: Sized,
+    {
+        ChunkIter {
+            chunker: self,
+            rest: buf,
+        }
+    }
+}
+
+/// Iterator over the chunks of a buffer, see `Chunker::chunks`
+pub struct ChunkIter<'a, 'c, C> {
+    chunker: &'c mut C,
+    rest: &'a [u8],
+}
+
+impl<'a, 'c, C: Chunker> Iterator for ChunkIter<'a, 'c, C> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let len = match self.chunker.find_chunk_edge(self.rest) {
+            Some((i, _)) => i,
+            None => self.rest.len(),
+        };
+        let (chunk, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Some(chunk)
+    }
 }
 
+impl<'a, 'c, C: Chunker> iter::FusedIterator for ChunkIter<'a, 'c, C> {}
+
+impl<C: Chunker + ?Sized> Chunker for &mut C {
+    type Digest = C::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, C::Digest)> {
+        (**self).find_chunk_edge(buf)
+    }
+
+    fn find_chunk_edge_result(&mut self, buf: &[u8]) -> EdgeResult<C::Digest> {
+        (**self).find_chunk_edge_result(buf)
+    }
+
+    fn find_chunk_edge_budget(&mut self, buf: &[u8], budget: usize) -> EdgeResult<C::Digest> {
+        (**self).find_chunk_edge_budget(buf, budget)
+    }
+
+    fn find_chunk_edge_limited(&mut self, buf: &[u8], max_scan: usize) -> ScanStop<C::Digest> {
+        (**self).find_chunk_edge_limited(buf, max_scan)
+    }
+
+    fn find_chunk_edge_chained(&mut self, bufs: &[&[u8]]) -> Option<(usize, C::Digest)> {
+        (**self).find_chunk_edge_chained(bufs)
+    }
+
+    fn find_chunk_edge_deque(&mut self, buf: &VecDeque<u8>) -> Option<(usize, C::Digest)> {
+        (**self).find_chunk_edge_deque(buf)
+    }
+
+    fn find_chunk_edge_iter<I>(&mut self, iter: &mut I) -> EdgeResult<C::Digest>
+    where
+        I: Iterator<Item = u8>,
+    {
+        (**self).find_chunk_edge_iter(iter)
+    }
+}
+
+/// Roll `engine` over the last `window_size` bytes of `data`
+///
+/// For engines whose digest only depends on the last `window_size` bytes
+/// rolled over, this has the same effect on the digest as rolling over the
+/// whole of `data`, without the cost. Custom engines can use it to implement
+/// `Engine::roll`.
+///
+/// Only valid if no other state of the engine depends on the skipped bytes
+/// (e.g. a byte counter). Panics if `window_size` is 0.
 #[inline]
-fn roll_windowed<E: Engine>(engine: &mut E, window_size: usize, data: &[u8]) {
+pub fn roll_windowed<E: Engine>(engine: &mut E, window_size: usize, data: &[u8]) {
     let last_window = data.windows(window_size).next_back().unwrap_or(data);
     for &b in last_window {
         engine.roll_byte(b);
     }
 }
 
+/// Convert a chunk size to `usize`, saturating sizes beyond the address space
+pub(crate) fn saturating_usize(size: u64) -> usize {
+    usize::try_from(size).unwrap_or(usize::MAX)
+}
+
+/// Return the bytes a chunker left unconsumed followed by `data`
+///
+/// Chunkers which only report an edge once they have seen the data after
+/// it, like `BorgChunker`, leave the bytes they could not decide on yet
+/// unconsumed in `find_chunk_edge_result`. Drivers keep them in `unconsumed`
+/// and pass them again with the next data, moved into `joined` with it
+/// unless there are none. At the end of the stream, they join the last
+/// chunk.
+pub(crate) fn join_unconsumed<'a>(
+    unconsumed: &mut Vec<u8>,
+    data: &'a [u8],
+    joined: &'a mut Vec<u8>,
+) -> &'a [u8] {
+    if unconsumed.is_empty() {
+        return data;
+    }
+    *joined = std::mem::take(unconsumed);
+    joined.extend_from_slice(data);
+    joined
+}
+
+/// Read from `reader` into `buf`, retrying interrupted reads
+pub(crate) fn read_block<R: io::Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Call `f` with the stream offset and contents of every block read from
+/// `reader`, until the end of the stream or `f` returns `false`
+///
+/// Return the number of bytes read.
+pub(crate) fn for_each_block<R, F>(mut reader: R, mut f: F) -> io::Result<u64>
+where
+    R: io::Read,
+    F: FnMut(u64, &[u8]) -> io::Result<bool>,
+{
+    let mut buf = vec![0; 64 * 1024];
+    let mut position = 0;
+    loop {
+        let n = read_block(&mut reader, &mut buf)?;
+        if n == 0 {
+            return Ok(position);
+        }
+        let more = f(position, &buf[..n])?;
+        position += n as u64;
+        if !more {
+            return Ok(position);
+        }
+    }
+}
+
+/// Split everything read from `reader` into chunks, calling `f` with the
+/// stream offset and contents of each chunk
+pub(crate) fn for_each_chunk<R, C, F>(reader: R, chunker: &mut C, f: F) -> io::Result<()>
+where
+    R: io::Read,
+    C: Chunker,
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    for_each_chunk_reporting(reader, chunker, |_| {}, f).map(drop)
+}
+
+/// `for_each_chunk`, also calling `read` after every read with the number of
+/// bytes read so far
+///
+/// Return the number of bytes read.
+pub(crate) fn for_each_chunk_reporting<R, C, P, F>(
+    reader: R,
+    chunker: &mut C,
+    mut read: P,
+    mut f: F,
+) -> io::Result<u64>
+where
+    R: io::Read,
+    C: Chunker,
+    P: FnMut(u64),
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    // Owned mode always returns the contents of chunks
+    let mut stream = StreamChunker::owned(chunker);
+    let total = for_each_block(reader, |_, block| {
+        for chunk in stream.push(block) {
+            f(chunk.offset, &chunk.data.unwrap_or_default())?;
+        }
+        read(stream.position());
+        Ok(true)
+    })?;
+    if let Some(chunk) = stream.finish() {
+        f(chunk.offset, &chunk.data.unwrap_or_default())?;
+    }
+    Ok(total)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use nanorand::{Rng, WyRand};
-    use std::collections::HashSet;
 
     pub(crate) fn rand_data(len: usize) -> Vec<u8> {
         let mut data = vec![0; len];
@@ -80,214 +883,706 @@ mod tests {
         data
     }
 
-    fn test_roll_byte_same_as_roll<E>()
-    where
-        E: Engine,
-        E: Default,
-        <E as Engine>::Digest: PartialEq,
-        <E as Engine>::Digest: std::fmt::Debug,
-    {
-        let mut engine1 = E::default();
-        let mut engine2 = E::default();
-
-        let data = rand_data(1024);
-        for (i, &b) in data.iter().enumerate() {
-            engine1.roll_byte(b);
+    /// Return the sizes of the chunks of `data` ended by an edge
+    pub(crate) fn chunk_sizes<C: Chunker>(chunker: &mut C, mut data: &[u8]) -> Vec<usize> {
+        let mut sizes = Vec::new();
+        while let Some((i, _)) = chunker.find_chunk_edge(data) {
+            sizes.push(i);
+            data = &data[i..];
+        }
+        sizes
+    }
 
-            engine2.reset();
-            engine2.roll(&data[..=i]);
-            assert_eq!(engine1.digest(), engine2.digest());
+    /// Return the offsets of the edges in `data`
+    pub(crate) fn chunk_edges<C: Chunker>(chunker: &mut C, data: &[u8]) -> Vec<usize> {
+        chunk_sizes(chunker, data)
+            .iter()
+            .scan(0, |offset, &size| {
+                *offset += size;
+                Some(*offset)
+            })
+            .collect()
+    }
 
-            let mut engine3 = E::default();
-            engine3.roll(&data[..=i]);
-            assert_eq!(engine1.digest(), engine3.digest());
+    /// Return the offsets of the edges in `data`, passed to the chunker in
+    /// buffers of `frame` bytes, after the bytes it left unconsumed
+    pub(crate) fn chunk_edges_framed<C: Chunker>(
+        chunker: &mut C,
+        data: &[u8],
+        frame: usize,
+    ) -> Vec<usize> {
+        let (mut edges, mut position, mut unconsumed) = (Vec::new(), 0, Vec::new());
+        for frame_data in data.chunks(frame) {
+            let mut joined = Vec::new();
+            let mut buf = join_unconsumed(&mut unconsumed, frame_data, &mut joined);
+            loop {
+                match chunker.find_chunk_edge_result(buf) {
+                    EdgeResult::Found { offset, .. } => {
+                        position += offset;
+                        edges.push(position);
+                        buf = &buf[offset..];
+                    }
+                    EdgeResult::NeedMore { consumed } => {
+                        position += consumed;
+                        unconsumed.extend_from_slice(&buf[consumed..]);
+                        break;
+                    }
+                }
+            }
         }
+        edges
     }
 
-    fn test_chunk_edge_correct_digest<E>()
-    where
-        E: Engine,
-        E: Default,
-        E::Digest: PartialEq,
-        E::Digest: From<u16>,
-        E::Digest: Copy,
-        E::Digest: std::ops::BitAnd<Output = E::Digest>,
-        E::Digest: std::fmt::Debug,
-    {
-        let mut engine1 = E::default();
+    /// Return `data` with 14 bytes inserted after its first 1000
+    pub(crate) fn with_insert(data: &[u8]) -> Vec<u8> {
+        let mut modified = data[..1000].to_vec();
+        modified.extend_from_slice(b"inserted bytes");
+        modified.extend_from_slice(&data[1000..]);
+        modified
+    }
 
-        let data = rand_data(512 * 1024);
-        let mut remaining = &data[..];
-        let mask = E::Digest::from(0x0FFF);
-        while let Some((i, digest)) =
-            engine1.find_chunk_edge_cond(remaining, |e| e.digest() & mask == mask)
+    /// Tests shared by the engines, run for each of them by `test_engine!`
+    #[cfg(any(
+        feature = "bup",
+        feature = "gear",
+        feature = "rabin",
+        feature = "adler32",
+        feature = "crc32",
+        feature = "rsync"
+    ))]
+    mod engines {
+        use super::*;
+        use std::collections::HashSet;
+
+        fn test_roll_byte_same_as_roll<E>()
+        where
+            E: Engine,
+            E: Default,
+            <E as Engine>::Digest: PartialEq,
+            <E as Engine>::Digest: std::fmt::Debug,
         {
-            assert_eq!(digest & mask, mask);
+            let mut engine1 = E::default();
             let mut engine2 = E::default();
-            engine2.roll(&remaining[..i]);
-            assert_eq!(engine2.digest(), digest);
-
-            // Ensure no previous digests matched the mask
-            engine2.reset();
-            for &b in &remaining[..i - 1] {
-                engine2.roll_byte(b);
-                assert_ne!(engine2.digest() & mask, mask)
+
+            let data = rand_data(1024);
+            for (i, &b) in data.iter().enumerate() {
+                engine1.roll_byte(b);
+
+                engine2.reset();
+                engine2.roll(&data[..=i]);
+                assert_eq!(engine1.digest(), engine2.digest());
+
+                let mut engine3 = E::default();
+                engine3.roll(&data[..=i]);
+                assert_eq!(engine1.digest(), engine3.digest());
             }
-            engine2.roll_byte(remaining[i - 1]);
-            assert_eq!(engine2.digest() & mask, mask);
-            assert_eq!(engine2.digest(), digest);
+            let mut engine4 = E::default();
+            engine4.roll_iter(data.iter().copied());
+            assert_eq!(engine1.digest(), engine4.digest());
+            let mut engine5 = E::default();
+            let reader = (&data[..100]).chain(&data[100..]);
+            assert_eq!(engine5.roll_from_reader(reader).unwrap(), data.len() as u64);
+            assert_eq!(engine1.digest(), engine5.digest());
+        }
+
+        fn test_chunk_edge_correct_digest<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: From<u16>,
+            E::Digest: Copy,
+            E::Digest: std::ops::BitAnd<Output = E::Digest>,
+            E::Digest: std::fmt::Debug,
+        {
+            let mut engine1 = E::default();
+
+            let data = rand_data(512 * 1024);
+            let mut remaining = &data[..];
+            let mask = E::Digest::from(0x0FFF);
+            while let Some((i, digest)) =
+                engine1.find_chunk_edge_cond(remaining, |e| e.digest() & mask == mask)
+            {
+                assert_eq!(digest & mask, mask);
+                let mut engine2 = E::default();
+                engine2.roll(&remaining[..i]);
+                assert_eq!(engine2.digest(), digest);
+
+                // Ensure no previous digests matched the mask
+                engine2.reset();
+                for &b in &remaining[..i - 1] {
+                    engine2.roll_byte(b);
+                    assert_ne!(engine2.digest() & mask, mask)
+                }
+                engine2.roll_byte(remaining[i - 1]);
+                assert_eq!(engine2.digest() & mask, mask);
+                assert_eq!(engine2.digest(), digest);
 
-            remaining = &remaining[i..];
-            engine2.reset();
-            assert_eq!(engine2.digest(), engine1.digest());
+                remaining = &remaining[i..];
+                engine2.reset();
+                assert_eq!(engine2.digest(), engine1.digest());
+            }
+            let mut engine2 = E::default();
+            engine2.roll(&data);
+            assert_eq!(engine1.digest(), engine2.digest());
         }
-        let mut engine2 = E::default();
-        engine2.roll(&data);
-        assert_eq!(engine1.digest(), engine2.digest());
-    }
 
-    fn chunk<E, F>(mut data: &[u8], f: F) -> Vec<&[u8]>
-    where
-        E: Engine,
-        E: Default,
-        F: Fn(&E) -> bool,
-    {
-        let mut engine = E::default();
-        let mut result = Vec::new();
+        fn chunk<E, F>(mut data: &[u8], f: F) -> Vec<&[u8]>
+        where
+            E: Engine,
+            E: Default,
+            F: Fn(&E) -> bool,
+        {
+            let mut engine = E::default();
+            let mut result = Vec::new();
 
-        while let Some((i, _)) = engine.find_chunk_edge_cond(data, &f) {
-            result.push(&data[..i]);
-            data = &data[i..];
+            while let Some((i, _)) = engine.find_chunk_edge_cond(data, &f) {
+                result.push(&data[..i]);
+                data = &data[i..];
+            }
+            result.push(data);
+
+            result
         }
-        result.push(data);
 
-        result
-    }
+        fn test_chunk_edge_converges<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: From<u16>,
+            E::Digest: Copy,
+            E::Digest: std::ops::BitAnd<Output = E::Digest>,
+            E::Digest: std::fmt::Debug,
+        {
+            let data = rand_data(64 * 1024);
+            let mask = E::Digest::from(0x0FFF);
 
-    fn test_chunk_edge_converges<E>()
-    where
-        E: Engine,
-        E: Default,
-        E::Digest: PartialEq,
-        E::Digest: From<u16>,
-        E::Digest: Copy,
-        E::Digest: std::ops::BitAnd<Output = E::Digest>,
-        E::Digest: std::fmt::Debug,
-    {
-        let data = rand_data(64 * 1024);
-        let mask = E::Digest::from(0x0FFF);
+            let f = |e: &E| e.digest() & mask == mask;
+            let chunks = chunk(&data, f);
+            for i in 1..300 {
+                let other_chunks = chunk(&data[i..], f);
+                // ensure the last several chunks are equal
+                let len = chunks.len() - 3;
+                assert_eq!(
+                    chunks.windows(len).last().unwrap(),
+                    other_chunks.windows(len).last().unwrap()
+                );
+            }
+        }
 
-        let f = |e: &E| e.digest() & mask == mask;
-        let chunks = chunk(&data, f);
-        for i in 1..300 {
-            let other_chunks = chunk(&data[i..], f);
-            // ensure the last several chunks are equal
-            let len = chunks.len() - 3;
-            assert_eq!(
-                chunks.windows(len).last().unwrap(),
-                other_chunks.windows(len).last().unwrap()
-            );
+        fn test_chunk_edge_with_insert<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: From<u16>,
+            E::Digest: Copy,
+            E::Digest: std::ops::BitAnd<Output = E::Digest>,
+            E::Digest: std::fmt::Debug,
+        {
+            let mut data = rand_data(1024 * 1024);
+            let mask = E::Digest::from(0x0FFF);
+            let f = |e: &E| e.digest() & mask == mask;
+            let chunks: HashSet<Vec<_>> = chunk(&data, f).iter().map(|x| x.to_vec()).collect();
+            data.insert(5000, b'!');
+            let other_chunks: HashSet<Vec<_>> =
+                chunk(&data, f).iter().map(|x| x.to_vec()).collect();
+            let different_chunks = chunks.symmetric_difference(&other_chunks).count();
+            assert!(chunks.len() > 100);
+            assert!(other_chunks.len() > 100);
+            assert!(different_chunks < 4);
         }
-    }
 
-    fn test_chunk_edge_with_insert<E>()
-    where
-        E: Engine,
-        E: Default,
-        E::Digest: PartialEq,
-        E::Digest: From<u16>,
-        E::Digest: Copy,
-        E::Digest: std::ops::BitAnd<Output = E::Digest>,
-        E::Digest: std::fmt::Debug,
-    {
-        let mut data = rand_data(1024 * 1024);
-        let mask = E::Digest::from(0x0FFF);
-        let f = |e: &E| e.digest() & mask == mask;
-        let chunks: HashSet<Vec<_>> = chunk(&data, f).iter().map(|x| x.to_vec()).collect();
-        data.insert(5000, b'!');
-        let other_chunks: HashSet<Vec<_>> = chunk(&data, f).iter().map(|x| x.to_vec()).collect();
-        let different_chunks = chunks.symmetric_difference(&other_chunks).count();
-        assert!(chunks.len() > 100);
-        assert!(other_chunks.len() > 100);
-        assert!(different_chunks < 4);
-    }
-
-    fn test_chunk_edge_incremental<E>()
-    where
-        E: Engine,
-        E: Default,
-        E::Digest: PartialEq,
-        E::Digest: From<u16>,
-        E::Digest: Copy,
-        E::Digest: std::ops::BitAnd<Output = E::Digest>,
-        E::Digest: std::fmt::Debug,
-    {
-        // Use a value that won't be a multiple of the window size (a prime)
-        const INCREMENTAL_SIZE: usize = 307;
-        let data = rand_data(1024 * 1024);
-        let mask = E::Digest::from(0x0FFF);
-        let f = |e: &E| e.digest() & mask == mask;
-
-        let mut engine1 = E::default();
-        let mut last_edge = 0;
-        for (frame_i, frame) in data.chunks(INCREMENTAL_SIZE).enumerate() {
-            let mut engine2 = E::default();
-            let mut consumed = 0;
-            while let Some((off, digest)) = engine1.find_chunk_edge_cond(&frame[consumed..], f) {
-                consumed += off;
-                let actual_edge = frame_i * INCREMENTAL_SIZE + consumed;
+        fn test_chunk_edge_incremental<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: From<u16>,
+            E::Digest: Copy,
+            E::Digest: std::ops::BitAnd<Output = E::Digest>,
+            E::Digest: std::fmt::Debug,
+        {
+            // Use a value that won't be a multiple of the window size (a prime)
+            const INCREMENTAL_SIZE: usize = 307;
+            let data = rand_data(1024 * 1024);
+            let mask = E::Digest::from(0x0FFF);
+            let f = |e: &E| e.digest() & mask == mask;
+
+            let mut engine1 = E::default();
+            let mut last_edge = 0;
+            for (frame_i, frame) in data.chunks(INCREMENTAL_SIZE).enumerate() {
+                let mut engine2 = E::default();
+                let mut consumed = 0;
+                while let Some((off, digest)) = engine1.find_chunk_edge_cond(&frame[consumed..], f)
+                {
+                    consumed += off;
+                    let actual_edge = frame_i * INCREMENTAL_SIZE + consumed;
+                    assert_eq!(
+                        engine2.find_chunk_edge_cond(&data[last_edge..], f),
+                        Some((actual_edge - last_edge, digest)),
+                    );
+                    last_edge = actual_edge;
+                }
                 assert_eq!(
-                    engine2.find_chunk_edge_cond(&data[last_edge..], f),
-                    Some((actual_edge - last_edge, digest)),
+                    engine2.find_chunk_edge_cond(
+                        &data[last_edge..frame_i * INCREMENTAL_SIZE + frame.len()],
+                        f
+                    ),
+                    None,
                 );
-                last_edge = actual_edge;
             }
-            assert_eq!(
-                engine2.find_chunk_edge_cond(
-                    &data[last_edge..frame_i * INCREMENTAL_SIZE + frame.len()],
-                    f
-                ),
-                None,
-            );
         }
-    }
 
-    macro_rules! test_engine {
-        ($name:ident, $engine:ty) => {
-            mod $name {
-                use super::*;
+        fn test_chunk_edge_chained<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: From<u16>,
+            E::Digest: Copy,
+            E::Digest: std::ops::BitAnd<Output = E::Digest>,
+            E::Digest: std::fmt::Debug,
+        {
+            let data = rand_data(256 * 1024);
+            let mask = E::Digest::from(0x0FFF);
+            let f = |e: &E| e.digest() & mask == mask;
 
-                #[test]
-                fn roll_byte_same_as_roll() {
-                    test_roll_byte_same_as_roll::<$engine>()
+            let mut engine1 = E::default();
+            let mut engine2 = E::default();
+            let mut remaining = &data[..];
+            while !remaining.is_empty() {
+                let split = remaining.len().min(101);
+                let (a, rest) = remaining.split_at(split);
+                let (b, c) = rest.split_at(rest.len().min(7));
+                let chained = engine1.find_chunk_edge_cond_chained(&[a, &[], b, c], f);
+                assert_eq!(chained, engine2.find_chunk_edge_cond(remaining, f));
+                match chained {
+                    Some((i, _)) => remaining = &remaining[i..],
+                    None => break,
                 }
+            }
+            assert_eq!(engine1.digest(), engine2.digest());
 
-                #[test]
-                fn chunk_edge_correct_digest() {
-                    test_chunk_edge_correct_digest::<$engine>()
+            let mut engine1 = E::default();
+            let mut engine2 = E::default();
+            let (a, b) = data.split_at(1000);
+            let (b, c) = b.split_at(10);
+            engine1.roll_chained(&[a, b, c]);
+            engine2.roll(&data);
+            assert_eq!(engine1.digest(), engine2.digest());
+        }
+
+        fn test_digest_of<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: std::fmt::Debug,
+        {
+            let data = rand_data(1024);
+            for len in 0..200 {
+                let mut engine = E::default();
+                engine.roll(&data[..len]);
+                assert_eq!(E::digest_of(&data[..len]), engine.digest());
+            }
+        }
+
+        fn test_combine<E>()
+        where
+            E: Engine,
+            E: Default,
+            E::Digest: PartialEq,
+            E::Digest: std::fmt::Debug,
+        {
+            let data = rand_data(1024);
+            for &split in &[0, 1, 10, 63, 64, 65, 100, 1000, 1024] {
+                let (mut left, mut right) = (E::default(), E::default());
+                left.roll(&data[..split]);
+                right.roll(&data[split..]);
+                let mut combined = E::combine(&left, &right, (data.len() - split) as u64)
+                    .expect("engine documented as combinable");
+                let mut whole = E::default();
+                whole.roll(&data);
+                assert_eq!(combined.digest(), whole.digest());
+                assert_eq!(combined.bytes_until_warm(), whole.bytes_until_warm());
+                // The combined state keeps rolling like the whole one
+                for &b in &data[..100] {
+                    combined.roll_byte(b);
+                    whole.roll_byte(b);
+                    assert_eq!(combined.digest(), whole.digest());
                 }
+            }
+        }
+
+        fn test_warmed_up<E>()
+        where
+            E: Engine,
+            E: Default,
+        {
+            let data = rand_data(1024);
+            let window = E::default().window_size().unwrap();
+
+            let mut engine = E::default();
+            for (i, &b) in data[..window].iter().enumerate() {
+                assert_eq!(engine.bytes_until_warm(), window - i);
+                engine.roll_byte(b);
+            }
+            assert!(engine.warmed_up());
+
+            let mut engine = E::default();
+            engine.roll(&data[..window - 1]);
+            assert_eq!(engine.bytes_until_warm(), 1);
+            engine.roll(&data[..3]);
+            assert!(engine.warmed_up());
+
+            // An edge wherever the window has just been filled
+            let mut engine = E::default();
+            let edge = engine.find_chunk_edge_cond(&data[..window - 1], |e: &E| e.warmed_up());
+            assert_eq!(edge.map(|(i, _)| i), None);
+            let edge = engine.find_chunk_edge_cond(&data, |e: &E| e.warmed_up());
+            assert_eq!(edge.map(|(i, _)| i), Some(1));
+            assert!(!engine.warmed_up());
+        }
+
+        macro_rules! test_engine {
+            ($name:ident, $engine:ty) => {
+                mod $name {
+                    use super::*;
+
+                    #[test]
+                    fn roll_byte_same_as_roll() {
+                        test_roll_byte_same_as_roll::<$engine>()
+                    }
+
+                    #[test]
+                    fn chunk_edge_correct_digest() {
+                        test_chunk_edge_correct_digest::<$engine>()
+                    }
 
-                #[test]
-                fn chunk_edge_converges() {
-                    test_chunk_edge_converges::<$engine>()
+                    #[test]
+                    fn chunk_edge_converges() {
+                        test_chunk_edge_converges::<$engine>()
+                    }
+
+                    #[test]
+                    fn chunk_edge_with_insert() {
+                        test_chunk_edge_with_insert::<$engine>()
+                    }
+
+                    #[test]
+                    fn chunk_edge_incremental() {
+                        test_chunk_edge_incremental::<$engine>()
+                    }
+
+                    #[test]
+                    fn digest_of() {
+                        test_digest_of::<$engine>()
+                    }
+
+                    #[test]
+                    fn chunk_edge_chained() {
+                        test_chunk_edge_chained::<$engine>()
+                    }
+
+                    #[test]
+                    fn warmed_up() {
+                        test_warmed_up::<$engine>()
+                    }
+
+                    #[test]
+                    fn combine() {
+                        test_combine::<$engine>()
+                    }
                 }
+            };
+        }
+
+        #[cfg(feature = "bup")]
+        test_engine!(bup, Bup);
+
+        #[cfg(feature = "bup")]
+        test_engine!(bup_dyn, crate::bup::BupDyn);
+
+        #[cfg(feature = "bup")]
+        test_engine!(bup64, crate::Bup64);
+
+        #[cfg(feature = "gear")]
+        test_engine!(gear, Gear);
+
+        #[cfg(feature = "fastcdc")]
+        test_engine!(fastcdc, crate::FastCdc);
 
-                #[test]
-                fn chunk_edge_with_insert() {
-                    test_chunk_edge_with_insert::<$engine>()
+        #[cfg(feature = "rabin")]
+        test_engine!(rabin, crate::Rabin);
+
+        #[cfg(feature = "adler32")]
+        test_engine!(adler32, crate::Adler32);
+
+        #[cfg(feature = "crc32")]
+        test_engine!(crc32, crate::Crc32);
+
+        #[cfg(feature = "rsync")]
+        test_engine!(rsync, crate::RsyncSum);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn chunk_edge_deque() {
+        let data = rand_data(64 * 1024);
+        let mut deque = VecDeque::with_capacity(data.len());
+        deque.extend(&data[data.len() / 2..]);
+        for &b in data[..data.len() / 2].iter().rev() {
+            deque.push_front(b);
+        }
+        assert!(!deque.as_slices().1.is_empty());
+
+        let mut gear1 = Gear::new_with_chunk_bits(10);
+        let mut gear2 = Gear::new_with_chunk_bits(10);
+        let edge = gear1.find_chunk_edge_deque(&deque);
+        assert!(edge.is_some());
+        assert_eq!(edge, gear2.find_chunk_edge(&data));
+    }
+
+    #[cfg(all(feature = "bup", feature = "gear"))]
+    #[test]
+    fn combine_mismatched_engines() {
+        let gear = Gear::new();
+        let seeded = Gear::with_seed(crate::gear::CHUNK_BITS, 1);
+        assert!(Gear::combine(&gear, &seeded, 10).is_none());
+        assert!(Gear::combine(&gear, &Gear::new(), 10).is_some());
+        let bup = crate::BupDyn::new(64, 10);
+        assert!(crate::BupDyn::combine(&bup, &crate::BupDyn::new(100, 10), 10).is_none());
+        assert!(crate::BupDyn::combine(&bup, &crate::BupDyn::new(64, 11), 10).is_none());
+        let bup = Bup::new_with_chunk_bits(10);
+        assert!(Bup::combine(&bup, &Bup::new_with_chunk_bits(11), 10).is_none());
+        assert!(Bup::combine(&bup, &Bup::new_with_chunk_bits(10), 10).is_some());
+        let bup64 = crate::Bup64::new_with_chunk_bits(10);
+        assert!(
+            crate::Bup64::combine(&bup64, &crate::Bup64::new_with_chunk_bits(11), 10).is_none()
+        );
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn chunks_iterates_over_chunks() {
+        let data = rand_data(64 * 1024);
+        let mut gear1 = Gear::new_with_chunk_bits(10);
+        let mut gear2 = Gear::new_with_chunk_bits(10);
+        let chunks: Vec<&[u8]> = gear1.chunks(&data).collect();
+        assert!(chunks.len() > 10);
+        assert_eq!(chunks.concat(), data);
+
+        let mut rest = &data[..];
+        for chunk in &chunks[..chunks.len() - 1] {
+            let (i, _) = gear2.find_chunk_edge(rest).unwrap();
+            assert_eq!(*chunk, &rest[..i]);
+            rest = &rest[i..];
+        }
+        assert_eq!(gear2.find_chunk_edge(rest), None);
+        assert_eq!(chunks[chunks.len() - 1], rest);
+        assert_eq!(gear1.chunks(&[]).next(), None);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn roll_windowed_matches_roll_byte() {
+        let data = rand_data(1000);
+        for &len in &[0, 1, 63, 64, 65, 1000] {
+            let mut gear1 = Gear::new();
+            let mut gear2 = Gear::new();
+            roll_windowed(&mut gear1, crate::gear::WINDOW_SIZE, &data[..len]);
+            data[..len].iter().for_each(|&b| gear2.roll_byte(b));
+            assert_eq!(gear1.digest(), gear2.digest());
+        }
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn edge_result_reports_consumed() {
+        let data = rand_data(64 * 1024);
+        let mut bup1 = Bup::new_with_chunk_bits(10);
+        let mut bup2 = Bup::new_with_chunk_bits(10);
+        let mut remaining = &data[..];
+        loop {
+            let result = bup1.find_chunk_edge_result(remaining);
+            assert_eq!(result.edge(), bup2.find_chunk_edge(remaining));
+            match result {
+                EdgeResult::Found { offset, .. } => {
+                    assert_eq!(result.consumed(), offset);
+                    remaining = &remaining[offset..];
+                }
+                EdgeResult::NeedMore { consumed } => {
+                    assert_eq!(consumed, remaining.len());
+                    break;
                 }
+            }
+        }
+    }
 
-                #[test]
-                fn chunk_edge_incremental() {
-                    test_chunk_edge_incremental::<$engine>()
+    #[cfg(feature = "gear")]
+    #[test]
+    fn edge_from_iterator_matches_slice() {
+        let data = rand_data(64 * 1024);
+        let mut expected = Vec::new();
+        let mut gear = Gear::new_with_chunk_bits(10);
+        let mut offset = 0;
+        while let Some((i, digest)) = gear.find_chunk_edge(&data[offset..]) {
+            offset += i;
+            expected.push((offset, digest));
+        }
+
+        let edges = |found: &mut dyn FnMut(&mut std::slice::Iter<u8>) -> EdgeResult<u64>| {
+            let mut iter = data.iter();
+            let mut edges = Vec::new();
+            let mut offset = 0;
+            loop {
+                match found(&mut iter) {
+                    EdgeResult::Found { offset: i, digest } => {
+                        offset += i;
+                        edges.push((offset, digest));
+                    }
+                    EdgeResult::NeedMore { consumed } => {
+                        assert_eq!(offset + consumed, data.len());
+                        return edges;
+                    }
                 }
             }
         };
+        let mut gear = Gear::new_with_chunk_bits(10);
+        assert_eq!(
+            edges(&mut |iter| gear.find_chunk_edge_iter(&mut iter.copied())),
+            expected
+        );
+        let mut gear = Gear::new_with_chunk_bits(10);
+        let cond = |e: &Gear| e.digest() >> 54 == 0;
+        assert_eq!(
+            edges(&mut |iter| gear.find_chunk_edge_cond_iter(&mut iter.copied(), cond)),
+            expected
+        );
     }
 
-    #[cfg(feature = "bup")]
-    test_engine!(bup, Bup);
+    #[cfg(feature = "gear")]
+    #[test]
+    fn edge_limited_reports_stop_reason() {
+        let data = rand_data(64 * 1024);
+        let mut expected = Vec::new();
+        let mut gear = Gear::new_with_chunk_bits(10);
+        let mut offset = 0;
+        while let Some((i, _)) = gear.find_chunk_edge(&data[offset..]) {
+            offset += i;
+            expected.push(offset);
+        }
+
+        let first = expected[0];
+        let mut gear = Gear::new_with_chunk_bits(10);
+        assert_eq!(
+            gear.find_chunk_edge_limited(&data, first - 1),
+            ScanStop::Limit {
+                consumed: first - 1
+            }
+        );
+        assert!(matches!(
+            gear.find_chunk_edge_limited(&data[first - 1..], 1),
+            ScanStop::Edge { offset: 1, .. }
+        ));
+        let end = expected[1] - 1;
+        assert_eq!(
+            gear.find_chunk_edge_limited(&data[first..end], usize::MAX),
+            ScanStop::Exhausted {
+                consumed: end - first
+            }
+        );
+        assert_eq!(
+            gear.find_chunk_edge_limited(&data[end..], 0),
+            ScanStop::Limit { consumed: 0 }
+        );
+        assert!(matches!(
+            gear.find_chunk_edge_limited(&data[end..], 10),
+            ScanStop::Edge { offset: 1, .. }
+        ));
+    }
 
+    #[test]
     #[cfg(feature = "gear")]
-    test_engine!(gear, Gear);
+    fn edge_budget_resumes() {
+        let data = rand_data(64 * 1024);
+        let mut gear1 = Gear::new_with_chunk_bits(10);
+        let mut gear2 = Gear::new_with_chunk_bits(10);
+        let mut offset = 0;
+        let mut calls = 0;
+        let mut edges = Vec::new();
+        while offset < data.len() {
+            calls += 1;
+            match gear1.find_chunk_edge_budget(&data[offset..], 100) {
+                EdgeResult::Found { offset: i, .. } => {
+                    assert!(i <= 100);
+                    offset += i;
+                    edges.push(offset);
+                }
+                EdgeResult::NeedMore { consumed } => {
+                    assert!(consumed <= 100);
+                    offset += consumed;
+                }
+            }
+        }
+        assert!(calls >= data.len() / 100);
+
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        while let Some((i, _)) = gear2.find_chunk_edge(&data[offset..]) {
+            offset += i;
+            expected.push(offset);
+        }
+        assert_eq!(edges, expected);
+    }
+
+    #[cfg(any(
+        feature = "bup",
+        feature = "adler32",
+        feature = "rsync",
+        feature = "librsync"
+    ))]
+    fn test_slide<E>(mut engine: E)
+    where
+        E: SlidingEngine + Clone,
+        E::Digest: PartialEq + std::fmt::Debug,
+    {
+        let data = rand_data(8 * 1024);
+        let w = engine.window_size().unwrap();
+        let mut rolled = engine.clone();
+        for (i, &b) in data.iter().enumerate() {
+            let old = if i < w { 0 } else { data[i - w] };
+            engine.slide(old, b);
+            rolled.roll_byte(b);
+            assert_eq!(engine.digest(), rolled.digest());
+        }
+        // The window of the engine is kept, so rolling can resume
+        engine.roll(&data[..100]);
+        rolled.roll(&data[..100]);
+        assert_eq!(engine.digest(), rolled.digest());
+    }
+
+    #[cfg(any(
+        feature = "bup",
+        feature = "adler32",
+        feature = "rsync",
+        feature = "librsync"
+    ))]
+    #[test]
+    fn slide_matches_roll_byte() {
+        #[cfg(feature = "bup")]
+        {
+            test_slide(Bup::default());
+            test_slide(crate::BupDyn::new(100, 10));
+            test_slide(crate::Bup64::default());
+        }
+        #[cfg(feature = "adler32")]
+        test_slide(crate::Adler32::with_window(100, 10));
+        #[cfg(feature = "rsync")]
+        test_slide(crate::RsyncSum::default());
+        #[cfg(feature = "librsync")]
+        test_slide(crate::librsync::Rollsum::default());
+    }
 }
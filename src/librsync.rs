@@ -0,0 +1,194 @@
+use super::{Engine, SlidingEngine};
+use std::cmp;
+use std::convert::TryFrom;
+
+pub type Digest = u32;
+
+/// Offset added to every byte, librsync's `ROLLSUM_CHAR_OFFSET`
+pub const CHAR_OFFSET: u32 = 31;
+
+/// Default block length of rdiff signatures
+pub const BLOCK_LEN: usize = 2048;
+
+/// librsync's `RollSum` checksum over a sliding block
+///
+/// This is the weak checksum of rdiff signatures made with
+/// `--hash=md4` or `--hash=blake2` and `--rollsum=rollsum` (not the default
+/// RabinKarp one of librsync 2.2). It is the rsync/bup checksum, but unlike
+/// `Bup` the sums start at zero instead of counting a window of zeros, and
+/// the weighted sum is packed in the high 16 bits. Before the block is
+/// filled, the digest is the checksum of the bytes rolled so far, as for
+/// librsync's shorter last block.
+#[derive(Clone)]
+pub struct Rollsum {
+    s1: u32,
+    s2: u32,
+    window: Box<[u8]>,
+    wofs: usize,
+    /// Bytes rolled over since the last reset, up to the block length
+    filled: usize,
+}
+
+impl Default for Rollsum {
+    fn default() -> Self {
+        Self::new(BLOCK_LEN)
+    }
+}
+
+impl Rollsum {
+    /// Create a new engine summing `block_len` byte blocks
+    pub fn new(block_len: usize) -> Self {
+        assert!(block_len > 0);
+        Rollsum {
+            s1: 0,
+            s2: 0,
+            window: vec![0; block_len].into_boxed_slice(),
+            wofs: 0,
+            filled: 0,
+        }
+    }
+
+    /// Return the block length
+    pub fn block_len(&self) -> usize {
+        self.window.len()
+    }
+}
+
+/// Return the weak checksum of `block`, librsync's `RollsumUpdate` on a new
+/// sum
+pub fn weak_sum(block: &[u8]) -> Digest {
+    let mut sum = Rollsum::new(cmp::max(block.len(), 1));
+    sum.roll(block);
+    sum.digest()
+}
+
+impl Engine for Rollsum {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let prevch = self.window[self.wofs];
+        self.slide(prevch, newch);
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        // Bytes rolled in before the last block are all rotated out again
+        crate::roll_windowed(self, self.window.len(), buf);
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        (self.s2 << 16) | (self.s1 & 0xffff)
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Only the last block matters, as for `Bup`
+        let block_len = left.window.len();
+        if block_len != right.window.len() {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(block_len, |len| cmp::min(len, block_len));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + block_len - take + i) % block_len]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.s1 = 0;
+        self.s2 = 0;
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl SlidingEngine for Rollsum {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        let block_len = self.window.len();
+        if self.filled < block_len {
+            // RollsumRollin
+            self.s1 = self.s1.wrapping_add(new as u32 + CHAR_OFFSET);
+            self.s2 = self.s2.wrapping_add(self.s1);
+            self.filled += 1;
+        } else {
+            // RollsumRotate
+            let out = old as u32;
+            self.s1 = self.s1.wrapping_add(new as u32).wrapping_sub(out);
+            self.s2 = self
+                .s2
+                .wrapping_add(self.s1)
+                .wrapping_sub((block_len as u32).wrapping_mul(out + CHAR_OFFSET));
+        }
+        self.window[self.wofs] = new;
+        self.wofs = (self.wofs + 1) % block_len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    /// Checksum of `block` computed directly from its definition
+    fn reference(block: &[u8]) -> Digest {
+        let n = block.len() as u32;
+        let (mut s1, mut s2) = (0u32, 0u32);
+        for (i, &b) in block.iter().enumerate() {
+            s1 = s1.wrapping_add(b as u32 + CHAR_OFFSET);
+            s2 = s2.wrapping_add((n - i as u32).wrapping_mul(b as u32 + CHAR_OFFSET));
+        }
+        (s2 << 16) | (s1 & 0xffff)
+    }
+
+    #[test]
+    fn small_sums() {
+        assert_eq!(weak_sum(b""), 0);
+        assert_eq!(weak_sum(b"a"), 0x0080_0080);
+        assert_eq!(weak_sum(b"ab"), 0x0181_0101);
+    }
+
+    #[test]
+    fn rolling_matches_blocks() {
+        let data = rand_data(16 * 1024);
+        for &block_len in &[1, 700, BLOCK_LEN] {
+            let mut sum = Rollsum::new(block_len);
+            for (i, &b) in data.iter().enumerate() {
+                sum.roll_byte(b);
+                let block = &data[(i + 1).saturating_sub(block_len)..=i];
+                assert_eq!(sum.digest(), reference(block));
+                if block.len() == block_len {
+                    assert_eq!(weak_sum(block), sum.digest());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combine_and_reset() {
+        let data = rand_data(8 * 1024);
+        let mut whole = Rollsum::new(700);
+        whole.roll(&data);
+        for &split in &[0, 100, 4000, 7900] {
+            let mut left = Rollsum::new(700);
+            left.roll(&data[..split]);
+            let mut right = Rollsum::new(700);
+            right.roll(&data[split..]);
+            let combined = Rollsum::combine(&left, &right, (data.len() - split) as u64).unwrap();
+            assert_eq!(combined.digest(), whole.digest());
+            right.reset();
+            assert_eq!(right.digest(), 0);
+            assert_eq!(right.bytes_until_warm(), 700);
+        }
+    }
+}
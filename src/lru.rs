@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+const NIL: usize = usize::MAX;
+
+/// Hit statistics of a `DigestCache`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups which found the digest
+    pub hits: u64,
+    /// Number of lookups which did not find the digest
+    pub misses: u64,
+    /// Number of digests evicted to make room for new ones
+    pub evictions: u64,
+}
+
+impl CacheStats {
+    /// Return the fraction of lookups which were hits
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+struct Node<K> {
+    key: K,
+    prev: usize,
+    next: usize,
+}
+
+/// Bounded cache of recently seen chunk digests, evicting the least recently
+/// used digest when full
+///
+/// Meant for marking chunks as likely duplicates inline while streaming,
+/// where remembering every digest ever seen is not an option. All operations
+/// are constant time.
+pub struct DigestCache<K> {
+    map: HashMap<K, usize>,
+    nodes: Vec<Node<K>>,
+    /// Most recently used node
+    head: usize,
+    /// Least recently used node
+    tail: usize,
+    capacity: usize,
+    stats: CacheStats,
+}
+
+impl<K: Hash + Eq + Clone> DigestCache<K> {
+    /// Create a cache holding at most `capacity` digests
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        DigestCache {
+            map: HashMap::with_capacity(capacity),
+            nodes: Vec::with_capacity(capacity),
+            head: NIL,
+            tail: NIL,
+            capacity,
+            stats: CacheStats::default(),
+        }
+    }
+
+    /// Look `key` up, then remember it as the most recently seen digest
+    ///
+    /// Returns whether `key` was already in the cache, and counts the lookup
+    /// in the statistics.
+    pub fn check(&mut self, key: &K) -> bool {
+        if let Some(&i) = self.map.get(key) {
+            self.stats.hits += 1;
+            self.unlink(i);
+            self.push_front(i);
+            true
+        } else {
+            self.stats.misses += 1;
+            self.insert_new(key.clone());
+            false
+        }
+    }
+
+    /// Return whether `key` is in the cache, without updating recency or
+    /// statistics
+    pub fn contains(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Remember `key` as the most recently seen digest, without counting a
+    /// lookup
+    pub fn insert(&mut self, key: K) {
+        if let Some(&i) = self.map.get(&key) {
+            self.unlink(i);
+            self.push_front(i);
+        } else {
+            self.insert_new(key);
+        }
+    }
+
+    /// Return the number of digests in the cache
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Return whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Return the maximum number of digests in the cache
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the hit statistics
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Forget all digests and statistics
+    pub fn clear(&mut self) {
+        self.map.clear();
+        self.nodes.clear();
+        self.head = NIL;
+        self.tail = NIL;
+        self.stats = CacheStats::default();
+    }
+
+    fn insert_new(&mut self, key: K) {
+        let i = if self.nodes.len() < self.capacity {
+            self.nodes.push(Node {
+                key: key.clone(),
+                prev: NIL,
+                next: NIL,
+            });
+            self.nodes.len() - 1
+        } else {
+            // Reuse the least recently used node
+            let i = self.tail;
+            self.unlink(i);
+            let old = std::mem::replace(&mut self.nodes[i].key, key.clone());
+            self.map.remove(&old);
+            self.stats.evictions += 1;
+            i
+        };
+        self.map.insert(key, i);
+        self.push_front(i);
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.nodes[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.nodes[next].prev = prev;
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = NIL;
+        self.nodes[i].next = self.head;
+        if self.head != NIL {
+            self.nodes[self.head].prev = i;
+        }
+        self.head = i;
+        if self.tail == NIL {
+            self.tail = i;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = DigestCache::new(3);
+        assert!(!cache.check(&1u32));
+        assert!(!cache.check(&2));
+        assert!(!cache.check(&3));
+        // 1 becomes the most recently used, so 2 is evicted next
+        assert!(cache.check(&1));
+        assert!(!cache.check(&4));
+        assert_eq!(cache.len(), 3);
+        assert!(cache.contains(&1));
+        assert!(!cache.contains(&2));
+        assert!(cache.contains(&3));
+        assert!(cache.contains(&4));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 4,
+                evictions: 1,
+            }
+        );
+        assert!((cache.stats().hit_ratio() - 0.2).abs() < 1e-9);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn marks_repeated_chunks() {
+        use crate::tests::rand_data;
+        use crate::Gear;
+
+        let block = rand_data(64 * 1024);
+        let data = [&block[..], &block[..]].concat();
+        let mut chunker = Gear::new_with_chunk_bits(10);
+        let mut cache = DigestCache::new(1024);
+        let mut rest = &data[..];
+        while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+            cache.check(&rest[..i].to_vec());
+            rest = &rest[i..];
+        }
+        let stats = cache.stats();
+        assert!(stats.hits > 0);
+        assert!(stats.hits + 2 >= stats.misses, "{:?}", stats);
+    }
+}
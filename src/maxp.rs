@@ -0,0 +1,174 @@
+use super::{Chunker, Engine};
+use std::collections::VecDeque;
+
+/// MAXP chunker, cutting at local maxima of the rolling digest
+///
+/// Every position has the digest of the window ending there, and a
+/// position is a cut point where its digest is the largest of the `horizon`
+/// positions on either side (the rightmost one on ties). No mask has to be
+/// tuned: cut points are at least `horizon + 1` bytes apart, and for random
+/// data about `2 * horizon + 1` apart on average. As a cut point is only
+/// known once the positions after it were seen, the edge is placed where it
+/// is confirmed, `horizon` bytes after it, which keeps edges content
+/// defined. An edge is forced once a chunk reaches `max_size` bytes, without
+/// moving the following cut points.
+///
+/// The engine rolls over the whole stream, across edges, so that cut points
+/// only depend on the data around them.
+///
+/// Based on "Content-Dependent Chunking for Differential Compression, the
+/// Local Maximum Approach" (Bjørner, Blass and Gurevich, JCSS 2010).
+pub struct MaxpChunker<E: Engine> {
+    engine: E,
+    k: usize,
+    horizon: usize,
+    max_size: usize,
+    /// Candidate maxima of the last `2 * horizon + 1` digests, as (index,
+    /// digest), with strictly decreasing digests
+    candidates: VecDeque<(u64, E::Digest)>,
+    /// Number of bytes rolled over since the start of the stream
+    position: u64,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl<E> MaxpChunker<E>
+where
+    E: Engine,
+    E::Digest: Ord + Copy,
+{
+    /// Create a new chunker comparing each digest with `horizon` digests on
+    /// either side
+    ///
+    /// Panics if `horizon` is 0, if `max_size` is not larger than `horizon`,
+    /// or if the engine has no fixed window.
+    pub fn new(engine: E, horizon: usize, max_size: usize) -> Self {
+        assert!(horizon > 0);
+        assert!(max_size > horizon);
+        let k = engine
+            .window_size()
+            .expect("MAXP requires an engine with a fixed window");
+        MaxpChunker {
+            engine,
+            k,
+            horizon,
+            max_size,
+            candidates: VecDeque::with_capacity(2 * horizon + 1),
+            position: 0,
+            len: 0,
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Return the number of digests compared on either side
+    pub fn horizon(&self) -> usize {
+        self.horizon
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl<E> Chunker for MaxpChunker<E>
+where
+    E: Engine,
+    E::Digest: Ord + Copy,
+{
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        let span = 2 * self.horizon as u64;
+        for (i, &b) in buf.iter().enumerate() {
+            self.engine.roll_byte(b);
+            self.position += 1;
+            self.len += 1;
+            let digest = self.engine.digest();
+            if self.position >= self.k as u64 {
+                let index = self.position - self.k as u64;
+                while self.candidates.back().is_some_and(|&(_, d)| d <= digest) {
+                    self.candidates.pop_back();
+                }
+                self.candidates.push_back((index, digest));
+                if index >= span {
+                    while self.candidates[0].0 < index - span {
+                        self.candidates.pop_front();
+                    }
+                    let (max_index, max_digest) = self.candidates[0];
+                    if max_index == index - self.horizon as u64 {
+                        self.len = 0;
+                        return Some((i + 1, max_digest));
+                    }
+                }
+            }
+            if self.len >= self.max_size {
+                self.len = 0;
+                return Some((i + 1, digest));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, rand_data, with_insert};
+    use crate::Gear;
+
+    #[test]
+    fn matches_definition() {
+        let data = rand_data(256 * 1024);
+        let (k, h) = (crate::gear::WINDOW_SIZE, 500);
+        let digests: Vec<_> = data.windows(k).map(Gear::digest_of).collect();
+        let expected: Vec<usize> = (h..digests.len().saturating_sub(h))
+            .filter(|&j| {
+                let window = &digests[j - h..=j + h];
+                let max = *window.iter().max().unwrap();
+                j - h + window.iter().rposition(|&d| d == max).unwrap() == j
+            })
+            .map(|j| j + k + h)
+            .collect();
+        assert!(expected.len() > 100);
+        let mut chunker = MaxpChunker::new(Gear::new(), h, usize::MAX);
+        assert_eq!(chunk_edges(&mut chunker, &data), expected);
+        assert!(expected.windows(2).all(|w| w[1] - w[0] > h));
+
+        let mut chunker = MaxpChunker::new(Gear::new(), h, usize::MAX);
+        assert_eq!(chunk_edges_framed(&mut chunker, &data, 307), expected);
+    }
+
+    #[test]
+    fn forced_edges_keep_cut_points() {
+        let data = rand_data(256 * 1024);
+        let unbounded = chunk_edges(&mut MaxpChunker::new(Gear::new(), 500, usize::MAX), &data);
+        let bounded = chunk_edges(&mut MaxpChunker::new(Gear::new(), 500, 1200), &data);
+        let mut last = 0;
+        for &edge in &bounded {
+            assert!(edge - last <= 1200);
+            last = edge;
+        }
+        assert!(unbounded.iter().all(|e| bounded.contains(e)));
+        assert!(bounded.len() > unbounded.len());
+    }
+
+    #[test]
+    fn resynchronizes_after_insert() {
+        let data = rand_data(256 * 1024);
+        let modified = with_insert(&data);
+
+        let new = || MaxpChunker::new(Gear::new(), 300, 4096);
+        let shifted: Vec<usize> = chunk_edges(&mut new(), &data)
+            .into_iter()
+            .filter(|&e| e > 5000)
+            .map(|e| e + 14)
+            .collect();
+        let modified_edges = chunk_edges(&mut new(), &modified);
+        assert!(shifted.iter().all(|e| modified_edges.contains(e)));
+    }
+}
@@ -0,0 +1,109 @@
+use super::ChunkHash;
+
+/// Incremental Merkle tree root computation over chunk hashes
+///
+/// Leaves are pushed in stream order, and only one pending node per tree
+/// level is kept, so memory use is logarithmic in the number of leaves. The
+/// tree has the shape of RFC 6962 Merkle trees: the left subtree of a node
+/// covering `n` leaves covers the largest power of two smaller than `n`.
+/// `combine` computes the hash of an inner node from its children, and
+/// should use domain separation from leaf hashes where that matters.
+pub struct MerkleBuilder<F> {
+    combine: F,
+    /// Roots of complete subtrees, with their level, largest first
+    stack: Vec<(u32, ChunkHash)>,
+    leaves: u64,
+}
+
+impl<F> MerkleBuilder<F>
+where
+    F: Fn(&ChunkHash, &ChunkHash) -> ChunkHash,
+{
+    /// Create a builder with no leaves
+    pub fn new(combine: F) -> Self {
+        MerkleBuilder {
+            combine,
+            stack: Vec::new(),
+            leaves: 0,
+        }
+    }
+
+    /// Add the next leaf
+    pub fn push(&mut self, leaf: ChunkHash) {
+        let mut node = (0, leaf);
+        while let Some(&(level, left)) = self.stack.last() {
+            if level != node.0 {
+                break;
+            }
+            self.stack.pop();
+            node = (level + 1, (self.combine)(&left, &node.1));
+        }
+        self.stack.push(node);
+        self.leaves += 1;
+    }
+
+    /// Return the number of leaves pushed
+    pub fn leaves(&self) -> u64 {
+        self.leaves
+    }
+
+    /// Return the root of the tree of the leaves pushed so far, or `None` if
+    /// there are none
+    pub fn root(&self) -> Option<ChunkHash> {
+        let mut nodes = self.stack.iter().rev();
+        let mut root = nodes.next()?.1;
+        for (_, left) in nodes {
+            root = (self.combine)(left, &root);
+        }
+        Some(root)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    /// Non-cryptographic stand-in for a strong hash
+    pub(crate) fn weak_hash(parts: &[&[u8]]) -> ChunkHash {
+        let mut hash = [0; 32];
+        for (i, word) in hash.chunks_mut(8).enumerate() {
+            let mut hasher = DefaultHasher::new();
+            hasher.write_usize(i);
+            parts.iter().for_each(|p| hasher.write(p));
+            word.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        hash
+    }
+
+    pub(crate) fn combine(left: &ChunkHash, right: &ChunkHash) -> ChunkHash {
+        weak_hash(&[&[1], left, right])
+    }
+
+    fn reference(leaves: &[ChunkHash]) -> ChunkHash {
+        if leaves.len() == 1 {
+            return leaves[0];
+        }
+        let split = leaves.len().next_power_of_two() / 2;
+        combine(&reference(&leaves[..split]), &reference(&leaves[split..]))
+    }
+
+    #[test]
+    fn matches_recursive_definition() {
+        let leaves: Vec<_> = (0u32..40).map(|i| weak_hash(&[&i.to_le_bytes()])).collect();
+        let mut builder = MerkleBuilder::new(combine);
+        assert_eq!(builder.root(), None);
+        for (n, &leaf) in leaves.iter().enumerate() {
+            builder.push(leaf);
+            assert_eq!(
+                builder.root(),
+                Some(reference(&leaves[..=n])),
+                "{} leaves",
+                n + 1
+            );
+        }
+        assert_eq!(builder.leaves(), 40);
+        assert!(builder.stack.len() <= 6);
+    }
+}
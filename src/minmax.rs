@@ -0,0 +1,203 @@
+use super::{Chunker, EdgeCondition, Engine, MaskEngine};
+use std::cmp;
+
+/// Chunker enforcing minimum and maximum chunk sizes around any `Engine`
+///
+/// Edges are ignored in the first `min_size` bytes of a chunk, but those bytes
+/// are still rolled into the engine, so the edge condition sees the same
+/// window as it would without the minimum. Once a chunk reaches `max_size`
+/// bytes an edge is forced and the engine is reset, exactly as after an edge
+/// found by the condition.
+pub struct MinMaxChunker<E, C> {
+    engine: E,
+    cond: C,
+    min_size: usize,
+    max_size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl<E: MaskEngine> MinMaxChunker<E, E::Condition> {
+    /// Create a new chunker using the engine's default edge condition
+    pub fn new(engine: E, min_size: usize, max_size: usize) -> Self {
+        let cond = E::condition_for_bits(engine.chunk_bits());
+        Self::with_condition(engine, cond, min_size, max_size)
+    }
+}
+
+impl<E: Engine, C: EdgeCondition<E>> MinMaxChunker<E, C> {
+    /// Create a new chunker using a custom edge condition
+    pub fn with_condition(engine: E, cond: C, min_size: usize, max_size: usize) -> Self {
+        assert!(min_size <= max_size);
+        assert!(max_size > 0);
+        MinMaxChunker {
+            engine,
+            cond,
+            min_size,
+            max_size,
+            len: 0,
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Return the wrapped engine and edge condition
+    pub fn into_inner(self) -> (E, C) {
+        (self.engine, self.cond)
+    }
+
+    /// Start a new chunk at the next byte, as at the start of a stream
+    pub fn reset(&mut self) {
+        self.engine.reset();
+        self.len = 0;
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl<E: Engine, C: EdgeCondition<E>> Chunker for MinMaxChunker<E, C> {
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        let mut consumed = 0;
+        if self.len < self.min_size {
+            consumed = cmp::min(self.min_size - self.len, buf.len());
+            self.engine.roll(&buf[..consumed]);
+            self.len += consumed;
+        }
+
+        if self.len >= self.min_size {
+            let end = consumed + cmp::min(buf.len() - consumed, self.max_size - self.len);
+            let part = &buf[consumed..end];
+            if let Some((i, digest)) = self.engine.find_chunk_edge_with(part, &self.cond) {
+                self.len = 0;
+                return Some((consumed + i, digest));
+            }
+            self.len += part.len();
+            consumed = end;
+        }
+
+        if self.len >= self.max_size {
+            let digest = self.engine.digest();
+            self.engine.reset();
+            self.len = 0;
+            return Some((consumed, digest));
+        }
+        None
+    }
+}
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, rand_data};
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn sizes_within_bounds() {
+        use crate::Bup;
+
+        let data = rand_data(1024 * 1024);
+        let edges = chunk_edges(
+            &mut MinMaxChunker::new(Bup::new_with_chunk_bits(10), 300, 2000),
+            &data,
+        );
+        assert!(edges.len() > 400);
+        let mut last = 0;
+        for edge in edges {
+            assert!((300..=2000).contains(&(edge - last)));
+            last = edge;
+        }
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn unbounded_matches_engine() {
+        use crate::Gear;
+
+        let data = rand_data(256 * 1024);
+        let mut chunker = MinMaxChunker::new(Gear::new_with_chunk_bits(10), 0, usize::MAX);
+        let expected = chunk_edges(&mut Gear::new_with_chunk_bits(10), &data);
+        assert_eq!(chunk_edges(&mut chunker, &data), expected);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn forced_edge_resets_engine() {
+        use crate::condition::ThresholdCondition;
+        use crate::Gear;
+
+        // A condition which never matches only produces forced edges
+        let data = rand_data(10000);
+        let mut chunker =
+            MinMaxChunker::with_condition(Gear::new(), ThresholdCondition::new(0), 10, 1000);
+        let mut remaining = &data[..];
+        while let Some((i, digest)) = chunker.find_chunk_edge(remaining) {
+            assert_eq!(i, 1000);
+            let mut gear = Gear::new();
+            gear.roll(&remaining[..i]);
+            assert_eq!(gear.digest(), digest);
+            remaining = &remaining[i..];
+        }
+        assert!(remaining.is_empty());
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn incremental_matches_whole() {
+        use crate::tests::chunk_edges_framed;
+        use crate::Gear;
+
+        let data = rand_data(512 * 1024);
+        let new = || MinMaxChunker::new(Gear::new_with_chunk_bits(11), 700, 3000);
+        let whole = chunk_edges(&mut new(), &data);
+
+        let mut chunker = new();
+        assert_eq!(chunk_edges_framed(&mut chunker, &data, 307), whole);
+
+        // After a reset, the next stream is chunked as by a new chunker
+        chunker.find_chunk_edge(&data[..1000]);
+        chunker.reset();
+        assert_eq!(chunk_edges(&mut chunker, &data), whole);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn forced_edges_of_huge_chunks() {
+        use crate::Gear;
+
+        // The minimum is skipped without looking for edges, so a stream of
+        // several GiB only has its last 64 KiB per chunk scanned
+        const GIB: u64 = 1 << 30;
+        let frame = rand_data(1024 * 1024);
+        let mut chunker = MinMaxChunker::new(
+            Gear::new_with_chunk_bits(30),
+            (GIB - 65536) as usize,
+            GIB as usize,
+        );
+        let mut position = 0;
+        let mut edges = Vec::new();
+        for _ in 0..4 * 1024 + 1 {
+            let mut rest = &frame[..];
+            while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+                edges.push(position + i as u64);
+                position += i as u64;
+                rest = &rest[i..];
+            }
+            position += rest.len() as u64;
+        }
+        assert_eq!(position, 4 * GIB + 1024 * 1024);
+        assert_eq!(edges, [GIB, 2 * GIB, 3 * GIB, 4 * GIB]);
+    }
+}
@@ -0,0 +1,275 @@
+use super::ChunkHash;
+use memmap2::Mmap;
+use std::cmp::Ordering;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Magic bytes at the start of every run file
+pub const MAGIC: [u8; 4] = *b"RSIX";
+
+/// Version of the run file format
+pub const VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 32 + 8;
+
+/// Persistent chunk index mapping chunk hashes to `u64` values (e.g. the
+/// location of the chunk)
+///
+/// The index is a directory of immutable sorted runs, each of which is
+/// memory-mapped and binary searched, so opening an index costs nothing
+/// regardless of its size. `append` writes a new run, and `compact` merges
+/// all runs into one to keep lookups fast.
+///
+/// Runs are never modified in place, but must not be modified by other
+/// processes while an index is open.
+pub struct MmapIndex {
+    dir: PathBuf,
+    /// Runs ordered from oldest to newest
+    runs: Vec<Run>,
+    next_run: u64,
+}
+
+struct Run {
+    path: PathBuf,
+    map: Mmap,
+}
+
+impl Run {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = fs::File::open(&path)?;
+        // Safe as long as nobody modifies the run, see `MmapIndex`
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if map.len() < HEADER_SIZE || map[..4] != MAGIC {
+            return Err(invalid("not a chunk index run"));
+        }
+        if map[4] != VERSION {
+            return Err(invalid("unsupported chunk index version"));
+        }
+        let run = Run { path, map };
+        let size = run.count().checked_mul(ENTRY_SIZE as u64);
+        if size != Some((run.map.len() - HEADER_SIZE) as u64) {
+            return Err(invalid("truncated chunk index run"));
+        }
+        Ok(run)
+    }
+
+    fn count(&self) -> u64 {
+        let mut count = [0; 8];
+        count.copy_from_slice(&self.map[8..16]);
+        u64::from_le_bytes(count)
+    }
+
+    fn len(&self) -> usize {
+        self.count() as usize
+    }
+
+    fn entry(&self, i: usize) -> (&[u8], u64) {
+        let entry = &self.map[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        let mut value = [0; 8];
+        value.copy_from_slice(&entry[32..]);
+        (&entry[..32], u64::from_le_bytes(value))
+    }
+
+    fn get(&self, hash: &ChunkHash) -> Option<u64> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (key, value) = self.entry(mid);
+            match key.cmp(&hash[..]) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(value),
+            }
+        }
+        None
+    }
+}
+
+fn run_number(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    name.strip_prefix("run-")?
+        .strip_suffix(".idx")?
+        .parse()
+        .ok()
+}
+
+impl MmapIndex {
+    /// Open the index in `dir`, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut numbered = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if let Some(n) = run_number(&path) {
+                numbered.push((n, path));
+            }
+        }
+        numbered.sort();
+        let next_run = numbered.last().map_or(0, |&(n, _)| n + 1);
+        let runs = numbered
+            .into_iter()
+            .map(|(_, path)| Run::open(path))
+            .collect::<io::Result<_>>()?;
+        Ok(MmapIndex {
+            dir,
+            runs,
+            next_run,
+        })
+    }
+
+    /// Return the value stored for `hash`
+    ///
+    /// If `hash` was appended several times, the most recent value wins.
+    pub fn get(&self, hash: &ChunkHash) -> Option<u64> {
+        self.runs.iter().rev().find_map(|run| run.get(hash))
+    }
+
+    /// Return whether `hash` is in the index
+    pub fn contains(&self, hash: &ChunkHash) -> bool {
+        self.get(hash).is_some()
+    }
+
+    /// Return the number of runs
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Return the number of entries in all runs, counting hashes present in
+    /// several runs multiple times
+    pub fn entries(&self) -> usize {
+        self.runs.iter().map(Run::len).sum()
+    }
+
+    /// Add `entries` to the index as a new run
+    pub fn append<I>(&mut self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (ChunkHash, u64)>,
+    {
+        let mut entries: Vec<_> = entries.into_iter().collect();
+        // Keep the last value appended for each hash
+        entries.reverse();
+        entries.sort_by_key(|e| e.0);
+        entries.dedup_by(|a, b| a.0 == b.0);
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let path = self.write_run(&entries)?;
+        self.runs.push(Run::open(path)?);
+        Ok(())
+    }
+
+    /// Merge all runs into a single run
+    pub fn compact(&mut self) -> io::Result<()> {
+        if self.runs.len() < 2 {
+            return Ok(());
+        }
+        let mut merged: Vec<(ChunkHash, u64)> = Vec::with_capacity(self.entries());
+        for run in self.runs.iter().rev() {
+            for i in 0..run.len() {
+                let (key, value) = run.entry(i);
+                let mut hash = [0; 32];
+                hash.copy_from_slice(key);
+                merged.push((hash, value));
+            }
+        }
+        // Stable sort keeps the newest value first for each hash
+        merged.sort_by_key(|e| e.0);
+        merged.dedup_by(|a, b| a.0 == b.0);
+
+        let path = self.write_run(&merged)?;
+        let old = std::mem::replace(&mut self.runs, vec![Run::open(path)?]);
+        for run in old {
+            fs::remove_file(&run.path)?;
+        }
+        Ok(())
+    }
+
+    /// Write sorted and deduplicated `entries` as the next run
+    fn write_run(&mut self, entries: &[(ChunkHash, u64)]) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("run-{:010}.idx", self.next_run));
+        let tmp = path.with_extension("tmp");
+        let mut out = BufWriter::new(fs::File::create(&tmp)?);
+        out.write_all(&MAGIC)?;
+        out.write_all(&[VERSION, 0, 0, 0])?;
+        out.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (hash, value) in entries {
+            out.write_all(hash)?;
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.into_inner()?.sync_all()?;
+        fs::rename(tmp, &path)?;
+        self.next_run += 1;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn hashes(count: usize) -> Vec<ChunkHash> {
+        rand_data(count * 32)
+            .chunks(32)
+            .map(|c| {
+                let mut hash = [0; 32];
+                hash.copy_from_slice(c);
+                hash
+            })
+            .collect()
+    }
+
+    #[test]
+    fn append_reopen_compact() {
+        let dir = std::env::temp_dir().join(format!("rollsum-mmap-index-{}", std::process::id()));
+        let hashes = hashes(2000);
+        {
+            let mut index = MmapIndex::open(&dir).unwrap();
+            index
+                .append(hashes[..1000].iter().map(|&h| (h, 1)))
+                .unwrap();
+            index
+                .append(hashes[500..1500].iter().map(|&h| (h, 2)))
+                .unwrap();
+            assert_eq!(index.runs(), 2);
+        }
+
+        let mut index = MmapIndex::open(&dir).unwrap();
+        assert_eq!(index.entries(), 2000);
+        let check = |index: &MmapIndex| {
+            assert_eq!(index.get(&hashes[0]), Some(1));
+            assert_eq!(index.get(&hashes[700]), Some(2));
+            assert_eq!(index.get(&hashes[1499]), Some(2));
+            assert!(!index.contains(&hashes[1500]));
+        };
+        check(&index);
+
+        index.compact().unwrap();
+        assert_eq!(index.runs(), 1);
+        assert_eq!(index.entries(), 1500);
+        check(&index);
+        drop(index);
+
+        let index = MmapIndex::open(&dir).unwrap();
+        check(&index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_overflowing_entry_count() {
+        let dir = std::env::temp_dir().join(format!("rollsum-mmap-count-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // The size of the entries wraps to zero
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&[VERSION, 0, 0, 0]);
+        header.extend_from_slice(&(1u64 << 61).to_le_bytes());
+        fs::write(dir.join("run-0000000000.idx"), header).unwrap();
+        let err = MmapIndex::open(&dir).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+use super::{Chunker, MaskEngine};
+use std::cmp;
+use std::mem;
+
+/// Default normalization level, as recommended by the FastCDC paper
+pub const NORMALIZATION_LEVEL: u32 = 2;
+
+/// FastCDC-style normalized chunking around any `MaskEngine`
+///
+/// No edge is looked for in the first `min_size` bytes of a chunk. Up to the
+/// average chunk size (`2^chunk_bits` of the wrapped engine) the edge
+/// condition matches `level` more bits than the engine's default, and past it
+/// `level` fewer bits, which concentrates chunk sizes around the average. An
+/// edge is forced once a chunk reaches `max_size` bytes.
+///
+/// `with_regions` generalizes this to any number of size regions, each with
+/// its own number of matched bits.
+///
+/// Based on "FastCDC: a Fast and Efficient Content-Defined Chunking Approach
+/// for Data Deduplication" (Xia et al., USENIX ATC 2016).
+pub struct NormalizedChunker<E: MaskEngine> {
+    engine: E,
+    /// Chunk sizes from which each condition applies, the first one being
+    /// the minimum chunk size, in increasing order
+    regions: Vec<(usize, E::Condition)>,
+    max_size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl<E: MaskEngine> NormalizedChunker<E> {
+    /// Create a new chunker with the default normalization level
+    pub fn new(engine: E, min_size: usize, max_size: usize) -> Self {
+        Self::with_level(engine, min_size, max_size, NORMALIZATION_LEVEL)
+    }
+
+    /// Create a new chunker with a custom normalization level
+    ///
+    /// Level 0 disables normalization, leaving only the size bounds.
+    pub fn with_level(engine: E, min_size: usize, max_size: usize, level: u32) -> Self {
+        let bits = engine.chunk_bits();
+        let digest_bits = (mem::size_of::<E::Digest>() * 8) as u32;
+        let avg_size = cmp::max(1 << bits, min_size);
+        let mut regions = vec![(
+            min_size,
+            cmp::min(bits.saturating_add(level), digest_bits - 1),
+        )];
+        if avg_size > min_size {
+            regions.push((avg_size, bits.saturating_sub(level)));
+        } else {
+            regions[0].1 = bits.saturating_sub(level);
+        }
+        Self::with_regions(engine, &regions, max_size)
+    }
+
+    /// Create a new chunker with custom size regions, as (chunk size from
+    /// which it applies, number of matched bits)
+    ///
+    /// The first region starts at the minimum chunk size, and each applies
+    /// until the next one starts, the last one until `max_size`. Regions
+    /// matching fewer and fewer bits, e.g. `[(2048, 15), (6144, 14), (8192,
+    /// 12), (12288, 10)]`, concentrate chunk sizes more than the two of
+    /// `with_level`. Panics unless the regions start in increasing order,
+    /// at most at `max_size`.
+    pub fn with_regions(engine: E, regions: &[(usize, u32)], max_size: usize) -> Self {
+        let digest_bits = (mem::size_of::<E::Digest>() * 8) as u32;
+        assert!(!regions.is_empty());
+        assert!(regions.windows(2).all(|r| r[0].0 < r[1].0));
+        assert!(regions[0].0 <= max_size);
+        assert!(max_size > 0);
+        assert!(regions.iter().all(|&(_, bits)| bits < digest_bits));
+        NormalizedChunker {
+            regions: regions
+                .iter()
+                .map(|&(start, bits)| (start, E::condition_for_bits(bits)))
+                .collect(),
+            engine,
+            max_size,
+            len: 0,
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.regions[0].0
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl<E: MaskEngine> Chunker for NormalizedChunker<E> {
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        let mut consumed = 0;
+        while consumed < buf.len() {
+            // The region of the current length, and where the next starts
+            let regions = &self.regions;
+            let next = regions.partition_point(|&(start, _)| start <= self.len);
+            let end = regions.get(next).map_or(self.max_size, |&(start, _)| start);
+            let limit = cmp::min(end, self.max_size) - self.len;
+            let cond = next.checked_sub(1).map(|region| &regions[region].1);
+            let part = &buf[consumed..consumed + cmp::min(buf.len() - consumed, limit)];
+            let engine = &mut self.engine;
+            match cond {
+                None => engine.roll(part),
+                Some(cond) => {
+                    if let Some((i, digest)) = engine.find_chunk_edge_with(part, cond) {
+                        self.len = 0;
+                        return Some((consumed + i, digest));
+                    }
+                }
+            }
+            self.len += part.len();
+            consumed += part.len();
+
+            if self.len >= self.max_size {
+                let digest = self.engine.digest();
+                self.engine.reset();
+                self.len = 0;
+                return Some((consumed, digest));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "gear")]
+    use crate::tests::{chunk_edges, chunk_edges_framed};
+    use crate::tests::{chunk_sizes, rand_data};
+
+    #[cfg(feature = "gear")]
+    fn variance(sizes: &[usize]) -> f64 {
+        let mean = sizes.iter().sum::<usize>() as f64 / sizes.len() as f64;
+        sizes
+            .iter()
+            .map(|&s| (s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / sizes.len() as f64
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn sizes_within_bounds() {
+        use crate::Bup;
+
+        let data = rand_data(1024 * 1024);
+        let mut chunker = NormalizedChunker::new(Bup::new_with_chunk_bits(10), 256, 4096);
+        let sizes = chunk_sizes(&mut chunker, &data);
+        assert!(sizes.len() > 500);
+        assert!(sizes.iter().all(|&s| (256..=4096).contains(&s)));
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn normalization_tightens_distribution() {
+        use crate::Gear;
+
+        let data = rand_data(2 * 1024 * 1024);
+        let plain = chunk_sizes(
+            &mut NormalizedChunker::with_level(Gear::new_with_chunk_bits(12), 0, 1 << 20, 0),
+            &data,
+        );
+        let normalized = chunk_sizes(
+            &mut NormalizedChunker::new(Gear::new_with_chunk_bits(12), 0, 1 << 20),
+            &data,
+        );
+        assert_eq!(
+            plain,
+            chunk_sizes(&mut Gear::new_with_chunk_bits(12), &data),
+            "level 0 without bounds behaves like the engine"
+        );
+        assert!(variance(&normalized) < variance(&plain) / 2.0);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn incremental_matches_whole() {
+        use crate::Gear;
+
+        let data = rand_data(512 * 1024);
+        let new = || NormalizedChunker::new(Gear::new_with_chunk_bits(11), 512, 8192);
+        let whole = chunk_edges(&mut new(), &data);
+        assert_eq!(chunk_edges_framed(&mut new(), &data, 307), whole);
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn huge_chunks_within_bounds() {
+        use crate::Gear;
+
+        const MIB: usize = 1 << 20;
+        let frame = rand_data(MIB);
+        let (min, max) = (256 * MIB - 65536, 256 * MIB + 65536);
+        let mut chunker = NormalizedChunker::new(Gear::new_with_chunk_bits(28), min, max);
+        let mut position = 0u64;
+        let mut last_edge = 0;
+        let mut sizes = Vec::new();
+        for _ in 0..1024 + 1 {
+            let mut rest = &frame[..];
+            while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+                position += i as u64;
+                sizes.push(position - last_edge);
+                last_edge = position;
+                rest = &rest[i..];
+            }
+            position += rest.len() as u64;
+        }
+        assert_eq!(position, 1025 * MIB as u64);
+        assert_eq!(sizes.len(), 4);
+        assert!(sizes
+            .iter()
+            .all(|&s| (min as u64..=max as u64).contains(&s)));
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn more_regions_tighten_distribution() {
+        use crate::Gear;
+
+        let data = rand_data(4 * 1024 * 1024);
+        let engine = || Gear::new_with_chunk_bits(13);
+        let two = chunk_sizes(&mut NormalizedChunker::new(engine(), 2048, 65536), &data);
+        assert_eq!(
+            two,
+            chunk_sizes(
+                &mut NormalizedChunker::with_regions(engine(), &[(2048, 15), (8192, 11)], 65536),
+                &data
+            )
+        );
+        let regions = [(2048, 16), (6144, 15), (8192, 12), (10240, 10), (12288, 8)];
+        let many = chunk_sizes(
+            &mut NormalizedChunker::with_regions(engine(), &regions, 65536),
+            &data,
+        );
+        assert!(many.iter().all(|&s| (2048..=65536).contains(&s)));
+        assert!(variance(&many) < variance(&two) / 2.0);
+    }
+}
@@ -0,0 +1,360 @@
+use super::{BuildError, ChunkBits};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// Chunking algorithm identifiers
+///
+/// The numeric identifiers are part of the on-wire formats of this crate and
+/// never change meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Algorithm {
+    Bup,
+    Gear,
+}
+
+impl Algorithm {
+    /// Return the numeric identifier of the algorithm
+    pub fn id(self) -> u16 {
+        match self {
+            Algorithm::Bup => 1,
+            Algorithm::Gear => 2,
+        }
+    }
+
+    /// Return the algorithm with the numeric identifier `id`
+    pub fn from_id(id: u16) -> Option<Self> {
+        match id {
+            1 => Some(Algorithm::Bup),
+            2 => Some(Algorithm::Gear),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Algorithm::Bup => write!(f, "bup"),
+            Algorithm::Gear => write!(f, "gear"),
+        }
+    }
+}
+
+/// Parameters describing how a stream was chunked
+///
+/// Two parties using equal parameters produce the same chunk boundaries for
+/// the same data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChunkerParams {
+    pub algorithm: Algorithm,
+    /// Number of digest bits matched by the edge condition
+    pub chunk_bits: u32,
+    /// Minimum chunk size, 0 if unbounded
+    pub min_size: u64,
+    /// Maximum chunk size, `u64::MAX` if unbounded
+    pub max_size: u64,
+}
+
+impl ChunkerParams {
+    /// Create parameters without chunk size bounds
+    pub fn new(algorithm: Algorithm, chunk_bits: u32) -> Self {
+        ChunkerParams {
+            algorithm,
+            chunk_bits,
+            min_size: 0,
+            max_size: u64::MAX,
+        }
+    }
+
+    /// Check that chunkers can be created with the parameters
+    ///
+    /// See `Params::validate`.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        Params::from(*self).validate()
+    }
+}
+
+/// Configuration of any chunker of this crate
+///
+/// Covers everything needed to construct the chunker, see
+/// `AnyChunker::new`. Sizes are in bytes; a `min_size` of 0 and a `max_size`
+/// of `u64::MAX` leave chunk sizes unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "algorithm", rename_all = "lowercase")
+)]
+pub enum Params {
+    /// `Bup` with size bounds
+    Bup {
+        chunk_bits: u32,
+        min_size: u64,
+        max_size: u64,
+    },
+    /// `Gear` with size bounds
+    Gear {
+        chunk_bits: u32,
+        min_size: u64,
+        max_size: u64,
+    },
+    /// `Gear` with normalized chunking, see `NormalizedChunker`
+    ///
+    /// Not the `FastCdc` engine: the minimum size is rolled over, and the
+    /// edge condition is the one of `Gear`.
+    Normalized {
+        chunk_bits: u32,
+        min_size: u64,
+        max_size: u64,
+        /// Normalization level
+        level: u32,
+    },
+    /// The `FastCdc` engine, which skips the first `min_size` bytes of a
+    /// chunk without rolling over them
+    FastCdc {
+        chunk_bits: u32,
+        min_size: u64,
+        max_size: u64,
+        /// Normalization level
+        level: u32,
+    },
+}
+
+impl Params {
+    /// Return the number of digest bits matched by the edge condition
+    pub fn chunk_bits(&self) -> u32 {
+        match *self {
+            Params::Bup { chunk_bits, .. }
+            | Params::Gear { chunk_bits, .. }
+            | Params::Normalized { chunk_bits, .. }
+            | Params::FastCdc { chunk_bits, .. } => chunk_bits,
+        }
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> u64 {
+        match *self {
+            Params::Bup { min_size, .. }
+            | Params::Gear { min_size, .. }
+            | Params::Normalized { min_size, .. }
+            | Params::FastCdc { min_size, .. } => min_size,
+        }
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> u64 {
+        match *self {
+            Params::Bup { max_size, .. }
+            | Params::Gear { max_size, .. }
+            | Params::Normalized { max_size, .. }
+            | Params::FastCdc { max_size, .. } => max_size,
+        }
+    }
+
+    /// Check that chunkers can be created with the parameters
+    ///
+    /// The number of chunk bits has to be valid for any engine, and the
+    /// minimum chunk size at most the maximum, itself not 0. Parameters read
+    /// from untrusted sources have to be checked before they are passed to
+    /// the engine constructors, which panic on invalid ones. Whether the
+    /// feature of the engine is enabled is up to `AnyChunker::new`.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        ChunkBits::new(self.chunk_bits())?;
+        let (min_size, max_size) = (self.min_size(), self.max_size());
+        if min_size > max_size || max_size == 0 {
+            return Err(BuildError::InvalidSizes {
+                min_size: crate::saturating_usize(min_size),
+                max_size: crate::saturating_usize(max_size),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl From<ChunkerParams> for Params {
+    fn from(params: ChunkerParams) -> Self {
+        let ChunkerParams {
+            chunk_bits,
+            min_size,
+            max_size,
+            ..
+        } = params;
+        match params.algorithm {
+            Algorithm::Bup => Params::Bup {
+                chunk_bits,
+                min_size,
+                max_size,
+            },
+            Algorithm::Gear => Params::Gear {
+                chunk_bits,
+                min_size,
+                max_size,
+            },
+        }
+    }
+}
+
+/// Fails for parameters without an `Algorithm` identifier, returning them
+impl TryFrom<Params> for ChunkerParams {
+    type Error = Params;
+
+    fn try_from(params: Params) -> Result<Self, Params> {
+        let algorithm = match params {
+            Params::Bup { .. } => Algorithm::Bup,
+            Params::Gear { .. } => Algorithm::Gear,
+            Params::Normalized { .. } | Params::FastCdc { .. } => return Err(params),
+        };
+        Ok(ChunkerParams {
+            algorithm,
+            chunk_bits: params.chunk_bits(),
+            min_size: params.min_size(),
+            max_size: params.max_size(),
+        })
+    }
+}
+
+/// Pinned behavior of an algorithm implementation
+///
+/// Each variant keeps producing exactly the digests and boundaries it
+/// produced when it was added, so data chunked with it can always be chunked
+/// the same way again. Changing the behavior of an engine (e.g. to fix a
+/// quirk) adds a new variant such as `BupV2` and leaves the old one
+/// selectable; stored formats should record the variant, not just the
+/// algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Variant {
+    BupV1,
+    GearV1,
+}
+
+impl Variant {
+    /// Every known variant
+    pub const ALL: &'static [Variant] = &[Variant::BupV1, Variant::GearV1];
+
+    /// Return the algorithm the variant implements
+    pub fn algorithm(self) -> Algorithm {
+        match self {
+            Variant::BupV1 => Algorithm::Bup,
+            Variant::GearV1 => Algorithm::Gear,
+        }
+    }
+
+    /// Return the behavior version, starting at 1 for each algorithm
+    pub fn version(self) -> u16 {
+        match self {
+            Variant::BupV1 | Variant::GearV1 => 1,
+        }
+    }
+
+    /// Return the variant of `algorithm` with behavior `version`
+    pub fn from_version(algorithm: Algorithm, version: u16) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|v| v.algorithm() == algorithm && v.version() == version)
+    }
+
+    /// Return the newest variant of `algorithm`, the behavior of this
+    /// crate's engines
+    pub fn latest(algorithm: Algorithm) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(|v| v.algorithm() == algorithm)
+            .max_by_key(|v| v.version())
+            .unwrap()
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-v{}", self.algorithm(), self.version())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_registry() {
+        for &variant in Variant::ALL {
+            let algorithm = variant.algorithm();
+            assert_eq!(
+                Variant::from_version(algorithm, variant.version()),
+                Some(variant)
+            );
+            assert!(Variant::latest(algorithm).version() >= variant.version());
+        }
+        assert_eq!(Variant::latest(Algorithm::Bup), Variant::BupV1);
+        assert_eq!(Variant::from_version(Algorithm::Gear, 0), None);
+        assert_eq!(Variant::GearV1.to_string(), "gear-v1");
+    }
+
+    #[test]
+    fn params_conversions() {
+        let params = ChunkerParams {
+            min_size: 100,
+            ..ChunkerParams::new(Algorithm::Bup, 12)
+        };
+        let any = Params::from(params);
+        assert_eq!(
+            any,
+            Params::Bup {
+                chunk_bits: 12,
+                min_size: 100,
+                max_size: u64::MAX
+            }
+        );
+        assert_eq!(ChunkerParams::try_from(any), Ok(params));
+
+        let normalized = Params::Normalized {
+            chunk_bits: 13,
+            min_size: 2048,
+            max_size: 65536,
+            level: 2,
+        };
+        assert_eq!(ChunkerParams::try_from(normalized), Err(normalized));
+        assert_eq!(
+            (normalized.chunk_bits(), normalized.max_size()),
+            (13, 65536)
+        );
+        let fastcdc = Params::FastCdc {
+            chunk_bits: 13,
+            min_size: 2048,
+            max_size: 65536,
+            level: 2,
+        };
+        assert_eq!(ChunkerParams::try_from(fastcdc), Err(fastcdc));
+        assert_eq!(fastcdc.min_size(), 2048);
+    }
+
+    // Outputs of every variant, which must never change
+    #[test]
+    #[cfg(all(feature = "bup", feature = "gear"))]
+    fn pinned_behavior() {
+        use crate::vectors::compute;
+
+        let bup = compute(
+            ChunkerParams::new(Variant::BupV1.algorithm(), 10),
+            1,
+            64 * 1024,
+        );
+        assert_eq!(bup.digest, 0x296c_70bb);
+        assert_eq!(bup.boundaries.len(), 66);
+        assert_eq!(bup.boundaries[..6], [423, 430, 1321, 1421, 1637, 2095]);
+
+        let gear = compute(
+            ChunkerParams::new(Variant::GearV1.algorithm(), 10),
+            1,
+            64 * 1024,
+        );
+        assert_eq!(gear.digest, 0x9272_8ea4_2a9a_5ecc);
+        assert_eq!(gear.boundaries.len(), 63);
+        assert_eq!(gear.boundaries[..6], [647, 1010, 1663, 1664, 2430, 2543]);
+    }
+}
@@ -0,0 +1,282 @@
+use super::ingest::ChunkInfo;
+use super::{for_each_chunk, ChunkHash, Chunker};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+fn aborted() -> io::Error {
+    io::Error::other("pipeline aborted")
+}
+
+/// Split everything read from `reader` with `chunker` on one thread, while
+/// `threads` workers compute the `hash` of each chunk and `process` it, e.g.
+/// to compress it, then call `f` with each chunk, its hash and the processed
+/// data in stream order
+///
+/// At most `4 * threads` chunks are in flight at once, so a slow `f` holds
+/// up reading instead of buffering the stream in memory. `process` can
+/// return the data unchanged if only hashing is needed.
+///
+/// The first error stops the pipeline and is returned. Errors from `process`
+/// and `f` are reported in stream order: all chunks before the failing one
+/// have been passed to `f`, none after it. A panic in `hash` or `process` is
+/// reported as an error for its chunk.
+pub fn pipeline<R, C, H, P, T, F>(
+    reader: R,
+    chunker: &mut C,
+    hash: H,
+    process: P,
+    threads: usize,
+    mut f: F,
+) -> io::Result<()>
+where
+    R: Read + Send,
+    C: Chunker + Send,
+    H: Fn(&[u8]) -> ChunkHash + Sync,
+    P: Fn(&ChunkHash, Vec<u8>) -> io::Result<T> + Sync,
+    T: Send,
+    F: FnMut(ChunkInfo, ChunkHash, T) -> io::Result<()>,
+{
+    let threads = threads.max(1);
+    let depth = 4 * threads;
+    // One token per chunk in flight, returned once the chunk is emitted
+    let (tokens_tx, tokens_rx) = mpsc::sync_channel(depth);
+    for _ in 0..depth {
+        tokens_tx.send(()).unwrap();
+    }
+    let (work_tx, work_rx) = mpsc::sync_channel::<(u64, ChunkInfo, Vec<u8>)>(depth);
+    let work_rx = Mutex::new(work_rx);
+    let (results_tx, results_rx) = mpsc::sync_channel(depth);
+
+    thread::scope(|s| {
+        let chunking = s.spawn(move || {
+            let mut seq = 0;
+            for_each_chunk(reader, chunker, |offset, data| {
+                tokens_rx.recv().map_err(|_| aborted())?;
+                let info = ChunkInfo {
+                    offset,
+                    len: data.len(),
+                };
+                work_tx
+                    .send((seq, info, data.to_vec()))
+                    .map_err(|_| aborted())?;
+                seq += 1;
+                Ok(())
+            })
+        });
+
+        let (hash, process, work_rx) = (&hash, &process, &work_rx);
+        for _ in 0..threads {
+            let results_tx = results_tx.clone();
+            s.spawn(move || loop {
+                let item = work_rx.lock().unwrap().recv();
+                let Ok((seq, info, data)) = item else { return };
+                let processed = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let hash = hash(&data);
+                    process(&hash, data).map(|processed| (hash, processed))
+                }))
+                .unwrap_or_else(|_| Err(io::Error::other("pipeline worker panicked")));
+                if results_tx.send((seq, info, processed)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(results_tx);
+
+        let emitted = (|| {
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (seq, info, processed) in &results_rx {
+                pending.insert(seq, (info, processed));
+                while let Some((info, processed)) = pending.remove(&next) {
+                    let (hash, processed) = processed?;
+                    f(info, hash, processed)?;
+                    next += 1;
+                    // Fails only once chunking is done
+                    let _ = tokens_tx.send(());
+                }
+            }
+            Ok(())
+        })();
+        // Unblock the chunking thread and the workers if stopped early
+        drop(tokens_tx);
+        drop(results_rx);
+        let chunked = chunking.join().unwrap();
+        emitted.and(chunked)
+    })
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    fn hash(data: &[u8]) -> ChunkHash {
+        weak_hash(&[data])
+    }
+
+    #[test]
+    fn emits_processed_chunks_in_order() {
+        let data = rand_data(512 * 1024);
+        let mut expected = Vec::new();
+        for_each_chunk(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            |offset, chunk| {
+                expected.push((offset, hash(chunk)));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let mut emitted = Vec::new();
+        pipeline(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            hash,
+            |_, mut chunk| {
+                chunk.reverse();
+                Ok(chunk)
+            },
+            4,
+            |info, hash, mut reversed| {
+                reversed.reverse();
+                let start = info.offset as usize;
+                assert!(reversed == data[start..start + info.len]);
+                emitted.push((info.offset, hash));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(expected.len() > 100);
+        assert_eq!(emitted, expected);
+    }
+
+    #[test]
+    fn stops_at_first_error() {
+        let data = rand_data(512 * 1024);
+        let mut chunks = Vec::new();
+        for_each_chunk(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            |offset, chunk| {
+                chunks.push((offset, hash(chunk)));
+                Ok(())
+            },
+        )
+        .unwrap();
+        let (failing, _) = chunks[20];
+
+        let mut emitted = 0;
+        let err = pipeline(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            hash,
+            |_, chunk| Ok(chunk),
+            3,
+            |info, _, _| {
+                if info.offset == failing {
+                    return Err(io::Error::other("store failed"));
+                }
+                emitted += 1;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "store failed");
+        assert_eq!(emitted, 20);
+
+        // The first chunk with the failing hash, which may repeat
+        let (_, bad) = chunks[30];
+        let first = chunks.iter().position(|&(_, h)| h == bad).unwrap();
+        let mut emitted = 0;
+        let err = pipeline(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            hash,
+            |hash, chunk| {
+                if *hash == bad {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, "bad chunk"))
+                } else {
+                    Ok(chunk)
+                }
+            },
+            3,
+            |_, _, _| {
+                emitted += 1;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(emitted, first);
+    }
+
+    #[test]
+    fn reports_worker_panics() {
+        let data = rand_data(512 * 1024);
+        let mut chunks = Vec::new();
+        for_each_chunk(&data[..], &mut Gear::new_with_chunk_bits(10), |_, chunk| {
+            chunks.push(hash(chunk));
+            Ok(())
+        })
+        .unwrap();
+        let bad = chunks[20];
+        let first = chunks.iter().position(|&h| h == bad).unwrap();
+
+        let mut emitted = 0;
+        let err = pipeline(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(10),
+            hash,
+            |hash, chunk| {
+                assert!(*hash != bad, "process failed");
+                Ok(chunk)
+            },
+            3,
+            |_, _, _| {
+                emitted += 1;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "pipeline worker panicked");
+        assert_eq!(emitted, first);
+    }
+
+    #[test]
+    fn propagates_read_errors() {
+        let data = rand_data(256 * 1024);
+        let mut bytes = 0;
+        let err = pipeline(
+            FailingReader(&data[..100_000]),
+            &mut Gear::new_with_chunk_bits(10),
+            hash,
+            |_, chunk| Ok(chunk),
+            2,
+            |info, _, _| {
+                bytes += info.len;
+                Ok(())
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        assert!(bytes <= 100_000);
+    }
+
+    struct FailingReader<R>(R);
+
+    impl<R: Read> Read for FailingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::BrokenPipe.into()),
+                n => Ok(n),
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use super::Engine;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+// Pools are shared between threads, so every engine has to be `Send`
+#[cfg(feature = "bup")]
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<crate::Bup>();
+    assert_send::<crate::BupDyn>();
+};
+#[cfg(feature = "gear")]
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<crate::Gear>();
+};
+
+/// Pool of reset engines, shared between threads
+///
+/// Engines are created by the factory only when the pool is empty, so
+/// engines which are expensive to construct, e.g. with tables derived from a
+/// key, are built once per concurrent user instead of once per request.
+pub struct EnginePool<E> {
+    new_engine: Box<dyn Fn() -> E + Send + Sync>,
+    idle: Mutex<Vec<E>>,
+    max_idle: usize,
+}
+
+impl<E: Engine + Send> EnginePool<E> {
+    /// Create an empty pool creating engines with `new_engine`
+    pub fn new<F>(new_engine: F) -> Self
+    where
+        F: Fn() -> E + Send + Sync + 'static,
+    {
+        Self::with_max_idle(new_engine, usize::MAX)
+    }
+
+    /// Create an empty pool keeping at most `max_idle` engines, dropping
+    /// engines checked in beyond that
+    pub fn with_max_idle<F>(new_engine: F, max_idle: usize) -> Self
+    where
+        F: Fn() -> E + Send + Sync + 'static,
+    {
+        EnginePool {
+            new_engine: Box::new(new_engine),
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+        }
+    }
+
+    /// Take an engine, returned to the pool when the guard is dropped
+    pub fn checkout(&self) -> PooledEngine<'_, E> {
+        PooledEngine {
+            pool: self,
+            engine: Some(self.take()),
+        }
+    }
+
+    /// Take an engine out of the pool, see `checkin`
+    pub fn take(&self) -> E {
+        let idle = self.idle.lock().unwrap().pop();
+        idle.unwrap_or_else(|| (self.new_engine)())
+    }
+
+    /// Reset `engine` and return it to the pool
+    pub fn checkin(&self, mut engine: E) {
+        engine.reset();
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(engine);
+        }
+    }
+
+    /// Return the number of engines waiting in the pool
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// Engine checked out of an `EnginePool`
+pub struct PooledEngine<'a, E: Engine + Send> {
+    pool: &'a EnginePool<E>,
+    engine: Option<E>,
+}
+
+impl<'a, E: Engine + Send> PooledEngine<'a, E> {
+    /// Keep the engine instead of returning it to the pool
+    pub fn detach(mut self) -> E {
+        self.engine.take().unwrap()
+    }
+}
+
+impl<'a, E: Engine + Send> Deref for PooledEngine<'a, E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        self.engine.as_ref().unwrap()
+    }
+}
+
+impl<'a, E: Engine + Send> DerefMut for PooledEngine<'a, E> {
+    fn deref_mut(&mut self) -> &mut E {
+        self.engine.as_mut().unwrap()
+    }
+}
+
+impl<'a, E: Engine + Send> Drop for PooledEngine<'a, E> {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            self.pool.checkin(engine);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn reuses_reset_engines() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let counter = created.clone();
+        let pool = EnginePool::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Gear::new_with_chunk_bits(10)
+        });
+        let data = rand_data(64 * 1024);
+        let expected = Gear::new_with_chunk_bits(10).find_chunk_edge(&data);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..50 {
+                        let mut gear = pool.checkout();
+                        assert_eq!(gear.find_chunk_edge(&data), expected);
+                        // Leave the engine in the middle of a chunk
+                        gear.roll(&data[..100]);
+                    }
+                });
+            }
+        });
+        assert!(created.load(Ordering::Relaxed) <= 4);
+        assert_eq!(pool.idle(), created.load(Ordering::Relaxed));
+
+        let detached = pool.checkout().detach();
+        assert_eq!(pool.idle(), created.load(Ordering::Relaxed) - 1);
+        pool.checkin(detached);
+
+        let small = EnginePool::with_max_idle(Gear::new, 1);
+        let (a, b) = (small.take(), small.take());
+        small.checkin(a);
+        small.checkin(b);
+        assert_eq!(small.idle(), 1);
+    }
+}
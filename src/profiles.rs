@@ -0,0 +1,148 @@
+use super::{AnyChunker, Params};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Chunking configurations for common workloads
+///
+/// Each profile picks the engine, the number of chunk bits and the size
+/// bounds, see `params`. The parameters of a profile never change, so data
+/// chunked with one is chunked the same way by later versions; record the
+/// profile name or its `Params`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Deduplicating backups of large data sets: 1 MiB normalized `Gear`
+    /// chunks, between 256 KiB and 8 MiB, which keep indexes small
+    Backup,
+    /// Transferring the differences between versions of files: 8 KiB
+    /// normalized `Gear` chunks, between 2 KiB and 64 KiB, so that small
+    /// edits resend little data
+    NetworkSync,
+    /// Deduplicating container image layers and similar archives: 64 KiB
+    /// `Gear` chunks, between 16 KiB and 256 KiB, like casync
+    ContainerLayers,
+}
+
+impl Profile {
+    /// Every profile
+    pub const ALL: &'static [Profile] = &[
+        Profile::Backup,
+        Profile::NetworkSync,
+        Profile::ContainerLayers,
+    ];
+
+    /// Return the name of the profile, as parsed by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Backup => "backup",
+            Profile::NetworkSync => "network-sync",
+            Profile::ContainerLayers => "container-layers",
+        }
+    }
+
+    /// Return the chunker configuration of the profile
+    pub fn params(self) -> Params {
+        match self {
+            Profile::Backup => Params::Normalized {
+                chunk_bits: 20,
+                min_size: 256 * 1024,
+                max_size: 8 * 1024 * 1024,
+                level: crate::normalized::NORMALIZATION_LEVEL,
+            },
+            Profile::NetworkSync => Params::Normalized {
+                chunk_bits: 13,
+                min_size: 2 * 1024,
+                max_size: 64 * 1024,
+                level: crate::normalized::NORMALIZATION_LEVEL,
+            },
+            Profile::ContainerLayers => Params::Gear {
+                chunk_bits: 16,
+                min_size: 16 * 1024,
+                max_size: 256 * 1024,
+            },
+        }
+    }
+
+    /// Return the nominal average chunk size
+    pub fn avg_size(self) -> u64 {
+        1 << self.params().chunk_bits()
+    }
+
+    /// Create a chunker of the profile
+    pub fn chunker(self) -> AnyChunker {
+        AnyChunker::new(self.params()).expect("profiles use valid parameters")
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error parsing a `Profile`, with the unknown name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownProfile(pub String);
+
+impl fmt::Display for UnknownProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown chunking profile {:?}", self.0)
+    }
+}
+
+impl error::Error for UnknownProfile {}
+
+impl FromStr for Profile {
+    type Err = UnknownProfile;
+
+    fn from_str(s: &str) -> Result<Self, UnknownProfile> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|p| p.name() == s)
+            .ok_or_else(|| UnknownProfile(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Chunker;
+
+    #[test]
+    fn profiles_chunk_near_their_average() {
+        let data = rand_data(64 * 1024 * 1024);
+        for &profile in Profile::ALL {
+            let params = profile.params();
+            let mut chunker = profile.chunker();
+            let mut rest = &data[..];
+            let mut sizes = Vec::new();
+            while let Some((i, _)) = chunker.find_chunk_edge(rest) {
+                sizes.push(i as u64);
+                rest = &rest[i..];
+            }
+            assert!(sizes
+                .iter()
+                .all(|s| (params.min_size()..=params.max_size()).contains(s)));
+            let avg = sizes.iter().sum::<u64>() / sizes.len() as u64;
+            assert!(
+                avg > profile.avg_size() / 2 && avg < profile.avg_size() * 2,
+                "{}: {}",
+                profile,
+                avg
+            );
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &profile in Profile::ALL {
+            assert_eq!(profile.to_string().parse(), Ok(profile));
+        }
+        assert_eq!(
+            "tape".parse::<Profile>(),
+            Err(UnknownProfile("tape".to_owned()))
+        );
+    }
+}
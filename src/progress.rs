@@ -0,0 +1,127 @@
+use super::{for_each_chunk_reporting, Chunker};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How far chunking a stream got
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes fed to the chunker so far
+    pub bytes: u64,
+    /// Chunks completed so far
+    pub chunks: u64,
+    /// Expected length of the stream, if known
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// Return the fraction of `total` processed, between 0 and 1
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| {
+            if total == 0 {
+                1.0
+            } else {
+                (self.bytes as f64 / total as f64).min(1.0)
+            }
+        })
+    }
+
+    /// Return the number of bytes left until `total`
+    pub fn remaining(&self) -> Option<u64> {
+        self.total.map(|total| total.saturating_sub(self.bytes))
+    }
+}
+
+/// Split everything read from `reader` with `chunker`, calling `f` with the
+/// stream offset and contents of each chunk, and `progress` after every read
+/// and once at the end
+///
+/// `total` is the expected stream length, which is only reported back.
+pub fn chunk_reader<R, C, P, F>(
+    reader: R,
+    total: Option<u64>,
+    chunker: &mut C,
+    mut progress: P,
+    mut f: F,
+) -> io::Result<Progress>
+where
+    R: Read,
+    C: Chunker,
+    P: FnMut(&Progress),
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    let chunks = Cell::new(0);
+    let bytes = for_each_chunk_reporting(
+        reader,
+        chunker,
+        |bytes| {
+            progress(&Progress {
+                bytes,
+                chunks: chunks.get(),
+                total,
+            })
+        },
+        |offset, data| {
+            chunks.set(chunks.get() + 1);
+            f(offset, data)
+        },
+    )?;
+    let state = Progress {
+        bytes,
+        chunks: chunks.get(),
+        total,
+    };
+    progress(&state);
+    Ok(state)
+}
+
+/// `chunk_reader` over the file at `path`, with its size as the total
+pub fn chunk_file<C, P, F>(path: &Path, chunker: &mut C, progress: P, f: F) -> io::Result<Progress>
+where
+    C: Chunker,
+    P: FnMut(&Progress),
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    let file = File::open(path)?;
+    let total = file.metadata()?.len();
+    chunk_reader(file, Some(total), chunker, progress, f)
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    #[test]
+    fn reports_progress() {
+        let data = rand_data(300 * 1024);
+        let path = std::env::temp_dir().join(format!("rollsum-progress-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+
+        let mut reports = Vec::new();
+        let mut chunks = 0;
+        let done = chunk_file(
+            &path,
+            &mut Gear::new_with_chunk_bits(12),
+            |p| reports.push(*p),
+            |_, _| {
+                chunks += 1;
+                Ok(())
+            },
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(done.bytes, data.len() as u64);
+        assert_eq!(done.chunks, chunks);
+        assert_eq!(done.fraction(), Some(1.0));
+        assert_eq!(reports.last(), Some(&done));
+        assert!(reports.len() >= 5);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].bytes <= w[1].bytes && w[0].chunks <= w[1].chunks));
+        assert_eq!(reports[0].remaining(), Some(data.len() as u64 - 64 * 1024));
+    }
+}
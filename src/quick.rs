@@ -0,0 +1,266 @@
+use super::Chunker;
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+
+/// Number of bytes at each end of a chunk that identify it
+pub const MARK_LEN: usize = 8;
+
+/// Default number of chunks remembered
+pub const DEFAULT_MAX_KNOWN: usize = 1 << 16;
+
+/// QuickCDC-style chunker jumping over chunks it has seen before
+///
+/// For every chunk found by the wrapped chunker, its first and last
+/// `MARK_LEN` bytes and its length are recorded. When a new chunk starts
+/// with the first bytes of a known chunk, and `buf` holds as many bytes as
+/// that chunk with its last bytes at the same place, the chunk is taken to
+/// be a duplicate and returned without hashing any of it. Highly redundant
+/// data, like successive backups, is chunked much faster.
+///
+/// Only the ends are compared, so a chunk merely starting and ending like a
+/// known one is cut at the same length instead of where the wrapped chunker
+/// would cut it. The edges still depend only on the data and the chunks seen
+/// before, but can differ from those of the wrapped chunker. As the whole
+/// chunk must be in `buf` to jump over it, pass buffers much larger than the
+/// chunks. Only the last `DEFAULT_MAX_KNOWN` chunks are remembered, see
+/// `set_max_known`.
+///
+/// Based on "QuickCDC: A Quick Content Defined Chunking Algorithm Based on
+/// Jumping and Dynamically Adjusting Mask Bits" (Xu and Zhang, ISPA 2019).
+pub struct QuickChunker<C: Chunker> {
+    inner: C,
+    /// Known chunks by their first bytes, as their last bytes, length and
+    /// digest
+    known: HashMap<u64, (u64, usize, C::Digest)>,
+    /// Keys of `known`, oldest first
+    order: VecDeque<u64>,
+    max_known: usize,
+    /// First bytes of the current chunk, until `MARK_LEN` of them
+    first: Vec<u8>,
+    /// Last bytes of the current chunk, oldest first
+    last: [u8; MARK_LEN],
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+    jumps: u64,
+    jumped_bytes: u64,
+}
+
+fn mark(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(<[u8; MARK_LEN]>::try_from(bytes).unwrap())
+}
+
+impl<C> QuickChunker<C>
+where
+    C: Chunker,
+    C::Digest: Copy,
+{
+    /// Wrap `inner`, which must be at the start of a chunk
+    pub fn new(inner: C) -> Self {
+        QuickChunker {
+            inner,
+            known: HashMap::new(),
+            order: VecDeque::new(),
+            max_known: DEFAULT_MAX_KNOWN,
+            first: Vec::with_capacity(MARK_LEN),
+            last: [0; MARK_LEN],
+            len: 0,
+            jumps: 0,
+            jumped_bytes: 0,
+        }
+    }
+
+    /// Return the wrapped chunker
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Return the number of chunks jumped over
+    pub fn jumps(&self) -> u64 {
+        self.jumps
+    }
+
+    /// Return the number of bytes in chunks jumped over
+    pub fn jumped_bytes(&self) -> u64 {
+        self.jumped_bytes
+    }
+
+    /// Return the number of known chunks
+    pub fn known_chunks(&self) -> usize {
+        self.known.len()
+    }
+
+    /// Remember up to `chunks` chunks, at least one
+    ///
+    /// The oldest chunks are forgotten first.
+    pub fn set_max_known(&mut self, chunks: usize) {
+        self.max_known = chunks.max(1);
+        self.evict();
+    }
+
+    /// Forget the known chunks
+    pub fn clear_known(&mut self) {
+        self.known.clear();
+        self.order.clear();
+    }
+
+    fn evict(&mut self) {
+        while self.order.len() > self.max_known {
+            let oldest = self.order.pop_front().unwrap();
+            self.known.remove(&oldest);
+        }
+    }
+
+    fn consume(&mut self, part: &[u8]) {
+        let missing = cmp::min(MARK_LEN - self.first.len(), part.len());
+        self.first.extend_from_slice(&part[..missing]);
+        let tail = &part[part.len() - cmp::min(MARK_LEN, part.len())..];
+        self.last.rotate_left(tail.len());
+        self.last[MARK_LEN - tail.len()..].copy_from_slice(tail);
+        self.len += part.len();
+    }
+
+    fn jump(&self, buf: &[u8]) -> Option<(usize, C::Digest)> {
+        if self.len != 0 || buf.len() < MARK_LEN {
+            return None;
+        }
+        let &(last, len, digest) = self.known.get(&mark(&buf[..MARK_LEN]))?;
+        if len > buf.len() || mark(&buf[len - MARK_LEN..len]) != last {
+            return None;
+        }
+        Some((len, digest))
+    }
+}
+
+impl<C> Chunker for QuickChunker<C>
+where
+    C: Chunker,
+    C::Digest: Copy,
+{
+    type Digest = C::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, C::Digest)> {
+        if let Some((len, digest)) = self.jump(buf) {
+            self.jumps += 1;
+            self.jumped_bytes += len as u64;
+            return Some((len, digest));
+        }
+        match self.inner.find_chunk_edge(buf) {
+            Some((i, digest)) => {
+                self.consume(&buf[..i]);
+                if self.len >= MARK_LEN {
+                    let entry = (mark(&self.last), self.len, digest);
+                    match self.known.entry(mark(&self.first)) {
+                        Entry::Occupied(mut known) => {
+                            known.insert(entry);
+                        }
+                        Entry::Vacant(known) => {
+                            self.order.push_back(*known.key());
+                            known.insert(entry);
+                            self.evict();
+                        }
+                    }
+                }
+                self.first.clear();
+                self.len = 0;
+                Some((i, digest))
+            }
+            None => {
+                self.consume(buf);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, rand_data};
+    use crate::{Gear, MinMaxChunker};
+
+    fn new() -> QuickChunker<MinMaxChunker<Gear, crate::condition::PrefixZeroCondition>> {
+        QuickChunker::new(MinMaxChunker::new(
+            Gear::new_with_chunk_bits(11),
+            256,
+            16384,
+        ))
+    }
+
+    #[test]
+    fn jumps_over_repeated_data() {
+        // Data ending at an edge, so that every pass starts a new chunk
+        let data = rand_data(512 * 1024);
+        let end = *chunk_edges(&mut new(), &data).last().unwrap();
+        let data = &data[..end];
+        let mut chunker = new();
+        let first = chunk_edges(&mut chunker, data);
+        assert_eq!(chunker.jumps(), 0);
+        assert_eq!(chunker.known_chunks(), first.len());
+
+        // The data again, chunked in frames to check the tracked ends
+        let mut position = 0;
+        let mut second = Vec::new();
+        for frame in data.chunks(1000) {
+            let mut consumed = 0;
+            while let Some((i, _)) = chunker.find_chunk_edge(&frame[consumed..]) {
+                consumed += i;
+                second.push(position + consumed);
+            }
+            position += frame.len();
+        }
+        assert_eq!(second, first);
+
+        // With the whole data at once, every chunk is jumped over
+        let (jumps, jumped_bytes) = (chunker.jumps(), chunker.jumped_bytes());
+        assert_eq!(chunk_edges(&mut chunker, data), first);
+        assert_eq!(chunker.jumps() - jumps, first.len() as u64);
+        assert_eq!(chunker.jumped_bytes() - jumped_bytes, end as u64);
+    }
+
+    #[test]
+    fn jumps_over_duplicate_region() {
+        let a = rand_data(256 * 1024);
+        let b: Vec<u8> = a.iter().rev().copied().collect();
+        let mut data = a.clone();
+        data.extend_from_slice(&b);
+        data.extend_from_slice(&a);
+
+        let mut chunker = new();
+        let edges = chunk_edges(&mut chunker, &data);
+        let plain = self::chunk_edges(
+            &mut MinMaxChunker::new(Gear::new_with_chunk_bits(11), 256, 16384),
+            &data,
+        );
+        // The first two regions are chunked as usual, and most of the copy
+        // of the first one is jumped over, ending at the same edges
+        let before_copy = |edges: &[usize]| -> Vec<usize> {
+            edges
+                .iter()
+                .copied()
+                .take_while(|&e| e < 2 * a.len())
+                .collect()
+        };
+        assert_eq!(before_copy(&edges), before_copy(&plain));
+        assert_eq!(edges[edges.len() - 10..], plain[plain.len() - 10..]);
+        assert!(chunker.jumped_bytes() > a.len() as u64 * 9 / 10);
+    }
+
+    #[test]
+    fn remembers_at_most_max_known() {
+        let data = rand_data(512 * 1024);
+        let mut chunker = new();
+        chunker.set_max_known(16);
+        let edges = chunk_edges(&mut chunker, &data);
+        assert!(edges.len() > 100);
+        assert_eq!(chunker.known_chunks(), 16);
+
+        // Only the chunks at the end are jumped over when chunking it again
+        assert_eq!(chunk_edges(&mut chunker, &data), edges);
+        assert!(chunker.jumps() <= 16);
+        assert_eq!(chunker.known_chunks(), 16);
+        chunker.set_max_known(4);
+        assert_eq!(chunker.known_chunks(), 4);
+    }
+}
@@ -0,0 +1,618 @@
+use super::condition::{MaskCondition, MaskEngine, MaskValueCondition, ZeroMaskCondition};
+use super::{ChunkBits, Chunker, Engine, MinMaxChunker};
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::{Arc, OnceLock};
+
+pub type Digest = u64;
+
+/// Default chunk size used by `rabin` (log2)
+pub const CHUNK_BITS: u32 = 13;
+
+/// Default window size used by `rabin`
+pub const WINDOW_SIZE: usize = 64;
+
+/// Default irreducible polynomial of degree 53, the one restic's tests use
+pub const POLYNOMIAL: u64 = 0x3DA3358B4DC173;
+
+/// Smallest supported polynomial degree, so a byte can be pushed at once
+pub const MIN_DEGREE: u32 = 9;
+
+/// Largest supported polynomial degree, so the digest fits in 64 bits
+pub const MAX_DEGREE: u32 = 63;
+
+/// Error returned for polynomials which can't be used for fingerprints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPolynomial {
+    /// The degree is outside `MIN_DEGREE..=MAX_DEGREE`
+    Degree(u32),
+    /// The polynomial has factors, so fingerprints would collide more often
+    Reducible,
+}
+
+impl fmt::Display for InvalidPolynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvalidPolynomial::Degree(degree) => write!(
+                f,
+                "polynomial degree {} is outside {}..={}",
+                degree, MIN_DEGREE, MAX_DEGREE
+            ),
+            InvalidPolynomial::Reducible => write!(f, "polynomial is reducible"),
+        }
+    }
+}
+
+impl error::Error for InvalidPolynomial {}
+
+fn degree(p: u128) -> u32 {
+    127 - p.leading_zeros()
+}
+
+/// Return `a` modulo `p` over GF(2)
+fn reduce(mut a: u128, p: u64) -> u64 {
+    let p = p as u128;
+    let deg = degree(p);
+    while a != 0 && degree(a) >= deg {
+        a ^= p << (degree(a) - deg);
+    }
+    a as u64
+}
+
+/// Return `a * b` modulo `p` over GF(2)
+fn mul_mod(a: u64, b: u64, p: u64) -> u64 {
+    let mut product = 0u128;
+    for i in 0..64 {
+        if b >> i & 1 == 1 {
+            product ^= (a as u128) << i;
+        }
+    }
+    reduce(product, p)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = reduce(a as u128, b);
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Return whether `polynomial` is irreducible over GF(2)
+///
+/// Uses Ben-Or's test: a polynomial of degree `d` is irreducible unless it
+/// has a common factor with `x^(2^i) - x` for some `i <= d / 2`.
+pub fn is_irreducible(polynomial: u64) -> bool {
+    if polynomial < 2 {
+        return false;
+    }
+    let deg = degree(polynomial as u128);
+    let x = 2;
+    let mut power = x;
+    for _ in 0..deg / 2 {
+        power = mul_mod(power, power, polynomial);
+        if gcd(polynomial, power ^ x) != 1 {
+            return false;
+        }
+    }
+    true
+}
+
+/// Tables precomputed for a polynomial and window size
+struct Tables {
+    polynomial: u64,
+    /// Degree of the polynomial minus 8
+    shift: u32,
+    window_size: usize,
+    /// Reduction of the byte shifted out at the top when pushing a byte,
+    /// combined with that byte so it also gets cleared
+    push: [u64; 256],
+    /// Contribution of a byte leaving the window
+    pop: [u64; 256],
+}
+
+impl Tables {
+    fn new(polynomial: u64, window_size: usize) -> Self {
+        let deg = degree(polynomial as u128);
+        let mut push = [0; 256];
+        let mut pop = [0; 256];
+        for b in 0..256u64 {
+            // Bits shifted out of the digest are dropped in both
+            let top = (b as u128) << deg;
+            push[b as usize] = reduce(top, polynomial) | top as u64;
+            // The byte being shifted over by the rest of the window
+            let mut h = reduce(b as u128, polynomial);
+            for _ in 1..window_size {
+                h = reduce((h as u128) << 8, polynomial);
+            }
+            pop[b as usize] = h;
+        }
+        Tables {
+            polynomial,
+            shift: deg - 8,
+            window_size,
+            push,
+            pop,
+        }
+    }
+}
+
+fn default_tables() -> Arc<Tables> {
+    static TABLES: OnceLock<Arc<Tables>> = OnceLock::new();
+    TABLES
+        .get_or_init(|| Arc::new(Tables::new(POLYNOMIAL, WINDOW_SIZE)))
+        .clone()
+}
+
+fn lbfs_tables() -> Arc<Tables> {
+    static TABLES: OnceLock<Arc<Tables>> = OnceLock::new();
+    TABLES
+        .get_or_init(|| Arc::new(Tables::new(LBFS_POLYNOMIAL, LBFS_WINDOW_SIZE)))
+        .clone()
+}
+
+/// Rabin fingerprint over a sliding window
+///
+/// The digest is the window, read as a polynomial over GF(2) with the first
+/// byte's most significant bit as the highest coefficient, modulo an
+/// irreducible polynomial. This is the rolling hash of LBFS and restic,
+/// which produce the same digests with the same polynomial and window size.
+#[derive(Clone)]
+pub struct Rabin {
+    digest: Digest,
+    tables: Arc<Tables>,
+    window: Box<[u8]>,
+    wofs: usize,
+    chunk_bits: u32,
+    /// Bytes rolled over since the last reset, up to the window size
+    filled: usize,
+}
+
+impl Default for Rabin {
+    fn default() -> Self {
+        Rabin {
+            digest: 0,
+            tables: default_tables(),
+            window: vec![0; WINDOW_SIZE].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits: CHUNK_BITS,
+            filled: 0,
+        }
+    }
+}
+
+impl Rabin {
+    /// Create new Rabin engine with default chunking settings
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Create new Rabin engine with custom chunking settings
+    ///
+    /// `chunk_bits` is number of bits that need to match in
+    /// the edge condition. `CHUNK_BITS` constant is the default.
+    pub fn new_with_chunk_bits(chunk_bits: u32) -> Self {
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, WINDOW_SIZE));
+        Rabin {
+            chunk_bits,
+            ..Default::default()
+        }
+    }
+
+    /// Create new Rabin engine fingerprinting `window_size` byte windows
+    /// modulo `polynomial`
+    ///
+    /// Computing the tables takes a moment, so create engines for the same
+    /// polynomial by cloning one. Panics unless `chunk_bits` is valid for the
+    /// window, see `ChunkBits::for_window`.
+    pub fn with_polynomial(
+        polynomial: u64,
+        window_size: usize,
+        chunk_bits: u32,
+    ) -> Result<Self, InvalidPolynomial> {
+        assert!(window_size > 0);
+        let deg = degree(polynomial as u128);
+        if polynomial == 0 || !(MIN_DEGREE..=MAX_DEGREE).contains(&deg) {
+            return Err(InvalidPolynomial::Degree(if polynomial == 0 {
+                0
+            } else {
+                deg
+            }));
+        }
+        if !is_irreducible(polynomial) {
+            return Err(InvalidPolynomial::Reducible);
+        }
+        let chunk_bits = ChunkBits::expect(ChunkBits::for_window(chunk_bits, window_size));
+        Ok(Rabin {
+            digest: 0,
+            tables: Arc::new(Tables::new(polynomial, window_size)),
+            window: vec![0; window_size].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits,
+            filled: 0,
+        })
+    }
+
+    /// Create new Rabin engine with the polynomial and window of LBFS
+    ///
+    /// See `lbfs_chunker` for its edge condition and chunk sizes.
+    pub fn lbfs() -> Self {
+        Rabin {
+            digest: 0,
+            tables: lbfs_tables(),
+            window: vec![0; LBFS_WINDOW_SIZE].into_boxed_slice(),
+            wofs: 0,
+            chunk_bits: LBFS_CHUNK_BITS,
+            filled: 0,
+        }
+    }
+
+    /// Return the polynomial fingerprints are reduced by
+    pub fn polynomial(&self) -> u64 {
+        self.tables.polynomial
+    }
+
+    /// Find chunk edge using Rabin defaults.
+    ///
+    /// See `Engine::find_chunk_edge_cond`.
+    pub fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        let cond = Self::condition_for_bits(self.chunk_bits);
+        self.find_chunk_edge_with(buf, &cond)
+    }
+}
+
+impl Engine for Rabin {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let tables = &*self.tables;
+        let prevch = mem::replace(&mut self.window[self.wofs], newch);
+        let digest = self.digest ^ tables.pop[prevch as usize];
+        let top = (digest >> tables.shift) as usize;
+        self.digest = ((digest << 8) | newch as u64) ^ tables.push[top];
+        self.wofs = (self.wofs + 1) % tables.window_size;
+        if self.filled < tables.window_size {
+            self.filled += 1;
+        }
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        let window_size = self.window.len();
+        let filled = window_size.min(self.filled.saturating_add(buf.len()));
+        crate::roll_windowed(self, window_size, buf);
+        self.filled = filled;
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        self.digest
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Only the last window matters, as for `Bup`
+        let window_size = left.window.len();
+        if left.polynomial() != right.polynomial() || window_size != right.window.len() {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(window_size, |len| len.min(window_size));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + window_size - take + i) % window_size]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.digest = 0;
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl Chunker for Rabin {
+    type Digest = Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, Digest)> {
+        Rabin::find_chunk_edge(self, buf)
+    }
+}
+
+impl MaskEngine for Rabin {
+    type Condition = MaskCondition;
+
+    fn condition_for_bits(bits: u32) -> MaskCondition {
+        MaskCondition::with_bits(bits)
+    }
+
+    fn chunk_bits(&self) -> u32 {
+        self.chunk_bits
+    }
+}
+
+/// Irreducible polynomial of degree 63 used by LBFS (`FINGERPRINT_PT` of
+/// its `rabinpoly`)
+pub const LBFS_POLYNOMIAL: u64 = 0xbfe6b8a5bf378d83;
+
+/// Window size used by LBFS
+pub const LBFS_WINDOW_SIZE: usize = 48;
+
+/// Number of digest bits LBFS compares, for 8 KiB chunks on average
+pub const LBFS_CHUNK_BITS: u32 = 13;
+
+/// Value of the low `LBFS_CHUNK_BITS` digest bits at LBFS chunk edges
+pub const LBFS_BREAKMARK: u64 = 0x78;
+
+/// Minimum chunk size of LBFS
+pub const LBFS_MIN_SIZE: usize = 2 * 1024;
+
+/// Maximum chunk size of LBFS
+pub const LBFS_MAX_SIZE: usize = 64 * 1024;
+
+/// Chunker with the parameters of LBFS, see `lbfs_chunker`
+pub type LbfsChunker = MinMaxChunker<Rabin, MaskValueCondition>;
+
+/// Create a chunker with the parameters of LBFS
+///
+/// Rabin fingerprints of 48 byte windows modulo `LBFS_POLYNOMIAL` end a
+/// chunk when their low 13 bits are `LBFS_BREAKMARK`, and chunks are
+/// between 2 KiB and 64 KiB long. From "A Low-bandwidth Network File
+/// System" (Muthitacharoen, Chen and Mazières, SOSP 2001).
+pub fn lbfs_chunker() -> LbfsChunker {
+    let cond = MaskValueCondition::new((1 << LBFS_CHUNK_BITS) - 1, LBFS_BREAKMARK);
+    MinMaxChunker::with_condition(Rabin::lbfs(), cond, LBFS_MIN_SIZE, LBFS_MAX_SIZE)
+}
+
+/// Minimum chunk size of restic's chunker
+pub const RESTIC_MIN_SIZE: usize = 512 * 1024;
+
+/// Maximum chunk size of restic's chunker
+pub const RESTIC_MAX_SIZE: usize = 8 * 1024 * 1024;
+
+/// Number of digest bits matched by restic's chunker, for 1 MiB chunks on
+/// average
+pub const RESTIC_AVG_BITS: u32 = 20;
+
+/// Chunker producing the chunks of restic, see `restic_chunker`
+pub type ResticChunker = MinMaxChunker<Rabin, ZeroMaskCondition>;
+
+/// Create a chunker producing the same chunks as restic's chunker with
+/// `polynomial`, the `chunker_polynomial` of a repository's config
+pub fn restic_chunker(polynomial: u64) -> Result<ResticChunker, InvalidPolynomial> {
+    restic_chunker_with(
+        polynomial,
+        RESTIC_MIN_SIZE,
+        RESTIC_MAX_SIZE,
+        RESTIC_AVG_BITS,
+    )
+}
+
+/// Create a chunker producing the same chunks as restic's chunker with
+/// custom sizes, as set by its `NewWithBoundaries` and `SetAverageBits`
+///
+/// restic cuts where the lowest `avg_bits` bits of the digest are zero.
+/// Panics unless `WINDOW_SIZE <= min_size <= max_size`.
+pub fn restic_chunker_with(
+    polynomial: u64,
+    min_size: usize,
+    max_size: usize,
+    avg_bits: u32,
+) -> Result<ResticChunker, InvalidPolynomial> {
+    assert!(WINDOW_SIZE <= min_size && min_size <= max_size);
+    let rabin = Rabin::with_polynomial(polynomial, WINDOW_SIZE, avg_bits)?;
+    // restic may cut after the `min_size`th byte, `MinMaxChunker` only after
+    // the next one. Its other quirks don't change the chunks: it skips all
+    // but the last window before `min_size`, and the byte it starts chunks
+    // with is out of the window once the first cut is possible.
+    Ok(MinMaxChunker::with_condition(
+        rabin,
+        ZeroMaskCondition::with_bits(avg_bits),
+        min_size - 1,
+        max_size,
+    ))
+}
+
+/// Number of candidates `derive_polynomial` tries, as restic
+const DERIVE_MAX_TRIES: usize = 1_000_000;
+
+/// Derive an irreducible polynomial of degree 53 from `source`, as restic's
+/// `DerivePolynomial`
+///
+/// Each candidate is 8 little-endian bytes from `source` with the bits
+/// above 53 cleared and bits 53 and 0 set; the first irreducible one is
+/// returned. restic creates repositories with `source` reading from the
+/// system's random number generator, while a seeded generator derives the
+/// same polynomial every time.
+pub fn derive_polynomial<R: Read>(mut source: R) -> io::Result<u64> {
+    for _ in 0..DERIVE_MAX_TRIES {
+        let mut bytes = [0; 8];
+        source.read_exact(&mut bytes)?;
+        let candidate = (u64::from_le_bytes(bytes) & ((1 << 54) - 1)) | (1 << 53) | 1;
+        if is_irreducible(candidate) {
+            return Ok(candidate);
+        }
+    }
+    Err(io::Error::other("unable to find an irreducible polynomial"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    /// Fingerprint of `data` computed with plain polynomial arithmetic
+    fn reference(data: &[u8], polynomial: u64) -> u64 {
+        data.iter().fold(0, |h, &b| {
+            reduce(((h as u128) << 8) | b as u128, polynomial)
+        })
+    }
+
+    #[test]
+    fn irreducible_polynomials() {
+        assert!(is_irreducible(POLYNOMIAL));
+        // x^2 + x + 1 and the AES polynomial x^8 + x^4 + x^3 + x + 1
+        assert!(is_irreducible(0b111));
+        assert!(is_irreducible(0x11b));
+        // (x + 1)^2, and and the default polynomial minus its constant term
+        assert!(!is_irreducible(0b101));
+        assert!(!is_irreducible(POLYNOMIAL ^ 1));
+        assert_eq!(
+            Rabin::with_polynomial(POLYNOMIAL ^ 1, 64, 13).err(),
+            Some(InvalidPolynomial::Reducible)
+        );
+        assert_eq!(
+            Rabin::with_polynomial(0x11b, 64, 13).err(),
+            Some(InvalidPolynomial::Degree(8))
+        );
+    }
+
+    #[test]
+    fn digest_is_window_modulo_polynomial() {
+        let data = rand_data(4096);
+        let mut rabin = Rabin::new();
+        for (i, &b) in data.iter().enumerate() {
+            rabin.roll_byte(b);
+            let window = &data[(i + 1).saturating_sub(WINDOW_SIZE)..=i];
+            assert_eq!(rabin.digest(), reference(window, POLYNOMIAL));
+        }
+
+        // A small window and a polynomial of the lowest degree
+        let polynomial = (9..1u64 << 10)
+            .find(|&p| p >> 9 == 1 && is_irreducible(p))
+            .unwrap();
+        let mut rabin = Rabin::with_polynomial(polynomial, 5, 3).unwrap();
+        assert_eq!(rabin.polynomial(), polynomial);
+        for (i, &b) in data[..200].iter().enumerate() {
+            rabin.roll_byte(b);
+            let window = &data[(i + 1).saturating_sub(5)..=i];
+            assert_eq!(rabin.digest(), reference(window, polynomial));
+        }
+    }
+
+    #[test]
+    fn edge_expected_size() {
+        let data = rand_data(2 * 1024 * 1024);
+        for bits in 8..13 {
+            let mut rabin = Rabin::new_with_chunk_bits(bits);
+            let (mut count, mut total) = (0, 0);
+            let mut remaining = &data[..];
+            while let Some((i, _)) = rabin.find_chunk_edge(remaining) {
+                count += 1;
+                total += i;
+                remaining = &remaining[i..];
+            }
+            let expected = (1 << bits) as f64;
+            let average = total as f64 / count as f64;
+            assert!((average - expected).abs() / expected < 0.1, "{}", average);
+        }
+    }
+
+    /// restic's `Chunker.slide`
+    fn slide(tables: &Tables, window: &mut [u8], wpos: &mut usize, digest: &mut u64, b: u8) {
+        let out = mem::replace(&mut window[*wpos], b);
+        *digest ^= tables.pop[out as usize];
+        *wpos = (*wpos + 1) % WINDOW_SIZE;
+        let index = (*digest >> tables.shift) as usize;
+        *digest = ((*digest << 8) | b as u64) ^ tables.push[index];
+    }
+
+    /// Chunk lengths of restic's `Chunker.Next`, transcribed with its
+    /// window reset quirk and skipped bytes
+    fn restic_reference(data: &[u8], min: usize, max: usize, bits: u32) -> Vec<usize> {
+        let tables = Tables::new(POLYNOMIAL, WINDOW_SIZE);
+        let mask = (1u64 << bits) - 1;
+        let mut sizes = Vec::new();
+        let mut start = 0;
+        'chunks: loop {
+            let mut window = [0u8; WINDOW_SIZE];
+            let (mut wpos, mut digest) = (0, 0);
+            slide(&tables, &mut window, &mut wpos, &mut digest, 1);
+            let mut add = min - WINDOW_SIZE;
+            for &b in &data[(start + add).min(data.len())..] {
+                slide(&tables, &mut window, &mut wpos, &mut digest, b);
+                add += 1;
+                if add >= min && (digest & mask == 0 || add >= max) {
+                    sizes.push(add);
+                    start += add;
+                    continue 'chunks;
+                }
+            }
+            return sizes;
+        }
+    }
+
+    #[test]
+    fn restic_chunks() {
+        let data = rand_data(4 * 1024 * 1024);
+        let sizes = |mut chunker: ResticChunker| {
+            let mut sizes = Vec::new();
+            let mut remaining = &data[..];
+            while let Some((i, _)) = chunker.find_chunk_edge(remaining) {
+                sizes.push(i);
+                remaining = &remaining[i..];
+            }
+            sizes
+        };
+        let expected = restic_reference(&data, 1024, 1100, 6);
+        // Bounds close together, so that chunks end at both of them
+        assert!(expected.contains(&1024) && expected.contains(&1100));
+        assert_eq!(
+            sizes(restic_chunker_with(POLYNOMIAL, 1024, 1100, 6).unwrap()),
+            expected
+        );
+        assert_eq!(
+            sizes(restic_chunker(POLYNOMIAL).unwrap()),
+            restic_reference(&data, RESTIC_MIN_SIZE, RESTIC_MAX_SIZE, RESTIC_AVG_BITS)
+        );
+    }
+
+    #[test]
+    fn derives_polynomials() {
+        let source = rand_data(8 * 1000);
+        let polynomial = derive_polynomial(&source[..]).unwrap();
+        assert_eq!(degree(polynomial as u128), 53);
+        assert!(is_irreducible(polynomial) && polynomial & 1 == 1);
+        assert_eq!(derive_polynomial(&source[..]).unwrap(), polynomial);
+        assert!(Rabin::with_polynomial(polynomial, WINDOW_SIZE, 20).is_ok());
+        let err = derive_polynomial(&[0u8; 8][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn lbfs_preset() {
+        let data = rand_data(2 * 1024 * 1024);
+        let mut rabin = Rabin::lbfs();
+        assert_eq!(rabin.polynomial(), LBFS_POLYNOMIAL);
+        assert!(is_irreducible(LBFS_POLYNOMIAL));
+        for (i, &b) in data[..1000].iter().enumerate() {
+            rabin.roll_byte(b);
+            let window = &data[(i + 1).saturating_sub(LBFS_WINDOW_SIZE)..=i];
+            assert_eq!(rabin.digest(), reference(window, LBFS_POLYNOMIAL));
+        }
+
+        let mut chunker = lbfs_chunker();
+        let mut remaining = &data[..];
+        let mut sizes = Vec::new();
+        while let Some((i, digest)) = chunker.find_chunk_edge(remaining) {
+            assert!((LBFS_MIN_SIZE..=LBFS_MAX_SIZE).contains(&i));
+            assert!(i == LBFS_MAX_SIZE || digest & 0x1fff == LBFS_BREAKMARK);
+            sizes.push(i);
+            remaining = &remaining[i..];
+        }
+        let average = sizes.iter().sum::<usize>() / sizes.len();
+        assert!((8 * 1024..12 * 1024).contains(&average), "{}", average);
+    }
+}
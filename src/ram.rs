@@ -0,0 +1,144 @@
+use super::Chunker;
+use std::cmp;
+
+/// RAM (Rapid Asymmetric Maximum) chunker
+///
+/// The first `window_size` bytes of every chunk make up its window, and the
+/// chunk ends at the first byte after them which is at least as large as
+/// the largest byte of the window, or at `max_size` bytes. No hash is
+/// computed: the window is scanned once for its maximum and the rest only
+/// compared with it, which makes RAM much faster than rolling an engine.
+/// The digest reported for an edge is the byte ending the chunk, or the
+/// window's maximum for forced edges.
+///
+/// Chunks are at least `window_size + 1` bytes long unless forced. As the
+/// maximum of a large window is close to 255 for random data, chunks are
+/// rarely much longer than `window_size + 256` bytes, so the window should
+/// be chosen close to the desired average.
+///
+/// Based on "A new content-defined chunking algorithm for data
+/// deduplication in cloud storage" (Widodo et al., FGCS 2017).
+#[derive(Debug, Clone)]
+pub struct RamChunker {
+    window_size: usize,
+    max_size: usize,
+    /// Largest byte of the window of the current chunk so far
+    max: u8,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl RamChunker {
+    /// Create a new chunker with a window of `window_size` bytes, forcing
+    /// edges at `max_size` bytes
+    pub fn new(window_size: usize, max_size: usize) -> Self {
+        assert!(window_size > 0);
+        assert!(window_size < max_size);
+        RamChunker {
+            window_size,
+            max_size,
+            max: 0,
+            len: 0,
+        }
+    }
+
+    /// Return the window size
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn edge(&mut self, end: usize, digest: u8) -> Option<(usize, u8)> {
+        self.max = 0;
+        self.len = 0;
+        Some((end, digest))
+    }
+}
+
+impl Chunker for RamChunker {
+    type Digest = u8;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u8)> {
+        let mut consumed = 0;
+        if self.len < self.window_size {
+            consumed = cmp::min(self.window_size - self.len, buf.len());
+            if let Some(&max) = buf[..consumed].iter().max() {
+                self.max = cmp::max(self.max, max);
+            }
+            self.len += consumed;
+        }
+        if self.len < self.window_size {
+            return None;
+        }
+
+        let end = consumed + cmp::min(buf.len() - consumed, self.max_size - self.len);
+        let max = self.max;
+        if let Some(i) = buf[consumed..end].iter().position(|&b| b >= max) {
+            return self.edge(consumed + i + 1, buf[consumed + i]);
+        }
+        self.len += end - consumed;
+        if self.len >= self.max_size {
+            return self.edge(end, max);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, chunk_sizes, rand_data, with_insert};
+
+    #[test]
+    fn edges_end_at_window_maximum() {
+        let data = rand_data(1024 * 1024);
+        let mut chunker = RamChunker::new(1024, 8192);
+        let mut remaining = &data[..];
+        let mut count = 0;
+        while let Some((i, digest)) = chunker.find_chunk_edge(remaining) {
+            let max = *remaining[..1024].iter().max().unwrap();
+            assert!(i > 1024 && i <= 8192);
+            if i < 8192 {
+                assert_eq!(digest, remaining[i - 1]);
+                assert!(digest >= max);
+                assert!(remaining[1024..i - 1].iter().all(|&b| b < max));
+            }
+            remaining = &remaining[i..];
+            count += 1;
+        }
+        assert!(count > 100);
+    }
+
+    #[test]
+    fn forced_edges() {
+        // Every byte after a window is below its maximum
+        let data: Vec<u8> = (0..1000).map(|i| (i % 100 == 0) as u8).collect();
+        let mut chunker = RamChunker::new(1, 100);
+        assert_eq!(chunk_sizes(&mut chunker, &data), vec![100; 10]);
+    }
+
+    #[test]
+    fn incremental_matches_whole() {
+        let data = rand_data(512 * 1024);
+        let new = || RamChunker::new(700, 4096);
+        let whole = chunk_edges(&mut new(), &data);
+        assert_eq!(chunk_edges_framed(&mut new(), &data, 307), whole);
+    }
+
+    #[test]
+    fn resynchronizes_after_insert() {
+        let data = rand_data(256 * 1024);
+        let modified = with_insert(&data);
+
+        let tail = |data: &[u8]| {
+            let mut sizes = chunk_sizes(&mut RamChunker::new(512, 4096), data);
+            sizes.drain(..sizes.len() / 2);
+            sizes
+        };
+        assert_eq!(tail(&data), tail(&modified));
+    }
+}
@@ -0,0 +1,186 @@
+use super::{for_each_block, Chunker};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Chunks covering a byte range, found by `rechunk_range`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeChunks {
+    /// Start of the first chunk
+    ///
+    /// At most the start of the range, unless scanning didn't stabilize
+    /// before the range; `lookback` has to be increased then.
+    pub start: u64,
+    /// End of every chunk from `start` on, up to the first chunk ending at
+    /// or after the end of the range (or the end of the data)
+    pub ends: Vec<u64>,
+}
+
+/// Feed `buf`, starting at stream offset `base`, to `chunker` and record the
+/// edges found
+fn feed<C: Chunker>(chunker: &mut C, mut buf: &[u8], mut base: u64, edges: &mut Vec<u64>) {
+    while let Some((i, _)) = chunker.find_chunk_edge(buf) {
+        base += i as u64;
+        edges.push(base);
+        buf = &buf[i..];
+    }
+}
+
+/// Return the first edge in both sorted lists
+fn first_common(a: &[u64], b: &[u64]) -> Option<u64> {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => return Some(a[i]),
+        }
+    }
+    None
+}
+
+/// Find the chunks of `range` of a large stream without chunking the stream
+/// from its start
+///
+/// Two chunkers from `new_chunker` scan the stream from `lookback` and
+/// `2 * lookback` bytes before the range. Once both find an edge at the same
+/// offset, their states are identical, so every later edge no longer depends
+/// on where scanning started, and with content-defined chunking it is the
+/// edge chunking the whole stream finds as well. That first common edge is
+/// the stable boundary the returned chunks start from. The end of the data
+/// is stable too, and scanning from the start of the stream is exact.
+///
+/// `lookback` should be several times the maximum (or a large multiple of
+/// the average) chunk size.
+pub fn rechunk_range<R, C, F>(
+    mut reader: R,
+    new_chunker: F,
+    range: Range<u64>,
+    lookback: u64,
+) -> io::Result<RangeChunks>
+where
+    R: Read + Seek,
+    C: Chunker,
+    F: Fn() -> C,
+{
+    let start_a = range.start.saturating_sub(lookback);
+    let start_b = start_a.saturating_sub(lookback);
+    reader.seek(SeekFrom::Start(start_b))?;
+
+    let mut a = new_chunker();
+    let mut b = if start_a > 0 {
+        Some(new_chunker())
+    } else {
+        None
+    };
+    let (mut edges_a, mut edges_b) = (Vec::new(), Vec::new());
+    // Stable boundary, once found
+    let mut stable = if b.is_none() { Some(0) } else { None };
+
+    let position = start_b
+        + for_each_block(&mut reader, |offset, data| {
+            let position = start_b + offset;
+            let end = position + data.len() as u64;
+            if let Some(b) = &mut b {
+                feed(b, data, position, &mut edges_b);
+            }
+            if end > start_a {
+                let skip = start_a.saturating_sub(position) as usize;
+                feed(&mut a, &data[skip..], position + skip as u64, &mut edges_a);
+            }
+
+            if stable.is_none() {
+                let common = first_common(&edges_a, &edges_b);
+                if let Some(edge) = common {
+                    stable = common;
+                    edges_a.retain(|&e| e > edge);
+                    b = None;
+                }
+            }
+            let done = stable.is_some() && edges_a.last().is_some_and(|&e| e >= range.end);
+            Ok(!done)
+        })?;
+
+    let mut ends = edges_a;
+    let stable = match stable {
+        Some(stable) => stable,
+        None => {
+            // Only the end of the data is common
+            ends.clear();
+            position
+        }
+    };
+    // The data ended before a chunk reached the end of the range
+    let last = ends.last().copied().unwrap_or(stable);
+    if last < position && last < range.end {
+        ends.push(position);
+    }
+
+    // Start from the last stable boundary at or before the range
+    let first = ends.iter().take_while(|&&e| e <= range.start).count();
+    let start = if first > 0 { ends[first - 1] } else { stable };
+    ends.drain(..first);
+    if let Some(last) = ends.iter().position(|&e| e >= range.end) {
+        ends.truncate(last + 1);
+    }
+    Ok(RangeChunks { start, ends })
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{Gear, MinMaxChunker};
+    use std::io::Cursor;
+
+    fn whole<C: Chunker>(mut chunker: C, data: &[u8]) -> Vec<u64> {
+        let mut edges = vec![0];
+        feed(&mut chunker, data, 0, &mut edges);
+        if *edges.last().unwrap() < data.len() as u64 {
+            edges.push(data.len() as u64);
+        }
+        edges
+    }
+
+    fn check<C: Chunker, F: Fn() -> C>(new: F, data: &[u8], range: Range<u64>, lookback: u64) {
+        let edges = whole(new(), data);
+        let chunks = rechunk_range(Cursor::new(data), &new, range.clone(), lookback).unwrap();
+        let first = edges.iter().rposition(|&e| e <= range.start).unwrap();
+        let mut last = edges.iter().position(|&e| e >= range.end).unwrap();
+        if first == last {
+            last += 1;
+        }
+        assert_eq!(chunks.start, edges[first], "{:?}", range);
+        assert_eq!(chunks.ends, edges[first + 1..=last], "{:?}", range);
+    }
+
+    #[test]
+    fn matches_whole_stream_chunking() {
+        let data = rand_data(1024 * 1024);
+        let plain = || Gear::new_with_chunk_bits(11);
+        let bounded = || MinMaxChunker::new(Gear::new_with_chunk_bits(11), 512, 8192);
+        for &(start, end) in &[
+            (0, 5000),
+            (300_000, 300_001),
+            (500_000, 600_000),
+            (1_000_000, 1024 * 1024),
+        ] {
+            check(plain, &data, start..end, 32 * 1024);
+            check(bounded, &data, start..end, 64 * 1024);
+        }
+    }
+
+    #[test]
+    fn reports_unstable_start() {
+        // Zeroes have no edges, so scanning can't stabilize before the range
+        let mut data = rand_data(100_000);
+        data[20_000..90_000].fill(0);
+        let chunks = rechunk_range(
+            Cursor::new(&data),
+            || Gear::new_with_chunk_bits(11),
+            80_000..85_000,
+            10_000,
+        )
+        .unwrap();
+        assert!(chunks.start > 80_000);
+    }
+}
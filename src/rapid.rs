@@ -0,0 +1,276 @@
+use super::{Chunker, EdgeCondition, Engine, MaskEngine};
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+
+/// Number of sizes of following chunks remembered per chunk
+pub const MAX_HINTS: usize = 4;
+
+/// Default number of chunks whose following sizes are remembered
+pub const DEFAULT_MAX_KNOWN: usize = 1 << 16;
+
+/// RapidCDC chunker, skipping to cut points suggested by earlier chunkings
+///
+/// Chunks are found as by `MinMaxChunker`, and for every chunk the sizes of
+/// the chunks that followed it are remembered, identified by the chunk's
+/// length and edge digest. When a chunk following a known one starts, the
+/// remembered sizes are tried first: if `buf` holds that many bytes and the
+/// edge condition is met by the window ending there, the chunk ends there
+/// without rolling the engine over the rest of it. Re-chunking slightly
+/// modified versions of the same data mostly skips from edge to edge.
+///
+/// A skipped chunk may contain an edge which chunking it would have found
+/// first, in which case the edges differ from those of `MinMaxChunker`. This
+/// needs the data to change inside the chunk while keeping the window at its
+/// end, since the start of the chunk and the previous chunk are the same as
+/// before. Engines without a fixed window (see `Engine::window_size`) can't
+/// verify cut points, so they never skip.
+///
+/// Only the sizes following the last `DEFAULT_MAX_KNOWN` chunks are
+/// remembered, see `set_max_known`.
+///
+/// Based on "RapidCDC: Leveraging Duplicate Locality to Accelerate
+/// Chunking in CDC-based Deduplication Systems" (Ni and Jiang, SoCC 2019).
+pub struct RapidChunker<E: Engine, C> {
+    engine: E,
+    cond: C,
+    min_size: usize,
+    max_size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+    /// Sizes of the chunks following known chunks, most recent first
+    hints: HashMap<(E::Digest, usize), VecDeque<usize>>,
+    /// Keys of `hints`, oldest first
+    order: VecDeque<(E::Digest, usize)>,
+    max_known: usize,
+    /// Edge digest and length of the previous chunk
+    previous: Option<(E::Digest, usize)>,
+    skips: u64,
+    skipped_bytes: u64,
+}
+
+impl<E> RapidChunker<E, E::Condition>
+where
+    E: MaskEngine,
+    E::Digest: Hash + Eq + Copy,
+{
+    /// Create a new chunker using the engine's default edge condition
+    pub fn new(engine: E, min_size: usize, max_size: usize) -> Self {
+        let cond = E::condition_for_bits(engine.chunk_bits());
+        Self::with_condition(engine, cond, min_size, max_size)
+    }
+}
+
+impl<E, C> RapidChunker<E, C>
+where
+    E: Engine,
+    E::Digest: Hash + Eq + Copy,
+    C: EdgeCondition<E>,
+{
+    /// Create a new chunker using a custom edge condition
+    pub fn with_condition(engine: E, cond: C, min_size: usize, max_size: usize) -> Self {
+        assert!(min_size <= max_size);
+        assert!(max_size > 0);
+        RapidChunker {
+            engine,
+            cond,
+            min_size,
+            max_size,
+            len: 0,
+            hints: HashMap::new(),
+            order: VecDeque::new(),
+            max_known: DEFAULT_MAX_KNOWN,
+            previous: None,
+            skips: 0,
+            skipped_bytes: 0,
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Return the number of chunks skipped to
+    pub fn skips(&self) -> u64 {
+        self.skips
+    }
+
+    /// Return the number of bytes in chunks skipped to
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Remember the sizes following up to `chunks` chunks, at least one
+    ///
+    /// The sizes following the oldest chunks are forgotten first.
+    pub fn set_max_known(&mut self, chunks: usize) {
+        self.max_known = chunks.max(1);
+        self.evict();
+    }
+
+    /// Forget the remembered chunk sizes
+    pub fn clear_hints(&mut self) {
+        self.hints.clear();
+        self.order.clear();
+        self.previous = None;
+    }
+
+    fn evict(&mut self) {
+        while self.order.len() > self.max_known {
+            let oldest = self.order.pop_front().unwrap();
+            self.hints.remove(&oldest);
+        }
+    }
+
+    fn edge(&mut self, len: usize, digest: E::Digest) {
+        if let Some(previous) = self.previous {
+            let hints = match self.hints.entry(previous) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    self.order.push_back(previous);
+                    entry.insert(VecDeque::new())
+                }
+            };
+            hints.retain(|&hint| hint != len);
+            hints.push_front(len);
+            hints.truncate(MAX_HINTS);
+            self.evict();
+        }
+        self.previous = Some((digest, len));
+        self.len = 0;
+    }
+
+    /// Try the sizes following the previous chunk as the end of the chunk
+    /// starting at `buf`
+    fn skip(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        let window_size = self.engine.window_size()?;
+        let hints = self.hints.get(&self.previous?)?;
+        for &hint in hints {
+            if hint < self.min_size || hint > cmp::min(buf.len(), self.max_size) {
+                continue;
+            }
+            self.engine
+                .roll(&buf[hint.saturating_sub(window_size)..hint]);
+            let found = self.cond.is_edge(&self.engine) || hint == self.max_size;
+            let digest = self.engine.digest();
+            self.engine.reset();
+            if found {
+                return Some((hint, digest));
+            }
+        }
+        None
+    }
+}
+
+impl<E, C> Chunker for RapidChunker<E, C>
+where
+    E: Engine,
+    E::Digest: Hash + Eq + Copy,
+    C: EdgeCondition<E>,
+{
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        if self.len == 0 {
+            if let Some((len, digest)) = self.skip(buf) {
+                self.skips += 1;
+                self.skipped_bytes += len as u64;
+                self.edge(len, digest);
+                return Some((len, digest));
+            }
+        }
+
+        let mut consumed = 0;
+        if self.len < self.min_size {
+            consumed = cmp::min(self.min_size - self.len, buf.len());
+            self.engine.roll(&buf[..consumed]);
+            self.len += consumed;
+        }
+        if self.len >= self.min_size {
+            let end = consumed + cmp::min(buf.len() - consumed, self.max_size - self.len);
+            let part = &buf[consumed..end];
+            if let Some((i, digest)) = self.engine.find_chunk_edge_with(part, &self.cond) {
+                let len = self.len + i;
+                self.edge(len, digest);
+                return Some((consumed + i, digest));
+            }
+            self.len += part.len();
+            consumed = end;
+        }
+        if self.len >= self.max_size {
+            let digest = self.engine.digest();
+            self.engine.reset();
+            let len = self.len;
+            self.edge(len, digest);
+            return Some((consumed, digest));
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, rand_data};
+    use crate::{Gear, MinMaxChunker};
+
+    fn new() -> RapidChunker<Gear, crate::condition::PrefixZeroCondition> {
+        RapidChunker::new(Gear::new_with_chunk_bits(11), 256, 16384)
+    }
+
+    fn minmax() -> MinMaxChunker<Gear, crate::condition::PrefixZeroCondition> {
+        MinMaxChunker::new(Gear::new_with_chunk_bits(11), 256, 16384)
+    }
+
+    #[test]
+    fn same_edges_as_minmax() {
+        let data = rand_data(1024 * 1024);
+        let mut chunker = new();
+        assert_eq!(
+            chunk_edges(&mut chunker, &data),
+            chunk_edges(&mut minmax(), &data)
+        );
+        assert_eq!(chunker.skips(), 0);
+
+        // Incrementally, no chunk is complete in the buffer at its start
+        assert_eq!(
+            chunk_edges_framed(&mut new(), &data, 100),
+            chunk_edges(&mut minmax(), &data)
+        );
+    }
+
+    #[test]
+    fn skips_when_rechunking_modified_data() {
+        // Data ending at an edge, so that the second pass starts a new chunk
+        let data = rand_data(1024 * 1024);
+        let end = *self::chunk_edges(&mut minmax(), &data).last().unwrap();
+        let data = &data[..end];
+        let mut modified = data.to_vec();
+        modified[300_000..300_100].fill(0);
+        modified.splice(700_000..700_000, b"inserted".iter().copied());
+
+        let mut chunker = new();
+        chunk_edges(&mut chunker, data);
+        let edges = chunk_edges(&mut chunker, &modified);
+        assert_eq!(edges, self::chunk_edges(&mut minmax(), &modified));
+        assert!(chunker.skips() as usize > edges.len() * 9 / 10);
+        assert!(chunker.skipped_bytes() > modified.len() as u64 * 9 / 10);
+    }
+
+    #[test]
+    fn remembers_at_most_max_known() {
+        let data = rand_data(1024 * 1024);
+        let mut chunker = new();
+        chunker.set_max_known(16);
+        let edges = chunk_edges(&mut chunker, &data);
+        assert!(edges.len() > 100);
+        assert_eq!((chunker.hints.len(), chunker.order.len()), (16, 16));
+
+        // Only the chunks at the end are skipped to when chunking it again
+        assert_eq!(chunk_edges(&mut chunker, &data), edges);
+        assert!(chunker.skips() <= 16);
+        assert_eq!(chunker.hints.len(), 16);
+    }
+}
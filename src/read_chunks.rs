@@ -0,0 +1,96 @@
+use super::{Chunker, StreamChunker};
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+/// Iterator over the chunks read from a `BufRead`, each in its own buffer
+///
+/// Chunks spanning several buffers of the reader are accumulated until
+/// their edge is found. The last chunk is the data after the last edge,
+/// unless it is empty.
+pub struct ReadChunks<R, C> {
+    reader: R,
+    stream: StreamChunker<C>,
+    /// Chunks found but not returned yet
+    ready: VecDeque<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: BufRead, C: Chunker> ReadChunks<R, C> {
+    /// Split the data of `reader` with `chunker`
+    pub fn new(reader: R, chunker: C) -> Self {
+        ReadChunks {
+            reader,
+            stream: StreamChunker::owned(chunker),
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Read the next chunk, or `None` at the end of the stream
+    ///
+    /// After an error, calling this again resumes reading the current chunk.
+    pub fn read_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        while self.ready.is_empty() && !self.done {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let chunks = if buf.is_empty() {
+                self.done = true;
+                self.stream.finish().into_iter().collect()
+            } else {
+                let chunks = self.stream.push(buf);
+                let n = buf.len();
+                self.reader.consume(n);
+                chunks
+            };
+            // Owned mode always returns the contents of chunks
+            self.ready.extend(
+                chunks
+                    .into_iter()
+                    .map(|chunk| chunk.data.unwrap_or_default()),
+            );
+        }
+        Ok(self.ready.pop_front())
+    }
+
+    /// Return the underlying reader and chunker
+    ///
+    /// Chunks already read but not returned yet are dropped.
+    pub fn into_inner(self) -> (R, C) {
+        (self.reader, self.stream.into_inner())
+    }
+}
+
+impl<R: BufRead, C: Chunker> Iterator for ReadChunks<R, C> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        self.read_chunk().transpose()
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+    use std::io::BufReader;
+
+    #[test]
+    fn chunks_span_buffers() {
+        let data = rand_data(256 * 1024);
+        let expected: Vec<&[u8]> = Gear::new_with_chunk_bits(12).chunks(&data).collect();
+        assert!(expected.len() > 20);
+        for &capacity in &[1, 100, 4096, 1024 * 1024] {
+            let reader = BufReader::with_capacity(capacity, &data[..]);
+            let chunks: Vec<Vec<u8>> = ReadChunks::new(reader, Gear::new_with_chunk_bits(12))
+                .collect::<io::Result<_>>()
+                .unwrap();
+            assert_eq!(chunks, expected, "{}", capacity);
+        }
+        let mut empty = ReadChunks::new(&[][..], Gear::new_with_chunk_bits(12));
+        assert!(empty.next().is_none());
+    }
+}
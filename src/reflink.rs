@@ -0,0 +1,248 @@
+use super::{for_each_chunk, Chunker};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::AsRawFd;
+
+/// Statistics of a `dedup_copy`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Number of chunks of the source which were found in the base file
+    pub matched_chunks: u64,
+    /// Number of bytes shared with the base file through `FICLONERANGE`
+    pub bytes_cloned: u64,
+    /// Number of bytes written to the destination
+    pub bytes_written: u64,
+}
+
+/// Copy `src` to `dst`, sharing extents with `base` where possible
+///
+/// Both `base` and `src` are split into chunks by chunkers created with
+/// `new_chunker`. Chunks of `src` whose content also appears in `base` are
+/// cloned from `base` with the `FICLONERANGE` ioctl, so on filesystems
+/// supporting reflinks (btrfs, XFS) the destination shares storage with the
+/// base file. Everything else is written normally.
+///
+/// The kernel only clones whole filesystem blocks, so only the block aligned
+/// part of a matching chunk can be shared, and only when the chunk has the
+/// same offset modulo the block size in both files. When the filesystem does
+/// not support reflinks the copy silently falls back to plain writes.
+///
+/// `dst` must be open for writing and is truncated to the length of `src`.
+pub fn dedup_copy<C, F>(
+    new_chunker: F,
+    src: &File,
+    base: &File,
+    dst: &File,
+) -> io::Result<CopyStats>
+where
+    C: Chunker,
+    F: Fn() -> C,
+{
+    let mut known: HashMap<u64, Vec<(u64, usize)>> = HashMap::new();
+    for_each_chunk(ReadAt::new(base), &mut new_chunker(), |offset, chunk| {
+        known
+            .entry(content_hash(chunk))
+            .or_default()
+            .push((offset, chunk.len()));
+        Ok(())
+    })?;
+
+    let block_size = dst.metadata()?.blksize();
+    let mut stats = CopyStats::default();
+    let mut reflink_supported = true;
+    let mut scratch = Vec::new();
+    let mut len = 0;
+    for_each_chunk(ReadAt::new(src), &mut new_chunker(), |offset, chunk| {
+        len = offset + chunk.len() as u64;
+        let base_offset = match find(&known, base, chunk, &mut scratch)? {
+            Some(base_offset) => base_offset,
+            None => {
+                dst.write_all_at(chunk, offset)?;
+                stats.bytes_written += chunk.len() as u64;
+                return Ok(());
+            }
+        };
+        stats.matched_chunks += 1;
+
+        // Clone the block aligned middle of the chunk, write the rest
+        let start = round_up(offset, block_size);
+        let end = (offset + chunk.len() as u64) / block_size * block_size;
+        let mut cloned = false;
+        if reflink_supported && offset % block_size == base_offset % block_size && start < end {
+            match clone_range(base, base_offset + start - offset, end - start, dst, start) {
+                Ok(()) => cloned = true,
+                Err(e) => reflink_supported = !is_unsupported(&e),
+            }
+        }
+        if cloned {
+            let head = (start - offset) as usize;
+            let tail = (end - offset) as usize;
+            dst.write_all_at(&chunk[..head], offset)?;
+            dst.write_all_at(&chunk[tail..], end)?;
+            stats.bytes_cloned += end - start;
+            stats.bytes_written += (head + chunk.len() - tail) as u64;
+        } else {
+            dst.write_all_at(chunk, offset)?;
+            stats.bytes_written += chunk.len() as u64;
+        }
+        Ok(())
+    })?;
+    dst.set_len(len)?;
+    Ok(stats)
+}
+
+fn content_hash(chunk: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    chunk.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Return the offset of a chunk of `base` with the same content as `chunk`
+fn find(
+    known: &HashMap<u64, Vec<(u64, usize)>>,
+    base: &File,
+    chunk: &[u8],
+    scratch: &mut Vec<u8>,
+) -> io::Result<Option<u64>> {
+    let candidates = match known.get(&content_hash(chunk)) {
+        Some(candidates) => candidates,
+        None => return Ok(None),
+    };
+    for &(offset, len) in candidates {
+        if len != chunk.len() {
+            continue;
+        }
+        scratch.resize(len, 0);
+        base.read_exact_at(scratch, offset)?;
+        if scratch[..] == *chunk {
+            return Ok(Some(offset));
+        }
+    }
+    Ok(None)
+}
+
+fn round_up(value: u64, multiple: u64) -> u64 {
+    value.div_ceil(multiple) * multiple
+}
+
+fn clone_range(
+    src: &File,
+    src_offset: u64,
+    len: u64,
+    dst: &File,
+    dst_offset: u64,
+) -> io::Result<()> {
+    let range = libc::file_clone_range {
+        src_fd: src.as_raw_fd().into(),
+        src_offset,
+        src_length: len,
+        dest_offset: dst_offset,
+    };
+    // Safe because `range` outlives the call and both descriptors are open
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONERANGE, &range) };
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Return whether `e` means the filesystem can't clone ranges at all
+fn is_unsupported(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(code) => [
+            libc::EOPNOTSUPP,
+            libc::EXDEV,
+            libc::ENOTTY,
+            libc::ENOSYS,
+            libc::EINVAL,
+        ]
+        .contains(&code),
+        None => false,
+    }
+}
+
+/// Sequential reader of a file which does not move its cursor
+struct ReadAt<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl<'a> ReadAt<'a> {
+    fn new(file: &'a File) -> Self {
+        ReadAt { file, offset: 0 }
+    }
+}
+
+impl Read for ReadAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read_at(buf, self.offset)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rollsum-reflink-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn copy_matches_source() {
+        let base_data = rand_data(512 * 1024);
+        let mut src_data = base_data.clone();
+        src_data.splice(100_000..100_010, b"edited!".iter().copied());
+        src_data.extend_from_slice(&[0xAA; 5000]);
+
+        let paths: Vec<_> = ["base", "src", "dst"]
+            .iter()
+            .map(|n| temp_path(n))
+            .collect();
+        File::create(&paths[0])
+            .unwrap()
+            .write_all(&base_data)
+            .unwrap();
+        File::create(&paths[1])
+            .unwrap()
+            .write_all(&src_data)
+            .unwrap();
+        let dst = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&paths[2])
+            .unwrap();
+
+        let stats = dedup_copy(
+            || Gear::new_with_chunk_bits(12),
+            &File::open(&paths[1]).unwrap(),
+            &File::open(&paths[0]).unwrap(),
+            &dst,
+        )
+        .unwrap();
+
+        let copied = std::fs::read(&paths[2]).unwrap();
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert!(copied == src_data);
+        assert!(stats.matched_chunks > 50);
+        assert_eq!(
+            stats.bytes_cloned + stats.bytes_written,
+            src_data.len() as u64
+        );
+    }
+}
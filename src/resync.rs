@@ -0,0 +1,135 @@
+use super::Chunker;
+use std::ops::Range;
+
+/// Part of a damaged stream, as classified by `scan`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// Byte range of the region within the scanned data
+    pub range: Range<usize>,
+    /// Whether the region consists of known chunks
+    pub known: bool,
+}
+
+/// Call `f` with the offset and contents of every chunk of `data`, stopping
+/// when `f` returns false
+fn chunks<C: Chunker, F: FnMut(usize, &[u8]) -> bool>(chunker: &mut C, data: &[u8], mut f: F) {
+    let mut start = 0;
+    while let Some((i, _)) = chunker.find_chunk_edge(&data[start..]) {
+        if !f(start, &data[start..start + i]) {
+            return;
+        }
+        start += i;
+    }
+    if start < data.len() {
+        f(start, &data[start..]);
+    }
+}
+
+/// Return the offset in `data` where known chunks resume
+///
+/// `data` is chunked with `chunker`, which should be set up as when the
+/// stream was first chunked, and the offset of the first chunk for which
+/// `is_known` returns true is returned. Since chunk edges only depend on the
+/// bytes around them, the chunker falls back in step with the original
+/// edges soon after the damaged part, whether `data` starts in the middle
+/// of a chunk or contains corrupted bytes.
+///
+/// Chunkers enforcing a minimum chunk size may take longer to fall back in
+/// step, because skipping an original edge shifts the following ones.
+pub fn resume_offset<C, F>(chunker: &mut C, data: &[u8], mut is_known: F) -> Option<usize>
+where
+    C: Chunker,
+    F: FnMut(&[u8]) -> bool,
+{
+    let mut resume = None;
+    chunks(chunker, data, |offset, chunk| {
+        if is_known(chunk) {
+            resume = Some(offset);
+        }
+        resume.is_none()
+    });
+    resume
+}
+
+/// Split damaged `data` into alternating regions of known chunks and of
+/// unknown (damaged or new) data
+///
+/// See `resume_offset`. Known regions can be recovered from the chunk
+/// store, and the other regions have to be salvaged otherwise.
+pub fn scan<C, F>(chunker: &mut C, data: &[u8], mut is_known: F) -> Vec<Region>
+where
+    C: Chunker,
+    F: FnMut(&[u8]) -> bool,
+{
+    let mut regions: Vec<Region> = Vec::new();
+    chunks(chunker, data, |offset, chunk| {
+        let known = is_known(chunk);
+        let end = offset + chunk.len();
+        match regions.last_mut() {
+            Some(last) if last.known == known => last.range.end = end,
+            _ => regions.push(Region {
+                range: offset..end,
+                known,
+            }),
+        }
+        true
+    });
+    regions
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::Gear;
+    use std::collections::HashSet;
+
+    fn original() -> (Vec<u8>, HashSet<Vec<u8>>, Vec<usize>) {
+        let data = rand_data(256 * 1024);
+        let mut known = HashSet::new();
+        let mut edges = vec![0];
+        chunks(
+            &mut Gear::new_with_chunk_bits(10),
+            &data,
+            |offset, chunk| {
+                known.insert(chunk.to_vec());
+                edges.push(offset + chunk.len());
+                true
+            },
+        );
+        (data, known, edges)
+    }
+
+    #[test]
+    fn resumes_after_truncation() {
+        let (data, known, edges) = original();
+        let cut = 5000;
+        let data = &data[cut..];
+        let new = || Gear::new_with_chunk_bits(10);
+        let resume = resume_offset(&mut new(), data, |c| known.contains(c)).unwrap();
+        // The first edge after the cut may be missed, the second one is not
+        let second_edge = edges.iter().filter(|&&e| e > cut).nth(1).unwrap() - cut;
+        assert!(resume <= second_edge);
+
+        let regions = scan(&mut new(), data, |c| known.contains(c));
+        let last = regions.last().unwrap();
+        assert!(last.known);
+        assert!(last.range.start <= second_edge);
+        assert_eq!(last.range.end, data.len());
+    }
+
+    #[test]
+    fn scan_isolates_corruption() {
+        let (mut data, known, edges) = original();
+        data[100_000..100_500].fill(0xff);
+        let regions = scan(&mut Gear::new_with_chunk_bits(10), &data, |c| {
+            known.contains(c)
+        });
+        assert_eq!(regions.len(), 3);
+        assert!(regions[0].known && !regions[1].known && regions[2].known);
+        assert!(regions[1].range.start <= 100_000 && regions[1].range.end >= 100_500);
+        assert!(regions[1].range.end < 110_000);
+        assert!(edges.contains(&regions[2].range.start));
+        assert_eq!(regions[2].range.end, data.len());
+    }
+}
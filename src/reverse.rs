@@ -0,0 +1,193 @@
+use super::Chunker;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Chunker rolling over a stream from its end toward its start
+///
+/// The backward scheme is defined as chunking the byte reversed stream: the
+/// bytes of the stream are fed to the wrapped chunker last byte first, and a
+/// boundary is placed before the byte whose feeding ended a chunk. Boundaries
+/// are measured as distances from the end of the stream, so they stay put
+/// when data is prepended, just like forward boundaries stay put when data is
+/// appended.
+pub struct ReverseChunker<C> {
+    chunker: C,
+    consumed: u64,
+    reversed: Vec<u8>,
+}
+
+impl<C: Chunker> ReverseChunker<C> {
+    /// Chunk backward with `chunker`
+    pub fn new(chunker: C) -> Self {
+        ReverseChunker {
+            chunker,
+            consumed: 0,
+            reversed: Vec::new(),
+        }
+    }
+
+    /// Feed the part of the stream right before everything fed so far,
+    /// returning the distances from the end of the stream of the boundaries
+    /// found in it, in decreasing order of stream offset
+    pub fn push_front(&mut self, block: &[u8]) -> Vec<u64> {
+        self.reversed.clear();
+        self.reversed.extend(block.iter().rev());
+        let mut boundaries = Vec::new();
+        let mut data = &self.reversed[..];
+        while let Some((i, _)) = self.chunker.find_chunk_edge(data) {
+            self.consumed += i as u64;
+            boundaries.push(self.consumed);
+            data = &data[i..];
+        }
+        self.consumed += data.len() as u64;
+        boundaries
+    }
+
+    /// Return the number of bytes fed so far
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// Return the wrapped chunker
+    pub fn into_inner(self) -> C {
+        self.chunker
+    }
+}
+
+/// Return the offsets of the backward boundaries of `data` in increasing
+/// order, see `ReverseChunker`
+///
+/// Neither 0 nor `data.len()` are included.
+pub fn reverse_boundaries<C: Chunker>(data: &[u8], chunker: C) -> Vec<usize> {
+    let mut reverse = ReverseChunker::new(chunker);
+    let mut boundaries: Vec<_> = reverse
+        .push_front(data)
+        .into_iter()
+        .map(|end| data.len() - end as usize)
+        .filter(|&b| b > 0)
+        .collect();
+    boundaries.reverse();
+    boundaries
+}
+
+/// Split `reader` backward with `chunker`, calling `f` with the offset and
+/// contents of each chunk, last chunk first
+///
+/// The stream is read in blocks from its end, for processing e.g. the newest
+/// entries of a log file first.
+pub fn for_each_chunk_backward<R, C, F>(mut reader: R, chunker: C, mut f: F) -> io::Result<()>
+where
+    R: Read + Seek,
+    C: Chunker,
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    const BLOCK: u64 = 64 * 1024;
+    let mut reverse = ReverseChunker::new(chunker);
+    let mut position = reader.seek(SeekFrom::End(0))?;
+    let mut buf = vec![0; BLOCK as usize];
+    // Contents of the current chunk, last byte first
+    let mut pending: Vec<u8> = Vec::new();
+    let mut chunk = Vec::new();
+    while position > 0 {
+        let len = position.min(BLOCK);
+        position -= len;
+        reader.seek(SeekFrom::Start(position))?;
+        let block = &mut buf[..len as usize];
+        reader.read_exact(block)?;
+
+        let end = reverse.consumed();
+        // Bytes at the end of `block` which were emitted already
+        let mut done = 0;
+        for boundary in reverse.push_front(block) {
+            let upto = (boundary - end) as usize;
+            pending.extend(block[len as usize - upto..len as usize - done].iter().rev());
+            chunk.clear();
+            chunk.extend(pending.iter().rev());
+            pending.clear();
+            f(position + len - upto as u64, &chunk)?;
+            done = upto;
+        }
+        pending.extend(block[..len as usize - done].iter().rev());
+    }
+    if !pending.is_empty() {
+        pending.reverse();
+        f(0, &pending)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{for_each_chunk, Gear};
+
+    #[test]
+    fn matches_forward_chunking_of_reversed_data() {
+        let data = rand_data(256 * 1024);
+        let reversed: Vec<u8> = data.iter().rev().copied().collect();
+        let mut forward = Vec::new();
+        for_each_chunk(
+            &reversed[..],
+            &mut Gear::new_with_chunk_bits(11),
+            |offset, _| {
+                if offset > 0 {
+                    forward.push(data.len() - offset as usize);
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        forward.reverse();
+        let boundaries = reverse_boundaries(&data, Gear::new_with_chunk_bits(11));
+        assert!(boundaries.len() > 50);
+        assert_eq!(boundaries, forward);
+
+        // Blocks fed from the end find the same boundaries
+        let mut reverse = ReverseChunker::new(Gear::new_with_chunk_bits(11));
+        let mut ends = Vec::new();
+        for block in data.rchunks(3000) {
+            ends.extend(reverse.push_front(block));
+        }
+        let mut pushed: Vec<_> = ends.iter().map(|&e| data.len() - e as usize).collect();
+        pushed.retain(|&b| b > 0);
+        pushed.reverse();
+        assert_eq!(pushed, boundaries);
+    }
+
+    #[test]
+    fn prepending_keeps_boundaries() {
+        let data = rand_data(128 * 1024);
+        let longer = [&rand_data(200 * 1024)[150 * 1024..], &data[..]].concat();
+        let shift = longer.len() - data.len();
+        let boundaries = reverse_boundaries(&data, Gear::new_with_chunk_bits(11));
+        let shifted: Vec<_> = reverse_boundaries(&longer, Gear::new_with_chunk_bits(11))
+            .into_iter()
+            .filter(|&b| b > shift)
+            .map(|b| b - shift)
+            .collect();
+        assert!(boundaries.len() > 20);
+        assert_eq!(shifted, boundaries);
+    }
+
+    #[test]
+    fn emits_chunks_last_first() {
+        let data = rand_data(300 * 1024);
+        let mut chunks = Vec::new();
+        for_each_chunk_backward(
+            io::Cursor::new(&data),
+            Gear::new_with_chunk_bits(12),
+            |offset, chunk| {
+                chunks.push((offset, chunk.to_vec()));
+                Ok(())
+            },
+        )
+        .unwrap();
+        let boundaries = reverse_boundaries(&data, Gear::new_with_chunk_bits(12));
+        assert_eq!(chunks.len(), boundaries.len() + 1);
+        let offsets: Vec<_> = chunks.iter().rev().map(|(o, _)| *o as usize).collect();
+        assert_eq!(offsets[0], 0);
+        assert_eq!(offsets[1..], boundaries[..]);
+        let joined: Vec<u8> = chunks.iter().rev().flat_map(|(_, c)| c.clone()).collect();
+        assert!(joined == data);
+    }
+}
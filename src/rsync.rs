@@ -0,0 +1,224 @@
+use super::{Engine, SlidingEngine};
+use std::cmp;
+use std::convert::TryFrom;
+
+pub type Digest = u32;
+
+/// Default block length, for files of up to 490 KB
+pub const BLOCK_LEN: usize = 700;
+
+/// rsync's weak checksum over a sliding block
+///
+/// `a1` is the sum of the bytes of the block and `a2` the sum of `a1` after
+/// each of them, both modulo 2^16, and the digest is `a2` above `a1` as
+/// rsync's `get_checksum1` returns it. rsync sums the bytes as signed chars,
+/// so bytes from 0x80 on count as negative numbers. Before the block is
+/// filled, the digest is the checksum of the bytes rolled so far, as for
+/// the shorter last block of a file.
+#[derive(Clone)]
+pub struct RsyncSum {
+    a1: u32,
+    a2: u32,
+    window: Box<[u8]>,
+    wofs: usize,
+    /// Bytes rolled over since the last reset, up to the block length
+    filled: usize,
+}
+
+fn signed(b: u8) -> u32 {
+    b as i8 as u32
+}
+
+impl Default for RsyncSum {
+    fn default() -> Self {
+        Self::new(BLOCK_LEN)
+    }
+}
+
+impl RsyncSum {
+    /// Create a new engine summing `block_len` byte blocks
+    pub fn new(block_len: usize) -> Self {
+        assert!(block_len > 0);
+        RsyncSum {
+            a1: 0,
+            a2: 0,
+            window: vec![0; block_len].into_boxed_slice(),
+            wofs: 0,
+            filled: 0,
+        }
+    }
+
+    /// Return the block length
+    pub fn block_len(&self) -> usize {
+        self.window.len()
+    }
+}
+
+/// Return the weak checksum of `block`, as rsync's `get_checksum1`
+pub fn weak_sum(block: &[u8]) -> Digest {
+    let mut sum = RsyncSum::new(cmp::max(block.len(), 1));
+    sum.roll(block);
+    sum.digest()
+}
+
+/// Return the weak checksums of the `block_len` byte blocks of `buf`, the
+/// last one shorter unless `block_len` divides the length of `buf`
+///
+/// These are the weak checksums rsync sends for a basis file. The sending
+/// side then rolls a `RsyncSum::new(block_len)` over its data and looks up
+/// each digest, confirming matches with a strong checksum.
+pub fn block_sums(buf: &[u8], block_len: usize) -> Vec<Digest> {
+    assert!(block_len > 0);
+    buf.chunks(block_len).map(weak_sum).collect()
+}
+
+impl Engine for RsyncSum {
+    type Digest = Digest;
+
+    #[inline(always)]
+    fn roll_byte(&mut self, newch: u8) {
+        let prevch = self.window[self.wofs];
+        self.slide(prevch, newch);
+    }
+
+    fn roll(&mut self, buf: &[u8]) {
+        // Bytes rolled in before the last block are all rotated out again
+        crate::roll_windowed(self, self.window.len(), buf);
+    }
+
+    #[inline(always)]
+    fn digest(&self) -> Digest {
+        (self.a2 << 16) | (self.a1 & 0xffff)
+    }
+
+    fn window_size(&self) -> Option<usize> {
+        Some(self.window.len())
+    }
+
+    fn bytes_until_warm(&self) -> usize {
+        self.window.len() - self.filled
+    }
+
+    fn combine(left: &Self, right: &Self, right_len: u64) -> Option<Self> {
+        // Only the last block matters, as for `Bup`
+        let block_len = left.window.len();
+        if block_len != right.window.len() {
+            return None;
+        }
+        let mut combined = left.clone();
+        let take = usize::try_from(right_len).map_or(block_len, |len| cmp::min(len, block_len));
+        for i in 0..take {
+            combined.roll_byte(right.window[(right.wofs + block_len - take + i) % block_len]);
+        }
+        Some(combined)
+    }
+
+    fn reset(&mut self) {
+        self.a1 = 0;
+        self.a2 = 0;
+        self.window.fill(0);
+        self.wofs = 0;
+        self.filled = 0;
+    }
+}
+
+impl SlidingEngine for RsyncSum {
+    #[inline(always)]
+    fn slide(&mut self, old: u8, new: u8) {
+        let block_len = self.window.len();
+        let new_sum = signed(new);
+        if self.filled < block_len {
+            self.a1 = self.a1.wrapping_add(new_sum);
+            self.a2 = self.a2.wrapping_add(self.a1);
+            self.filled += 1;
+        } else {
+            let out = signed(old);
+            self.a1 = self.a1.wrapping_sub(out).wrapping_add(new_sum);
+            self.a2 = self
+                .a2
+                .wrapping_sub((block_len as u32).wrapping_mul(out))
+                .wrapping_add(self.a1);
+        }
+        self.window[self.wofs] = new;
+        self.wofs = (self.wofs + 1) % block_len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    /// rsync's `get_checksum1`, transcribed with its four byte unrolling
+    fn get_checksum1(buf: &[u8]) -> Digest {
+        let b = |i: usize| signed(buf[i]);
+        let (mut s1, mut s2) = (0u32, 0u32);
+        let mut i = 0;
+        while i + 4 < buf.len() {
+            s2 = s2.wrapping_add(
+                s1.wrapping_add(b(i))
+                    .wrapping_mul(4)
+                    .wrapping_add(b(i + 1).wrapping_mul(3))
+                    .wrapping_add(b(i + 2).wrapping_mul(2))
+                    .wrapping_add(b(i + 3)),
+            );
+            s1 = s1
+                .wrapping_add(b(i))
+                .wrapping_add(b(i + 1))
+                .wrapping_add(b(i + 2))
+                .wrapping_add(b(i + 3));
+            i += 4;
+        }
+        for i in i..buf.len() {
+            s1 = s1.wrapping_add(b(i));
+            s2 = s2.wrapping_add(s1);
+        }
+        (s1 & 0xffff).wrapping_add(s2 << 16)
+    }
+
+    #[test]
+    fn small_sums() {
+        assert_eq!(weak_sum(b""), 0);
+        assert_eq!(weak_sum(b"a"), 0x0061_0061);
+        assert_eq!(weak_sum(b"ab"), 0x0124_00c3);
+        // Signed bytes
+        assert_eq!(weak_sum(&[0xff]), 0xffff_ffff);
+    }
+
+    #[test]
+    fn rolling_matches_blocks() {
+        let data = rand_data(16 * 1024);
+        for &block_len in &[1, 5, BLOCK_LEN, 4096] {
+            let mut sum = RsyncSum::new(block_len);
+            for (i, &b) in data.iter().enumerate() {
+                sum.roll_byte(b);
+                let block = &data[(i + 1).saturating_sub(block_len)..=i];
+                assert_eq!(sum.digest(), get_checksum1(block));
+            }
+        }
+    }
+
+    #[test]
+    fn block_sums_find_blocks() {
+        let basis = rand_data(10_000);
+        let sums = block_sums(&basis, BLOCK_LEN);
+        assert_eq!(sums.len(), 15);
+        assert_eq!(sums[14], get_checksum1(&basis[9800..]));
+
+        // Blocks moved by an insertion are found at their new offsets
+        let mut new = b"inserted".to_vec();
+        new.extend_from_slice(&basis);
+        let mut sum = RsyncSum::new(BLOCK_LEN);
+        let mut found = Vec::new();
+        for (i, &b) in new.iter().enumerate() {
+            sum.roll_byte(b);
+            if let Some(block) = sums[..14].iter().position(|&s| s == sum.digest()) {
+                found.push((block, i + 1 - BLOCK_LEN));
+            }
+        }
+        let expected: Vec<_> = (0..14)
+            .map(|block| (block, 8 + block * BLOCK_LEN))
+            .collect();
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,150 @@
+use super::Engine;
+use ::digest::generic_array::ArrayLength;
+use ::digest::typenum::{U4, U8};
+use ::digest::{FixedOutput, FixedOutputReset, Output, OutputSizeUser, Reset, Update};
+
+/// Rolling digests which can be written out as big-endian bytes
+pub trait DigestBytes {
+    /// Number of bytes in the serialised digest
+    type Size: ArrayLength<u8> + 'static;
+
+    /// Write the digest into `out`, which is exactly `Size` bytes long
+    fn write_be(&self, out: &mut [u8]);
+}
+
+impl DigestBytes for u32 {
+    type Size = U4;
+
+    fn write_be(&self, out: &mut [u8]) {
+        out.copy_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl DigestBytes for u64 {
+    type Size = U8;
+
+    fn write_be(&self, out: &mut [u8]) {
+        out.copy_from_slice(&self.to_be_bytes());
+    }
+}
+
+/// Wrapper exposing an engine through the RustCrypto fixed-output traits
+///
+/// The output is the big-endian rolling digest. Rolling sums are not
+/// cryptographic hashes, so `HashMarker` is deliberately not implemented and
+/// the wrapper does not get a blanket `digest::Digest` implementation.
+#[derive(Default)]
+pub struct FixedOutputEngine<E>(pub E);
+
+impl<E: Engine> Update for FixedOutputEngine<E> {
+    fn update(&mut self, data: &[u8]) {
+        self.0.roll(data);
+    }
+}
+
+impl<E: Engine> Reset for FixedOutputEngine<E> {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+impl<E> OutputSizeUser for FixedOutputEngine<E>
+where
+    E: Engine,
+    E::Digest: DigestBytes,
+{
+    type OutputSize = <E::Digest as DigestBytes>::Size;
+}
+
+impl<E> FixedOutput for FixedOutputEngine<E>
+where
+    E: Engine,
+    E::Digest: DigestBytes,
+{
+    fn finalize_into(self, out: &mut Output<Self>) {
+        self.0.digest().write_be(out);
+    }
+}
+
+impl<E> FixedOutputReset for FixedOutputEngine<E>
+where
+    E: Engine,
+    E::Digest: DigestBytes,
+{
+    fn finalize_into_reset(&mut self, out: &mut Output<Self>) {
+        self.0.digest().write_be(out);
+        self.0.reset();
+    }
+}
+
+#[cfg(any(feature = "bup", feature = "gear"))]
+macro_rules! impl_engine_traits {
+    ($engine:ty) => {
+        impl Update for $engine {
+            fn update(&mut self, data: &[u8]) {
+                self.roll(data);
+            }
+        }
+
+        impl Reset for $engine {
+            fn reset(&mut self) {
+                Engine::reset(self);
+            }
+        }
+    };
+}
+
+#[cfg(feature = "bup")]
+impl_engine_traits!(crate::Bup);
+#[cfg(feature = "gear")]
+impl_engine_traits!(crate::Gear);
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn feed<U: Update>(hasher: &mut U, data: &[u8]) {
+        for part in data.chunks(100) {
+            hasher.update(part);
+        }
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn fixed_output_matches_digest() {
+        use crate::Gear;
+
+        let data = rand_data(4096);
+        let mut gear = Gear::new();
+        gear.roll(&data);
+
+        let mut wrapper = FixedOutputEngine(Gear::new());
+        feed(&mut wrapper, &data);
+        assert_eq!(
+            wrapper.finalize_fixed_reset()[..],
+            gear.digest().to_be_bytes()
+        );
+        assert_eq!(wrapper.0.digest(), Gear::new().digest());
+
+        let mut plain = Gear::new();
+        feed(&mut plain, &data);
+        assert_eq!(plain.digest(), gear.digest());
+        Reset::reset(&mut plain);
+        assert_eq!(plain.digest(), Gear::new().digest());
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn bup_output_size() {
+        use crate::Bup;
+
+        let data = rand_data(4096);
+        let mut wrapper = FixedOutputEngine(Bup::new());
+        feed(&mut wrapper, &data);
+        let digest = wrapper.0.digest();
+        let out = wrapper.finalize_fixed();
+        assert_eq!(out.len(), 4);
+        assert_eq!(out[..], digest.to_be_bytes());
+    }
+}
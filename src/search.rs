@@ -0,0 +1,222 @@
+use super::Engine;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Occurrence of a needle found by `Searcher`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    /// Index of the needle, in the order the needles were given
+    pub needle: usize,
+    /// Offset of the first byte of the occurrence within the haystack
+    pub offset: u64,
+}
+
+/// Rabin–Karp search for several needles in a stream
+///
+/// The rolling digest of the engine is compared against the digest of the
+/// last window of every needle, and candidates are verified byte by byte, so
+/// there are no false positives. The engine must have a fixed window (see
+/// `Engine::window_size`). Needles shorter than the window can't be
+/// prefiltered and are compared at every position, so they are slow.
+///
+/// Data can be fed in pieces of any size; occurrences spanning several
+/// pieces are found as well.
+pub struct Searcher<E: Engine> {
+    engine: E,
+    window: usize,
+    needles: Vec<Vec<u8>>,
+    /// Needles at least `window` bytes long, by digest of their last window
+    by_digest: HashMap<E::Digest, Vec<usize>>,
+    /// Needles shorter than `window`
+    short: Vec<usize>,
+    /// Last bytes fed, as many as needed to verify the longest needle
+    history: Vec<u8>,
+    max_len: usize,
+    position: u64,
+}
+
+impl<E> Searcher<E>
+where
+    E: Engine + Default,
+    E::Digest: Hash + Eq,
+{
+    /// Create a searcher for `needles`
+    ///
+    /// Panics if a needle is empty, or if the engine has no fixed window.
+    pub fn new<I, N>(needles: I) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: Into<Vec<u8>>,
+    {
+        let engine = E::default();
+        let window = engine
+            .window_size()
+            .expect("searching requires an engine with a fixed window");
+        let needles: Vec<Vec<u8>> = needles.into_iter().map(Into::into).collect();
+        let mut by_digest: HashMap<_, Vec<_>> = HashMap::new();
+        let mut short = Vec::new();
+        for (i, needle) in needles.iter().enumerate() {
+            assert!(!needle.is_empty(), "needles must not be empty");
+            if needle.len() >= window {
+                by_digest.entry(E::digest_of(needle)).or_default().push(i);
+            } else {
+                short.push(i);
+            }
+        }
+        Searcher {
+            engine,
+            window,
+            max_len: needles.iter().map(Vec::len).max().unwrap_or(0),
+            needles,
+            by_digest,
+            short,
+            history: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Search the next piece of the haystack, calling `f` for every
+    /// occurrence ending in `buf`
+    ///
+    /// Occurrences are reported in order of their end offset.
+    pub fn feed<F: FnMut(Match)>(&mut self, buf: &[u8], mut f: F) {
+        for (i, &b) in buf.iter().enumerate() {
+            self.engine.roll_byte(b);
+            let end = i + 1;
+            if self.position + end as u64 >= self.window as u64 {
+                if let Some(candidates) = self.by_digest.get(&self.engine.digest()) {
+                    for &n in candidates {
+                        self.verify(n, buf, end, &mut f);
+                    }
+                }
+            }
+            for &n in &self.short {
+                self.verify(n, buf, end, &mut f);
+            }
+        }
+        self.position += buf.len() as u64;
+
+        let keep = self.max_len.saturating_sub(1);
+        if buf.len() >= keep {
+            self.history.clear();
+            self.history.extend_from_slice(&buf[buf.len() - keep..]);
+        } else {
+            let drop = (self.history.len() + buf.len()).saturating_sub(keep);
+            self.history.drain(..drop);
+            self.history.extend_from_slice(buf);
+        }
+    }
+
+    /// Check whether needle `n` ends right before `buf[end]`
+    fn verify<F: FnMut(Match)>(&self, n: usize, buf: &[u8], end: usize, f: &mut F) {
+        let needle = &self.needles[n];
+        let available = self.history.len() + end;
+        if available < needle.len() {
+            return;
+        }
+        let start = available - needle.len();
+        let found = if start >= self.history.len() {
+            buf[start - self.history.len()..end] == needle[..]
+        } else {
+            let (head, tail) = needle.split_at(self.history.len() - start);
+            self.history[start..] == *head && buf[..end] == *tail
+        };
+        if found {
+            f(Match {
+                needle: n,
+                offset: self.position + end as u64 - needle.len() as u64,
+            });
+        }
+    }
+
+    /// Return the number of bytes fed so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Return the needles
+    pub fn needles(&self) -> &[Vec<u8>] {
+        &self.needles
+    }
+}
+
+/// Find all occurrences of `needles` in `haystack`
+///
+/// See `Searcher`.
+pub fn find_all<E>(needles: &[&[u8]], haystack: &[u8]) -> Vec<Match>
+where
+    E: Engine + Default,
+    E::Digest: Hash + Eq,
+{
+    let mut searcher = Searcher::<E>::new(needles.iter().copied());
+    let mut matches = Vec::new();
+    searcher.feed(haystack, |m| matches.push(m));
+    matches
+}
+
+#[cfg(all(test, any(feature = "bup", feature = "gear")))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    fn naive(needles: &[&[u8]], haystack: &[u8]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        for end in 1..=haystack.len() {
+            for (n, needle) in needles.iter().enumerate() {
+                if end >= needle.len() && haystack[end - needle.len()..end] == **needle {
+                    matches.push(Match {
+                        needle: n,
+                        offset: (end - needle.len()) as u64,
+                    });
+                }
+            }
+        }
+        matches
+    }
+
+    fn haystack_and_needles() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let data = rand_data(64 * 1024);
+        let needles = vec![
+            data[1000..1100].to_vec(),
+            data[5000..5064].to_vec(),
+            data[20000..20300].to_vec(),
+            data[300..305].to_vec(),
+            rand_data(200),
+        ];
+        let mut haystack = data.clone();
+        haystack.extend_from_slice(&needles[2]);
+        haystack.extend_from_slice(&data[..4096]);
+        (haystack, needles)
+    }
+
+    fn check<E>()
+    where
+        E: Engine + Default,
+        E::Digest: Hash + Eq,
+    {
+        let (haystack, needles) = haystack_and_needles();
+        let needles: Vec<&[u8]> = needles.iter().map(|n| &n[..]).collect();
+        let expected = naive(&needles, &haystack);
+        assert!(expected.len() >= 7);
+        assert_eq!(find_all::<E>(&needles, &haystack), expected);
+
+        let mut searcher = Searcher::<E>::new(needles.iter().copied());
+        let mut incremental = Vec::new();
+        for piece in haystack.chunks(37) {
+            searcher.feed(piece, |m| incremental.push(m));
+        }
+        assert_eq!(incremental, expected);
+    }
+
+    #[cfg(feature = "bup")]
+    #[test]
+    fn bup() {
+        check::<crate::Bup>();
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn gear() {
+        check::<crate::Gear>();
+    }
+}
@@ -0,0 +1,392 @@
+use super::{ChunkHash, ChunkStore};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Segment size used by `SegmentPacker::new`
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 << 20;
+
+/// Magic bytes at the start of a serialized `SegmentMap`
+pub const MAGIC: [u8; 4] = *b"RSSM";
+
+/// Version of the segment map format written by `SegmentMap::write_to`
+pub const VERSION: u8 = 1;
+
+const ENTRY_SIZE: usize = 32 + 3 * 8;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Return the identifier of the segment after `id`
+fn next_id(id: u64) -> io::Result<u64> {
+    id.checked_add(1)
+        .ok_or_else(|| invalid_data("segment identifiers exhausted"))
+}
+
+/// Where a chunk is stored within the segments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SegmentLocation {
+    /// Identifier of the segment
+    pub segment: u64,
+    /// Offset of the chunk within the segment
+    pub offset: u64,
+    /// Length of the chunk
+    pub len: u64,
+}
+
+/// Map from chunk hashes to their locations within segments
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SegmentMap {
+    entries: HashMap<ChunkHash, SegmentLocation>,
+}
+
+impl SegmentMap {
+    /// Create an empty map
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Return the location of the chunk `hash`
+    pub fn get(&self, hash: &ChunkHash) -> Option<SegmentLocation> {
+        self.entries.get(hash).copied()
+    }
+
+    /// Return the number of chunks
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return whether the map is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Return an iterator over the chunks and their locations, in no
+    /// particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&ChunkHash, &SegmentLocation)> {
+        self.entries.iter()
+    }
+
+    /// Serialize the map, sorted by hash:
+    ///
+    /// ```text
+    /// "RSSM" | version: u8 | count: u64
+    /// | (hash: [u8; 32] | segment: u64 | offset: u64 | len: u64)*
+    /// ```
+    ///
+    /// Integers are little-endian.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_unstable_by_key(|&(hash, _)| hash);
+        out.write_all(&MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (hash, location) in entries {
+            out.write_all(hash)?;
+            out.write_all(&location.segment.to_le_bytes())?;
+            out.write_all(&location.offset.to_le_bytes())?;
+            out.write_all(&location.len.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    /// Deserialize a map written by `write_to`
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 13];
+        input.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid_data("not a segment map"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported segment map version"));
+        }
+        let count = u64::from_le_bytes(header[5..].try_into().unwrap());
+        let mut map = SegmentMap::new();
+        let mut entry = [0; ENTRY_SIZE];
+        // Not preallocated, as a damaged count could be huge
+        for _ in 0..count {
+            input.read_exact(&mut entry)?;
+            let word =
+                |i: usize| u64::from_le_bytes(entry[32 + 8 * i..40 + 8 * i].try_into().unwrap());
+            let location = SegmentLocation {
+                segment: word(0),
+                offset: word(1),
+                len: word(2),
+            };
+            map.entries
+                .insert(entry[..32].try_into().unwrap(), location);
+        }
+        Ok(map)
+    }
+}
+
+/// Storage of whole segments, e.g. objects in an object store
+pub trait SegmentBackend {
+    /// Store the complete segment `id`
+    fn put_segment(&mut self, id: u64, data: &[u8]) -> io::Result<()>;
+
+    /// Return `len` bytes at `offset` of segment `id`
+    fn read_segment(&self, id: u64, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+}
+
+/// Segments stored as files in a directory
+#[derive(Debug, Clone)]
+pub struct DirSegments {
+    dir: PathBuf,
+}
+
+impl DirSegments {
+    /// Store segments in `dir`, creating it if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(DirSegments {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    /// Return the path of segment `id`
+    pub fn segment_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.seg", id))
+    }
+}
+
+impl SegmentBackend for DirSegments {
+    fn put_segment(&mut self, id: u64, data: &[u8]) -> io::Result<()> {
+        // Written under a temporary name, so a segment is complete or absent
+        let path = self.segment_path(id);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)
+    }
+
+    fn read_segment(&self, id: u64, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut file = fs::File::open(self.segment_path(id))?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::new();
+        file.take(len).read_to_end(&mut data)?;
+        if (data.len() as u64) < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(data)
+    }
+}
+
+/// Chunk store packing consecutive chunks into large segments
+///
+/// Storing millions of small chunks as separate objects is slow and
+/// expensive with most object stores, so chunks are appended to the current
+/// segment, which is stored as one object once it reaches the segment size.
+/// Only a chunk larger than the segment size makes a segment exceed it. The
+/// `SegmentMap` locating every chunk has to be persisted, e.g. with
+/// `SegmentMap::write_to`, after `flush`.
+pub struct SegmentPacker<B> {
+    backend: B,
+    segment_size: u64,
+    map: SegmentMap,
+    /// Contents of the segment being filled
+    current: Vec<u8>,
+    /// Identifier of the segment being filled
+    current_id: u64,
+}
+
+impl<B: SegmentBackend> SegmentPacker<B> {
+    /// Pack chunks into segments of `DEFAULT_SEGMENT_SIZE` bytes
+    pub fn new(backend: B) -> Self {
+        Self::with_map(backend, DEFAULT_SEGMENT_SIZE, SegmentMap::new())
+            .expect("an empty map has no segments")
+    }
+
+    /// Pack chunks into segments of `segment_size` bytes, adding to the
+    /// segments of `map`
+    ///
+    /// New segments get identifiers after the highest one in `map`. Fails if
+    /// `map` holds the highest possible identifier.
+    pub fn with_map(backend: B, segment_size: u64, map: SegmentMap) -> io::Result<Self> {
+        assert!(segment_size > 0);
+        let current_id = match map.iter().map(|(_, l)| l.segment).max() {
+            Some(last) => next_id(last)?,
+            None => 0,
+        };
+        Ok(SegmentPacker {
+            backend,
+            segment_size,
+            map,
+            current: Vec::new(),
+            current_id,
+        })
+    }
+
+    /// Return the locations of the stored chunks
+    pub fn map(&self) -> &SegmentMap {
+        &self.map
+    }
+
+    /// Store the partially filled segment, if any
+    ///
+    /// The remaining space of the segment is not reused; later chunks go to
+    /// a new segment.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.current.is_empty() {
+            return Ok(());
+        }
+        let next = next_id(self.current_id)?;
+        self.backend.put_segment(self.current_id, &self.current)?;
+        self.current.clear();
+        self.current_id = next;
+        Ok(())
+    }
+
+    /// Store the partially filled segment and return the backend and the map
+    pub fn finish(mut self) -> io::Result<(B, SegmentMap)> {
+        self.flush()?;
+        Ok((self.backend, self.map))
+    }
+}
+
+impl<B: SegmentBackend> ChunkStore for SegmentPacker<B> {
+    fn has(&self, hash: &ChunkHash) -> io::Result<bool> {
+        Ok(self.map.entries.contains_key(hash))
+    }
+
+    fn put(&mut self, hash: &ChunkHash, data: &[u8]) -> io::Result<()> {
+        if self.map.entries.contains_key(hash) {
+            return Ok(());
+        }
+        let len = data.len() as u64;
+        if !self.current.is_empty() && self.current.len() as u64 + len > self.segment_size {
+            self.flush()?;
+        }
+        let location = SegmentLocation {
+            segment: self.current_id,
+            offset: self.current.len() as u64,
+            len,
+        };
+        self.current.extend_from_slice(data);
+        self.map.entries.insert(*hash, location);
+        if self.current.len() as u64 >= self.segment_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, hash: &ChunkHash) -> io::Result<Vec<u8>> {
+        let location = self.map.get(hash).ok_or(io::ErrorKind::NotFound)?;
+        if location.segment == self.current_id {
+            let start = location.offset as usize;
+            return Ok(self.current[start..start + location.len as usize].to_vec());
+        }
+        self.backend
+            .read_segment(location.segment, location.offset, location.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::tests::rand_data;
+
+    #[derive(Default)]
+    struct MemorySegments(HashMap<u64, Vec<u8>>);
+
+    impl SegmentBackend for MemorySegments {
+        fn put_segment(&mut self, id: u64, data: &[u8]) -> io::Result<()> {
+            assert!(self.0.insert(id, data.to_vec()).is_none());
+            Ok(())
+        }
+
+        fn read_segment(&self, id: u64, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+            let segment = self.0.get(&id).ok_or(io::ErrorKind::NotFound)?;
+            Ok(segment[offset as usize..(offset + len) as usize].to_vec())
+        }
+    }
+
+    fn chunks() -> Vec<(ChunkHash, Vec<u8>)> {
+        let data = rand_data(200 * 1024);
+        let mut offset = 0;
+        (0..100)
+            .map(|i| {
+                let len = 100 + (i * 37) % 3000;
+                let chunk = data[offset..offset + len].to_vec();
+                offset += len;
+                (weak_hash(&[&chunk]), chunk)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn packs_chunks_into_segments() {
+        let chunks = chunks();
+        let mut packer =
+            SegmentPacker::with_map(MemorySegments::default(), 16 * 1024, SegmentMap::new())
+                .unwrap();
+        for (hash, chunk) in &chunks {
+            packer.put(hash, chunk).unwrap();
+            // Readable before and after the segment is stored
+            assert!(packer.get(hash).unwrap() == *chunk);
+        }
+        packer.put(&chunks[0].0, &chunks[0].1).unwrap();
+        let (backend, map) = packer.finish().unwrap();
+        assert_eq!(map.len(), chunks.len());
+        let total: usize = chunks.iter().map(|(_, c)| c.len()).sum();
+        assert!(backend.0.len() >= total / (16 * 1024));
+        assert!(backend.0.values().all(|s| s.len() <= 16 * 1024));
+
+        let mut serialized = Vec::new();
+        map.write_to(&mut serialized).unwrap();
+        let map = SegmentMap::read_from(&serialized[..]).unwrap();
+        let mut packer = SegmentPacker::with_map(backend, 16 * 1024, map).unwrap();
+        for (hash, chunk) in &chunks {
+            assert!(packer.has(hash).unwrap());
+            assert!(packer.get(hash).unwrap() == *chunk);
+        }
+        // New chunks go to new segments
+        let big = rand_data(40 * 1024);
+        let hash = weak_hash(&[&big]);
+        packer.put(&hash, &big).unwrap();
+        let segments = packer.backend.0.len();
+        assert_eq!(
+            packer.map().get(&hash).unwrap().segment,
+            segments as u64 - 1
+        );
+        assert!(packer.get(&hash).unwrap() == big);
+        assert_eq!(
+            packer.get(&[0; 32]).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // A map holding the last segment identifier can't get new segments
+        let mut last = serialized.clone();
+        last[13 + 32..13 + 40].copy_from_slice(&u64::MAX.to_le_bytes());
+        let map = SegmentMap::read_from(&last[..]).unwrap();
+        let err = SegmentPacker::with_map(MemorySegments::default(), 16 * 1024, map)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        serialized[0] = b'X';
+        assert!(SegmentMap::read_from(&serialized[..]).is_err());
+    }
+
+    #[test]
+    fn dir_segments() {
+        let dir = std::env::temp_dir().join(format!("rollsum-segments-{}", std::process::id()));
+        let mut packer =
+            SegmentPacker::with_map(DirSegments::open(&dir).unwrap(), 8192, SegmentMap::new())
+                .unwrap();
+        let chunks = chunks();
+        for (hash, chunk) in &chunks[..20] {
+            packer.put(hash, chunk).unwrap();
+        }
+        packer.flush().unwrap();
+        let restored: Vec<_> = chunks[..20]
+            .iter()
+            .map(|(hash, _)| packer.get(hash).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(restored.iter().zip(&chunks).all(|(r, (_, c))| r == c));
+    }
+}
@@ -0,0 +1,234 @@
+use super::Chunker;
+use std::cmp;
+
+/// Default number of consecutive increasing (or decreasing) bytes ending a
+/// chunk
+pub const SEQ_LENGTH: usize = 5;
+
+/// Default number of bytes going the opposite way which start a skip
+pub const SKIP_TRIGGER: usize = 50;
+
+/// Default number of bytes skipped without comparing them
+pub const SKIP_SIZE: usize = 256;
+
+/// Direction of the byte sequences ending chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SeqMode {
+    /// Every byte larger than the one before it
+    Increasing,
+    /// Every byte smaller than the one before it
+    Decreasing,
+}
+
+/// SeqCDC chunker, cutting after monotonic byte sequences
+///
+/// No edge is looked for in the first `min_size` bytes of a chunk. After
+/// them, a chunk ends after `seq_length` bytes each larger than the one
+/// before it (or smaller, in `Decreasing` mode). Bytes going the opposite
+/// way are counted, and after `skip_trigger` of them without a sequence
+/// `skip_size` bytes are skipped without being compared, as such regions
+/// seldom contain one. An edge is forced at `max_size` bytes. No hash is
+/// computed, and the digest reported for an edge is the last byte of the
+/// chunk.
+///
+/// Chunks mostly end soon after `min_size`, so it sets the chunk size more
+/// than the sequence length does.
+///
+/// Based on "SeqCDC: Hashless Content-Defined Chunking for Data
+/// Deduplication" (Udayashankar, Baradaran and Al-Kiswany, Middleware 2024).
+#[derive(Debug, Clone)]
+pub struct SeqCdcChunker {
+    min_size: usize,
+    max_size: usize,
+    seq_length: usize,
+    skip_trigger: usize,
+    skip_size: usize,
+    mode: SeqMode,
+    /// Last byte compared, none at the start of the chunk and after skips
+    previous: Option<u8>,
+    /// Length of the current sequence, and number of opposing bytes
+    run: usize,
+    opposing: usize,
+    /// Bytes left to skip
+    skipping: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+}
+
+impl SeqCdcChunker {
+    /// Create a new chunker with the default sequence and skip parameters
+    pub fn new(min_size: usize, max_size: usize) -> Self {
+        Self::with_params(
+            min_size,
+            max_size,
+            SEQ_LENGTH,
+            SKIP_TRIGGER,
+            SKIP_SIZE,
+            SeqMode::Increasing,
+        )
+    }
+
+    /// Create a new chunker with custom sequence and skip parameters
+    ///
+    /// A `skip_size` of 0 disables skipping.
+    pub fn with_params(
+        min_size: usize,
+        max_size: usize,
+        seq_length: usize,
+        skip_trigger: usize,
+        skip_size: usize,
+        mode: SeqMode,
+    ) -> Self {
+        assert!(min_size <= max_size);
+        assert!(max_size > 0);
+        assert!(seq_length > 0 && skip_trigger > 0);
+        SeqCdcChunker {
+            min_size,
+            max_size,
+            seq_length,
+            skip_trigger,
+            skip_size,
+            mode,
+            previous: None,
+            run: 0,
+            opposing: 0,
+            skipping: 0,
+            len: 0,
+        }
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn edge(&mut self, end: usize, digest: u8) -> Option<(usize, u8)> {
+        self.previous = None;
+        self.run = 0;
+        self.opposing = 0;
+        self.skipping = 0;
+        self.len = 0;
+        Some((end, digest))
+    }
+}
+
+impl Chunker for SeqCdcChunker {
+    type Digest = u8;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, u8)> {
+        let mut i = 0;
+        while i < buf.len() {
+            // Bytes which are not compared, before `min_size` or skipped
+            let unchecked = cmp::max(self.min_size.saturating_sub(self.len), self.skipping);
+            if unchecked > 0 {
+                let n = cmp::min(cmp::min(unchecked, buf.len() - i), self.max_size - self.len);
+                i += n;
+                self.len += n;
+                self.skipping = self.skipping.saturating_sub(n);
+                if self.len >= self.max_size {
+                    return self.edge(i, buf[i - 1]);
+                }
+                continue;
+            }
+
+            let b = buf[i];
+            i += 1;
+            self.len += 1;
+            if let Some(previous) = self.previous {
+                let continues = match self.mode {
+                    SeqMode::Increasing => b > previous,
+                    SeqMode::Decreasing => b < previous,
+                };
+                if continues {
+                    self.run += 1;
+                    if self.run >= self.seq_length {
+                        return self.edge(i, b);
+                    }
+                } else {
+                    self.run = 0;
+                    if b != previous {
+                        self.opposing += 1;
+                    }
+                }
+            }
+            self.previous = Some(b);
+            if self.opposing >= self.skip_trigger {
+                self.opposing = 0;
+                self.skipping = self.skip_size;
+                self.previous = None;
+            }
+            if self.len >= self.max_size {
+                return self.edge(i, b);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, chunk_sizes, rand_data, with_insert};
+
+    #[test]
+    fn edges_end_sequences() {
+        let data = rand_data(1024 * 1024);
+        for &mode in &[SeqMode::Increasing, SeqMode::Decreasing] {
+            let mut chunker = SeqCdcChunker::with_params(2048, 16384, 5, 50, 256, mode);
+            let mut remaining = &data[..];
+            let mut count = 0;
+            while let Some((i, digest)) = chunker.find_chunk_edge(remaining) {
+                assert!((2048..=16384).contains(&i));
+                assert_eq!(digest, remaining[i - 1]);
+                if i < 16384 {
+                    let seq = &remaining[i - 6..i];
+                    assert!(seq.windows(2).all(|w| match mode {
+                        SeqMode::Increasing => w[0] < w[1],
+                        SeqMode::Decreasing => w[0] > w[1],
+                    }));
+                }
+                remaining = &remaining[i..];
+                count += 1;
+            }
+            assert!(count > 100);
+        }
+    }
+
+    #[test]
+    fn skips_and_forced_edges() {
+        // Decreasing bytes trigger skips, and never end a chunk
+        let data: Vec<u8> = (0..10_000).map(|i| (i as u8).wrapping_neg()).collect();
+        let mut chunker = SeqCdcChunker::new(100, 1000);
+        assert_eq!(chunk_sizes(&mut chunker, &data), vec![1000; 10]);
+        // Increasing ones end a chunk as soon as possible
+        let data: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let sizes = chunk_sizes(&mut SeqCdcChunker::new(100, 1000), &data);
+        assert!(sizes.iter().all(|&s| s <= 112), "{:?}", sizes);
+    }
+
+    #[test]
+    fn incremental_matches_whole() {
+        let data = rand_data(512 * 1024);
+        let new = || SeqCdcChunker::new(2048, 16384);
+        let whole = chunk_edges(&mut new(), &data);
+        assert_eq!(chunk_edges_framed(&mut new(), &data, 97), whole);
+    }
+
+    #[test]
+    fn resynchronizes_after_insert() {
+        let data = rand_data(256 * 1024);
+        let modified = with_insert(&data);
+
+        let tail = |data: &[u8]| {
+            let mut sizes = chunk_sizes(&mut SeqCdcChunker::new(2048, 16384), data);
+            sizes.drain(..sizes.len() / 2);
+            sizes
+        };
+        assert_eq!(tail(&data), tail(&modified));
+    }
+}
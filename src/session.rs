@@ -0,0 +1,225 @@
+use super::{ChunkHash, Chunker};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// Chunk of a stream found by a `DedupSession`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionChunk<K> {
+    /// Stream the chunk belongs to
+    pub stream: K,
+    /// Offset of the first byte of the chunk within its stream
+    pub offset: u64,
+    /// Length of the chunk
+    pub len: usize,
+    /// Strong hash of the chunk
+    pub hash: ChunkHash,
+    /// Whether the chunk was already in the index
+    pub duplicate: bool,
+}
+
+/// Counters of a `DedupSession`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    /// Number of chunks emitted
+    pub chunks: u64,
+    /// Number of duplicate chunks emitted
+    pub duplicate_chunks: u64,
+    /// Bytes in all chunks emitted
+    pub bytes: u64,
+    /// Bytes in duplicate chunks emitted
+    pub duplicate_bytes: u64,
+}
+
+struct Stream<C> {
+    chunker: C,
+    pending: Vec<u8>,
+    offset: u64,
+}
+
+/// Deduplication of many interleaved streams against one chunk index
+///
+/// Each stream gets its own chunker from `new_chunker`, so its boundaries
+/// only depend on its own content no matter how writes to different streams
+/// interleave. All streams share the index of chunk hashes computed by
+/// `hash`, which makes a chunk a duplicate when any stream emitted it
+/// before.
+pub struct DedupSession<K, C, F, H> {
+    new_chunker: F,
+    hash: H,
+    streams: HashMap<K, Stream<C>>,
+    index: HashSet<ChunkHash>,
+    stats: SessionStats,
+}
+
+impl<K, C, F, H> DedupSession<K, C, F, H>
+where
+    K: Hash + Eq + Clone,
+    C: Chunker,
+    F: Fn() -> C,
+    H: Fn(&[u8]) -> ChunkHash,
+{
+    /// Create a session with an empty index
+    pub fn new(new_chunker: F, hash: H) -> Self {
+        DedupSession {
+            new_chunker,
+            hash,
+            streams: HashMap::new(),
+            index: HashSet::new(),
+            stats: SessionStats::default(),
+        }
+    }
+
+    /// Mark `hash` as known, e.g. to preload chunks stored earlier
+    pub fn insert_known(&mut self, hash: ChunkHash) {
+        self.index.insert(hash);
+    }
+
+    /// Return whether `hash` is in the index
+    pub fn contains(&self, hash: &ChunkHash) -> bool {
+        self.index.contains(hash)
+    }
+
+    /// Return the number of chunk hashes in the index
+    pub fn index_len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Return the number of streams which were ingested into but not
+    /// finished
+    pub fn open_streams(&self) -> usize {
+        self.streams.len()
+    }
+
+    /// Return the counters of all chunks emitted so far
+    pub fn stats(&self) -> SessionStats {
+        self.stats
+    }
+
+    fn emit<G>(&mut self, stream: &K, offset: u64, data: &[u8], f: &mut G)
+    where
+        G: FnMut(&SessionChunk<K>, &[u8]),
+    {
+        let hash = (self.hash)(data);
+        let duplicate = !self.index.insert(hash);
+        self.stats.chunks += 1;
+        self.stats.bytes += data.len() as u64;
+        if duplicate {
+            self.stats.duplicate_chunks += 1;
+            self.stats.duplicate_bytes += data.len() as u64;
+        }
+        let chunk = SessionChunk {
+            stream: stream.clone(),
+            offset,
+            len: data.len(),
+            hash,
+            duplicate,
+        };
+        f(&chunk, data);
+    }
+
+    /// Feed the next `data` of `stream`, starting the stream if needed
+    ///
+    /// `f` is called with every chunk of the stream completed by `data`,
+    /// along with its contents.
+    pub fn ingest<G>(&mut self, stream: K, mut data: &[u8], mut f: G)
+    where
+        G: FnMut(&SessionChunk<K>, &[u8]),
+    {
+        let new_chunker = &self.new_chunker;
+        let mut state = self.streams.remove(&stream).unwrap_or_else(|| Stream {
+            chunker: new_chunker(),
+            pending: Vec::new(),
+            offset: 0,
+        });
+        while let Some((i, _)) = state.chunker.find_chunk_edge(data) {
+            state.pending.extend_from_slice(&data[..i]);
+            self.emit(&stream, state.offset, &state.pending, &mut f);
+            state.offset += state.pending.len() as u64;
+            state.pending.clear();
+            data = &data[i..];
+        }
+        state.pending.extend_from_slice(data);
+        self.streams.insert(stream, state);
+    }
+
+    /// End `stream`, emitting its last chunk if it is not empty
+    ///
+    /// Returns the length of the stream, `None` if nothing was ingested into
+    /// it.
+    pub fn finish<G>(&mut self, stream: &K, mut f: G) -> Option<u64>
+    where
+        G: FnMut(&SessionChunk<K>, &[u8]),
+    {
+        let state = self.streams.remove(stream)?;
+        if !state.pending.is_empty() {
+            self.emit(stream, state.offset, &state.pending, &mut f);
+        }
+        Some(state.offset + state.pending.len() as u64)
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    #[test]
+    fn interleaved_streams_deduplicate() {
+        let data = rand_data(256 * 1024);
+        let mut session = DedupSession::new(
+            || Gear::new_with_chunk_bits(12),
+            |chunk: &[u8]| weak_hash(&[chunk]),
+        );
+        let mut chunks = Vec::new();
+        let mut record = |chunk: &SessionChunk<u32>, data: &[u8]| {
+            assert_eq!(chunk.len, data.len());
+            chunks.push(chunk.clone());
+        };
+        // Stream 2 is a copy of stream 1, written in different pieces
+        for (a, b) in data.chunks(1000).zip(data.chunks(3000)) {
+            session.ingest(1, a, &mut record);
+            session.ingest(2, b, &mut record);
+        }
+        for piece in data.chunks(1000).skip(data.len() / 3000 + 1) {
+            session.ingest(1, piece, &mut record);
+        }
+        assert_eq!(session.open_streams(), 2);
+        assert_eq!(session.finish(&1, &mut record), Some(data.len() as u64));
+        assert_eq!(session.finish(&2, &mut record), Some(data.len() as u64));
+        assert_eq!(session.finish(&3, &mut record), None);
+        assert_eq!(session.open_streams(), 0);
+
+        let of = |stream| -> Vec<_> { chunks.iter().filter(|c| c.stream == stream).collect() };
+        let (one, two) = (of(1), of(2));
+        assert_eq!(one.len(), two.len());
+        assert!(one.len() > 10);
+        for (a, b) in one.iter().zip(&two) {
+            assert_eq!((a.offset, a.len, a.hash), (b.offset, b.len, b.hash));
+            // Whichever stream emitted a chunk first, the other one found it known
+            assert!(a.duplicate != b.duplicate);
+        }
+
+        let stats = session.stats();
+        assert_eq!(stats.bytes, 2 * data.len() as u64);
+        assert_eq!(stats.duplicate_bytes, data.len() as u64);
+        assert_eq!(
+            session.index_len() as u64,
+            stats.chunks - stats.duplicate_chunks
+        );
+    }
+
+    #[test]
+    fn preloaded_chunks_are_duplicates() {
+        let mut session = DedupSession::new(
+            || Gear::new_with_chunk_bits(12),
+            |chunk: &[u8]| weak_hash(&[chunk]),
+        );
+        session.insert_known(weak_hash(&[b"known"]));
+        let mut duplicate = None;
+        session.ingest("a", b"known", |_, _| unreachable!());
+        session.finish(&"a", |chunk, _| duplicate = Some(chunk.duplicate));
+        assert_eq!(duplicate, Some(true));
+    }
+}
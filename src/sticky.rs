@@ -0,0 +1,158 @@
+use super::{for_each_block, Chunker};
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+/// Moves candidate boundaries onto nearby boundaries of a previous chunking
+///
+/// A candidate within `tolerance` bytes of a previous boundary is replaced
+/// by the closest one (the earlier one on ties). Candidates must be fed in
+/// increasing order; a candidate at or before the last returned boundary is
+/// dropped, so the returned boundaries stay strictly increasing.
+#[derive(Debug, Clone)]
+pub struct Snapper<'a> {
+    previous: &'a [u64],
+    tolerance: u64,
+    last: u64,
+}
+
+impl<'a> Snapper<'a> {
+    /// Create a snapper to the sorted boundaries `previous`
+    pub fn new(previous: &'a [u64], tolerance: u64) -> Self {
+        debug_assert!(previous.windows(2).all(|w| w[0] <= w[1]));
+        Snapper {
+            previous,
+            tolerance,
+            last: 0,
+        }
+    }
+
+    /// Return the boundary to use for `candidate`, `None` if it is dropped
+    pub fn snap(&mut self, candidate: u64) -> Option<u64> {
+        let i = self.previous.partition_point(|&b| b < candidate);
+        let before = i.checked_sub(1).map(|i| self.previous[i]);
+        let after = self.previous.get(i).copied();
+        let nearest = [before, after]
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|&b| b > self.last && b.abs_diff(candidate) <= self.tolerance)
+            .min_by_key(|&b| b.abs_diff(candidate));
+        let boundary = nearest.unwrap_or(candidate);
+        if boundary <= self.last {
+            return None;
+        }
+        self.last = boundary;
+        Some(boundary)
+    }
+}
+
+/// Snap every boundary of `candidates` to `previous`, see `Snapper`
+pub fn snap_boundaries(candidates: &[u64], previous: &[u64], tolerance: u64) -> Vec<u64> {
+    let mut snapper = Snapper::new(previous, tolerance);
+    candidates.iter().filter_map(|&c| snapper.snap(c)).collect()
+}
+
+/// Split everything read from `reader` with `chunker`, snapping boundaries
+/// to the sorted stream offsets `previous`, and call `f` with the stream
+/// offset and contents of each chunk
+///
+/// Keeps chunk boundaries where they were for data edited in place, as long
+/// as the edits move them by at most `tolerance` bytes. Chunks may exceed the
+/// chunker's size bounds by up to `tolerance` bytes.
+pub fn sticky_chunks<R, C, F>(
+    reader: R,
+    chunker: &mut C,
+    previous: &[u64],
+    tolerance: u64,
+    mut f: F,
+) -> io::Result<()>
+where
+    R: Read,
+    C: Chunker,
+    F: FnMut(u64, &[u8]) -> io::Result<()>,
+{
+    let mut snapper = Snapper::new(previous, tolerance);
+    let mut cuts = VecDeque::new();
+    // Bytes read but not emitted yet, starting at stream offset `start`
+    let mut pending = Vec::new();
+    let mut start = 0;
+    for_each_block(reader, |mut position, block| {
+        let mut data = block;
+        while let Some((i, _)) = chunker.find_chunk_edge(data) {
+            position += i as u64;
+            cuts.extend(snapper.snap(position));
+            data = &data[i..];
+        }
+        pending.extend_from_slice(block);
+
+        let end = start + pending.len() as u64;
+        let mut emitted = 0;
+        while let Some(&cut) = cuts.front().filter(|&&c| c <= end) {
+            let from = emitted;
+            emitted = (cut - start) as usize;
+            f(start + from as u64, &pending[from..emitted])?;
+            cuts.pop_front();
+        }
+        pending.drain(..emitted);
+        start += emitted as u64;
+        Ok(true)
+    })?;
+    if !pending.is_empty() {
+        f(start, &pending)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snaps_within_tolerance() {
+        let previous = [100, 200, 300, 400];
+        assert_eq!(
+            snap_boundaries(&[95, 190, 260, 301, 305, 500], &previous, 10),
+            [100, 200, 260, 300, 305, 500]
+        );
+        // Ties go to the earlier boundary
+        assert_eq!(snap_boundaries(&[150], &previous, 50), [100]);
+        assert_eq!(snap_boundaries(&[150], &previous, 49), [150]);
+        assert_eq!(snap_boundaries(&[10, 20], &[], 100), [10, 20]);
+    }
+
+    #[cfg(feature = "gear")]
+    fn boundaries(data: &[u8], previous: &[u64], tolerance: u64) -> Vec<u64> {
+        let mut chunker = crate::Gear::new_with_chunk_bits(11);
+        let mut result = Vec::new();
+        let mut next = 0;
+        sticky_chunks(data, &mut chunker, previous, tolerance, |offset, chunk| {
+            assert_eq!(offset, next);
+            next += chunk.len() as u64;
+            result.push(next);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(next, data.len() as u64);
+        result
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn keeps_boundaries_of_edited_data() {
+        let data = crate::tests::rand_data(512 * 1024);
+        let original = boundaries(&data, &[], 0);
+        assert!(original.len() > 100);
+        assert_eq!(boundaries(&data, &original, 32), original);
+
+        // Boundaries of slightly different chunking, e.g. by an older version
+        let mut previous = original.clone();
+        for i in (1..previous.len() - 1).step_by(3) {
+            if previous[i] - previous[i - 1] > 64 && previous[i + 1] - previous[i] > 64 {
+                previous[i] -= 20;
+            }
+        }
+        assert_ne!(previous, original);
+        assert_eq!(boundaries(&data, &previous, 32), previous);
+        assert_eq!(boundaries(&data, &previous, 16), original);
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::io;
+
+/// Strong hash identifying a chunk by its content, e.g. SHA-256
+pub type ChunkHash = [u8; 32];
+
+/// Content-addressed chunk store
+pub trait ChunkStore {
+    /// Return whether the chunk `hash` is stored
+    fn has(&self, hash: &ChunkHash) -> io::Result<bool>;
+
+    /// Store `data` as the chunk `hash`
+    ///
+    /// Storing a chunk which is already present is not an error.
+    fn put(&mut self, hash: &ChunkHash, data: &[u8]) -> io::Result<()>;
+
+    /// Return the contents of the chunk `hash`
+    ///
+    /// Fails with `io::ErrorKind::NotFound` if the chunk is not stored.
+    fn get(&self, hash: &ChunkHash) -> io::Result<Vec<u8>>;
+}
+
+/// Chunk store keeping chunks in memory
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    chunks: HashMap<ChunkHash, Vec<u8>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Return the number of stored chunks
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Return whether the store is empty
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl ChunkStore for MemoryStore {
+    fn has(&self, hash: &ChunkHash) -> io::Result<bool> {
+        Ok(self.chunks.contains_key(hash))
+    }
+
+    fn put(&mut self, hash: &ChunkHash, data: &[u8]) -> io::Result<()> {
+        self.chunks.entry(*hash).or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, hash: &ChunkHash) -> io::Result<Vec<u8>> {
+        self.chunks
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
@@ -0,0 +1,259 @@
+use super::{join_unconsumed, ChunkEdge, Chunker, EdgeResult};
+use std::mem;
+
+/// Chunk completed by `StreamChunker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamChunk {
+    /// Offset of the first byte of the chunk within the stream
+    pub offset: u64,
+    /// Length of the chunk
+    pub len: u64,
+    /// Contents of the chunk, only in owned mode
+    pub data: Option<Vec<u8>>,
+}
+
+/// Chunker fed by pushing data as it arrives, without doing any I/O
+///
+/// By default only the positions of chunks are reported, and callers needing
+/// the contents of chunks spanning several `push` calls have to keep the
+/// data themselves. In owned mode, created by `owned`, pushed data is
+/// accumulated until the chunk is complete and returned contiguously.
+///
+/// Bytes the chunker leaves unconsumed are passed to it again with the next
+/// data, see `Chunker::find_chunk_edge_result`.
+pub struct StreamChunker<C> {
+    chunker: C,
+    /// Offset of the first byte of the current chunk
+    start: u64,
+    /// Offset of the first byte not consumed by the chunker
+    position: u64,
+    buffer: Option<Vec<u8>>,
+    unconsumed: Vec<u8>,
+}
+
+impl<C: Chunker> StreamChunker<C> {
+    /// Report the positions of the chunks found by `chunker`
+    pub fn new(chunker: C) -> Self {
+        StreamChunker {
+            chunker,
+            start: 0,
+            position: 0,
+            buffer: None,
+            unconsumed: Vec::new(),
+        }
+    }
+
+    /// Report the chunks found by `chunker` with their contents
+    pub fn owned(chunker: C) -> Self {
+        StreamChunker {
+            buffer: Some(Vec::new()),
+            ..Self::new(chunker)
+        }
+    }
+
+    /// Feed the next bytes of the stream, returning the chunks they complete
+    pub fn push(&mut self, data: &[u8]) -> Vec<StreamChunk> {
+        let mut chunks = Vec::new();
+        let mut joined = Vec::new();
+        let mut data = join_unconsumed(&mut self.unconsumed, data, &mut joined);
+        loop {
+            let (i, edge) = match self.chunker.find_chunk_edge_result(data) {
+                EdgeResult::Found { offset, .. } => (offset, true),
+                EdgeResult::NeedMore { consumed } => (consumed, false),
+            };
+            self.position += i as u64;
+            if let Some(buffer) = &mut self.buffer {
+                buffer.extend_from_slice(&data[..i]);
+            }
+            data = &data[i..];
+            if !edge {
+                self.unconsumed.extend_from_slice(data);
+                return chunks;
+            }
+            let contents = self.buffer.as_mut().map(mem::take);
+            chunks.push(self.complete(contents));
+        }
+    }
+
+    /// End the stream, returning the last chunk unless it is empty
+    ///
+    /// Bytes left unconsumed by the chunker are part of the last chunk.
+    pub fn finish(&mut self) -> Option<StreamChunk> {
+        self.position += self.unconsumed.len() as u64;
+        if let Some(buffer) = &mut self.buffer {
+            buffer.extend_from_slice(&self.unconsumed);
+        }
+        self.unconsumed.clear();
+        if self.position == self.start {
+            return None;
+        }
+        let contents = self.buffer.as_mut().map(mem::take);
+        Some(self.complete(contents))
+    }
+
+    fn complete(&mut self, data: Option<Vec<u8>>) -> StreamChunk {
+        let chunk = StreamChunk {
+            offset: self.start,
+            len: self.position - self.start,
+            data,
+        };
+        self.start = self.position;
+        chunk
+    }
+
+    /// Return the number of bytes pushed so far
+    pub fn position(&self) -> u64 {
+        self.position + self.unconsumed.len() as u64
+    }
+
+    /// Return the wrapped chunker
+    pub fn into_inner(self) -> C {
+        self.chunker
+    }
+}
+
+/// Chunker fed by pushing data, reporting edges at their stream positions
+///
+/// Like `StreamChunker::new`, but keeps the digests of the edges.
+pub struct EdgeTracker<C> {
+    chunker: C,
+    /// Offset of the first byte of the current chunk
+    start: u64,
+    /// Offset of the first byte not consumed by the chunker
+    position: u64,
+    unconsumed: Vec<u8>,
+}
+
+impl<C: Chunker> EdgeTracker<C> {
+    /// Report the edges found by `chunker` from the start of the stream
+    pub fn new(chunker: C) -> Self {
+        Self::at(chunker, 0)
+    }
+
+    /// Report the edges found by `chunker`, which starts a chunk at stream
+    /// offset `position`
+    pub fn at(chunker: C, position: u64) -> Self {
+        EdgeTracker {
+            chunker,
+            start: position,
+            position,
+            unconsumed: Vec::new(),
+        }
+    }
+
+    /// Feed the next bytes of the stream, returning the edges they contain
+    pub fn push(&mut self, data: &[u8]) -> Vec<ChunkEdge<C::Digest>> {
+        let mut edges = Vec::new();
+        let mut joined = Vec::new();
+        let mut data = join_unconsumed(&mut self.unconsumed, data, &mut joined);
+        loop {
+            match self.chunker.find_chunk_edge_result(data) {
+                EdgeResult::Found { offset, digest } => {
+                    self.position += offset as u64;
+                    edges.push(ChunkEdge::new(self.start, self.position, digest));
+                    self.start = self.position;
+                    data = &data[offset..];
+                }
+                EdgeResult::NeedMore { consumed } => {
+                    self.position += consumed as u64;
+                    self.unconsumed.extend_from_slice(&data[consumed..]);
+                    return edges;
+                }
+            }
+        }
+    }
+
+    /// Return the offset of the first byte of the current chunk
+    pub fn chunk_start(&self) -> u64 {
+        self.start
+    }
+
+    /// Return the offset of the next byte to push
+    pub fn position(&self) -> u64 {
+        self.position + self.unconsumed.len() as u64
+    }
+
+    /// Return the wrapped chunker
+    pub fn into_inner(self) -> C {
+        self.chunker
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{for_each_chunk, Gear};
+
+    #[test]
+    fn owned_chunks_span_pushes() {
+        let data = rand_data(256 * 1024);
+        let mut expected = Vec::new();
+        for_each_chunk(
+            &data[..],
+            &mut Gear::new_with_chunk_bits(12),
+            |offset, chunk| {
+                expected.push((offset, chunk.to_vec()));
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        let mut owned = StreamChunker::owned(Gear::new_with_chunk_bits(12));
+        let mut positions = StreamChunker::new(Gear::new_with_chunk_bits(12));
+        let (mut chunks, mut ends) = (Vec::new(), Vec::new());
+        for part in data.chunks(1000) {
+            chunks.extend(owned.push(part));
+            ends.extend(positions.push(part));
+        }
+        chunks.extend(owned.finish());
+        ends.extend(positions.finish());
+        assert_eq!(owned.finish(), None);
+        assert_eq!(owned.position(), data.len() as u64);
+
+        assert!(expected.len() > 20);
+        let owned: Vec<_> = chunks
+            .into_iter()
+            .map(|c| {
+                assert_eq!(c.len, c.data.as_ref().unwrap().len() as u64);
+                (c.offset, c.data.unwrap())
+            })
+            .collect();
+        assert!(owned == expected);
+        let ends: Vec<_> = ends
+            .iter()
+            .map(|c| (c.offset, c.len, c.data.is_none()))
+            .collect();
+        let lens: Vec<_> = expected
+            .iter()
+            .map(|(offset, chunk)| (*offset, chunk.len() as u64, true))
+            .collect();
+        assert_eq!(ends, lens);
+    }
+
+    #[test]
+    fn edges_at_stream_positions() {
+        let data = rand_data(256 * 1024);
+        let mut gear = Gear::new_with_chunk_bits(12);
+        let mut expected = Vec::new();
+        let mut end = 1000;
+        let mut rest = &data[1000..];
+        while let Some((i, digest)) = gear.find_chunk_edge(rest) {
+            expected.push(ChunkEdge::new(end, end + i as u64, digest));
+            end += i as u64;
+            rest = &rest[i..];
+        }
+        assert!(expected.len() > 20);
+
+        let mut tracker = EdgeTracker::at(Gear::new_with_chunk_bits(12), 1000);
+        let mut edges = Vec::new();
+        for part in data[1000..].chunks(1000) {
+            edges.extend(tracker.push(part));
+        }
+        assert_eq!(edges, expected);
+        assert!(edges.windows(2).all(|e| e[0].end == e[1].start));
+        assert!(edges.iter().all(|e| u64::from(e.len) == e.end - e.start));
+        assert_eq!(tracker.chunk_start(), end);
+        assert_eq!(tracker.position(), data.len() as u64);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{read_block, ChunkHash, Chunker, StreamChunker};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io::{self, Read};
+
+/// Chunk index which can be queried by chunk hash
+pub trait ChunkLookup {
+    /// Reference to a stored chunk, e.g. its location
+    type Ref;
+
+    /// Return the reference to the chunk `hash`, if it is indexed
+    fn lookup(&self, hash: &ChunkHash) -> Option<Self::Ref>;
+}
+
+impl<R: Clone> ChunkLookup for HashMap<ChunkHash, R> {
+    type Ref = R;
+
+    fn lookup(&self, hash: &ChunkHash) -> Option<R> {
+        self.get(hash).cloned()
+    }
+}
+
+impl<R: Clone> ChunkLookup for BTreeMap<ChunkHash, R> {
+    type Ref = R;
+
+    fn lookup(&self, hash: &ChunkHash) -> Option<R> {
+        self.get(hash).cloned()
+    }
+}
+
+impl ChunkLookup for HashSet<ChunkHash> {
+    type Ref = ();
+
+    fn lookup(&self, hash: &ChunkHash) -> Option<()> {
+        self.get(hash).map(|_| ())
+    }
+}
+
+impl ChunkLookup for super::index::ChunkSet {
+    type Ref = ();
+
+    fn lookup(&self, hash: &ChunkHash) -> Option<()> {
+        self.hashes.binary_search(hash).ok().map(|_| ())
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ChunkLookup for super::mmap_index::MmapIndex {
+    type Ref = u64;
+
+    fn lookup(&self, hash: &ChunkHash) -> Option<u64> {
+        self.get(hash)
+    }
+}
+
+/// Whether a chunk needs to be stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tag<R> {
+    /// Neither in the index nor earlier in the stream
+    New,
+    /// In the index, with its reference
+    Duplicate(R),
+    /// Not in the index, but equal to the earlier chunk of the stream at
+    /// `offset`
+    Repeated { offset: u64 },
+}
+
+/// Chunk yielded by `TaggedChunks`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedChunk<R> {
+    /// Offset of the first byte of the chunk within the stream
+    pub offset: u64,
+    /// Contents of the chunk
+    pub data: Vec<u8>,
+    /// Strong hash of the chunk
+    pub hash: ChunkHash,
+    pub tag: Tag<R>,
+}
+
+/// Iterator over the chunks of a stream, tagged with whether they are
+/// already stored
+///
+/// Returned by `tag_chunks`.
+pub struct TaggedChunks<'a, R, C, H, L> {
+    reader: R,
+    stream: StreamChunker<C>,
+    hash: H,
+    index: &'a L,
+    buf: Vec<u8>,
+    ready: VecDeque<(u64, Vec<u8>)>,
+    /// Offset of the first occurrence of every new chunk
+    seen: HashMap<ChunkHash, u64>,
+    done: bool,
+}
+
+/// Split everything read from `reader` with `chunker`, identifying chunks by
+/// `hash` and tagging each with whether `index` or an earlier chunk of the
+/// stream has it
+///
+/// Chunks are yielded as they are found, so a pipeline can skip compressing
+/// and uploading duplicates without first collecting the list of chunks.
+pub fn tag_chunks<R, C, H, L>(
+    reader: R,
+    chunker: C,
+    hash: H,
+    index: &L,
+) -> TaggedChunks<'_, R, C, H, L>
+where
+    R: Read,
+    C: Chunker,
+    H: Fn(&[u8]) -> ChunkHash,
+    L: ChunkLookup,
+{
+    TaggedChunks {
+        reader,
+        stream: StreamChunker::owned(chunker),
+        hash,
+        index,
+        buf: vec![0; 64 * 1024],
+        ready: VecDeque::new(),
+        seen: HashMap::new(),
+        done: false,
+    }
+}
+
+impl<'a, R, C, H, L> TaggedChunks<'a, R, C, H, L>
+where
+    R: Read,
+    C: Chunker,
+{
+    /// Read until a chunk is complete or the stream ends
+    fn fill(&mut self) -> io::Result<()> {
+        while self.ready.is_empty() && !self.done {
+            let n = read_block(&mut self.reader, &mut self.buf)?;
+            let chunks = if n == 0 {
+                self.done = true;
+                self.stream.finish().into_iter().collect()
+            } else {
+                self.stream.push(&self.buf[..n])
+            };
+            // Owned mode always returns the contents of chunks
+            self.ready.extend(
+                chunks
+                    .into_iter()
+                    .map(|chunk| (chunk.offset, chunk.data.unwrap_or_default())),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R, C, H, L> Iterator for TaggedChunks<'a, R, C, H, L>
+where
+    R: Read,
+    C: Chunker,
+    H: Fn(&[u8]) -> ChunkHash,
+    L: ChunkLookup,
+{
+    type Item = io::Result<TaggedChunk<L::Ref>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        let (offset, data) = self.ready.pop_front()?;
+        let hash = (self.hash)(&data);
+        let tag = match self.index.lookup(&hash) {
+            Some(r) => Tag::Duplicate(r),
+            None => match self.seen.get(&hash) {
+                Some(&offset) => Tag::Repeated { offset },
+                None => {
+                    self.seen.insert(hash, offset);
+                    Tag::New
+                }
+            },
+        };
+        Some(Ok(TaggedChunk {
+            offset,
+            data,
+            hash,
+            tag,
+        }))
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::merkle::tests::weak_hash;
+    use crate::tests::rand_data;
+    use crate::Gear;
+
+    fn hash(data: &[u8]) -> ChunkHash {
+        weak_hash(&[data])
+    }
+
+    #[test]
+    fn tags_known_and_repeated_chunks() {
+        let old = rand_data(128 * 1024);
+        let mut index = HashMap::new();
+        for chunk in tag_chunks(
+            &old[..],
+            Gear::new_with_chunk_bits(11),
+            hash,
+            &HashSet::new(),
+        ) {
+            let chunk = chunk.unwrap();
+            index.insert(chunk.hash, chunk.offset);
+        }
+
+        let fresh = rand_data(256 * 1024)[128 * 1024..].to_vec();
+        let data = [&old[..], &fresh, &fresh].concat();
+        let chunks: Vec<_> = tag_chunks(&data[..], Gear::new_with_chunk_bits(11), hash, &index)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let joined: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+        assert!(joined == data);
+
+        let old_chunks: Vec<_> = chunks
+            .iter()
+            .take_while(|c| c.offset < old.len() as u64)
+            .collect();
+        for chunk in &old_chunks[..old_chunks.len() - 1] {
+            // Equal chunks may occur at several offsets in the old data
+            match chunk.tag {
+                Tag::Duplicate(at) => {
+                    assert!(old[at as usize..at as usize + chunk.data.len()] == chunk.data[..]);
+                }
+                _ => panic!("{:?}", chunk.tag),
+            }
+        }
+        let new = chunks.iter().filter(|c| c.tag == Tag::New).count();
+        let repeated: Vec<_> = chunks
+            .iter()
+            .filter_map(|c| match c.tag {
+                Tag::Repeated { offset } => Some((offset, c.offset)),
+                _ => None,
+            })
+            .collect();
+        assert!(new > 10);
+        assert!(repeated.len() > new - 5);
+        for (first, again) in repeated {
+            assert!(first < again);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::Chunker;
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting the rate data is processed at
+///
+/// Bursts of up to `burst` bytes pass without delay, after that the average
+/// rate is held at `bytes_per_sec`.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    /// Bytes which may be processed without delay, negative when in debt
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing bursts of one second's worth of data
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self::with_burst(bytes_per_sec, bytes_per_sec)
+    }
+
+    /// Create a limiter allowing bursts of `burst` bytes
+    pub fn with_burst(bytes_per_sec: u64, burst: u64) -> Self {
+        assert!(bytes_per_sec > 0);
+        RateLimiter {
+            rate: bytes_per_sec as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Return the limited rate in bytes per second
+    pub fn rate(&self) -> u64 {
+        self.rate as u64
+    }
+
+    /// Record that `bytes` were processed at `now`, returning how long to
+    /// wait before processing more
+    pub fn consume_at(&mut self, bytes: u64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = cmp::max(self.last, now);
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    /// `consume_at` the current time
+    pub fn consume(&mut self, bytes: u64) -> Duration {
+        self.consume_at(bytes, Instant::now())
+    }
+}
+
+/// Chunker sleeping as needed to process data at a limited rate
+///
+/// Throttles any synchronous chunking loop without copying the data. For the
+/// async channel, see `ChunkSender::set_rate_limit` instead, which doesn't
+/// block.
+pub struct ThrottledChunker<C> {
+    inner: C,
+    limiter: RateLimiter,
+}
+
+impl<C> ThrottledChunker<C> {
+    /// Limit `inner` to the rate of `limiter`
+    pub fn new(inner: C, limiter: RateLimiter) -> Self {
+        ThrottledChunker { inner, limiter }
+    }
+
+    /// Return the wrapped chunker
+    pub fn into_inner(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Chunker> Chunker for ThrottledChunker<C> {
+    type Digest = C::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, C::Digest)> {
+        let edge = self.inner.find_chunk_edge(buf);
+        let consumed = edge.as_ref().map_or(buf.len(), |&(i, _)| i);
+        let delay = self.limiter.consume(consumed as u64);
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        edge
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_rate_after_burst() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::with_burst(1000, 500);
+        assert_eq!(limiter.consume_at(500, start), Duration::ZERO);
+        assert_eq!(limiter.consume_at(250, start), Duration::from_millis(250));
+        // Waiting pays the debt back, but doesn't save up beyond the burst
+        let later = start + Duration::from_secs(10);
+        assert_eq!(limiter.consume_at(500, later), Duration::ZERO);
+        assert_eq!(limiter.consume_at(100, later), Duration::from_millis(100));
+    }
+
+    #[cfg(feature = "gear")]
+    #[test]
+    fn throttled_chunker_is_slower_but_equal() {
+        use crate::Gear;
+
+        let data = crate::tests::rand_data(64 * 1024);
+        let limiter = RateLimiter::with_burst(256 * 1024, 16 * 1024);
+        let mut throttled = ThrottledChunker::new(Gear::new_with_chunk_bits(10), limiter);
+        let mut plain = Gear::new_with_chunk_bits(10);
+        let start = Instant::now();
+        let mut rest = &data[..];
+        while let Some((i, digest)) = throttled.find_chunk_edge(rest) {
+            assert_eq!(plain.find_chunk_edge(rest), Some((i, digest)));
+            rest = &rest[i..];
+        }
+        // 48 KiB past the burst at 256 KiB/s
+        assert!(start.elapsed() >= Duration::from_millis(180));
+    }
+}
@@ -0,0 +1,251 @@
+use super::{Chunker, EdgeCondition, EdgeResult, Engine, MaskEngine};
+
+/// TTTD (Two Thresholds, Two Divisors) chunker around any `Engine`
+///
+/// No cut point is looked for in the first `min_size` bytes of a chunk.
+/// After them, the main condition ends the chunk as usual, while a looser
+/// backup condition (the second divisor) marks backup cut points. When a
+/// chunk reaches `max_size` bytes without a main cut point, it ends at the
+/// last backup one rather than at `max_size`, and only if there was none is
+/// the cut forced. This keeps most oversized chunks content defined.
+///
+/// Whether a backup cut point ends a chunk is only known once the bytes
+/// after it were seen, so `find_chunk_edge_result` leaves them unconsumed,
+/// to be passed again with the following data. The bytes already scanned
+/// are not scanned twice, and the edge may then be at offset 0 of `buf`.
+/// The crate's drivers, like `StreamChunker`, pass them again, so the edges
+/// don't depend on how the data is split. `find_chunk_edge` has to consume
+/// all of `buf` when it finds no edge, so it only cuts at backup cut points
+/// within `buf`.
+///
+/// Based on "A Framework for Analyzing and Improving Content-Based Chunking
+/// Algorithms" (Eshghi and Tang, HP Labs 2005).
+pub struct TttdChunker<E: Engine, C> {
+    engine: E,
+    main: C,
+    backup: C,
+    min_size: usize,
+    max_size: usize,
+    /// Number of bytes of the current chunk consumed so far
+    len: usize,
+    /// Number of bytes after the consumed ones already scanned
+    ahead: usize,
+    /// Last backup cut point of the current chunk, as the chunk length and
+    /// the digest there
+    last_backup: Option<(usize, E::Digest)>,
+}
+
+impl<E: MaskEngine> TttdChunker<E, E::Condition> {
+    /// Create a new chunker using the engine's default edge condition, and
+    /// matching one bit less (half the divisor) for backup cut points
+    pub fn new(engine: E, min_size: usize, max_size: usize) -> Self {
+        let bits = engine.chunk_bits();
+        let main = E::condition_for_bits(bits);
+        let backup = E::condition_for_bits(bits.saturating_sub(1));
+        Self::with_conditions(engine, main, backup, min_size, max_size)
+    }
+}
+
+impl<E: Engine, C: EdgeCondition<E>> TttdChunker<E, C> {
+    /// Create a new chunker using custom main and backup edge conditions
+    ///
+    /// The backup condition should match wherever the main one does, and
+    /// more often.
+    pub fn with_conditions(
+        engine: E,
+        main: C,
+        backup: C,
+        min_size: usize,
+        max_size: usize,
+    ) -> Self {
+        assert!(min_size <= max_size);
+        assert!(max_size > 0);
+        TttdChunker {
+            engine,
+            main,
+            backup,
+            min_size,
+            max_size,
+            len: 0,
+            ahead: 0,
+            last_backup: None,
+        }
+    }
+
+    /// Return the wrapped engine
+    pub fn engine(&self) -> &E {
+        &self.engine
+    }
+
+    /// Return the minimum chunk size
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    /// Return the maximum chunk size
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    fn edge(&mut self, offset: usize, digest: E::Digest) -> EdgeResult<E::Digest> {
+        self.engine.reset();
+        self.len = 0;
+        self.ahead = 0;
+        self.last_backup = None;
+        EdgeResult::Found { offset, digest }
+    }
+}
+
+impl<E, C> TttdChunker<E, C>
+where
+    E: Engine,
+    E::Digest: Copy,
+    C: EdgeCondition<E>,
+{
+    fn scan(&mut self, buf: &[u8]) -> EdgeResult<E::Digest> {
+        debug_assert!(self.ahead <= buf.len());
+        for (i, &b) in buf.iter().enumerate().skip(self.ahead) {
+            self.engine.roll_byte(b);
+            let len = self.len + i + 1;
+            if len >= self.min_size {
+                if self.main.is_edge(&self.engine) {
+                    let digest = self.engine.digest();
+                    return self.edge(i + 1, digest);
+                }
+                if self.backup.is_edge(&self.engine) {
+                    self.last_backup = Some((len, self.engine.digest()));
+                }
+            }
+            if len >= self.max_size {
+                return match self.last_backup {
+                    Some((backup, digest)) => self.edge(backup - self.len, digest),
+                    None => {
+                        let digest = self.engine.digest();
+                        self.edge(i + 1, digest)
+                    }
+                };
+            }
+        }
+        // The bytes after the last backup cut point may still be cut off
+        let consumed = self
+            .last_backup
+            .map_or(buf.len(), |(backup, _)| backup - self.len);
+        self.len += consumed;
+        self.ahead = buf.len() - consumed;
+        EdgeResult::NeedMore { consumed }
+    }
+}
+
+impl<E, C> Chunker for TttdChunker<E, C>
+where
+    E: Engine,
+    E::Digest: Copy,
+    C: EdgeCondition<E>,
+{
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        match self.scan(buf) {
+            EdgeResult::Found { offset, digest } => Some((offset, digest)),
+            EdgeResult::NeedMore { .. } => {
+                self.len += self.ahead;
+                self.ahead = 0;
+                self.last_backup = None;
+                None
+            }
+        }
+    }
+
+    fn find_chunk_edge_result(&mut self, buf: &[u8]) -> EdgeResult<E::Digest> {
+        self.scan(buf)
+    }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges, chunk_edges_framed, chunk_sizes, rand_data};
+    use crate::{Gear, MinMaxChunker};
+
+    fn new() -> TttdChunker<Gear, crate::condition::PrefixZeroCondition> {
+        TttdChunker::new(Gear::new_with_chunk_bits(12), 1024, 8192)
+    }
+
+    /// Cut points of `new` in `data` by the definition, with whether each
+    /// is a backup cut point
+    fn cut_points(data: &[u8]) -> Vec<(usize, bool)> {
+        let main = Gear::condition_for_bits(12);
+        let backup = Gear::condition_for_bits(11);
+        let mut cuts = Vec::new();
+        let mut start = 0;
+        'chunks: loop {
+            let mut gear = Gear::new_with_chunk_bits(12);
+            let mut last_backup = None;
+            for (len, &b) in (1..).zip(&data[start..]) {
+                gear.roll_byte(b);
+                let cut = if len >= 1024 && main.is_edge(&gear) {
+                    Some((len, false))
+                } else {
+                    if len >= 1024 && backup.is_edge(&gear) {
+                        last_backup = Some(len);
+                    }
+                    (len >= 8192).then(|| last_backup.map_or((len, false), |l| (l, true)))
+                };
+                if let Some((len, is_backup)) = cut {
+                    start += len;
+                    cuts.push((start, is_backup));
+                    continue 'chunks;
+                }
+            }
+            return cuts;
+        }
+    }
+
+    #[test]
+    fn matches_definition() {
+        let data = rand_data(2 * 1024 * 1024);
+        let cuts = cut_points(&data);
+        let expected: Vec<_> = cuts.iter().map(|&(cut, _)| cut).collect();
+        assert!(cuts.iter().filter(|&&(_, is_backup)| is_backup).count() > 10);
+        assert_eq!(chunk_edges(&mut new(), &data), expected);
+    }
+
+    #[test]
+    fn fewer_oversized_chunks() {
+        let data = rand_data(8 * 1024 * 1024);
+        let tttd = chunk_sizes(&mut new(), &data);
+        let minmax = chunk_sizes(
+            &mut MinMaxChunker::new(Gear::new_with_chunk_bits(12), 1024, 8192),
+            &data,
+        );
+        let (_, sizes) = tttd.split_last().unwrap();
+        assert!(sizes.iter().all(|&s| (1024..=8192).contains(&s)));
+        let at_max = |sizes: &[usize]| sizes.iter().filter(|&&s| s == 8192).count();
+        assert!(at_max(&minmax) > 100);
+        assert!(at_max(&tttd) * 3 < at_max(&minmax));
+    }
+
+    #[test]
+    fn for_each_chunk_cuts_at_backup_points() {
+        let data = rand_data(2 * 1024 * 1024);
+        let mut offsets = Vec::new();
+        crate::for_each_chunk(&data[..], &mut new(), |offset, _| {
+            offsets.push(offset as usize);
+            Ok(())
+        })
+        .unwrap();
+        let cuts = cut_points(&data);
+        assert!(cuts.iter().any(|&(_, is_backup)| is_backup));
+        let expected: Vec<_> = cuts.iter().map(|&(cut, _)| cut).collect();
+        assert_eq!(offsets[1..], expected[..]);
+    }
+
+    #[test]
+    fn find_chunk_edge_independent_of_buffers() {
+        let data = rand_data(2 * 1024 * 1024);
+        let whole = chunk_edges(&mut new(), &data);
+        for &frame in &[1, 307, 4096, 50_000] {
+            assert_eq!(chunk_edges_framed(&mut new(), &data, frame), whole);
+        }
+    }
+}
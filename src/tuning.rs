@@ -0,0 +1,300 @@
+use super::{Algorithm, AnyChunker, ChunkBits, Chunker, ChunkerParams, InvalidChunkBits, Params};
+use std::collections::HashSet;
+
+/// What `suggest_params` optimizes for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// Average chunk size in bytes
+    AvgSize(u64),
+    /// Number of index entries (chunks) `total_size` bytes of data may use
+    IndexEntries { total_size: u64, max_entries: u64 },
+}
+
+impl Target {
+    fn avg_size(self) -> u64 {
+        match self {
+            Target::AvgSize(size) => size,
+            Target::IndexEntries {
+                total_size,
+                max_entries,
+            } => total_size / max_entries.max(1),
+        }
+    }
+}
+
+/// Outcome of chunking a sample with some parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Evaluation {
+    pub params: ChunkerParams,
+    /// Number of chunks in the sample
+    pub chunks: usize,
+    /// Observed average chunk size
+    pub avg_size: f64,
+    /// Sample size divided by the size of its distinct chunks, 1.0 if no
+    /// chunk repeats
+    pub dedup_ratio: f64,
+}
+
+/// Parameters recommended by `suggest_params`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    /// The recommended parameters and how they fared on the sample
+    pub best: Evaluation,
+    /// All evaluated parameters, by increasing `chunk_bits`
+    pub candidates: Vec<Evaluation>,
+}
+
+/// Return the parameters `algorithm` uses for `chunk_bits` in suggestions:
+/// chunks between a quarter and four times the nominal average size
+pub fn params_for_bits(algorithm: Algorithm, chunk_bits: u32) -> ChunkerParams {
+    ChunkerParams {
+        algorithm,
+        chunk_bits,
+        min_size: 1 << chunk_bits.saturating_sub(2),
+        max_size: 1 << (chunk_bits + 2),
+    }
+}
+
+/// Smallest average chunk size of the small chunk parameterizations
+pub const MIN_SMALL_AVG_SIZE: u64 = 128;
+
+fn small_avg_bits(avg_size: u64) -> Result<ChunkBits, InvalidChunkBits> {
+    let bits = ChunkBits::from_avg_size(avg_size)?;
+    // The minimum sizes below are at least one 64 byte window
+    ChunkBits::for_window(bits.get(), MIN_SMALL_AVG_SIZE as usize)
+}
+
+/// Return `Gear` parameters averaging `avg_size` byte chunks, for small
+/// averages like 128 to 1024 bytes
+///
+/// Chunks are at least half the average, and so at least one 64 byte window,
+/// long, and the edge condition makes up the other half. Fails unless
+/// `avg_size` is a power of two of at least `MIN_SMALL_AVG_SIZE`.
+pub fn small_gear_params(avg_size: u64) -> Result<Params, InvalidChunkBits> {
+    let bits = small_avg_bits(avg_size)?;
+    Ok(Params::Gear {
+        chunk_bits: bits.get() - 1,
+        min_size: avg_size / 2,
+        max_size: avg_size * 4,
+    })
+}
+
+/// Return normalized `Gear` parameters averaging `avg_size` byte chunks, for
+/// small averages like 128 to 1024 bytes
+///
+/// Normalization keeps most chunks close to the average, which ends up up to
+/// a fifth above `avg_size`; chunks are at least a quarter of it and one 64
+/// byte window long. Fails unless `avg_size` is a
+/// power of two of at least `MIN_SMALL_AVG_SIZE`.
+pub fn small_normalized_params(avg_size: u64) -> Result<Params, InvalidChunkBits> {
+    let bits = small_avg_bits(avg_size)?;
+    Ok(Params::Normalized {
+        chunk_bits: bits.get(),
+        min_size: (avg_size / 4).max(64),
+        max_size: avg_size * 4,
+        level: crate::normalized::NORMALIZATION_LEVEL,
+    })
+}
+
+fn evaluate_with<C: Chunker>(params: ChunkerParams, mut chunker: C, sample: &[u8]) -> Evaluation {
+    let mut distinct = HashSet::new();
+    let mut unique_bytes = 0;
+    let mut chunks = 0;
+    let mut rest = sample;
+    while !rest.is_empty() {
+        let len = chunker.find_chunk_edge(rest).map_or(rest.len(), |(i, _)| i);
+        if distinct.insert(&rest[..len]) {
+            unique_bytes += len;
+        }
+        chunks += 1;
+        rest = &rest[len..];
+    }
+    Evaluation {
+        params,
+        chunks,
+        avg_size: sample.len() as f64 / chunks.max(1) as f64,
+        dedup_ratio: sample.len() as f64 / unique_bytes.max(1) as f64,
+    }
+}
+
+/// Chunk `sample` with `params`
+///
+/// Panics if the parameters are invalid or the feature of the algorithm is
+/// disabled.
+pub fn evaluate(params: ChunkerParams, sample: &[u8]) -> Evaluation {
+    let chunker = AnyChunker::new(params).unwrap_or_else(|e| panic!("{}", e));
+    evaluate_with(params, chunker, sample)
+}
+
+/// Recommend chunking parameters for data like `sample`
+///
+/// The sample is chunked with `chunk_bits` within two of the target average
+/// size, using `params_for_bits`. For `Target::IndexEntries`, candidates
+/// whose observed average would take more than `max_entries` chunks for
+/// `total_size` bytes are dropped first, and if none is left the one with
+/// the fewest is recommended. Among the remaining candidates whose observed
+/// average chunk size is within a factor of two of the target, the one
+/// deduplicating the sample best is recommended (the largest on ties), and
+/// otherwise the one closest to the target. The sample should be several
+/// times larger than the maximum chunk size for the results to mean
+/// anything.
+///
+/// Panics if the feature of the algorithm is disabled.
+pub fn suggest_params(algorithm: Algorithm, sample: &[u8], target: Target) -> Suggestion {
+    let avg = target.avg_size().max(2) as f64;
+    let bits = (avg.log2().round() as u32).clamp(4, ChunkBits::MAX - 2);
+    let candidates: Vec<_> = (bits - 2..=bits + 2)
+        .map(|bits| evaluate(params_for_bits(algorithm, bits), sample))
+        .collect();
+
+    let fits = |e: &&Evaluation| match target {
+        Target::AvgSize(_) => true,
+        Target::IndexEntries {
+            total_size,
+            max_entries,
+        } => total_size as f64 / e.avg_size <= max_entries as f64,
+    };
+    let distance = |e: &Evaluation| (e.avg_size / avg).log2().abs();
+    let best = candidates
+        .iter()
+        .filter(fits)
+        .filter(|e| distance(e) <= 1.0)
+        .max_by(|a, b| a.dedup_ratio.total_cmp(&b.dedup_ratio))
+        .or_else(|| {
+            candidates
+                .iter()
+                .filter(fits)
+                .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        })
+        .or_else(|| {
+            candidates
+                .iter()
+                .max_by(|a, b| a.avg_size.total_cmp(&b.avg_size))
+        })
+        .copied()
+        .unwrap();
+    Suggestion { best, candidates }
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+
+    #[test]
+    fn suggests_close_to_target() {
+        let block = rand_data(512 * 1024);
+        let sample = [&block[..], &block[100..], &block[..200_000]].concat();
+
+        let suggestion = suggest_params(Algorithm::Gear, &sample, Target::AvgSize(4096));
+        assert_eq!(suggestion.candidates.len(), 5);
+        let best = suggestion.best;
+        assert!((10..=14).contains(&best.params.chunk_bits));
+        assert!(best.avg_size > 2048.0 && best.avg_size < 8192.0);
+        assert!(best.dedup_ratio > 1.5);
+        assert!(suggestion
+            .candidates
+            .windows(2)
+            .all(|w| w[0].avg_size < w[1].avg_size));
+
+        let by_index = suggest_params(
+            Algorithm::Gear,
+            &sample,
+            Target::IndexEntries {
+                total_size: 1 << 30,
+                max_entries: 1 << 18,
+            },
+        );
+        assert_eq!(by_index.candidates, suggestion.candidates);
+    }
+
+    #[test]
+    fn index_entries_cap_candidates() {
+        let block = rand_data(512 * 1024);
+        let sample = [&block[..], &block[100..], &block[..200_000]].concat();
+        let entries = |e: &Evaluation| (1u64 << 30) as f64 / e.avg_size;
+
+        let max_entries = 1 << 18;
+        let suggestion = suggest_params(
+            Algorithm::Gear,
+            &sample,
+            Target::IndexEntries {
+                total_size: 1 << 30,
+                max_entries,
+            },
+        );
+        let fitting: Vec<_> = suggestion
+            .candidates
+            .iter()
+            .filter(|e| entries(e) <= max_entries as f64)
+            .collect();
+        assert!(!fitting.is_empty() && fitting.len() < suggestion.candidates.len());
+        assert!(entries(&suggestion.best) <= max_entries as f64);
+        assert!(fitting
+            .iter()
+            .filter(|e| e.avg_size <= 8192.0)
+            .all(|e| e.dedup_ratio <= suggestion.best.dedup_ratio));
+
+        let capped = suggest_params(
+            Algorithm::Gear,
+            &sample,
+            Target::IndexEntries {
+                total_size: 1 << 30,
+                max_entries: 1,
+            },
+        );
+        assert_eq!(capped.best, *capped.candidates.last().unwrap());
+    }
+
+    #[test]
+    fn small_chunk_params_hold_average() {
+        use crate::SizeDistribution;
+
+        let data = rand_data(1024 * 1024);
+        for &avg in &[128, 256, 512, 1024] {
+            for params in [small_gear_params(avg), small_normalized_params(avg)] {
+                let params = params.unwrap();
+                let mut sizes = Vec::new();
+                crate::for_each_chunk(
+                    &data[..],
+                    &mut AnyChunker::new(params).unwrap(),
+                    |_, chunk| {
+                        sizes.push(chunk.len() as u64);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+                let last = sizes.pop().unwrap();
+                assert!(last <= params.max_size());
+                assert!(sizes
+                    .iter()
+                    .all(|&s| s >= params.min_size() && s <= params.max_size()));
+                let mean = sizes.iter().sum::<u64>() as f64 / sizes.len() as f64;
+                let ratio = mean / avg as f64;
+                match params {
+                    Params::Normalized { .. } => assert!(ratio > 1.0 && ratio < 1.21, "{}", mean),
+                    _ => assert!((ratio - 1.0).abs() < 0.1, "{}", mean),
+                }
+                if let Params::Gear {
+                    chunk_bits,
+                    min_size,
+                    max_size,
+                } = params
+                {
+                    let expected = SizeDistribution::with_bounds(chunk_bits, min_size, max_size)
+                        .expected_size();
+                    assert!((mean / expected - 1.0).abs() < 0.05);
+                }
+            }
+        }
+
+        assert_eq!(
+            small_gear_params(64),
+            Err(InvalidChunkBits::SmallerThanWindow {
+                bits: 6,
+                window_size: 128
+            })
+        );
+        assert!(small_normalized_params(300).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use super::{Algorithm, Chunker, ChunkerParams, Engine, MinMaxChunker};
+use std::fmt::Write as _;
+
+/// Inputs used by `generate`, as (seed, length) pairs
+pub const INPUTS: [(u64, usize); 5] = [(0, 0), (1, 1), (2, 100), (3, 4096), (4, 256 * 1024)];
+
+/// Chunk bits used by `generate`
+pub const CHUNK_BITS: [u32; 3] = [6, 10, 13];
+
+/// Return `len` deterministic pseudo-random bytes
+///
+/// The bytes are the little-endian outputs of SplitMix64 started at `seed`,
+/// truncated to `len`, which is easy to reproduce in any language.
+pub fn input(seed: u64, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    let mut data = Vec::with_capacity(len + 8);
+    while data.len() < len {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        data.extend_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    data.truncate(len);
+    data
+}
+
+/// Expected results of chunking one input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    pub params: ChunkerParams,
+    /// Seed of the input, see `input`
+    pub seed: u64,
+    /// Length of the input
+    pub len: usize,
+    /// Digest of the engine after rolling the whole input
+    pub digest: u64,
+    /// End offset of every chunk found, not including the end of the input
+    pub boundaries: Vec<u64>,
+    /// Digest of the engine at every boundary
+    pub digests: Vec<u64>,
+}
+
+fn vector<E, C>(
+    params: ChunkerParams,
+    seed: u64,
+    len: usize,
+    mut engine: E,
+    mut chunker: C,
+) -> TestVector
+where
+    E: Engine,
+    E::Digest: Into<u64>,
+    C: Chunker<Digest = E::Digest>,
+{
+    let data = input(seed, len);
+    engine.roll(&data);
+    let mut vector = TestVector {
+        params,
+        seed,
+        len,
+        digest: engine.digest().into(),
+        boundaries: Vec::new(),
+        digests: Vec::new(),
+    };
+    let mut offset = 0;
+    while let Some((i, digest)) = chunker.find_chunk_edge(&data[offset..]) {
+        offset += i;
+        vector.boundaries.push(offset as u64);
+        vector.digests.push(digest.into());
+    }
+    vector
+}
+
+/// Compute the test vector of `params` for the input `seed` and `len`
+pub fn compute(params: ChunkerParams, seed: u64, len: usize) -> TestVector {
+    let min = crate::saturating_usize(params.min_size);
+    let max = crate::saturating_usize(params.max_size);
+    match params.algorithm {
+        #[cfg(feature = "bup")]
+        Algorithm::Bup => vector(
+            params,
+            seed,
+            len,
+            super::Bup::new_with_chunk_bits(params.chunk_bits),
+            MinMaxChunker::new(super::Bup::new_with_chunk_bits(params.chunk_bits), min, max),
+        ),
+        #[cfg(feature = "gear")]
+        Algorithm::Gear => vector(
+            params,
+            seed,
+            len,
+            super::Gear::new_with_chunk_bits(params.chunk_bits),
+            MinMaxChunker::new(
+                super::Gear::new_with_chunk_bits(params.chunk_bits),
+                min,
+                max,
+            ),
+        ),
+        #[allow(unreachable_patterns)]
+        algorithm => panic!("algorithm {} is not enabled", algorithm),
+    }
+}
+
+/// Compute the test vectors of every enabled algorithm, for every chunk bits
+/// in `CHUNK_BITS` with and without size bounds, and every input in `INPUTS`
+pub fn generate() -> Vec<TestVector> {
+    let algorithms = [
+        #[cfg(feature = "bup")]
+        Algorithm::Bup,
+        #[cfg(feature = "gear")]
+        Algorithm::Gear,
+    ];
+
+    let mut vectors = Vec::new();
+    for &algorithm in &algorithms {
+        for &bits in &CHUNK_BITS {
+            let unbounded = ChunkerParams::new(algorithm, bits);
+            let bounded = ChunkerParams {
+                min_size: 1 << (bits - 2),
+                max_size: 1 << (bits + 2),
+                ..unbounded
+            };
+            for &params in &[unbounded, bounded] {
+                for &(seed, len) in &INPUTS {
+                    vectors.push(compute(params, seed, len));
+                }
+            }
+        }
+    }
+    vectors
+}
+
+fn push_list(json: &mut String, values: &[u64]) {
+    json.push('[');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write!(json, "{}", value).unwrap();
+    }
+    json.push(']');
+}
+
+/// Serialize `vectors` as a JSON array, one vector per line
+///
+/// 64 bit numbers exceed the exact integer range of some JSON parsers, so
+/// unsigned maximum sizes are written as `null` and digests as strings of
+/// hex digits.
+pub fn to_json(vectors: &[TestVector]) -> String {
+    let mut json = String::from("[\n");
+    for (i, v) in vectors.iter().enumerate() {
+        write!(
+            json,
+            "{{\"algorithm\":\"{}\",\"chunk_bits\":{},\"min_size\":{},\"max_size\":",
+            v.params.algorithm, v.params.chunk_bits, v.params.min_size
+        )
+        .unwrap();
+        if v.params.max_size == u64::MAX {
+            json.push_str("null");
+        } else {
+            write!(json, "{}", v.params.max_size).unwrap();
+        }
+        write!(
+            json,
+            ",\"seed\":{},\"len\":{},\"digest\":\"{:x}\",\"boundaries\":",
+            v.seed, v.len, v.digest
+        )
+        .unwrap();
+        push_list(&mut json, &v.boundaries);
+        json.push_str(",\"digests\":[");
+        for (j, digest) in v.digests.iter().enumerate() {
+            if j > 0 {
+                json.push(',');
+            }
+            write!(json, "\"{:x}\"", digest).unwrap();
+        }
+        json.push_str("]}");
+        json.push_str(if i + 1 < vectors.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("]\n");
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_is_splitmix64() {
+        // First output of SplitMix64 seeded with 0
+        assert_eq!(input(0, 8), 0xe220_a839_7b1d_cdafu64.to_le_bytes());
+        assert_eq!(input(7, 100)[..50], input(7, 50)[..]);
+    }
+
+    #[test]
+    fn vectors_are_consistent() {
+        let vectors = generate();
+        assert!(!vectors.is_empty());
+        for v in &vectors {
+            assert_eq!(v.boundaries.len(), v.digests.len());
+            assert!(v.boundaries.windows(2).all(|w| w[0] < w[1]));
+            assert!(v.boundaries.last().is_none_or(|&b| b <= v.len as u64));
+            if v.len == 256 * 1024 {
+                assert!(v.boundaries.len() > 10);
+            }
+            if v.params.max_size != u64::MAX {
+                let mut sizes = v.boundaries.iter().scan(0, |last, &b| {
+                    let size = b - *last;
+                    *last = b;
+                    Some(size)
+                });
+                assert!(sizes.all(|s| s >= v.params.min_size && s <= v.params.max_size));
+            }
+        }
+        assert_eq!(generate(), vectors);
+
+        let json = to_json(&vectors);
+        assert_eq!(json.lines().count(), vectors.len() + 2);
+        assert!(json.contains("\"max_size\":null"));
+    }
+}
@@ -0,0 +1,206 @@
+use super::{for_each_block, AnyChunker, Chunker, ChunkerParams};
+use std::io::{self, Read};
+
+/// First difference between a stored boundary list and the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Divergence<D> {
+    /// Chunk `index` ends at `actual` instead of `expected`. `None` stands
+    /// for a boundary missing from the list or from the data.
+    Boundary {
+        index: usize,
+        expected: Option<u64>,
+        actual: Option<u64>,
+    },
+    /// Chunk `index` ends where expected, but with another digest
+    Digest {
+        index: usize,
+        expected: D,
+        actual: D,
+    },
+}
+
+/// Outcome of `verify`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport<D> {
+    /// Number of chunks which matched before the first divergence
+    pub chunks: usize,
+    /// Length of the data covered by the matching chunks
+    pub bytes: u64,
+    /// First divergence, `None` if everything matched
+    pub divergence: Option<Divergence<D>>,
+}
+
+impl<D> VerifyReport<D> {
+    /// Return whether the boundaries match the data
+    pub fn is_ok(&self) -> bool {
+        self.divergence.is_none()
+    }
+}
+
+/// Check that chunking everything read from `reader` with `chunker` still
+/// gives the stored chunk `boundaries` and `digests`
+///
+/// `boundaries` holds the end offset of every chunk, including the final
+/// chunk which ends at the end of the data without an edge. `digests` holds
+/// the digests returned for the edges, or is empty if they aren't checked.
+/// Reading stops at the first divergence.
+///
+/// Changes to the data which move no edge and don't touch the window before
+/// an edge go unnoticed, so this doesn't replace strong chunk hashes.
+pub fn verify<R, C>(
+    reader: R,
+    chunker: &mut C,
+    boundaries: &[u64],
+    digests: &[C::Digest],
+) -> io::Result<VerifyReport<C::Digest>>
+where
+    R: Read,
+    C: Chunker,
+    C::Digest: PartialEq + Copy,
+{
+    let mut report = VerifyReport {
+        chunks: 0,
+        bytes: 0,
+        divergence: None,
+    };
+    let check = |report: &mut VerifyReport<_>, end: u64, digest: Option<C::Digest>| {
+        let index = report.chunks;
+        let expected = boundaries.get(index).copied();
+        report.divergence = if expected != Some(end) {
+            Some(Divergence::Boundary {
+                index,
+                expected,
+                actual: Some(end),
+            })
+        } else {
+            match (digests.get(index), digest) {
+                (Some(&expected), Some(actual)) if expected != actual => Some(Divergence::Digest {
+                    index,
+                    expected,
+                    actual,
+                }),
+                _ => None,
+            }
+        };
+        if report.divergence.is_none() {
+            report.chunks += 1;
+            report.bytes = end;
+        }
+        report.is_ok()
+    };
+
+    let mut diverged = false;
+    let position = for_each_block(reader, |mut position, mut data| {
+        while let Some((i, digest)) = chunker.find_chunk_edge(data) {
+            position += i as u64;
+            if !check(&mut report, position, Some(digest)) {
+                diverged = true;
+                return Ok(false);
+            }
+            data = &data[i..];
+        }
+        Ok(true)
+    })?;
+    if diverged {
+        return Ok(report);
+    }
+    if position > report.bytes && !check(&mut report, position, None) {
+        return Ok(report);
+    }
+    if report.chunks < boundaries.len() {
+        report.divergence = Some(Divergence::Boundary {
+            index: report.chunks,
+            expected: Some(boundaries[report.chunks]),
+            actual: None,
+        });
+    }
+    Ok(report)
+}
+
+/// `verify` using the chunker described by `params`, with digests widened
+/// to `u64`
+///
+/// Fails with `io::ErrorKind::InvalidInput` if the parameters are invalid or
+/// the feature of the algorithm is disabled.
+pub fn verify_params<R: Read>(
+    reader: R,
+    params: &ChunkerParams,
+    boundaries: &[u64],
+    digests: &[u64],
+) -> io::Result<VerifyReport<u64>> {
+    let mut chunker =
+        AnyChunker::new(*params).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    verify(reader, &mut chunker, boundaries, digests)
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::rand_data;
+    use crate::{Algorithm, Gear, MinMaxChunker};
+
+    fn chunk(data: &[u8], params: &ChunkerParams) -> (Vec<u64>, Vec<u64>) {
+        let mut chunker = MinMaxChunker::new(
+            Gear::new_with_chunk_bits(params.chunk_bits),
+            params.min_size as usize,
+            params.max_size as usize,
+        );
+        let (mut boundaries, mut digests) = (Vec::new(), Vec::new());
+        let mut offset = 0;
+        while let Some((i, digest)) = chunker.find_chunk_edge(&data[offset..]) {
+            offset += i;
+            boundaries.push(offset as u64);
+            digests.push(digest);
+        }
+        if offset < data.len() {
+            boundaries.push(data.len() as u64);
+        }
+        (boundaries, digests)
+    }
+
+    #[test]
+    fn reports_first_divergence() {
+        let data = rand_data(256 * 1024);
+        let params = ChunkerParams {
+            min_size: 256,
+            max_size: 8192,
+            ..ChunkerParams::new(Algorithm::Gear, 11)
+        };
+        let (boundaries, digests) = chunk(&data, &params);
+        let report = verify_params(&data[..], &params, &boundaries, &digests).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.chunks, boundaries.len());
+        assert_eq!(report.bytes, data.len() as u64);
+
+        // Only changes within the window before an edge are visible
+        let index = boundaries.iter().position(|&b| b > 100_000).unwrap();
+        let mut modified = data.clone();
+        modified[boundaries[index] as usize - 1] ^= 0xff;
+        let report = verify_params(&modified[..], &params, &boundaries, &digests).unwrap();
+        assert_eq!(report.chunks, index);
+        assert_eq!(report.bytes, boundaries[index - 1]);
+        match report.divergence {
+            Some(Divergence::Boundary { index: i, .. })
+            | Some(Divergence::Digest { index: i, .. }) => {
+                assert_eq!(i, index)
+            }
+            None => panic!("modification not detected"),
+        }
+
+        let mut wrong = digests.clone();
+        wrong[3] ^= 1;
+        let report = verify_params(&data[..], &params, &boundaries, &wrong).unwrap();
+        assert_eq!(
+            report.divergence,
+            Some(Divergence::Digest {
+                index: 3,
+                expected: wrong[3],
+                actual: digests[3],
+            })
+        );
+
+        let report = verify_params(&data[..100_000], &params, &boundaries, &[]).unwrap();
+        assert_eq!(report.chunks, index);
+        assert!(!report.is_ok());
+    }
+}
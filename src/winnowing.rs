@@ -0,0 +1,256 @@
+use super::{Chunker, Engine};
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// Digest selected by winnowing, with the position of its k-gram
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint<D> {
+    /// Digest of the k-gram
+    pub digest: D,
+    /// Offset of the first byte of the k-gram
+    pub offset: u64,
+}
+
+/// Winnowing document fingerprinting
+///
+/// The digests of all k-grams are computed by rolling the engine, where `k`
+/// is the engine's window size (see `Engine::window_size`). In every window
+/// of `w` consecutive digests the minimal one is selected (the rightmost one
+/// on ties), and each selected digest is reported once. Any substring shared
+/// by two documents of at least `w + k - 1` bytes is guaranteed to yield a
+/// common fingerprint.
+///
+/// Based on "Winnowing: Local Algorithms for Document Fingerprinting"
+/// (Schleimer, Wilkerson and Aiken, SIGMOD 2003).
+pub struct Winnower<E: Engine> {
+    engine: E,
+    k: usize,
+    w: usize,
+    /// Candidate minima of the current window, as (index, digest), with
+    /// strictly increasing digests
+    candidates: VecDeque<(u64, E::Digest)>,
+    /// Index of the last selected k-gram
+    selected: Option<u64>,
+    position: u64,
+}
+
+impl<E> Winnower<E>
+where
+    E: Engine,
+    E::Digest: Ord + Copy,
+{
+    /// Create a winnower with windows of `w` digests
+    ///
+    /// Panics if `w` is 0, or if the engine has no fixed window.
+    pub fn new(engine: E, w: usize) -> Self {
+        assert!(w > 0);
+        let k = engine
+            .window_size()
+            .expect("winnowing requires an engine with a fixed window");
+        Winnower {
+            engine,
+            k,
+            w,
+            candidates: VecDeque::with_capacity(w),
+            selected: None,
+            position: 0,
+        }
+    }
+
+    /// Feed the next piece of the document, calling `f` for every selected
+    /// fingerprint, in order of position
+    pub fn feed<F: FnMut(Fingerprint<E::Digest>)>(&mut self, buf: &[u8], mut f: F) {
+        for &b in buf {
+            if let Some(fp) = self.push(b) {
+                f(fp);
+            }
+        }
+    }
+
+    /// Feed the next byte, returning the fingerprint it selects if any
+    fn push(&mut self, b: u8) -> Option<Fingerprint<E::Digest>> {
+        self.engine.roll_byte(b);
+        self.position += 1;
+        if self.position < self.k as u64 {
+            return None;
+        }
+        let index = self.position - self.k as u64;
+        let digest = self.engine.digest();
+        while self.candidates.back().is_some_and(|&(_, d)| d >= digest) {
+            self.candidates.pop_back();
+        }
+        self.candidates.push_back((index, digest));
+        if index + 1 < self.w as u64 {
+            return None;
+        }
+        while self.candidates[0].0 + (self.w as u64) <= index {
+            self.candidates.pop_front();
+        }
+        let (min_index, min_digest) = self.candidates[0];
+        if self.selected == Some(min_index) {
+            return None;
+        }
+        self.selected = Some(min_index);
+        Some(Fingerprint {
+            digest: min_digest,
+            offset: min_index,
+        })
+    }
+
+    /// Return the k-gram length
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Return the number of digests per window
+    pub fn w(&self) -> usize {
+        self.w
+    }
+}
+
+/// Chunker cutting where winnowing selects a new fingerprint
+///
+/// A chunk ends at the byte whose window of `w` digests has a different
+/// minimum than the window before it, see `Winnower`. As every window has a
+/// minimum and a digest is in only `w` windows, chunks after the first are
+/// at most `w` bytes long without any edge being forced, and about
+/// `(w + 1) / 2` bytes on average for random data. The engine rolls over the
+/// whole stream, across edges, and the digest reported for an edge is the
+/// selected one.
+pub struct WinnowingChunker<E: Engine> {
+    winnower: Winnower<E>,
+}
+
+impl<E> WinnowingChunker<E>
+where
+    E: Engine,
+    E::Digest: Ord + Copy,
+{
+    /// Create a chunker selecting over windows of `w` digests
+    ///
+    /// Panics if `w` is 0, or if the engine has no fixed window.
+    pub fn new(engine: E, w: usize) -> Self {
+        WinnowingChunker {
+            winnower: Winnower::new(engine, w),
+        }
+    }
+
+    /// Return the largest chunk size after the first chunk
+    pub fn w(&self) -> usize {
+        self.winnower.w
+    }
+}
+
+impl<E> Chunker for WinnowingChunker<E>
+where
+    E: Engine,
+    E::Digest: Ord + Copy,
+{
+    type Digest = E::Digest;
+
+    fn find_chunk_edge(&mut self, buf: &[u8]) -> Option<(usize, E::Digest)> {
+        for (i, &b) in buf.iter().enumerate() {
+            if let Some(fp) = self.winnower.push(b) {
+                return Some((i + 1, fp.digest));
+            }
+        }
+        None
+    }
+}
+
+/// Return the winnowing fingerprints of `data`
+///
+/// See `Winnower`.
+pub fn fingerprints<E>(data: &[u8], w: usize) -> Vec<Fingerprint<E::Digest>>
+where
+    E: Engine + Default,
+    E::Digest: Ord + Copy,
+{
+    let mut winnower = Winnower::new(E::default(), w);
+    let mut result = Vec::new();
+    winnower.feed(data, |fp| result.push(fp));
+    result
+}
+
+/// Return the Jaccard similarity of the fingerprint digest sets of two
+/// documents, between 0 (nothing shared) and 1
+pub fn similarity<D: Hash + Eq>(a: &[Fingerprint<D>], b: &[Fingerprint<D>]) -> f64 {
+    let a: HashSet<_> = a.iter().map(|fp| &fp.digest).collect();
+    let b: HashSet<_> = b.iter().map(|fp| &fp.digest).collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[cfg(all(test, feature = "gear"))]
+mod tests {
+    use super::*;
+    use crate::tests::{chunk_edges_framed, rand_data};
+    use crate::Gear;
+
+    #[test]
+    fn matches_definition() {
+        let data = rand_data(8192);
+        let w = 16;
+        let k = crate::gear::WINDOW_SIZE;
+        let digests: Vec<_> = data.windows(k).map(Gear::digest_of).collect();
+        let mut expected: Vec<Fingerprint<u64>> = Vec::new();
+        for (start, window) in digests.windows(w).enumerate() {
+            let min = *window.iter().min().unwrap();
+            let i = start + window.iter().rposition(|&d| d == min).unwrap();
+            if expected.last().map(|fp| fp.offset) != Some(i as u64) {
+                expected.push(Fingerprint {
+                    digest: min,
+                    offset: i as u64,
+                });
+            }
+        }
+        assert_eq!(fingerprints::<Gear>(&data, w), expected);
+
+        let mut winnower = Winnower::new(Gear::default(), w);
+        let mut incremental = Vec::new();
+        for piece in data.chunks(100) {
+            winnower.feed(piece, |fp| incremental.push(fp));
+        }
+        assert_eq!(incremental, expected);
+    }
+
+    #[test]
+    fn detects_shared_content() {
+        let shared = rand_data(4096);
+        let a = [&[0x11; 2000][..], &shared].concat();
+        let b = [&shared[..], &[0xaa; 3000][..]].concat();
+        let fa = fingerprints::<Gear>(&a, 32);
+        let fb = fingerprints::<Gear>(&b, 32);
+        let fc = fingerprints::<Gear>(&[0x55; 4096], 32);
+        assert!(similarity(&fa, &fb) > 0.2);
+        assert_eq!(similarity(&fa, &fc), 0.0);
+        assert_eq!(similarity(&fa, &fa), 1.0);
+    }
+
+    #[test]
+    fn chunker_cuts_at_selections() {
+        let data = rand_data(64 * 1024);
+        let (w, k) = (100, crate::gear::WINDOW_SIZE);
+        let digests: Vec<_> = data.windows(k).map(Gear::digest_of).collect();
+        let mut expected = Vec::new();
+        let mut selected = None;
+        for (start, window) in digests.windows(w).enumerate() {
+            let min = *window.iter().min().unwrap();
+            let i = start + window.iter().rposition(|&d| d == min).unwrap();
+            if selected != Some(i) {
+                selected = Some(i);
+                expected.push(start + w - 1 + k);
+            }
+        }
+        assert!(expected.windows(2).all(|e| e[1] - e[0] <= w));
+
+        let mut chunker = WinnowingChunker::new(Gear::new(), w);
+        let edges = chunk_edges_framed(&mut chunker, &data, 97);
+        assert_eq!(edges, expected);
+        let average = data.len() / edges.len();
+        assert!((40..60).contains(&average), "{}", average);
+    }
+}